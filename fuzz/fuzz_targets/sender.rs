@@ -0,0 +1,6 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use secsnail::proto::{SendInput, fuzz};
+
+fuzz_target!(|inputs: Vec<SendInput>| fuzz::sender(inputs));
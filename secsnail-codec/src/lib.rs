@@ -0,0 +1,1012 @@
+//! Snail Transfer Protocol – packet codec
+//!
+//! Encoding, decoding and checksum of packets, `no_std` with `alloc`, so
+//! microcontrollers can speak the wire format. The sockets and fsms live
+//! in the `secsnail` crate.
+//!
+//! # Format:
+//!
+//! ```text
+//!  ┌───────────────────────────────────────────────┐
+//!  │                     Packet                    │
+//!  ├───────────┬───────────────┬───────────────────┤
+//!  │ N | A | C | K | F | I | N | S | Y | N |       │
+//!  │        unused (fixed zero) | Transfer Id      │
+//!  │                     Checksum                  │
+//!  │            (rest of header + data)            │
+//!  ├───────────────────────────────────────────────┤
+//!  │                 Payload Size                  │
+//!  ├───────────────────────────────────────────────┤
+//!  │ Application Data (variable length, ≤ 512 B)   │
+//!  └───────────────────────────────────────────────┘
+//! ```
+//!
+//! ## Fields
+//!
+//! - **Flags (8 bit)**  
+//!   - `N` – Alternating bit (0 or 1)  
+//!   - `ACK` – Acknowledgment flag  
+//!   - `FIN` – Finish flag  
+//!   - `SYN` – Synchronize flag  
+//!   - `SYN` + `FIN` – Reset flag (`RST`), transfer rejected by the receiver,
+//!     the payload holds the reason  
+//! - **unused** – reserved bit, always `0`  
+//! - **Transfer Id (3 bit)** – tells the concurrent transfers of a single
+//!   sender socket apart, echoed by the receiver, `0` for a sender with a
+//!   single transfer  
+//! - **Checksum (8 bit)** – CRC-8/I-432-1 checksum over header + data  
+//! - **Payload Size (16 bit)** – size of the following data in bytes  
+//! - **Application Data** – variable-length payload (max. 512 bytes)
+//!
+//! The checksum is computed over the encoded header (without checksum) and the payload.  
+//!
+//! On a trusted link, negotiated by sender and receiver, packets after the
+//! syn carry the checksum `0` and are not verified, see `Packet::new_unchecked`.
+//!
+//! `Packet::decode` ignores bytes after the payload, a receiver facing
+//! hostile input decodes with `DecodeMode::Strict` instead.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+pub type Result<T> = core::result::Result<T, CodecError>;
+
+/// errors of encoding and decoding a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    /// received bytes could not be decoded into a packet
+    CorruptPacket(&'static str),
+    /// payload does not fit into a single packet
+    PayloadTooLarge { len: usize, max: usize },
+    /// length field disagrees with the bytes after the header, surplus
+    /// bytes are only rejected by `DecodeMode::Strict`
+    LengthMismatch { announced: usize, received: usize },
+    /// an unused header bit is set, holds the unused bits
+    ReservedBits(u8),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::CorruptPacket(reason) => write!(f, "corrupt packet: {reason}"),
+            CodecError::PayloadTooLarge { len, max } => {
+                write!(f, "payload size {len} exceeds max payload size {max}")
+            }
+            CodecError::LengthMismatch {
+                announced,
+                received,
+            } => write!(
+                f,
+                "payload size {announced} announced, {received} bytes received"
+            ),
+            CodecError::ReservedBits(bits) => {
+                write!(f, "reserved header bits {bits:#06b} are set")
+            }
+        }
+    }
+}
+
+impl core::error::Error for CodecError {}
+
+pub const MAX_PAYLOAD_SIZE: usize = 512;
+pub const HEADER_LEN: usize = 4;
+/// largest transfer id the header holds, see `Packet::with_transfer_id`
+pub const MAX_TRANSFER_ID: u8 = 0b111;
+
+/// how a received datagram is checked before it counts as a packet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecodeMode {
+    /// bytes after the payload are ignored, the payload is only limited by
+    /// the length field
+    #[default]
+    Lenient,
+    /// the datagram holds exactly one packet of at most `MAX_PAYLOAD_SIZE`
+    /// bytes, e.g. for receivers reachable from hostile networks
+    Strict,
+}
+
+/// payload bytes shown by `Display` of a packet
+const PREVIEW_LEN: usize = 16;
+
+/// CRC-8/I-432-1: https://reveng.sourceforge.io/crc-catalogue/1-15.htm
+const CRC_8_I_423_1: crc::Algorithm<u8> = crc::Algorithm {
+    width: 8,
+    poly: 0x07,
+    init: 0x00,
+    refin: false,
+    refout: false,
+    xorout: 0x55,
+    check: 0xA1,
+    residue: 0xAC,
+};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq, Eq, Clone, Debug, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Flag {
+    SYN,
+    ACK,
+    FIN,
+    FINACK,
+    RST,
+    Data,
+}
+
+impl Flag {
+    fn to_byte(self, n: bool) -> u8 {
+        let mut f = match self {
+            Flag::SYN => 0b00010000,
+            Flag::ACK => 0b01000000,
+            Flag::FIN => 0b00100000,
+            Flag::FINACK => 0b01100000,
+            Flag::RST => 0b00110000,
+            Flag::Data => 0b00000000,
+        };
+
+        f |= match n {
+            // 128
+            true => 0b10000000,
+            // 0
+            false => 0b00000000,
+        };
+        f
+    }
+
+    /// first header byte of a packet of transfer `id`
+    fn header_byte(self, n: bool, id: u8) -> u8 {
+        self.to_byte(n) | id
+    }
+
+    fn byte_to_flag_n_and_id(b: u8) -> Result<(Flag, bool, u8)> {
+        // check for a fixed zero violation
+        let fixed_zeros = b & 0b00001000;
+        if fixed_zeros != 0 {
+            return Err(CodecError::ReservedBits(fixed_zeros));
+        }
+
+        let id = b & MAX_TRANSFER_ID;
+
+        // extract n
+        let n = (b & 0b10000000) != 0;
+
+        // extract flag bits - ignore n
+        let flag_bits = b & 0b01110000;
+        let flag = match flag_bits {
+            0b00010000 => Flag::SYN,
+            0b01000000 => Flag::ACK,
+            0b00100000 => Flag::FIN,
+            0b01100000 => Flag::FINACK,
+            0b00110000 => Flag::RST,
+            0b00000000 => Flag::Data,
+            _ => {
+                return Err(CodecError::CorruptPacket("unknown flag combination"));
+            }
+        };
+
+        Ok((flag, n, id))
+    }
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Flag::SYN => "SYN",
+            Flag::ACK => "ACK",
+            Flag::FIN => "FIN",
+            Flag::FINACK => "FINACK",
+            Flag::RST => "RST",
+            Flag::Data => "DATA",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    n: bool,
+    flag: Flag,
+    transfer_id: u8,
+    checksum: u8,
+    payload_len: u16,
+    /// MAX_PACKSIZE
+    buf: Vec<u8>,
+    /// received on a trusted link, the checksum is not verified
+    trusted: bool,
+}
+
+impl Packet {
+    pub fn max_pck_payload_size() -> usize {
+        MAX_PAYLOAD_SIZE - HEADER_LEN
+    }
+
+    /// n needs to be bool because it can only be 0 or 1
+    /// Condition of Alternating bit protocol
+    pub fn new(n: bool, f: Flag, p: Vec<u8>) -> Result<Self> {
+        Packet::build(PacketBuilder::new(n, f).payload(&p))
+    }
+
+    /// a packet for a trusted link, the checksum is not computed but `0`,
+    /// and the packet counts as intact
+    pub fn new_unchecked(n: bool, f: Flag, p: Vec<u8>) -> Result<Self> {
+        Ok(Packet::build(PacketBuilder::new(n, f).payload(&p).unchecked())?.trusted())
+    }
+
+    fn build(builder: PacketBuilder<'_>) -> Result<Self> {
+        let mut buf: Vec<u8> = vec![0; builder.encoded_len()];
+        builder.write(&mut buf)?;
+
+        Ok(Self {
+            flag: builder.flag,
+            transfer_id: builder.transfer_id,
+            payload_len: builder.payload.len() as u16,
+            checksum: buf[1],
+            buf,
+            n: builder.n,
+            trusted: false,
+        })
+    }
+
+    /// the packet was received on a trusted link, it counts as intact
+    /// without verifying its checksum
+    pub fn trusted(mut self) -> Self {
+        self.trusted = true;
+        self
+    }
+
+    pub fn is_trusted(&self) -> bool {
+        self.trusted
+    }
+
+    /// the packet of transfer `id`, the checksum is computed anew unless
+    /// the packet is for a trusted link
+    ///
+    /// # Panics
+    /// if `id` exceeds `MAX_TRANSFER_ID`
+    pub fn with_transfer_id(mut self, id: u8) -> Self {
+        assert!(id <= MAX_TRANSFER_ID, "transfer id {id} out of range");
+        if id == self.transfer_id {
+            return self;
+        }
+        self.transfer_id = id;
+        self.buf[0] = self.flag.header_byte(self.n, id);
+        if !self.trusted {
+            self.checksum = self.calc_checksum();
+            self.buf[1] = self.checksum;
+        }
+        self
+    }
+
+    // getter
+
+    pub fn n(&self) -> u8 {
+        match self.n {
+            true => 1,
+            false => 0,
+        }
+    }
+
+    pub fn flag(&self) -> Flag {
+        self.flag
+    }
+
+    /// transfer of the sender socket the packet belongs to
+    pub fn transfer_id(&self) -> u8 {
+        self.transfer_id
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.buf[HEADER_LEN..HEADER_LEN + self.payload_len as usize]
+    }
+
+    // syntax sugar: functions named as in fsm diagram
+
+    #[allow(non_snake_case)]
+    pub fn is_SYN(&self) -> bool {
+        self.flag == Flag::SYN
+    }
+
+    #[allow(non_snake_case)]
+    pub fn is_not_SYN(&self) -> bool {
+        !self.is_SYN()
+    }
+
+    #[allow(non_snake_case)]
+    pub fn is_ACK(&self) -> bool {
+        self.flag == Flag::ACK
+    }
+
+    #[allow(non_snake_case)]
+    pub fn is_FIN(&self) -> bool {
+        self.flag == Flag::FIN
+    }
+
+    #[allow(non_snake_case)]
+    pub fn is_Data(&self) -> bool {
+        self.flag == Flag::Data
+    }
+
+    #[allow(non_snake_case)]
+    pub fn is_FINACK(&self) -> bool {
+        self.flag == Flag::FINACK
+    }
+
+    #[allow(non_snake_case)]
+    pub fn is_RST(&self) -> bool {
+        self.flag == Flag::RST
+    }
+
+    pub fn notcorrupt(&self) -> bool {
+        self.trusted || self.checksum == self.calc_checksum()
+    }
+
+    pub fn corrupt(&self) -> bool {
+        !self.notcorrupt()
+    }
+
+    // checksum
+
+    pub fn calc_checksum(&self) -> u8 {
+        Packet::calc_checksum_crc_8_i_423_1(
+            self.flag.header_byte(self.n, self.transfer_id),
+            self.payload_len,
+            self.payload(),
+        )
+    }
+
+    fn calc_checksum_crc_8_i_423_1(f_and_n: u8, p_l: u16, p: &[u8]) -> u8 {
+        let crc = crc::Crc::<u8>::new(&CRC_8_I_423_1);
+        let mut digst = crc.digest();
+
+        digst.update(&[f_and_n]);
+        digst.update(&p_l.to_be_bytes());
+        digst.update(p);
+
+        digst.finalize()
+    }
+
+    /// `Display` of the packet followed by a hex dump of header and payload
+    pub fn describe(&self) -> String {
+        let mut s = self.to_string();
+        let bytes = &self.buf[..HEADER_LEN + self.payload_len as usize];
+        for (i, line) in bytes.chunks(16).enumerate() {
+            let hex: Vec<String> = line.iter().map(|b| format!("{b:02x}")).collect();
+            s.push_str(&format!(
+                "\n{:04x}  {:<47}  |{}|",
+                i * 16,
+                hex.join(" "),
+                printable(line)
+            ));
+        }
+        s
+    }
+
+    // encoding && decoding
+    pub fn encode(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// copy the encoded packet to the start of `buf`
+    ///
+    /// # Return
+    /// the length of the packet
+    ///
+    /// # Panics
+    /// if `buf` is shorter than the packet
+    pub fn encode_into(&self, buf: &mut [u8]) -> usize {
+        buf[..self.buf.len()].copy_from_slice(&self.buf);
+        self.buf.len()
+    }
+
+    /// decode the packet at the start of `buf`, only its own bytes are copied
+    pub fn decode_from(buf: &[u8]) -> Result<Self> {
+        Ok(PacketView::parse(buf)?.to_packet())
+    }
+
+    pub fn decode(buf: Vec<u8>) -> Result<Self> {
+        Packet::decode_with(buf, DecodeMode::Lenient)
+    }
+
+    /// decode a received datagram, see `DecodeMode`
+    pub fn decode_with(mut buf: Vec<u8>, mode: DecodeMode) -> Result<Self> {
+        let view = PacketView::parse_with(&buf, mode)?;
+        let (flag, n, transfer_id, checksum) = (view.flag, view.n, view.transfer_id, view.checksum);
+        let payload_len = view.payload.len() as u16;
+
+        // bytes after the payload are no part of the packet
+        buf.truncate(view.encoded_len());
+
+        Ok(Self {
+            flag,
+            transfer_id,
+            payload_len,
+            checksum,
+            buf,
+            n,
+            trusted: false,
+        })
+    }
+
+    /// fields of the packet borrowed from its buffer
+    pub fn view(&self) -> PacketView<'_> {
+        PacketView {
+            n: self.n,
+            flag: self.flag,
+            transfer_id: self.transfer_id,
+            checksum: self.checksum,
+            payload: self.payload(),
+        }
+    }
+}
+
+/// header fields and payload of an encoded packet, borrowed from e.g. a
+/// receive buffer, so the payload can be written to disk without a copy
+///
+/// ```
+/// use secsnail_codec::{Flag, Packet, PacketView};
+///
+/// let buf = Packet::new(true, Flag::Data, b"slow".to_vec()).unwrap().encode().to_vec();
+/// let view = PacketView::parse(&buf).unwrap();
+/// assert!(view.notcorrupt());
+/// assert_eq!(view.payload(), b"slow");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketView<'a> {
+    n: bool,
+    flag: Flag,
+    transfer_id: u8,
+    checksum: u8,
+    payload: &'a [u8],
+}
+
+impl<'a> PacketView<'a> {
+    /// parse the packet at the start of `buf`, bytes after it are ignored
+    pub fn parse(buf: &'a [u8]) -> Result<Self> {
+        PacketView::parse_with(buf, DecodeMode::Lenient)
+    }
+
+    /// parse a received datagram, see `DecodeMode`
+    pub fn parse_with(buf: &'a [u8], mode: DecodeMode) -> Result<Self> {
+        let [f_and_n, checksum, hi, lo, rest @ ..] = buf else {
+            return Err(CodecError::CorruptPacket("Buffer too short"));
+        };
+
+        let (flag, n, transfer_id) = Flag::byte_to_flag_n_and_id(*f_and_n)?;
+        let payload_len = u16::from_be_bytes([*hi, *lo]) as usize;
+
+        let max = Packet::max_pck_payload_size();
+        if mode == DecodeMode::Strict && payload_len > max {
+            return Err(CodecError::PayloadTooLarge {
+                len: payload_len,
+                max,
+            });
+        }
+        let mismatch = match mode {
+            DecodeMode::Lenient => rest.len() < payload_len,
+            DecodeMode::Strict => rest.len() != payload_len,
+        };
+        if mismatch {
+            return Err(CodecError::LengthMismatch {
+                announced: payload_len,
+                received: rest.len(),
+            });
+        }
+        let payload = &rest[..payload_len];
+
+        Ok(PacketView {
+            n,
+            flag,
+            transfer_id,
+            checksum: *checksum,
+            payload,
+        })
+    }
+
+    pub fn n(&self) -> u8 {
+        self.n as u8
+    }
+
+    pub fn flag(&self) -> Flag {
+        self.flag
+    }
+
+    pub fn transfer_id(&self) -> u8 {
+        self.transfer_id
+    }
+
+    /// checksum as received
+    pub fn checksum(&self) -> u8 {
+        self.checksum
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    /// bytes of the encoded packet
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.payload.len()
+    }
+
+    pub fn notcorrupt(&self) -> bool {
+        let p_l = self.payload.len() as u16;
+        self.checksum
+            == Packet::calc_checksum_crc_8_i_423_1(
+                self.flag.header_byte(self.n, self.transfer_id),
+                p_l,
+                self.payload,
+            )
+    }
+
+    pub fn corrupt(&self) -> bool {
+        !self.notcorrupt()
+    }
+
+    /// an owned copy, a corrupt checksum is kept
+    pub fn to_packet(&self) -> Packet {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        buf.push(self.flag.header_byte(self.n, self.transfer_id));
+        buf.push(self.checksum);
+        buf.extend_from_slice(&(self.payload.len() as u16).to_be_bytes());
+        buf.extend_from_slice(self.payload);
+        Packet {
+            n: self.n,
+            flag: self.flag,
+            transfer_id: self.transfer_id,
+            checksum: self.checksum,
+            payload_len: self.payload.len() as u16,
+            buf,
+            trusted: false,
+        }
+    }
+}
+
+/// encodes a packet straight into a buffer of the caller, e.g. a reusable
+/// send buffer, without a `Packet` in between
+///
+/// ```
+/// use secsnail_codec::{Flag, Packet, PacketBuilder, MAX_PAYLOAD_SIZE};
+///
+/// let mut buf = [0; MAX_PAYLOAD_SIZE];
+/// let len = PacketBuilder::new(true, Flag::Data)
+///     .payload(b"slow and steady")
+///     .write(&mut buf)
+///     .unwrap();
+/// assert_eq!(Packet::decode_from(&buf[..len]).unwrap().payload(), b"slow and steady");
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct PacketBuilder<'a> {
+    n: bool,
+    flag: Flag,
+    transfer_id: u8,
+    payload: &'a [u8],
+    /// write the checksum `0` instead of computing it
+    unchecked: bool,
+}
+
+impl<'a> PacketBuilder<'a> {
+    /// a packet without payload
+    pub fn new(n: bool, flag: Flag) -> Self {
+        PacketBuilder {
+            n,
+            flag,
+            transfer_id: 0,
+            payload: &[],
+            unchecked: false,
+        }
+    }
+
+    pub fn payload(mut self, payload: &'a [u8]) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    /// a packet of transfer `id`, see `Packet::with_transfer_id`
+    ///
+    /// # Panics
+    /// if `id` exceeds `MAX_TRANSFER_ID`
+    pub fn transfer_id(mut self, id: u8) -> Self {
+        assert!(id <= MAX_TRANSFER_ID, "transfer id {id} out of range");
+        self.transfer_id = id;
+        self
+    }
+
+    /// skip the checksum for a trusted link, see `Packet::new_unchecked`
+    pub fn unchecked(mut self) -> Self {
+        self.unchecked = true;
+        self
+    }
+
+    /// bytes `write` needs
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + self.payload.len()
+    }
+
+    /// encode the packet to the start of `buf`
+    ///
+    /// # Return
+    /// the length of the packet, fails with `PayloadTooLarge` if the payload
+    /// exceeds a packet or `buf`
+    pub fn write(&self, buf: &mut [u8]) -> Result<usize> {
+        let max = Packet::max_pck_payload_size().min(buf.len().saturating_sub(HEADER_LEN));
+        if self.payload.len() > max {
+            return Err(CodecError::PayloadTooLarge {
+                len: self.payload.len(),
+                max,
+            });
+        }
+
+        let p_l = self.payload.len() as u16;
+        buf[0] = self.flag.header_byte(self.n, self.transfer_id);
+        buf[2..HEADER_LEN].copy_from_slice(&p_l.to_be_bytes());
+        buf[HEADER_LEN..self.encoded_len()].copy_from_slice(self.payload);
+        buf[1] = match self.unchecked {
+            true => 0,
+            false => Packet::calc_checksum_crc_8_i_423_1(buf[0], p_l, self.payload),
+        };
+        Ok(self.encoded_len())
+    }
+}
+
+/// e.g. `DATA n=1 checksum=0x3c (ok) len=508 "slow and steady "…`
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} n={} checksum={:#04x} ({}) len={}",
+            self.flag,
+            self.n(),
+            self.checksum,
+            if self.notcorrupt() { "ok" } else { "corrupt" },
+            self.payload_len
+        )?;
+        if self.transfer_id != 0 {
+            write!(f, " id={}", self.transfer_id)?;
+        }
+        let payload = self.payload();
+        if !payload.is_empty() {
+            let preview = &payload[..payload.len().min(PREVIEW_LEN)];
+            write!(f, " \"{}\"", printable(preview))?;
+            if payload.len() > PREVIEW_LEN {
+                f.write_str("…")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// ascii of `bytes`, non printable ones replaced by a dot
+fn printable(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| match b {
+            b' '..=b'~' => *b as char,
+            _ => '.',
+        })
+        .collect()
+}
+
+/// fields of a serialized packet, the checksum is kept as is, so a corrupt
+/// packet stays corrupt
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PacketFields {
+    n: bool,
+    flag: Flag,
+    /// missing in packets serialized before transfer ids
+    #[serde(default)]
+    transfer_id: u8,
+    checksum: u8,
+    payload: Vec<u8>,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Packet {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        PacketFields {
+            n: self.n,
+            flag: self.flag,
+            transfer_id: self.transfer_id,
+            checksum: self.checksum,
+            payload: self.payload().to_vec(),
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Packet {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> core::result::Result<Self, D::Error> {
+        let fields = PacketFields::deserialize(deserializer)?;
+        if fields.transfer_id > MAX_TRANSFER_ID {
+            return Err(serde::de::Error::custom("transfer id out of range"));
+        }
+        let mut pck = Packet::new(fields.n, fields.flag, fields.payload)
+            .map_err(serde::de::Error::custom)?
+            .with_transfer_id(fields.transfer_id);
+        pck.checksum = fields.checksum;
+        pck.buf[1] = fields.checksum;
+        Ok(pck)
+    }
+}
+
+/// valid packets only, a fuzzer hands raw bytes to `decode` itself
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Packet {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let n = u.arbitrary()?;
+        let flag = u.arbitrary()?;
+        let len = u.int_in_range(0..=Packet::max_pck_payload_size())?;
+        let payload = u.bytes(len)?.to_vec();
+        let id = u.int_in_range(0..=MAX_TRANSFER_ID)?;
+        Ok(Packet::new(n, flag, payload)
+            .expect("payload within the limit")
+            .with_transfer_id(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calc_checksum() {
+        let pck1 = Packet::new(false, Flag::SYN, vec![b'a']).unwrap();
+        let pck2 = Packet::new(true, Flag::SYN, vec![b'a']).unwrap();
+        let pck3 = Packet::new(false, Flag::SYN, vec![b'a']).unwrap();
+        let pck4 = Packet::new(false, Flag::SYN, vec![b'a', b'b']).unwrap();
+
+        assert_eq!(pck1.calc_checksum(), pck3.calc_checksum());
+        assert_ne!(pck1.calc_checksum(), pck2.calc_checksum());
+        assert_ne!(pck1.calc_checksum(), pck4.calc_checksum());
+    }
+
+    #[test]
+    fn test_encode() {
+        let pck1 = Packet::new(false, Flag::SYN, vec![b'a']).unwrap();
+        let pck2 = Packet::new(true, Flag::ACK, vec![b'a', b'b']).unwrap();
+        let pck3 = Packet::new(true, Flag::ACK, vec![b'a', b'b']).unwrap();
+        let pck4 = Packet::new(true, Flag::ACK, vec![b'a', b'b']).unwrap();
+
+        assert_ne!(pck1.encode(), pck2.encode());
+        assert_eq!(pck3.encode(), pck4.encode());
+    }
+
+    #[test]
+    fn test_decode() {
+        let pck1 = Packet::new(false, Flag::SYN, vec![b'a']).unwrap();
+        let pck2 = Packet::new(true, Flag::ACK, vec![b'a', b'b']).unwrap();
+
+        assert_eq!(Packet::decode(pck1.encode().to_vec()).unwrap(), pck1);
+
+        assert_eq!(Packet::decode(pck2.encode().to_vec()).unwrap(), pck2,);
+    }
+
+    #[test]
+    fn builder_writes_into_buffer() {
+        let pck = Packet::new(true, Flag::FIN, b"snail".to_vec()).unwrap();
+        let mut buf = [0xff; 16];
+        let builder = PacketBuilder::new(true, Flag::FIN).payload(b"snail");
+        assert_eq!(builder.write(&mut buf).unwrap(), 9);
+        assert_eq!(&buf[..9], pck.encode());
+        assert!(builder.write(&mut buf[..8]).is_err());
+
+        let mut copy = [0; 16];
+        assert_eq!(pck.encode_into(&mut copy), 9);
+        assert_eq!(copy, [&buf[..9], &[0; 7]].concat()[..]);
+    }
+
+    #[test]
+    fn unchecked_packets_skip_the_checksum() {
+        let pck = Packet::new_unchecked(true, Flag::Data, b"slow".to_vec()).unwrap();
+        assert_eq!(pck.encode()[1], 0);
+        assert!(pck.notcorrupt());
+
+        let decoded = Packet::decode(pck.encode().to_vec()).unwrap();
+        assert!(decoded.corrupt());
+        assert!(decoded.trusted().notcorrupt());
+    }
+
+    #[test]
+    fn decode_every_header_byte() {
+        let mut valid = 0;
+        for b in 0..=u8::MAX {
+            let buf = [b, 0, 0, 0];
+            for mode in [DecodeMode::Lenient, DecodeMode::Strict] {
+                match PacketView::parse_with(&buf, mode) {
+                    Ok(view) => {
+                        let header = view.flag().header_byte(view.n() == 1, view.transfer_id());
+                        assert_eq!(header, b);
+                        valid += 1;
+                    }
+                    Err(CodecError::ReservedBits(bits)) => assert_eq!(bits, b & 0x08),
+                    Err(e) => {
+                        assert_eq!(e, CodecError::CorruptPacket("unknown flag combination"));
+                        // ack with syn, with or without fin
+                        assert!(matches!(b & 0b01110000, 0b01010000 | 0b01110000));
+                    }
+                }
+            }
+        }
+        // six flags, with n either way and every transfer id, in both modes
+        assert_eq!(valid, 6 * 2 * 8 * 2);
+    }
+
+    #[test]
+    fn transfer_id_roundtrip() {
+        let pck = Packet::new(true, Flag::Data, b"slow".to_vec())
+            .unwrap()
+            .with_transfer_id(5);
+        assert!(pck.notcorrupt());
+        let decoded = Packet::decode(pck.encode().to_vec()).unwrap();
+        assert_eq!(decoded, pck);
+        assert_eq!(decoded.transfer_id(), 5);
+
+        let mut buf = [0; MAX_PAYLOAD_SIZE];
+        let len = PacketBuilder::new(true, Flag::Data)
+            .payload(b"slow")
+            .transfer_id(5)
+            .write(&mut buf)
+            .unwrap();
+        assert_eq!(&buf[..len], pck.encode());
+
+        // the id is covered by the checksum
+        let mut other = pck.encode().to_vec();
+        other[0] ^= 0b011;
+        assert!(Packet::decode(other).unwrap().corrupt());
+    }
+
+    #[test]
+    fn decode_every_length() {
+        let max = Packet::max_pck_payload_size();
+        for len in 0..=max + 1 {
+            let mut buf = vec![0; HEADER_LEN + len + 1];
+            buf[2..HEADER_LEN].copy_from_slice(&(len as u16).to_be_bytes());
+            for received in [len.saturating_sub(1), len, len + 1] {
+                let datagram = &buf[..HEADER_LEN + received];
+                let lenient = PacketView::parse(datagram);
+                let strict = PacketView::parse_with(datagram, DecodeMode::Strict);
+                match (received < len, len > max, received > len) {
+                    (true, _, _) => {
+                        assert!(matches!(lenient, Err(CodecError::LengthMismatch { .. })))
+                    }
+                    (false, true, _) => {
+                        assert_eq!(lenient.unwrap().payload().len(), len);
+                        assert_eq!(strict, Err(CodecError::PayloadTooLarge { len, max }));
+                        continue;
+                    }
+                    (false, false, true) => {
+                        assert_eq!(lenient.unwrap().encoded_len(), HEADER_LEN + len);
+                        assert_eq!(
+                            strict,
+                            Err(CodecError::LengthMismatch {
+                                announced: len,
+                                received
+                            })
+                        );
+                        continue;
+                    }
+                    (false, false, false) => {
+                        assert_eq!(lenient, strict);
+                        assert_eq!(strict.unwrap().payload().len(), len);
+                        continue;
+                    }
+                }
+                if len <= max {
+                    assert!(matches!(strict, Err(CodecError::LengthMismatch { .. })));
+                }
+            }
+        }
+        assert_eq!(
+            Packet::decode_with(vec![0; 3], DecodeMode::Strict),
+            Err(CodecError::CorruptPacket("Buffer too short"))
+        );
+    }
+
+    #[test]
+    fn view_borrows_the_payload() {
+        let pck = Packet::new(false, Flag::Data, b"snail".to_vec()).unwrap();
+        let mut buf = pck.encode().to_vec();
+        buf.extend_from_slice(b"trailing");
+
+        let view = PacketView::parse(&buf).unwrap();
+        assert_eq!(view, pck.view());
+        assert_eq!(view.payload().as_ptr(), buf[HEADER_LEN..].as_ptr());
+        assert_eq!(view.to_packet(), pck);
+
+        buf[HEADER_LEN] ^= 1;
+        let view = PacketView::parse(&buf).unwrap();
+        assert!(view.corrupt());
+        assert!(view.to_packet().corrupt());
+        assert!(PacketView::parse(&buf[..HEADER_LEN + 2]).is_err());
+    }
+
+    #[test]
+    fn decode_truncates_to_the_packet() {
+        let pck = Packet::new(true, Flag::Data, b"snail".to_vec()).unwrap();
+        let mut buf = pck.encode().to_vec();
+        buf.resize(MAX_PAYLOAD_SIZE, 0);
+
+        assert_eq!(Packet::decode(buf.clone()).unwrap().encode(), pck.encode());
+        assert_eq!(Packet::decode_from(&buf).unwrap(), pck);
+        assert!(Packet::decode_from(&buf[..6]).is_err());
+    }
+
+    #[test]
+    fn test_decode_rst() {
+        let pck = Packet::new(false, Flag::RST, b"rejected".to_vec()).unwrap();
+        let decoded = Packet::decode(pck.encode().to_vec()).unwrap();
+
+        assert!(decoded.is_RST());
+        assert!(decoded.notcorrupt());
+        assert_eq!(decoded.payload(), b"rejected");
+    }
+
+    #[test]
+    fn test_encode_decode_checksum() {
+        let pck1 = Packet::new(false, Flag::SYN, vec![b'a']).unwrap();
+        let pck2 = Packet::new(true, Flag::ACK, vec![b'a', b'b']).unwrap();
+
+        let pck1_decoded = Packet::decode(pck1.encode().to_vec()).unwrap();
+        let pck2_decoded = Packet::decode(pck2.encode().to_vec()).unwrap();
+
+        assert_eq!(pck1_decoded.calc_checksum(), pck1.calc_checksum());
+
+        assert_eq!(pck2_decoded.calc_checksum(), pck2.calc_checksum());
+    }
+
+    #[test]
+    fn display_and_describe() {
+        let pck = Packet::new(true, Flag::Data, b"slow and steady wins".to_vec()).unwrap();
+        assert_eq!(
+            pck.to_string(),
+            format!(
+                "DATA n=1 checksum={:#04x} (ok) len=20 \"slow and steady \"…",
+                pck.calc_checksum()
+            )
+        );
+
+        let mut buf = Packet::new(false, Flag::ACK, Vec::new())
+            .unwrap()
+            .encode()
+            .to_vec();
+        buf[1] ^= 0xFF;
+        let corrupt = Packet::decode(buf).unwrap();
+        assert!(corrupt.to_string().starts_with("ACK n=0"));
+        assert!(corrupt.to_string().ends_with("(corrupt) len=0"));
+
+        let lines: Vec<String> = pck.describe().lines().map(String::from).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("0000  80 "));
+        assert!(lines[2].ends_with("|ady wins|"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_keeps_checksum() {
+        let pck = Packet::new(true, Flag::Data, b"snail".to_vec()).unwrap();
+        let json = serde_json::to_string(&pck).unwrap();
+        assert_eq!(serde_json::from_str::<Packet>(&json).unwrap(), pck);
+
+        let mut buf = pck.encode().to_vec();
+        buf[1] ^= 1;
+        let corrupt = Packet::decode(buf).unwrap();
+        let json = serde_json::to_string(&corrupt).unwrap();
+        let replayed: Packet = serde_json::from_str(&json).unwrap();
+        assert!(replayed.corrupt());
+        assert_eq!(replayed.encode(), corrupt.encode());
+    }
+}
@@ -0,0 +1,33 @@
+/* C bindings of the Secure Snail Protocol, see secsnail-ffi/src/lib.rs */
+#ifndef SECSNAIL_H
+#define SECSNAIL_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SECSNAIL_OK 0
+#define SECSNAIL_INVALID_ARGUMENT 1
+#define SECSNAIL_IO 2
+#define SECSNAIL_TIMEOUT 3
+#define SECSNAIL_PROTOCOL 4
+#define SECSNAIL_REJECTED 5
+#define SECSNAIL_INVALID_FILENAME 6
+#define SECSNAIL_OTHER 7
+
+typedef struct SecSnailHandle secsnail_socket;
+
+/* null if binding failed */
+secsnail_socket *secsnail_bind(const char *addr);
+void secsnail_close(secsnail_socket *sock);
+/* -1 if sock is null */
+int secsnail_local_port(const secsnail_socket *sock);
+int secsnail_send_file(secsnail_socket *sock, const char *path, const char *addr);
+int secsnail_recv_file(secsnail_socket *sock, const char *dir);
+const char *secsnail_strerror(int code);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,212 @@
+//! C bindings of the Secure Snail Protocol 🐌
+//!
+//! A socket is an opaque handle of `secsnail_bind`, every call returns one
+//! of the `SECSNAIL_*` error codes, `0` on success. The declarations are in
+//! `include/secsnail.h`.
+//!
+//! ```c
+//! secsnail_socket *sock = secsnail_bind("0.0.0.0:0");
+//! int err = secsnail_send_file(sock, "snail.txt", "127.0.0.1:55055");
+//! if (err != SECSNAIL_OK)
+//!     fprintf(stderr, "%s\n", secsnail_strerror(err));
+//! secsnail_close(sock);
+//! ```
+
+use std::{
+    ffi::{CStr, c_char, c_int},
+    ptr,
+};
+
+use secsnail::{error::SecSnailError, sock::SecSnailSocket};
+
+pub const SECSNAIL_OK: c_int = 0;
+/// a null pointer or a string which is no valid UTF-8 or address
+pub const SECSNAIL_INVALID_ARGUMENT: c_int = 1;
+pub const SECSNAIL_IO: c_int = 2;
+/// the peer stopped answering
+pub const SECSNAIL_TIMEOUT: c_int = 3;
+/// the peer broke the protocol or sent corrupt packets
+pub const SECSNAIL_PROTOCOL: c_int = 4;
+/// the receiver refused the file, e.g. as it is too large
+pub const SECSNAIL_REJECTED: c_int = 5;
+pub const SECSNAIL_INVALID_FILENAME: c_int = 6;
+/// any other error
+pub const SECSNAIL_OTHER: c_int = 7;
+
+/// opaque socket handle, `secsnail_socket` in C
+pub struct SecSnailHandle(SecSnailSocket);
+
+fn error_code(e: &SecSnailError) -> c_int {
+    match e {
+        SecSnailError::Io(_) => SECSNAIL_IO,
+        SecSnailError::MaxRetransmitsExceeded
+        | SecSnailError::ConnectionTimeout
+        | SecSnailError::DeadlineExceeded => SECSNAIL_TIMEOUT,
+        SecSnailError::CorruptPacket(_)
+        | SecSnailError::LengthMismatch { .. }
+        | SecSnailError::ReservedBits(_)
+        | SecSnailError::PayloadTooLarge { .. }
+        | SecSnailError::ProtocolViolation(_) => SECSNAIL_PROTOCOL,
+        SecSnailError::Rejected(_)
+        | SecSnailError::FileTooLarge { .. }
+        | SecSnailError::InsufficientSpace { .. }
+        | SecSnailError::QuotaExceeded { .. } => SECSNAIL_REJECTED,
+        SecSnailError::InvalidFilename(_) => SECSNAIL_INVALID_FILENAME,
+        SecSnailError::InvalidConfig(_) => SECSNAIL_INVALID_ARGUMENT,
+        _ => SECSNAIL_OTHER,
+    }
+}
+
+fn code_of<T>(r: secsnail::error::Result<T>) -> c_int {
+    match r {
+        Ok(_) => SECSNAIL_OK,
+        Err(e) => error_code(&e),
+    }
+}
+
+/// # Safety
+/// `s` is null or a nul-terminated string
+unsafe fn str_arg<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(s) }.to_str().ok()
+}
+
+/// bind a socket to `addr`, e.g. `0.0.0.0:55055`
+///
+/// # Return
+/// the handle to free with `secsnail_close`, null if binding failed
+///
+/// # Safety
+/// `addr` is null or a nul-terminated string
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn secsnail_bind(addr: *const c_char) -> *mut SecSnailHandle {
+    match unsafe { str_arg(addr) }.map(SecSnailSocket::bind) {
+        Some(Ok(sock)) => Box::into_raw(Box::new(SecSnailHandle(sock))),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// free a handle of `secsnail_bind`, null is ignored
+///
+/// # Safety
+/// `sock` is null or a handle of `secsnail_bind` which is not used afterwards
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn secsnail_close(sock: *mut SecSnailHandle) {
+    if !sock.is_null() {
+        drop(unsafe { Box::from_raw(sock) });
+    }
+}
+
+/// local port of the socket, `-1` if `sock` is null
+///
+/// # Safety
+/// `sock` is null or a handle of `secsnail_bind`
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn secsnail_local_port(sock: *const SecSnailHandle) -> c_int {
+    match unsafe { sock.as_ref() }.map(|sock| sock.0.local_addr()) {
+        Some(Ok(addr)) => addr.port() as c_int,
+        _ => -1,
+    }
+}
+
+/// send the file at `path` to the receiver at `addr`, blocks until the
+/// transfer is acknowledged or failed
+///
+/// # Safety
+/// `sock` is null or a handle of `secsnail_bind`, `path` and `addr` are
+/// null or nul-terminated strings
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn secsnail_send_file(
+    sock: *mut SecSnailHandle,
+    path: *const c_char,
+    addr: *const c_char,
+) -> c_int {
+    let sock = unsafe { sock.as_mut() };
+    let (path, addr) = unsafe { (str_arg(path), str_arg(addr)) };
+    let (Some(sock), Some(path), Some(Ok(addr))) = (sock, path, addr.map(str::parse)) else {
+        return SECSNAIL_INVALID_ARGUMENT;
+    };
+    code_of(sock.0.send_file_to_blocking(path, addr))
+}
+
+/// receive a single file into the directory `dir`, blocks until the
+/// transfer is finished or failed
+///
+/// # Safety
+/// `sock` is null or a handle of `secsnail_bind`, `dir` is null or a
+/// nul-terminated string
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn secsnail_recv_file(
+    sock: *mut SecSnailHandle,
+    dir: *const c_char,
+) -> c_int {
+    let (Some(sock), Some(dir)) = (unsafe { sock.as_mut() }, unsafe { str_arg(dir) }) else {
+        return SECSNAIL_INVALID_ARGUMENT;
+    };
+    code_of(sock.0.recv_file_blocking(dir))
+}
+
+/// static description of an error code
+#[unsafe(no_mangle)]
+pub extern "C" fn secsnail_strerror(code: c_int) -> *const c_char {
+    let s: &'static CStr = match code {
+        SECSNAIL_OK => c"success",
+        SECSNAIL_INVALID_ARGUMENT => c"invalid argument",
+        SECSNAIL_IO => c"socket or file system error",
+        SECSNAIL_TIMEOUT => c"peer stopped responding",
+        SECSNAIL_PROTOCOL => c"protocol violation",
+        SECSNAIL_REJECTED => c"transfer rejected",
+        SECSNAIL_INVALID_FILENAME => c"invalid file name",
+        _ => c"unknown error",
+    };
+    s.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ffi::CString, fs, thread};
+
+    #[test]
+    fn send_and_recv_through_handles() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-ffi", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("out")).unwrap();
+        fs::write(dir.join("snail.txt"), vec![9u8; 2000]).unwrap();
+        let path = CString::new(dir.join("snail.txt").to_str().unwrap()).unwrap();
+        let out = CString::new(dir.join("out").to_str().unwrap()).unwrap();
+
+        unsafe {
+            let receiver = secsnail_bind(c"127.0.0.1:0".as_ptr());
+            assert!(!receiver.is_null());
+            let addr =
+                CString::new(format!("127.0.0.1:{}", secsnail_local_port(receiver))).unwrap();
+            // raw pointers are not Send, the address of the handle is
+            let receiver = receiver as usize;
+            let recv = thread::spawn(move || {
+                let receiver = receiver as *mut SecSnailHandle;
+                let code = secsnail_recv_file(receiver, out.as_ptr());
+                secsnail_close(receiver);
+                code
+            });
+
+            let sender = secsnail_bind(c"127.0.0.1:0".as_ptr());
+            assert_eq!(
+                secsnail_send_file(sender, path.as_ptr(), addr.as_ptr()),
+                SECSNAIL_OK
+            );
+            assert_eq!(
+                secsnail_send_file(sender, ptr::null(), addr.as_ptr()),
+                SECSNAIL_INVALID_ARGUMENT
+            );
+            secsnail_close(sender);
+            assert_eq!(recv.join().unwrap(), SECSNAIL_OK);
+        }
+        assert_eq!(
+            fs::read(dir.join("out/snail.txt")).unwrap(),
+            vec![9u8; 2000]
+        );
+    }
+}
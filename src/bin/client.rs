@@ -1,45 +1,356 @@
+mod common;
+
 use clap::Parser;
-use secsnail::sock::{DEFAULT_SECSNAIL_PORT, SecSnailSocket};
-use std::{io, net::SocketAddr};
+use common::Verbosity;
+use indicatif::{ProgressBar, ProgressStyle};
+use secsnail::sock::{
+    DEFAULT_RENDEZVOUS_PORT, DEFAULT_SECSNAIL_PORT, Progress, SecSnailSocket, SendOutcome, Verdict,
+    outcomes_to_json,
+};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    time::Duration,
+};
+
+/// how long to wait for the server at the rendezvous
+const RENDEZVOUS_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// Demo client starts a secure snail file transmission:
 ///
-///   Use default secsnail port 55055
+///   Use default secsnail port 55055 unless `--port` is given
 fn main() -> io::Result<()> {
     let args = Args::parse();
-    let recv_addr: SocketAddr = format!("{}:{}", args.ip, DEFAULT_SECSNAIL_PORT)
-        .parse()
-        .expect("Unable to parse socket address");
+    args.verbosity.init_tracing();
+    // the address of the server is only known after the rendezvous
+    let server_addr = match &args.rendezvous {
+        Some(rendezvous) => rendezvous.as_str(),
+        None => args
+            .ip
+            .as_deref()
+            .expect("clap requires --ip without --rendezvous"),
+    };
+    let port = match args.rendezvous {
+        Some(_) => DEFAULT_RENDEZVOUS_PORT,
+        None => args.port,
+    };
+    let server_addr = match server_addr.to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(_) => (server_addr, port).to_socket_addrs()?,
+    }
+    .next()
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address of the server"))?;
+
+    let mut builder = SecSnailSocket::builder();
+    if args.rendezvous.is_none() {
+        builder = builder.connect(server_addr);
+    }
+    // an ephemeral port of the server's address family unless fixed
+    if args.bind.is_some() || args.source_port != 0 || args.rendezvous.is_some() {
+        let ip = args.bind.unwrap_or(match server_addr {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        });
+        builder = builder.bind((ip, args.source_port));
+    }
+    builder = builder
+        .rcv_timeout(Duration::from_millis(100))
+        .max_retransmits(10)
+        .loss_p(args.loss_p)
+        .error_p(args.error_p)
+        .dup_p(args.dup_p)
+        .delay(Duration::from_millis(args.delay_ms))
+        .jitter(Duration::from_millis(args.jitter_ms))
+        .offer_resume(args.resume)
+        .mmap_reads(args.mmap)
+        .read_ahead(args.read_ahead)
+        .min_packet_gap(Duration::from_micros(args.min_gap_us))
+        .trusted_link(args.trusted_link)
+        .timestamps(args.timestamps);
+    if let Some(seed) = args.seed {
+        builder = builder.rng_seed(seed);
+    }
+    if let Some(path) = &args.journal {
+        builder = builder.journal(path);
+    }
+    let mut secsnail_sock = builder.build()?;
+    if let Some(path) = args.capture {
+        secsnail_sock.set_capture_file(path)?;
+    }
+    if let Some(path) = args.trace {
+        secsnail_sock.set_trace_file(path)?;
+    }
+    let recv_addr = match &args.token {
+        Some(token) => secsnail_sock.rendezvous(server_addr, token, RENDEZVOUS_TIMEOUT)?,
+        None => server_addr,
+    };
+
+    if args.verify {
+        let file_name = args.file_name.expect("clap requires a file with --verify");
+        let verdict = match &args.name {
+            Some(name) => secsnail_sock.verify_file_as_blocking(&file_name, name, recv_addr)?,
+            None => secsnail_sock.verify_file_blocking(&file_name, recv_addr)?,
+        };
+        if args.json {
+            println!(r#"{{"verdict":"{verdict}"}}"#);
+            return Ok(());
+        }
+        match verdict {
+            Verdict::Identical => {
+                println!("{file_name} is identical on the server, nothing to send")
+            }
+            Verdict::Differs => println!("{file_name} differs from the server's copy"),
+            Verdict::Missing => println!("{file_name} is missing on the server"),
+        }
+        return Ok(());
+    }
 
-    let mut secsnail_sock = SecSnailSocket::bind("0.0.0.0:45454").unwrap();
-    secsnail_sock.set_rcv_file_timeout_ms(100);
-    secsnail_sock.set_snd_file_max_retransmits(10);
-    secsnail_sock.set_unreliable_transmit_parameters(args.loss_p, args.error_p, args.dup_p);
+    // scripts reading the json get no bar
+    let bar = match args.json {
+        true => ProgressBar::hidden(),
+        false => progress_bar(),
+    };
+    let on_progress = bar.clone();
+    secsnail_sock.set_progress_callback(move |p: &Progress| {
+        if let Some(total) = p.total {
+            on_progress.set_length(total);
+        }
+        // a new file of a recursive send starts over
+        if (p.bytes as u64) < on_progress.position() {
+            on_progress.reset();
+        }
+        on_progress.set_position(p.bytes as u64);
+        on_progress.set_message(format!("{} retransmits", p.retransmissions));
+    });
 
-    let (amt_bytes, dur) = secsnail_sock.send_file_blocking(args.file_name, recv_addr)?;
+    // files a crashed run left go first
+    if !secsnail_sock.pending_transfers().is_empty() {
+        let results = secsnail_sock.resume_pending_transfers();
+        if !args.json {
+            for (path, r) in &results {
+                match r {
+                    Ok(_) => println!("resumed {}", path.display()),
+                    Err(e) => println!("failed to resume {}: {e}", path.display()),
+                }
+            }
+        }
+    }
 
+    let dir = args.recursive.as_ref().or(args.sync.as_ref());
+    if let Some(dir) = dir {
+        let results = match args.sync.is_some() {
+            true => secsnail_sock.sync_dir_blocking(dir, recv_addr)?,
+            false => secsnail_sock.send_dir_blocking(dir, recv_addr)?,
+        };
+        bar.finish_and_clear();
+        return match args.json {
+            true => print_json(&results),
+            false => print_summary(&results),
+        };
+    }
+
+    let report = match (args.file_name, args.name) {
+        (_, Some(name)) if args.stdin => {
+            secsnail_sock.send_reader_blocking(io::stdin(), &name, recv_addr)?
+        }
+        (Some(file_name), Some(name)) => {
+            secsnail_sock.send_file_as_blocking(file_name, &name, recv_addr)?
+        }
+        (Some(file_name), None) if args.delta => {
+            secsnail_sock.send_file_delta_blocking(file_name, recv_addr)?
+        }
+        (Some(file_name), None) if args.anonymous => {
+            secsnail_sock.send_file_anonymous_blocking(file_name, recv_addr)?
+        }
+        (Some(file_name), None) => secsnail_sock.send_file_to_blocking(file_name, recv_addr)?,
+        _ => unreachable!("clap requires a file or --stdin with --name"),
+    };
+    bar.finish_and_clear();
+
+    if args.json {
+        println!("{}", report.to_json());
+        return Ok(());
+    }
     println!(
-        "Sent {amt_bytes} bytes via secure snail 🐌 in {} s",
-        dur.as_secs_f64()
+        "Sent {} bytes via secure snail 🐌 in {} s",
+        report.bytes,
+        report.duration.as_secs_f64()
     );
+    println!("-> Goodput: {:.1} kByte/s", report.goodput() / 1000.0);
+    if let Some(name) = &report.stored_as {
+        println!("-> Stored as {name} by the server");
+    }
     println!(
-        "-> Goodput: {} kByte/s",
-        amt_bytes as u128 / dur.as_millis()
+        "-> {} packets ({} bytes) on the wire, {} retransmissions, {} timeouts",
+        report.stats.packets_sent,
+        report.stats.bytes_on_wire,
+        report.stats.retransmissions,
+        report.stats.timeouts
     );
+    if let Some(rtt) = report.mean_rtt {
+        println!("-> Mean RTT: {:.3} ms", rtt.as_secs_f64() * 1000.0);
+    }
+    if let Some(q) = report.queueing_delay {
+        println!(
+            "-> Queueing delay: mean {:.3} ms, max {:.3} ms, last {:.3} ms",
+            q.mean.as_secs_f64() * 1000.0,
+            q.max.as_secs_f64() * 1000.0,
+            q.last.as_secs_f64() * 1000.0
+        );
+    }
     Ok(())
 }
 
+/// a row per file of a recursive send, fails if any of them failed
+fn print_summary(results: &[SendOutcome]) -> io::Result<()> {
+    let width = results
+        .iter()
+        .map(|(path, _)| path.display().to_string().len())
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:<width$}  {:>6}  {:>12}  {:>9}",
+        "file", "status", "bytes", "seconds"
+    );
+    for (path, r) in results {
+        let path = path.display();
+        match r {
+            Ok(report) => println!(
+                "{path:<width$}  {:>6}  {:>12}  {:>9.3}",
+                "ok",
+                report.bytes,
+                report.duration.as_secs_f64()
+            ),
+            Err(e) => println!("{path:<width$}  {:>6}  {e}", "failed"),
+        }
+    }
+    let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+    println!("{} sent, {failed} failed", results.len() - failed);
+    match failed {
+        0 => Ok(()),
+        _ => Err(io::Error::other(format!("{failed} files failed"))),
+    }
+}
+
+/// an array of the path and report or error of every file of a recursive
+/// send, fails if any of them failed
+fn print_json(results: &[SendOutcome]) -> io::Result<()> {
+    println!("{}", outcomes_to_json(results));
+    match results.iter().filter(|(_, r)| r.is_err()).count() {
+        0 => Ok(()),
+        failed => Err(io::Error::other(format!("{failed} files failed"))),
+    }
+}
+
+/// bytes, percentage, rate and eta of a send, a spinner until the size is known
+fn progress_bar() -> ProgressBar {
+    let bar = ProgressBar::no_length();
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{spinner} [{bar:30}] {bytes}/{total_bytes} {percent}% {binary_bytes_per_sec} eta {eta} {msg}",
+        )
+        .expect("valid progress template")
+        .progress_chars("=> "),
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about= None)]
 struct Args {
-    #[arg(short, long)]
-    ip: String,
-    #[arg(short, long)]
-    file_name: String,
+    #[command(flatten)]
+    verbosity: Verbosity,
+    /// address or host name of the server
+    #[arg(short, long, required_unless_present = "rendezvous")]
+    ip: Option<String>,
+    /// port of the server
+    #[arg(long, default_value_t = DEFAULT_SECSNAIL_PORT)]
+    port: u16,
+    /// local address to send from, defaults to any of the server's address family
+    #[arg(long)]
+    bind: Option<IpAddr>,
+    /// local port to send from, 0 picks an ephemeral port
+    #[arg(long, default_value_t = 0)]
+    source_port: u16,
+    #[arg(short, long, required_unless_present_any = ["stdin", "recursive", "sync"])]
+    file_name: Option<String>,
+    /// send every file below this directory, the server stores them side by side
+    #[arg(long, conflicts_with_all = ["file_name", "stdin", "name", "verify"])]
+    recursive: Option<String>,
+    /// send only the files below this directory the server misses or holds
+    /// another version of, it keeps the tree of subdirectories
+    #[arg(long, conflicts_with_all = ["file_name", "stdin", "name", "verify", "recursive"])]
+    sync: Option<String>,
+    /// send standard input instead of a file, e.g. `tar c dir | client --stdin --name backup.tar`
+    #[arg(long, requires = "name", conflicts_with = "file_name")]
+    stdin: bool,
+    /// name the server stores the file under, defaults to the local file name
+    #[arg(long)]
+    name: Option<String>,
+    /// announce no file name, the server names the file by its arrival and
+    /// this client
+    #[arg(long, requires = "file_name", conflicts_with_all = ["name", "verify"])]
+    anonymous: bool,
+    /// send only the blocks which differ from the server's copy of the file
+    #[arg(long, requires = "file_name", conflicts_with_all = ["name", "anonymous", "verify"])]
+    delta: bool,
+    /// only ask the server whether it holds an identical file, under
+    /// --name if given, send nothing
+    #[arg(long, conflicts_with = "stdin")]
+    verify: bool,
     #[arg(short, long, default_value_t = 0.0)]
     loss_p: f64,
     #[arg(short, long, default_value_t = 0.0)]
     error_p: f64,
     #[arg(short, long, default_value_t = 0.0)]
     dup_p: f64,
+    /// fixed delay of every sent packet
+    #[arg(long, default_value_t = 0)]
+    delay_ms: u64,
+    /// random delay of up to jitter_ms on top of delay_ms
+    #[arg(long, default_value_t = 0)]
+    jitter_ms: u64,
+    /// seed of the simulated packet loss, errors and duplicates
+    #[arg(long)]
+    seed: Option<u64>,
+    /// continue an interrupted transfer if the server kept its partial file
+    #[arg(long)]
+    resume: bool,
+    /// read the file through a memory mapping, the file must not change while it is sent
+    #[arg(long)]
+    mmap: bool,
+    /// packets to read ahead of the wire in a background thread, 0 reads on demand
+    #[arg(long, default_value_t = 0)]
+    read_ahead: usize,
+    /// least microseconds between two sent packets, e.g. for a small router queue
+    #[arg(long, default_value_t = 0)]
+    min_gap_us: u64,
+    /// skip checksums if the server agrees, e.g. on loopback
+    #[arg(long)]
+    trusted_link: bool,
+    /// stamp data packets to measure their queueing delay, if the server agrees
+    #[arg(long)]
+    timestamps: bool,
+    /// write all sent and received packets into this pcapng file
+    #[arg(long)]
+    capture: Option<String>,
+    /// write a line per protocol event and sent packet into this file
+    #[arg(long)]
+    trace: Option<String>,
+    /// note sent files in this journal until they are done, files a crashed
+    /// run left in it are sent again first
+    #[arg(long)]
+    journal: Option<String>,
+    /// print the report as json instead of the summary, an array of the
+    /// results with --recursive
+    #[arg(long)]
+    json: bool,
+    /// meet a server behind a NAT at this rendezvous server instead of
+    /// sending to --ip, e.g. `rendezvous.example.org:55056`
+    #[arg(long, requires = "token", conflicts_with = "ip")]
+    rendezvous: Option<String>,
+    /// token the server registered at the rendezvous server as well
+    #[arg(long, requires = "rendezvous")]
+    token: Option<String>,
 }
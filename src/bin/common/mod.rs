@@ -0,0 +1,31 @@
+//! Flags shared by the binaries.
+
+use clap::{ArgAction, Args};
+use tracing::Level;
+
+/// `-v` logs transfers, `-vv` every protocol event and packet, `-q` only errors
+#[derive(Args, Debug, Clone)]
+pub struct Verbosity {
+    /// more log output on stderr, may be repeated
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+    /// only log errors
+    #[arg(short, long, conflicts_with = "verbose", global = true)]
+    quiet: bool,
+}
+
+impl Verbosity {
+    /// install a subscriber writing to stderr at the selected level
+    pub fn init_tracing(&self) {
+        let level = match (self.quiet, self.verbose) {
+            (true, _) => Level::ERROR,
+            (false, 0) => Level::WARN,
+            (false, 1) => Level::INFO,
+            (false, _) => Level::TRACE,
+        };
+        tracing_subscriber::fmt()
+            .with_max_level(level)
+            .with_writer(std::io::stderr)
+            .init();
+    }
+}
@@ -0,0 +1,289 @@
+mod common;
+
+use clap::{Args, Parser, Subcommand};
+use common::Verbosity;
+use rand::{RngCore, SeedableRng, rngs::StdRng};
+use secsnail::{
+    proto::vectors,
+    sock::{
+        DEFAULT_RENDEZVOUS_PORT, DEFAULT_SECSNAIL_PORT, RendezvousServer, SecSnailListener,
+        SecSnailSocket, SecSnailSocketBuilder, SendReport, Verdict,
+    },
+};
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+/// Secure snail tools
+///
+///   `secsnail bench --size 10M --loss 0.05` measures transfers over loopback
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+    cli.verbosity.init_tracing();
+    match cli.command {
+        Command::Bench(args) => bench(args),
+        Command::Verify(args) => verify(args),
+        Command::Rendezvous(args) => rendezvous(args),
+        Command::Vectors => Ok(vectors::write_vectors(io::stdout().lock())?),
+    }
+}
+
+/// print whether the peer holds the file, fails unless it is identical
+fn verify(args: VerifyArgs) -> io::Result<()> {
+    let peer = resolve(&args.peer)?;
+    let mut sock = SecSnailSocket::builder().connect(peer).build()?;
+    let verdict = match &args.name {
+        Some(name) => sock.verify_file_as_blocking(&args.file, name, peer)?,
+        None => sock.verify_file_blocking(&args.file, peer)?,
+    };
+    println!("{}: {verdict}", args.name.as_ref().unwrap_or(&args.file));
+    match verdict {
+        Verdict::Identical => Ok(()),
+        verdict => Err(io::Error::other(format!("file {verdict}"))),
+    }
+}
+
+/// pair clients and servers by their token until interrupted
+fn rendezvous(args: RendezvousArgs) -> io::Result<()> {
+    let mut server = RendezvousServer::bind((Ipv4Addr::UNSPECIFIED, args.port))?;
+    println!("rendezvous server on {}", server.local_addr()?);
+    Ok(server.run()?)
+}
+
+/// first address of `peer`, the default port if it has none
+fn resolve(peer: &str) -> io::Result<SocketAddr> {
+    let addrs = match peer.to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(_) => (peer, DEFAULT_SECSNAIL_PORT).to_socket_addrs()?,
+    };
+    addrs
+        .into_iter()
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address of the peer"))
+}
+
+fn bench(args: BenchArgs) -> io::Result<()> {
+    let mut data = vec![0; args.size];
+    StdRng::seed_from_u64(args.seed.unwrap_or(0)).fill_bytes(&mut data);
+
+    // an in-process receiver discarding the data unless a peer is given
+    let (peer, receiver) = match &args.peer {
+        Some(peer) => (resolve(peer)?, None),
+        None => {
+            let sock = args.impaired(SecSnailSocket::builder().bind("127.0.0.1:0"));
+            let mut listener = SecSnailListener::from_socket(sock.build()?);
+            let peer = listener.local_addr()?;
+            let handle = listener.shutdown_handle();
+            let worker = thread::spawn(move || {
+                while let Ok((transfer, _)) = listener.accept() {
+                    let _ = transfer.write_to(io::sink());
+                }
+            });
+            (peer, Some((handle, worker)))
+        }
+    };
+
+    println!(
+        "{} runs of {} bytes to {peer}, loss {} error {} dup {}",
+        args.runs, args.size, args.loss, args.error, args.dup
+    );
+    let mut reports = Vec::new();
+    for run in 1..=args.runs {
+        let mut sock = args.impaired(SecSnailSocket::builder().connect(peer));
+        if let Some(timeout) = args.snd_timeout_ms {
+            sock = sock.snd_timeout(Duration::from_millis(timeout));
+        }
+        let mut sock = sock.build()?;
+        let name = format!("bench-{run}.bin");
+        match sock.send_reader_blocking(io::Cursor::new(data.clone()), &name, peer) {
+            Ok(report) => {
+                println!(
+                    "run {run}: {:.1} kByte/s, {:.1} % retransmitted, {} timeouts",
+                    report.goodput() / 1000.0,
+                    overhead(&report) * 100.0,
+                    report.stats.timeouts
+                );
+                reports.push(report);
+            }
+            Err(e) => println!("run {run}: failed, {e}"),
+        }
+    }
+    if let Some((handle, worker)) = receiver {
+        handle.shutdown()?;
+        let _ = worker.join();
+    }
+    print_summary(&reports, args.runs);
+    Ok(())
+}
+
+/// retransmissions per packet sent once
+fn overhead(report: &SendReport) -> f64 {
+    let stats = report.stats;
+    match stats.packets_sent - stats.retransmissions {
+        0 => 0.0,
+        first => stats.retransmissions as f64 / first as f64,
+    }
+}
+
+/// mean and sample standard deviation
+fn mean_sd(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let sd = match values.len() {
+        0 | 1 => 0.0,
+        _ => (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt(),
+    };
+    (mean, sd)
+}
+
+fn print_summary(reports: &[SendReport], runs: usize) {
+    println!("{} of {runs} runs completed", reports.len());
+    if reports.is_empty() {
+        return;
+    }
+    let stat = |name: &str, unit: &str, values: Vec<f64>| {
+        let (mean, sd) = mean_sd(&values);
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        println!(
+            "{name:<16} mean {mean:>10.3} sd {sd:>10.3} min {min:>10.3} max {max:>10.3} {unit}"
+        );
+    };
+    stat(
+        "goodput",
+        "kByte/s",
+        reports.iter().map(|r| r.goodput() / 1000.0).collect(),
+    );
+    stat(
+        "retransmitted",
+        "%",
+        reports.iter().map(|r| overhead(r) * 100.0).collect(),
+    );
+    stat(
+        "timeouts",
+        "",
+        reports.iter().map(|r| r.stats.timeouts as f64).collect(),
+    );
+    stat(
+        "duration",
+        "s",
+        reports.iter().map(|r| r.duration.as_secs_f64()).collect(),
+    );
+    let rtts: Vec<_> = reports
+        .iter()
+        .filter_map(|r| r.mean_rtt)
+        .map(|rtt| rtt.as_secs_f64() * 1000.0)
+        .collect();
+    if !rtts.is_empty() {
+        stat("rtt", "ms", rtts);
+    }
+}
+
+/// a size in bytes with an optional binary suffix, e.g. `512K` or `10M`
+fn parse_size(s: &str) -> Result<usize, String> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        _ => return Err(format!("unknown unit {unit:?}")),
+    };
+    let n: usize = digits.parse().map_err(|e| format!("{s:?}: {e}"))?;
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("{s:?} is too large"))
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(flatten)]
+    verbosity: Verbosity,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// send synthetic data several times and print goodput, retransmissions and timers
+    Bench(BenchArgs),
+    /// ask a receiver whether it holds a copy of a local file, by SHA-256
+    Verify(VerifyArgs),
+    /// pair clients and servers behind NATs, which meet by a token
+    Rendezvous(RendezvousArgs),
+    /// print conformance test vectors of the wire format
+    #[command(hide = true)]
+    Vectors,
+}
+
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// local file to compare
+    file: String,
+    /// receiver, e.g. 127.0.0.1 or 127.0.0.1:55055
+    #[arg(long)]
+    peer: String,
+    /// name the receiver holds the file under, defaults to the local file name
+    #[arg(long)]
+    name: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct RendezvousArgs {
+    #[arg(long, default_value_t = DEFAULT_RENDEZVOUS_PORT)]
+    port: u16,
+}
+
+#[derive(Args, Debug)]
+struct BenchArgs {
+    /// bytes per transfer, with K, M or G as powers of 1024
+    #[arg(long, default_value = "10M", value_parser = parse_size)]
+    size: usize,
+    /// transfers to average over
+    #[arg(long, default_value_t = 5)]
+    runs: usize,
+    /// receiver to send to, e.g. a running server, instead of one in this process
+    #[arg(long)]
+    peer: Option<String>,
+    #[arg(long, default_value_t = 0.0)]
+    loss: f64,
+    #[arg(long, default_value_t = 0.0)]
+    error: f64,
+    #[arg(long, default_value_t = 0.0)]
+    dup: f64,
+    /// fixed delay of every sent packet
+    #[arg(long, default_value_t = 0)]
+    delay_ms: u64,
+    /// random delay of up to jitter_ms on top of delay_ms
+    #[arg(long, default_value_t = 0)]
+    jitter_ms: u64,
+    /// retransmission timeout of the sender
+    #[arg(long)]
+    snd_timeout_ms: Option<u64>,
+    /// seed of the data and the simulated impairment
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+impl BenchArgs {
+    /// `builder` with the simulated impairment, applied to both directions
+    /// with the in-process receiver
+    fn impaired(&self, mut builder: SecSnailSocketBuilder) -> SecSnailSocketBuilder {
+        builder = builder
+            .loss_p(self.loss)
+            .error_p(self.error)
+            .dup_p(self.dup)
+            .delay(Duration::from_millis(self.delay_ms))
+            .jitter(Duration::from_millis(self.jitter_ms));
+        if let Some(seed) = self.seed {
+            builder = builder.rng_seed(seed);
+        }
+        builder
+    }
+}
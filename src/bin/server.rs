@@ -1,27 +1,425 @@
+mod common;
+
 use clap::Parser;
-use secsnail::sock::SecSnailSocket;
-use std::io;
+use common::Verbosity;
+use secsnail::sock::{
+    CheckpointPolicy, DEFAULT_RENDEZVOUS_PORT, DEFAULT_SECSNAIL_PORT, IpNet, MetricsHandle,
+    NamePolicy, OverwritePolicy, SecSnailSocket, TransferReport,
+};
+use serde::Deserialize;
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    ops::ControlFlow,
+    path::Path,
+    thread,
+    time::Duration,
+};
+
+/// read by the server if no `--config` is given and it exists
+const DEFAULT_CONFIG: &str = "secsnail.toml";
+/// how long to wait for the client at the rendezvous
+const RENDEZVOUS_TIMEOUT: Duration = Duration::from_secs(300);
 
-/// Demo server listens for incoming secure snail file transmissions
+/// Demo server receives secure snail file transmissions until interrupted
 ///
-///   Use default secsnail port 55055
+///   Use default secsnail port 55055 unless `--port` is given
 fn main() -> io::Result<()> {
     let args = Args::parse();
-    let mut secsnail_sock = SecSnailSocket::bind_default_port().unwrap();
-    secsnail_sock.set_unreliable_transmit_parameters(args.loss_p, args.error_p, args.dup_p);
-    secsnail_sock.recv_file_blocking(args.destination).unwrap();
+    args.verbosity.init_tracing();
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None if Path::new(DEFAULT_CONFIG).exists() => Config::load(DEFAULT_CONFIG)?,
+        None => Config::default(),
+    };
+    let args = args.or_config(config)?;
+    let destination = args.destination.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no destination, neither by --destination nor in the config file",
+        )
+    })?;
+
+    let mut builder = SecSnailSocket::builder()
+        .bind((
+            args.bind.unwrap_or(Ipv4Addr::UNSPECIFIED.into()),
+            args.port.unwrap_or(DEFAULT_SECSNAIL_PORT),
+        ))
+        .loss_p(args.loss_p.unwrap_or(0.0))
+        .error_p(args.error_p.unwrap_or(0.0))
+        .dup_p(args.dup_p.unwrap_or(0.0))
+        .delay(Duration::from_millis(args.delay_ms.unwrap_or(0)))
+        .jitter(Duration::from_millis(args.jitter_ms.unwrap_or(0)));
+    if let Some(timeout) = args.rcv_timeout_ms {
+        builder = builder.rcv_timeout(Duration::from_millis(timeout));
+    }
+    if let Some(threshold) = args.congestion_threshold_ms {
+        builder = builder.congestion_threshold(Duration::from_millis(threshold));
+    }
+    builder = builder.checkpoints(CheckpointPolicy {
+        bytes: args.checkpoint_bytes,
+        interval: args.checkpoint_secs.map(Duration::from_secs),
+    });
+    if let Some(seed) = args.seed {
+        builder = builder.rng_seed(seed);
+    }
+    if let Some(max) = args.max_size {
+        builder = builder.max_recv_file_size(max);
+    }
+    if !args.allow.is_empty() {
+        builder = builder.allowed_senders(args.allow);
+    }
+    builder = builder.denied_senders(args.deny);
+    if args.resume {
+        builder = builder.overwrite_policy(OverwritePolicy::Resume);
+    } else if args.rename_existing {
+        builder = builder.overwrite_policy(OverwritePolicy::RenameWithSuffix);
+    }
+    builder = builder
+        .sync_on_close(args.sync_on_close)
+        .trusted_link(args.trusted_link)
+        .timestamps(args.timestamps);
+    if let Some(policy) = args.names {
+        builder = builder.name_policy(policy);
+    }
+    if let Some(path) = args.journal {
+        builder = builder.journal(path);
+    }
+    if let Some(dir) = args.spool_dir {
+        builder = builder.spool_dir(dir);
+    }
+    let mut secsnail_sock = builder.build()?;
+    if let Some(path) = args.capture {
+        secsnail_sock.set_capture_file(path)?;
+    }
+    if let Some(path) = args.trace {
+        secsnail_sock.set_trace_file(path)?;
+    }
+    let mut log: Box<dyn Write> = match &args.log {
+        Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+        None => Box::new(io::stderr()),
+    };
+    if let (Some(rendezvous), Some(token)) = (&args.rendezvous, &args.token) {
+        let server = match rendezvous.to_socket_addrs() {
+            Ok(addrs) => addrs,
+            Err(_) => (rendezvous.as_str(), DEFAULT_RENDEZVOUS_PORT).to_socket_addrs()?,
+        }
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address of the rendezvous"))?;
+        let peer = secsnail_sock.rendezvous(server, token, RENDEZVOUS_TIMEOUT)?;
+        if !args.json {
+            writeln!(log, "met {peer} at the rendezvous")?;
+        }
+    }
+
+    if let Some(addr) = args.metrics_addr {
+        serve_metrics(TcpListener::bind(addr)?, secsnail_sock.metrics_handle());
+    }
+
+    // finish the open transfer on ctrl-c, then return
+    let handle = secsnail_sock.shutdown_handle();
+    ctrlc::set_handler(move || {
+        if let Err(e) = handle.shutdown() {
+            eprintln!("shutdown failed: {e}");
+        }
+    })
+    .map_err(io::Error::other)?;
+
+    match args.threaded {
+        true => secsnail_sock.serve_threaded(&destination, |report| {
+            match log_report(&mut log, &report, args.json) {
+                Ok(()) => ControlFlow::Continue(()),
+                Err(_) => ControlFlow::Break(()),
+            }
+        })?,
+        false => {
+            for report in secsnail_sock.incoming(&destination) {
+                log_report(&mut log, &report?, args.json)?;
+            }
+        }
+    }
+    if !args.json {
+        writeln!(log, "shut down")?;
+    }
     Ok(())
 }
 
+/// answer every http request on `listener` with the metrics of the socket,
+/// in a background thread
+fn serve_metrics(listener: TcpListener, metrics: MetricsHandle) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            _ = answer_scrape(stream, &metrics);
+        }
+    });
+}
+
+/// whatever was requested, only read up to the blank line ending the
+/// request head
+fn answer_scrape(mut stream: TcpStream, metrics: &MetricsHandle) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = BufReader::new(&stream);
+    let mut line = String::new();
+    while request.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let body = metrics.metrics_text();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// a line per accepted file with its peer, name, size and duration, or
+/// its report as json
+fn log_report(log: &mut impl Write, report: &TransferReport, json: bool) -> io::Result<()> {
+    match report.verified {
+        _ if json => writeln!(log, "{}", report.to_json())?,
+        Some(verdict) => writeln!(
+            log,
+            "verified {} from {}: {verdict}",
+            report.file_name, report.peer
+        )?,
+        None => writeln!(
+            log,
+            "received {} ({} bytes) from {} in {:?}",
+            report.file_name, report.bytes, report.peer, report.duration
+        )?,
+    }
+    log.flush()
+}
+
+/// defaults of the flags, e.g.
+///
+/// ```toml
+/// destination = "./received"
+/// max_size = 1_000_000_000
+/// allow = ["192.168.0.0/16"]
+/// loss_p = 0.1
+/// ```
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    destination: Option<String>,
+    bind: Option<IpAddr>,
+    port: Option<u16>,
+    rcv_timeout_ms: Option<u64>,
+    congestion_threshold_ms: Option<u64>,
+    checkpoint_bytes: Option<u64>,
+    checkpoint_secs: Option<u64>,
+    loss_p: Option<f64>,
+    error_p: Option<f64>,
+    dup_p: Option<f64>,
+    delay_ms: Option<u64>,
+    jitter_ms: Option<u64>,
+    seed: Option<u64>,
+    max_size: Option<u64>,
+    #[serde(default)]
+    allow: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+    #[serde(default)]
+    resume: bool,
+    #[serde(default)]
+    rename_existing: bool,
+    #[serde(default)]
+    sync_on_close: bool,
+    #[serde(default)]
+    trusted_link: bool,
+    #[serde(default)]
+    timestamps: bool,
+    names: Option<String>,
+    #[serde(default)]
+    threaded: bool,
+    capture: Option<String>,
+    trace: Option<String>,
+    journal: Option<String>,
+    spool_dir: Option<String>,
+    log: Option<String>,
+    metrics_addr: Option<SocketAddr>,
+    #[serde(default)]
+    json: bool,
+    rendezvous: Option<String>,
+    token: Option<String>,
+}
+
+impl Config {
+    fn load<P: AsRef<Path>>(path: P) -> io::Result<Config> {
+        let path = path.as_ref();
+        toml::from_str(&fs::read_to_string(path)?).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {e}", path.display()),
+            )
+        })
+    }
+}
+
+impl Args {
+    /// values of `config` for all flags not given on the command line
+    fn or_config(self, config: Config) -> io::Result<Args> {
+        let nets = |nets: Vec<String>| {
+            nets.iter()
+                .map(|net| net.parse().map_err(io::Error::from))
+                .collect::<io::Result<Vec<IpNet>>>()
+        };
+        Ok(Args {
+            verbosity: self.verbosity,
+            config: self.config,
+            destination: self.destination.or(config.destination),
+            bind: self.bind.or(config.bind),
+            port: self.port.or(config.port),
+            rcv_timeout_ms: self.rcv_timeout_ms.or(config.rcv_timeout_ms),
+            congestion_threshold_ms: self
+                .congestion_threshold_ms
+                .or(config.congestion_threshold_ms),
+            checkpoint_bytes: self.checkpoint_bytes.or(config.checkpoint_bytes),
+            checkpoint_secs: self.checkpoint_secs.or(config.checkpoint_secs),
+            loss_p: self.loss_p.or(config.loss_p),
+            error_p: self.error_p.or(config.error_p),
+            dup_p: self.dup_p.or(config.dup_p),
+            delay_ms: self.delay_ms.or(config.delay_ms),
+            jitter_ms: self.jitter_ms.or(config.jitter_ms),
+            seed: self.seed.or(config.seed),
+            max_size: self.max_size.or(config.max_size),
+            allow: match self.allow.is_empty() {
+                true => nets(config.allow)?,
+                false => self.allow,
+            },
+            deny: match self.deny.is_empty() {
+                true => nets(config.deny)?,
+                false => self.deny,
+            },
+            resume: self.resume || config.resume,
+            rename_existing: self.rename_existing || config.rename_existing,
+            sync_on_close: self.sync_on_close || config.sync_on_close,
+            trusted_link: self.trusted_link || config.trusted_link,
+            timestamps: self.timestamps || config.timestamps,
+            names: match self.names {
+                Some(policy) => Some(policy),
+                None => config.names.map(|s| s.parse()).transpose()?,
+            },
+            threaded: self.threaded || config.threaded,
+            capture: self.capture.or(config.capture),
+            trace: self.trace.or(config.trace),
+            journal: self.journal.or(config.journal),
+            spool_dir: self.spool_dir.or(config.spool_dir),
+            log: self.log.or(config.log),
+            metrics_addr: self.metrics_addr.or(config.metrics_addr),
+            json: self.json || config.json,
+            rendezvous: self.rendezvous.or(config.rendezvous),
+            token: self.token.or(config.token),
+        })
+    }
+}
+
+/// flags given on the command line override those of the config file
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about= None)]
 struct Args {
+    #[command(flatten)]
+    verbosity: Verbosity,
+    /// toml file with defaults of the flags below, `secsnail.toml` if it exists
+    #[arg(long)]
+    config: Option<String>,
+    #[arg(long)]
+    destination: Option<String>,
+    /// local address to listen on, 0.0.0.0 by default
+    #[arg(long)]
+    bind: Option<IpAddr>,
+    /// port to listen on, replies are sent from it as well
+    #[arg(long, visible_alias = "source-port")]
+    port: Option<u16>,
+    /// abort a session without a packet for this long
+    #[arg(long)]
+    rcv_timeout_ms: Option<u64>,
+    /// ask senders to slow down while writing a packet takes longer than this
+    #[arg(long)]
+    congestion_threshold_ms: Option<u64>,
+    /// flush and sync a received file to disk every this many bytes
+    #[arg(long)]
+    checkpoint_bytes: Option<u64>,
+    /// flush and sync a received file to disk every this many seconds
+    #[arg(long)]
+    checkpoint_secs: Option<u64>,
+    #[arg(short, long)]
+    loss_p: Option<f64>,
+    #[arg(short, long)]
+    error_p: Option<f64>,
+    #[arg(short, long)]
+    dup_p: Option<f64>,
+    /// fixed delay of every sent packet
+    #[arg(long)]
+    delay_ms: Option<u64>,
+    /// random delay of up to jitter_ms on top of delay_ms
+    #[arg(long)]
+    jitter_ms: Option<u64>,
+    /// seed of the simulated packet loss, errors and duplicates
+    #[arg(long)]
+    seed: Option<u64>,
+    /// refuse files larger than this many bytes
+    #[arg(long, alias = "max-file-size")]
+    max_size: Option<u64>,
+    /// only accept senders of this network, e.g. 192.168.0.0/16, may be repeated
+    #[arg(long)]
+    allow: Vec<IpNet>,
+    /// ignore senders of this network, may be repeated
+    #[arg(long)]
+    deny: Vec<IpNet>,
+    /// keep partial files of interrupted transfers and let senders resume them
+    #[arg(long)]
+    resume: bool,
+    /// keep existing files, received ones of the same name are stored as
+    /// "name (1).ext" and so on
+    #[arg(long, conflicts_with = "resume")]
+    rename_existing: bool,
+    /// sync received files to disk before confirming them to the sender
+    #[arg(long)]
+    sync_on_close: bool,
+    /// skip checksums for senders which ask for it, e.g. on loopback
+    #[arg(long)]
+    trusted_link: bool,
+    /// echo the timestamps of senders which stamp their data packets
+    #[arg(long)]
+    timestamps: bool,
+    /// store names with characters outside `A-Za-z0-9._-` as announced
+    /// (unicode), transliterated to ascii (transliterate) or percent-encoded
+    /// (escape)
+    #[arg(long)]
+    names: Option<NamePolicy>,
+    /// receive every sender on a thread of its own
+    #[arg(long)]
+    threaded: bool,
+    /// write all sent and received packets into this pcapng file
+    #[arg(long)]
+    capture: Option<String>,
+    /// write a line per protocol event and sent packet into this file
+    #[arg(long)]
+    trace: Option<String>,
+    /// note partial files in this journal and resume them after a crash,
+    /// whatever the overwrite policy
+    #[arg(long)]
+    journal: Option<String>,
+    /// write received files here until complete, then move them into the
+    /// destination
+    #[arg(long)]
+    spool_dir: Option<String>,
+    /// append a line per received file to this file instead of stderr
+    #[arg(long)]
+    log: Option<String>,
+    /// serve prometheus metrics over http at this address, e.g. `127.0.0.1:9555`
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+    /// log the report of every received file as a line of json
     #[arg(long)]
-    destination: String,
-    #[arg(short, long, default_value_t = 0.0)]
-    loss_p: f64,
-    #[arg(short, long, default_value_t = 0.0)]
-    error_p: f64,
-    #[arg(short, long, default_value_t = 0.0)]
-    dup_p: f64,
+    json: bool,
+    /// wait for a client behind a NAT at this rendezvous server before
+    /// receiving, e.g. `rendezvous.example.org:55056`
+    #[arg(long, requires = "token")]
+    rendezvous: Option<String>,
+    /// token the client registers at the rendezvous server as well
+    #[arg(long, requires = "rendezvous")]
+    token: Option<String>,
 }
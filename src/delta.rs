@@ -0,0 +1,430 @@
+//! Snail Transfer Protocol – delta transfer of a changed file
+//!
+//! A sender updating a file the receiver holds an older version of first
+//! transfers the signature of its version, announced with the delta flag
+//! of the SYN under `SIGNATURE_NAME`:
+//!
+//! ```text
+//!  ┌──────────────┬─────────────┬──────────────────┬─────────────┬──────────────┐
+//!  │ Block Length │ File Size   │ SHA-256 of File  │ Name Length │ Name (UTF-8) │
+//!  │ (32 bit)     │ (64 bit)    │ (256 bit)        │ (16 bit)    │              │
+//!  └──────────────┴─────────────┴──────────────────┴─────────────┴──────────────┘
+//!  then per block: ┌──────────────────────────┬─────────────────────────────┐
+//!                  │ Rolling Checksum (32 bit)│ SHA-256, first 64 bit       │
+//!                  └──────────────────────────┴─────────────────────────────┘
+//! ```
+//!
+//! The receiver rolls the checksum over its copy to find the blocks it
+//! holds at any offset, like rsync the other way round, notes where in a
+//! plan next to the file and answers the FIN with the bitmap of the blocks
+//! it misses, as for a manifest. The last block, which may be shorter, is
+//! always missing. The sender then sends only those blocks, one after
+//! another, announced with the delta flag under the name of the file, and
+//! the receiver puts the new version together from the plan, checked
+//! against the SHA-256 of the signature.
+//!
+//! A receiver which does not know delta transfers stores the signature as
+//! a hidden file and answers with an empty FINACK, the sender then sends
+//! the whole file.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    error::{Result, SecSnailError},
+    manifest::MAX_MANIFEST_ENTRIES,
+    meta::{Digest, check_file_name},
+};
+
+/// name a signature is announced under, hidden if stored by a receiver
+/// which does not know delta transfers
+pub const SIGNATURE_NAME: &str = ".secsnail-signature";
+
+/// blocks the bitmap in a single finack can answer
+pub const MAX_DELTA_BLOCKS: usize = MAX_MANIFEST_ENTRIES;
+
+const MIN_BLOCK_LEN: u64 = 2048;
+/// smaller files are sent whole, their signature would not pay off
+pub const MIN_DELTA_SIZE: u64 = 4 * MIN_BLOCK_LEN;
+
+const STRONG_LEN: usize = 8;
+const HEADER_LEN: usize = 4 + 8 + 32 + 2;
+
+type Strong = [u8; STRONG_LEN];
+
+/// blocks of a file of `size` are as small as the bitmap allows
+pub fn block_len(size: u64) -> u32 {
+    MIN_BLOCK_LEN.max(size.div_ceil(MAX_DELTA_BLOCKS as u64)) as u32
+}
+
+/// offset and length of block `i` of a file of `size`
+fn block_range(size: u64, block_len: u32, i: usize) -> (u64, u64) {
+    let start = i as u64 * u64::from(block_len);
+    (start, u64::from(block_len).min(size - start))
+}
+
+/// sums of rsync's rolling checksum, combined by `weak`
+fn sums(block: &[u8]) -> (u32, u32) {
+    let len = block.len() as u32;
+    block.iter().enumerate().fold((0, 0), |(a, b), (i, x)| {
+        let x = u32::from(*x);
+        (
+            a.wrapping_add(x),
+            b.wrapping_add((len - i as u32).wrapping_mul(x)),
+        )
+    })
+}
+
+fn weak((a, b): (u32, u32)) -> u32 {
+    (a & 0xffff) | (b << 16)
+}
+
+fn strong(block: &[u8]) -> Strong {
+    Sha256::digest(block)[..STRONG_LEN].try_into().unwrap()
+}
+
+/// signature of the version of a file the sender holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub size: u64,
+    pub block_len: u32,
+    pub digest: Digest,
+    /// rolling checksum and strong hash of every block
+    pub blocks: Vec<(u32, Strong)>,
+}
+
+impl Signature {
+    /// signature of the `size` bytes of `file` under `name`
+    pub fn of_file(name: &str, size: u64, mut file: impl Read) -> Result<Signature> {
+        let block_len = block_len(size);
+        let mut digest = Sha256::new();
+        let mut blocks = Vec::new();
+        let mut buf = vec![0; block_len as usize];
+        for i in 0..size.div_ceil(u64::from(block_len)) as usize {
+            let block = &mut buf[..block_range(size, block_len, i).1 as usize];
+            file.read_exact(block)?;
+            digest.update(&*block);
+            blocks.push((weak(sums(block)), strong(block)));
+        }
+        Ok(Signature {
+            name: name.to_string(),
+            size,
+            block_len,
+            digest: digest.finalize().into(),
+            blocks,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(HEADER_LEN + self.name.len() + 12 * self.blocks.len());
+        buf.extend_from_slice(&self.block_len.to_be_bytes());
+        buf.extend_from_slice(&self.size.to_be_bytes());
+        buf.extend_from_slice(&self.digest);
+        buf.extend_from_slice(&(self.name.len() as u16).to_be_bytes());
+        buf.extend_from_slice(self.name.as_bytes());
+        for (weak, strong) in &self.blocks {
+            buf.extend_from_slice(&weak.to_be_bytes());
+            buf.extend_from_slice(strong);
+        }
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Signature> {
+        let corrupt = || SecSnailError::ProtocolViolation("malformed delta signature".to_string());
+        if data.len() < HEADER_LEN {
+            return Err(corrupt());
+        }
+        let (header, rest) = data.split_at(HEADER_LEN);
+        let block_len = u32::from_be_bytes(header[..4].try_into().unwrap());
+        let size = u64::from_be_bytes(header[4..12].try_into().unwrap());
+        let name_len = u16::from_be_bytes(header[44..].try_into().unwrap()) as usize;
+        let (name, rest) = rest.split_at_checked(name_len).ok_or_else(corrupt)?;
+        let name = str::from_utf8(name).map_err(|_| corrupt())?;
+        check_file_name(name)?;
+        let count = match block_len {
+            0 => return Err(corrupt()),
+            len => size.div_ceil(u64::from(len)),
+        };
+        if count > MAX_DELTA_BLOCKS as u64 || rest.len() as u64 != count * 12 {
+            return Err(corrupt());
+        }
+        Ok(Signature {
+            name: name.to_string(),
+            size,
+            block_len,
+            digest: header[12..44].try_into().unwrap(),
+            blocks: rest
+                .chunks(12)
+                .map(|b| {
+                    let weak = u32::from_be_bytes(b[..4].try_into().unwrap());
+                    (weak, b[4..].try_into().unwrap())
+                })
+                .collect(),
+        })
+    }
+
+    /// where the receiver finds the blocks in its copy `old`
+    pub fn plan(&self, old: &[u8]) -> DeltaPlan {
+        let len = self.block_len as usize;
+        let mut blocks = vec![None; self.blocks.len()];
+        // the last block may be short and is always sent
+        let held = self.blocks.len().saturating_sub(1);
+        let mut by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, (weak, _)) in self.blocks[..held].iter().enumerate() {
+            by_weak.entry(*weak).or_default().push(i);
+        }
+        if !by_weak.is_empty() && old.len() >= len {
+            let mut pos = 0;
+            let (mut a, mut b) = sums(&old[..len]);
+            loop {
+                let mut matched = false;
+                if let Some(candidates) = by_weak.get(&weak((a, b))) {
+                    let strong = strong(&old[pos..pos + len]);
+                    for &i in candidates {
+                        if blocks[i].is_none() && self.blocks[i].1 == strong {
+                            blocks[i] = Some(pos as u64);
+                            matched = true;
+                        }
+                    }
+                }
+                // a matched block is skipped whole, like rsync does
+                let next = match matched {
+                    true => pos + len,
+                    false => pos + 1,
+                };
+                if next + len > old.len() {
+                    break;
+                }
+                match matched {
+                    true => (a, b) = sums(&old[next..next + len]),
+                    false => {
+                        let (out, new) = (u32::from(old[pos]), u32::from(old[pos + len]));
+                        a = a.wrapping_sub(out).wrapping_add(new);
+                        b = b
+                            .wrapping_sub((len as u32).wrapping_mul(out))
+                            .wrapping_add(a);
+                    }
+                }
+                pos = next;
+            }
+        }
+        DeltaPlan {
+            size: self.size,
+            block_len: self.block_len,
+            digest: self.digest,
+            blocks,
+        }
+    }
+
+    /// bytes of the blocks `missing`
+    pub fn missing_len(&self, missing: &[usize]) -> u64 {
+        missing
+            .iter()
+            .map(|i| block_range(self.size, self.block_len, *i).1)
+            .sum()
+    }
+}
+
+/// blocks of the new version the receiver holds, at their offset in its
+/// old version, kept in a file until the missing blocks arrive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaPlan {
+    pub size: u64,
+    pub block_len: u32,
+    pub digest: Digest,
+    pub blocks: Vec<Option<u64>>,
+}
+
+impl DeltaPlan {
+    pub fn missing(&self) -> Vec<bool> {
+        self.blocks.iter().map(Option::is_none).collect()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(44 + 8 * self.blocks.len());
+        buf.extend_from_slice(&self.block_len.to_be_bytes());
+        buf.extend_from_slice(&self.size.to_be_bytes());
+        buf.extend_from_slice(&self.digest);
+        for offset in &self.blocks {
+            buf.extend_from_slice(&offset.unwrap_or(u64::MAX).to_be_bytes());
+        }
+        buf
+    }
+
+    pub fn decode(data: &[u8]) -> Result<DeltaPlan> {
+        let corrupt = || SecSnailError::ProtocolViolation("malformed delta plan".to_string());
+        if data.len() < 44 || !(data.len() - 44).is_multiple_of(8) {
+            return Err(corrupt());
+        }
+        let block_len = u32::from_be_bytes(data[..4].try_into().unwrap());
+        let size = u64::from_be_bytes(data[4..12].try_into().unwrap());
+        let blocks: Vec<_> = data[44..]
+            .chunks(8)
+            .map(|b| match u64::from_be_bytes(b.try_into().unwrap()) {
+                u64::MAX => None,
+                offset => Some(offset),
+            })
+            .collect();
+        if block_len == 0 || blocks.len() as u64 != size.div_ceil(u64::from(block_len)) {
+            return Err(corrupt());
+        }
+        Ok(DeltaPlan {
+            size,
+            block_len,
+            digest: data[12..44].try_into().unwrap(),
+            blocks,
+        })
+    }
+}
+
+/// reads the blocks `blocks` of a file one after another, the data of a
+/// delta transfer
+pub struct BlockReader<R> {
+    file: R,
+    size: u64,
+    block_len: u32,
+    blocks: std::vec::IntoIter<usize>,
+    /// bytes left of the current block
+    left: u64,
+}
+
+impl<R: Read + Seek> BlockReader<R> {
+    pub fn new(file: R, signature: &Signature, blocks: Vec<usize>) -> Self {
+        BlockReader {
+            file,
+            size: signature.size,
+            block_len: signature.block_len,
+            blocks: blocks.into_iter(),
+            left: 0,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for BlockReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.left == 0 {
+            let Some(i) = self.blocks.next() else {
+                return Ok(0);
+            };
+            let (start, len) = block_range(self.size, self.block_len, i);
+            self.file.seek(SeekFrom::Start(start))?;
+            self.left = len;
+        }
+        let n = buf.len().min(self.left as usize);
+        let n = self.file.read(&mut buf[..n])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.left -= n as u64;
+        Ok(n)
+    }
+}
+
+/// writes the new version of a file from the missing blocks written to
+/// it, copying the blocks of the plan from the old version in between
+pub struct DeltaWriter<W, R> {
+    out: W,
+    /// the old version, `None` if the plan holds no block of it
+    old: Option<R>,
+    plan: DeltaPlan,
+    /// next block of the new version
+    block: usize,
+    /// bytes of the current missing block written
+    filled: u64,
+}
+
+impl<W: Write, R: Read + Seek> DeltaWriter<W, R> {
+    pub fn new(out: W, old: Option<R>, plan: DeltaPlan) -> io::Result<Self> {
+        let mut writer = DeltaWriter {
+            out,
+            old,
+            plan,
+            block: 0,
+            filled: 0,
+        };
+        writer.copy_held()?;
+        Ok(writer)
+    }
+
+    /// copy the blocks the receiver holds up to the next missing one
+    fn copy_held(&mut self) -> io::Result<()> {
+        while let Some(Some(offset)) = self.plan.blocks.get(self.block) {
+            let (_, len) = block_range(self.plan.size, self.plan.block_len, self.block);
+            let old = self.old.as_mut().ok_or(io::ErrorKind::NotFound)?;
+            old.seek(SeekFrom::Start(*offset))?;
+            let copied = io::copy(&mut old.take(len), &mut self.out)?;
+            if copied != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.block += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write, R: Read + Seek> Write for DeltaWriter<W, R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.block >= self.plan.blocks.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "more data than missing blocks",
+            ));
+        }
+        let (_, len) = block_range(self.plan.size, self.plan.block_len, self.block);
+        let n = buf.len().min((len - self.filled) as usize);
+        self.out.write_all(&buf[..n])?;
+        self.filled += n as u64;
+        if self.filled == len {
+            self.block += 1;
+            self.filled = 0;
+            self.copy_held()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn delta_rebuilds_a_shifted_file() {
+        let old: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        // an insertion shifts everything after it, a block in the middle changes
+        let mut new = b"snail was here".to_vec();
+        new.extend_from_slice(&old[..50_000]);
+        new.extend_from_slice(&[0; 3000]);
+        new.extend_from_slice(&old[53_000..]);
+
+        let signature = Signature::of_file("snail.bin", new.len() as u64, &new[..]).unwrap();
+        assert_eq!(Signature::decode(&signature.encode()).unwrap(), signature);
+        let plan = signature.plan(&old);
+        assert_eq!(DeltaPlan::decode(&plan.encode()).unwrap(), plan);
+        let missing: Vec<_> = (0..plan.blocks.len())
+            .filter(|i| plan.blocks[*i].is_none())
+            .collect();
+        assert!(missing.len() <= 5, "{missing:?}");
+
+        let mut data = Vec::new();
+        BlockReader::new(Cursor::new(&new), &signature, missing.clone())
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data.len() as u64, signature.missing_len(&missing));
+        let mut rebuilt = Vec::new();
+        let mut writer = DeltaWriter::new(&mut rebuilt, Some(Cursor::new(&old)), plan).unwrap();
+        for chunk in data.chunks(508) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(rebuilt, new);
+    }
+}
@@ -0,0 +1,160 @@
+//! Snail Transfer Protocol – LAN discovery
+//!
+//! A client broadcasts a probe to the default port, every discoverable
+//! receiver answers with its name and port.
+//!
+//! ```text
+//!  probe:  ┌────────────────┐
+//!          │ "SNAIL?" │ 0x01│
+//!          └────────────────┘
+//!  reply:  ┌────────────────┬──────────────────┬──────────────────┐
+//!          │ "SNAIL!" │ 0x01│ Port (16 bit)    │ Name (UTF-8)     │
+//!          └────────────────┴──────────────────┴──────────────────┘
+//! ```
+//!
+//! The leading `S` violates the fixed zero bits of a packet header, so
+//! receivers without discovery drop a probe like any corrupt packet.
+//!
+//! Every receiver also answers an echo request with the address it saw the
+//! request from, which a peer behind a NAT learns its public address by:
+//!
+//! ```text
+//!  echo:   ┌────────────────┬──────────────────┬──────────────────┐
+//!          │ "SNAIL^" │ 0x01│ Nonce (32 bit)   │ Zeros (18 B)     │
+//!          └────────────────┴──────────────────┴──────────────────┘
+//!  reply:  ┌────────────────┬──────────────────┬──────────────────┬──────────────────┐
+//!          │ "SNAIL~" │ 0x01│ Nonce (32 bit)   │ IP (4 or 16 B)   │ Port (16 bit)    │
+//!          └────────────────┴──────────────────┴──────────────────┴──────────────────┘
+//! ```
+//!
+//! The zeros pad a request to the length of the largest reply, so a
+//! receiver can not be abused to amplify traffic to a spoofed address.
+
+use std::net::{IpAddr, SocketAddr};
+
+const PROBE_MAGIC: &[u8] = b"SNAIL?";
+const REPLY_MAGIC: &[u8] = b"SNAIL!";
+const ECHO_MAGIC: &[u8] = b"SNAIL^";
+const ECHO_REPLY_MAGIC: &[u8] = b"SNAIL~";
+const VERSION: u8 = 0x01;
+/// an echo request with the padding, as long as a reply to an ipv6 address
+const ECHO_LEN: usize = 6 + 1 + 4 + 16 + 2;
+
+/// receiver which answered a discovery probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveredPeer {
+    pub name: String,
+    /// address to send files to
+    pub addr: SocketAddr,
+}
+
+pub fn probe() -> Vec<u8> {
+    [PROBE_MAGIC, &[VERSION]].concat()
+}
+
+pub fn is_probe(buf: &[u8]) -> bool {
+    buf.starts_with(PROBE_MAGIC) && buf.get(PROBE_MAGIC.len()) == Some(&VERSION)
+}
+
+pub fn reply(name: &str, port: u16) -> Vec<u8> {
+    [
+        REPLY_MAGIC,
+        &[VERSION],
+        &port.to_be_bytes(),
+        name.as_bytes(),
+    ]
+    .concat()
+}
+
+/// # Return
+/// `None` if `buf` is no valid reply
+pub fn decode_reply(buf: &[u8], src: SocketAddr) -> Option<DiscoveredPeer> {
+    let rest = buf.strip_prefix(REPLY_MAGIC)?.strip_prefix(&[VERSION])?;
+    let (port, name) = rest.split_first_chunk::<2>()?;
+    let name = str::from_utf8(name).ok()?.to_string();
+
+    let mut addr = src;
+    addr.set_port(u16::from_be_bytes(*port));
+    Some(DiscoveredPeer { name, addr })
+}
+
+/// `addr` as its ip, 4 or 16 bytes, followed by the port
+pub fn encode_addr(addr: SocketAddr) -> Vec<u8> {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    [ip.as_slice(), &addr.port().to_be_bytes()].concat()
+}
+
+pub fn decode_addr(buf: &[u8]) -> Option<SocketAddr> {
+    let (ip, port) = buf.split_last_chunk::<2>()?;
+    let ip = match ip.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(ip).ok()?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(ip).ok()?),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, u16::from_be_bytes(*port)))
+}
+
+pub fn echo_request(nonce: u32) -> Vec<u8> {
+    let mut buf = [ECHO_MAGIC, &[VERSION], &nonce.to_be_bytes()].concat();
+    buf.resize(ECHO_LEN, 0);
+    buf
+}
+
+/// # Return
+/// `None` if `buf` is no echo request, else the reply to send to `src`
+pub fn echo_reply(buf: &[u8], src: SocketAddr) -> Option<Vec<u8>> {
+    let rest = buf.strip_prefix(ECHO_MAGIC)?.strip_prefix(&[VERSION])?;
+    if buf.len() < ECHO_LEN {
+        return None;
+    }
+    let nonce = &rest[..4];
+    Some([ECHO_REPLY_MAGIC, &[VERSION], nonce, &encode_addr(src)].concat())
+}
+
+/// # Return
+/// the address the reply to the request of `nonce` names, `None` if `buf`
+/// is no such reply
+pub fn decode_echo_reply(buf: &[u8], nonce: u32) -> Option<SocketAddr> {
+    let rest = buf
+        .strip_prefix(ECHO_REPLY_MAGIC)?
+        .strip_prefix(&[VERSION])?
+        .strip_prefix(&nonce.to_be_bytes())?;
+    decode_addr(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pck::Packet;
+
+    #[test]
+    fn reply_roundtrip() {
+        let src: SocketAddr = "192.168.0.7:40000".parse().unwrap();
+        let peer = decode_reply(&reply("lab-pc", 55055), src).unwrap();
+        assert_eq!(peer.name, "lab-pc");
+        assert_eq!(peer.addr, "192.168.0.7:55055".parse().unwrap());
+    }
+
+    #[test]
+    fn probe_is_no_packet() {
+        assert!(is_probe(&probe()));
+        assert!(Packet::decode(probe()).is_err());
+    }
+
+    #[test]
+    fn echo_roundtrip() {
+        for src in ["198.51.100.7:40000", "[2001:db8::1]:50000"] {
+            let src: SocketAddr = src.parse().unwrap();
+            let reply = echo_reply(&echo_request(7), src).unwrap();
+            assert!(reply.len() <= ECHO_LEN);
+            assert_eq!(decode_echo_reply(&reply, 7), Some(src));
+            assert_eq!(decode_echo_reply(&reply, 8), None);
+        }
+        let src: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+        assert!(echo_reply(&echo_request(7)[..ECHO_LEN - 1], src).is_none());
+        assert!(Packet::decode(echo_request(7)).is_err());
+    }
+}
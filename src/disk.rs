@@ -0,0 +1,100 @@
+//! Free space, preallocation and syncs of the file system a file is
+//! received into.
+
+use std::{fs::File, io, path::Path};
+
+/// bytes available to unprivileged users on the file system of `dir`
+///
+/// `None` on platforms without a way to query it, the transfer is accepted then
+#[cfg(unix)]
+pub fn available_space(dir: &Path) -> io::Result<Option<u64>> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: path is nul terminated and stat is only read after success
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn available_space(_dir: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// reserve the first `len` bytes of `file` without changing its length,
+/// so the file system can allocate them together and a full disk fails
+/// the transfer before its first byte instead of midway
+///
+/// a file system which can not reserve space is left to allocate as the
+/// file is written, as are other platforms than linux
+#[cfg(target_os = "linux")]
+pub fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let Ok(len @ 1..) = libc::off_t::try_from(len) else {
+        return Ok(());
+    };
+    // SAFETY: the descriptor is open as long as `file` is borrowed
+    if unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) } == 0 {
+        return Ok(());
+    }
+    match io::Error::last_os_error() {
+        e if e.raw_os_error() == Some(libc::EOPNOTSUPP) => Ok(()),
+        e => Err(e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn preallocate(_file: &File, _len: u64) -> io::Result<()> {
+    Ok(())
+}
+
+/// sync the directory holding `path`, which makes a rename into it durable
+#[cfg(unix)]
+pub fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => File::open(".")?.sync_all(),
+    }
+}
+
+/// directories can not be opened to be synced on other platforms
+#[cfg(not(unix))]
+pub fn sync_dir(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn space_of_temp_dir() {
+        let space = available_space(&std::env::temp_dir()).unwrap();
+        assert!(space.is_some_and(|s| s > 0));
+        assert!(available_space(Path::new("/no/such/secsnail/dir")).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preallocation_keeps_the_length() {
+        use std::os::unix::fs::MetadataExt;
+
+        let path = std::env::temp_dir().join(format!("secsnail-{}-prealloc", std::process::id()));
+        let file = File::create(&path).unwrap();
+        preallocate(&file, 1 << 20).unwrap();
+        let meta = file.metadata().unwrap();
+        assert_eq!(meta.len(), 0);
+        // unless the file system can not reserve space
+        assert!(meta.blocks() * 512 >= 1 << 20 || meta.blocks() == 0);
+        std::fs::remove_file(path).unwrap();
+    }
+}
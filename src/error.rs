@@ -0,0 +1,184 @@
+//! Crate-level error type returned by all public secure snail APIs.
+
+use std::{error, fmt, io};
+
+use crate::pck::{CodecError, Packet};
+
+pub type Result<T> = std::result::Result<T, SecSnailError>;
+
+#[derive(Debug)]
+pub enum SecSnailError {
+    /// underlying socket or file system error
+    Io(io::Error),
+    /// received bytes could not be decoded into a packet
+    CorruptPacket(&'static str),
+    /// payload does not fit into a single packet
+    PayloadTooLarge { len: usize, max: usize },
+    /// length field of a received packet disagrees with its datagram
+    LengthMismatch { announced: usize, received: usize },
+    /// an unused header bit of a received packet is set
+    ReservedBits(u8),
+    /// sender gave up after the configured amount of retransmissions
+    MaxRetransmitsExceeded,
+    /// peer stopped responding during an established session
+    ConnectionTimeout,
+    /// file name is missing, not valid UTF-8 or otherwise unusable
+    InvalidFilename(String),
+    /// socket configuration rejected before any packet was sent
+    InvalidConfig(String),
+    /// peer sent a packet that is not allowed in the current state
+    ProtocolViolation(String),
+    /// polled for progress without starting a transfer first
+    NoActiveTransfer,
+    /// receiver refused the transfer, holds the reason sent along
+    Rejected(String),
+    /// transfer did not finish within the configured transfer deadline
+    DeadlineExceeded,
+    /// received file exceeds the limit of the receiver, `size` is the
+    /// announced size or the bytes received so far
+    FileTooLarge { size: u64, max: u64 },
+    /// not enough free space in the target dir for the announced file size
+    InsufficientSpace { size: u64, available: u64 },
+    /// sender already transferred its quota of bytes within the quota window
+    QuotaExceeded { used: u64, quota: u64 },
+    /// receiving was stopped by a `ShutdownHandle`
+    Shutdown,
+    /// transfer was cancelled through its `TransferHandle`
+    Cancelled,
+}
+
+impl SecSnailError {
+    /// a non-blocking socket had no datagram ready
+    pub fn is_would_block(&self) -> bool {
+        matches!(self, SecSnailError::Io(e) if e.kind() == io::ErrorKind::WouldBlock)
+    }
+
+    /// an fsm has no transition for `event` in `state`
+    pub(crate) fn undefined_transition(state: &str, event: &str, pck: Option<&Packet>) -> Self {
+        SecSnailError::ProtocolViolation(match pck {
+            Some(pck) => format!("{event} {pck} in state {state}"),
+            None => format!("{event} in state {state}"),
+        })
+    }
+}
+
+impl fmt::Display for SecSnailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecSnailError::Io(e) => write!(f, "i/o error: {e}"),
+            SecSnailError::CorruptPacket(reason) => write!(f, "corrupt packet: {reason}"),
+            SecSnailError::PayloadTooLarge { len, max } => {
+                write!(f, "payload size {len} exceeds max payload size {max}")
+            }
+            SecSnailError::LengthMismatch {
+                announced,
+                received,
+            } => write!(
+                f,
+                "corrupt packet: payload size {announced} announced, {received} bytes received"
+            ),
+            SecSnailError::ReservedBits(bits) => {
+                write!(
+                    f,
+                    "corrupt packet: reserved header bits {bits:#06b} are set"
+                )
+            }
+            SecSnailError::MaxRetransmitsExceeded => {
+                write!(f, "max retransmits exceeded, receiver not responding")
+            }
+            SecSnailError::ConnectionTimeout => write!(f, "connection timeout"),
+            SecSnailError::InvalidFilename(reason) => write!(f, "invalid file name: {reason}"),
+            SecSnailError::InvalidConfig(reason) => write!(f, "invalid configuration: {reason}"),
+            SecSnailError::ProtocolViolation(reason) => write!(f, "protocol violation: {reason}"),
+            SecSnailError::NoActiveTransfer => write!(f, "no transfer in progress"),
+            SecSnailError::Rejected(reason) => write!(f, "transfer rejected by receiver: {reason}"),
+            SecSnailError::DeadlineExceeded => write!(f, "transfer deadline exceeded"),
+            SecSnailError::FileTooLarge { size, max } => {
+                write!(f, "file size {size} exceeds the limit of {max} bytes")
+            }
+            SecSnailError::InsufficientSpace { size, available } => write!(
+                f,
+                "not enough space for {size} bytes, only {available} bytes available"
+            ),
+            SecSnailError::QuotaExceeded { used, quota } => {
+                write!(f, "sender quota exceeded, {used} of {quota} bytes used")
+            }
+            SecSnailError::Shutdown => write!(f, "socket shut down"),
+            SecSnailError::Cancelled => write!(f, "transfer cancelled"),
+        }
+    }
+}
+
+impl error::Error for SecSnailError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SecSnailError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SecSnailError {
+    fn from(e: io::Error) -> Self {
+        SecSnailError::Io(e)
+    }
+}
+
+impl From<CodecError> for SecSnailError {
+    fn from(e: CodecError) -> Self {
+        match e {
+            CodecError::CorruptPacket(reason) => SecSnailError::CorruptPacket(reason),
+            CodecError::PayloadTooLarge { len, max } => SecSnailError::PayloadTooLarge { len, max },
+            CodecError::LengthMismatch {
+                announced,
+                received,
+            } => SecSnailError::LengthMismatch {
+                announced,
+                received,
+            },
+            CodecError::ReservedBits(bits) => SecSnailError::ReservedBits(bits),
+        }
+    }
+}
+
+/// allows using secsnail calls with `?` in functions returning `io::Result`
+impl From<SecSnailError> for io::Error {
+    fn from(e: SecSnailError) -> Self {
+        let kind = match e {
+            SecSnailError::Io(inner) => return inner,
+            SecSnailError::MaxRetransmitsExceeded
+            | SecSnailError::ConnectionTimeout
+            | SecSnailError::DeadlineExceeded => io::ErrorKind::TimedOut,
+            SecSnailError::InvalidFilename(_)
+            | SecSnailError::InvalidConfig(_)
+            | SecSnailError::PayloadTooLarge { .. } => io::ErrorKind::InvalidInput,
+            SecSnailError::FileTooLarge { .. } => io::ErrorKind::FileTooLarge,
+            SecSnailError::InsufficientSpace { .. } => io::ErrorKind::StorageFull,
+            SecSnailError::CorruptPacket(_)
+            | SecSnailError::LengthMismatch { .. }
+            | SecSnailError::ReservedBits(_)
+            | SecSnailError::ProtocolViolation(_) => io::ErrorKind::InvalidData,
+            SecSnailError::NoActiveTransfer => io::ErrorKind::NotConnected,
+            SecSnailError::Shutdown | SecSnailError::Cancelled => io::ErrorKind::ConnectionAborted,
+            SecSnailError::Rejected(_) | SecSnailError::QuotaExceeded { .. } => {
+                io::ErrorKind::ConnectionRefused
+            }
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_roundtrip_keeps_kind() {
+        let e: SecSnailError = io::Error::new(io::ErrorKind::NotFound, "missing").into();
+        let io_e: io::Error = e.into();
+        assert_eq!(io_e.kind(), io::ErrorKind::NotFound);
+
+        let io_e: io::Error = SecSnailError::MaxRetransmitsExceeded.into();
+        assert_eq!(io_e.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,126 @@
+//! Snail Transfer Protocol – feedback of the receiver
+//!
+//! The ACK of a data packet is empty, holds the echoed timestamps, see
+//! `timestamp`, and may end with a flags byte:
+//!
+//! ```text
+//!  ┌──────────────────────────────────────────┬───────────┐
+//!  │ Sent, Received (2 × 32 bit, optional)    │ Flags (8) │
+//!  └──────────────────────────────────────────┴───────────┘
+//! ```
+//!
+//! Bit 0 tells that the receiver falls behind, e.g. writing the data took
+//! longer than it allows, like an ECN mark. The sender answers by waiting
+//! a gap before every data packet, doubled by every flagged ack and shrunk
+//! by every other, so the rate drops fast and recovers slowly. Senders of
+//! 1.x ignore the payload of a data ack, no negotiation is needed.
+//!
+//! Independent of the feedback, a sender may keep a minimum gap between
+//! two packets, e.g. for a small router queue on a link with a short
+//! round trip.
+
+use std::time::{Duration, Instant};
+
+use crate::timestamp::{decode_echo, encode_echo};
+
+const FLAG_CONGESTED: u8 = 0b0000_0001;
+/// bytes of an echo, see `timestamp`
+const ECHO_LEN: usize = 8;
+
+/// first gap after a flagged ack
+const MIN_PACE: Duration = Duration::from_millis(1);
+/// largest gap, well below the retransmission timeout
+const MAX_PACE: Duration = Duration::from_millis(200);
+
+/// payload of the ack of a data packet
+pub fn encode_data_ack(echo: Option<(u32, u32)>, congested: bool) -> Vec<u8> {
+    let mut buf = echo.map_or_else(Vec::new, |(sent, received)| encode_echo(sent, received));
+    if congested {
+        buf.push(FLAG_CONGESTED);
+    }
+    buf
+}
+
+/// echo and congestion flag of the payload of a data ack
+pub fn decode_data_ack(payload: &[u8]) -> (Option<(u32, u32)>, bool) {
+    let (echo, flags) = match payload.len() {
+        0 | ECHO_LEN => (payload, 0),
+        _ => {
+            let (flags, echo) = payload.split_last().unwrap();
+            (echo, *flags)
+        }
+    };
+    (decode_echo(echo), flags & FLAG_CONGESTED != 0)
+}
+
+/// gap a sender waits before a data packet, see the module docs
+#[derive(Debug, Default)]
+pub struct Pacer {
+    pace: Duration,
+    next_send: Option<Instant>,
+    /// least time between two sent packets, zero for none
+    min_gap: Duration,
+    last_sent: Option<Instant>,
+}
+
+impl Pacer {
+    pub fn with_min_gap(min_gap: Duration) -> Pacer {
+        Pacer {
+            min_gap,
+            ..Pacer::default()
+        }
+    }
+
+    /// a packet was sent at `now`
+    pub fn sent(&mut self, now: Instant) {
+        self.last_sent = Some(now);
+    }
+
+    /// an ack arrived at `now`
+    pub fn acked(&mut self, congested: bool, now: Instant) {
+        self.pace = match congested {
+            true => (self.pace * 2).clamp(MIN_PACE, MAX_PACE),
+            false if self.pace < MIN_PACE / 8 => Duration::ZERO,
+            false => self.pace * 7 / 8,
+        };
+        self.next_send = (!self.pace.is_zero()).then(|| now + self.pace);
+    }
+
+    /// earliest time of the next data packet, `None` sends right away
+    pub fn next_send(&self) -> Option<Instant> {
+        let gap = match self.min_gap.is_zero() {
+            true => None,
+            false => self.last_sent.map(|at| at + self.min_gap),
+        };
+        self.next_send.max(gap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flagged_acks_slow_the_sender_down() {
+        for (echo, congested) in [(None, false), (None, true), (Some((1, 2)), true)] {
+            let payload = encode_data_ack(echo, congested);
+            assert_eq!(decode_data_ack(&payload), (echo, congested));
+        }
+        assert!(encode_data_ack(None, false).is_empty());
+
+        let now = Instant::now();
+        let mut pacer = Pacer::default();
+        pacer.acked(false, now);
+        assert_eq!(pacer.next_send(), None);
+        for _ in 0..20 {
+            pacer.acked(true, now);
+        }
+        assert_eq!(pacer.next_send(), Some(now + MAX_PACE));
+        let mut acks = 0;
+        while pacer.next_send().is_some() {
+            pacer.acked(false, now);
+            acks += 1;
+        }
+        assert!(acks > 40);
+    }
+}
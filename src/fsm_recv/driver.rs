@@ -1,36 +1,219 @@
-use super::fsm::FsmStateWrapper;
-use super::fsm::FsmWrap;
-use std::io;
+use crate::error::{Result, SecSnailError};
+use std::task::Poll;
 
+#[cfg(feature = "async")]
+use super::fsm::AsyncProtocolEventSource;
+use super::fsm::ProtocolEventSource;
 use super::fsm::ProtocolIoContext;
 use super::fsm::RcvEvent;
 use super::fsm::RcvFsm;
-use super::fsm::StateRouter;
+use super::fsm::RcvState;
 
-pub fn run_rcv_fsm_loop(ctx: &mut impl ProtocolIoContext) -> io::Result<()> {
-    // connection handshake via SYN and file name pkt
-    let mut cur_fsm_wrap = RcvFsm::init().wrap();
+/// run fsm until a session reached its end or failed, blocking on every event
+///
+/// starts from `cur_fsm`, which is `RcvFsm::init()` or the fsm
+/// after an accepted syn
+pub fn run_rcv_fsm_loop(
+    mut cur_fsm: RcvFsm,
+    ctx: &mut (impl ProtocolIoContext + ProtocolEventSource),
+) -> Result<()> {
+    loop {
+        let progress;
+        (cur_fsm, progress) = poll_rcv_fsm(cur_fsm, ctx)?;
+        if let Poll::Ready(outcome) = progress {
+            return outcome;
+        }
+    }
+}
 
-    // run fsm
+/// `run_rcv_fsm_loop` of a receive which stops reading the socket once
+/// its session is closed, it lingers to answer fins until the sender got
+/// its finack, see `linger`
+pub fn run_rcv_fsm_loop_lingering(
+    mut cur_fsm: RcvFsm,
+    ctx: &mut (impl ProtocolIoContext + ProtocolEventSource),
+) -> Result<()> {
     loop {
-        let event = get_next_event_for_current_state(&mut cur_fsm_wrap, ctx)?;
+        let progress;
+        (cur_fsm, progress) = poll_rcv_fsm(cur_fsm, ctx)?;
+        if let Poll::Ready(outcome) = progress {
+            if outcome.is_ok() {
+                linger(cur_fsm, ctx);
+            }
+            return outcome;
+        }
+    }
+}
 
-        cur_fsm_wrap = match cur_fsm_wrap {
-            FsmStateWrapper::WaitForConnection(fsm) => fsm.goto(event, ctx)?,
-            FsmStateWrapper::WaitForPkt(fsm) => fsm.goto(event, ctx)?,
+/// answer the fins of the session closed in `cur_fsm` again until its
+/// sender was quiet for a connection timeout
+///
+/// the finack of a closed session may get lost, its sender retransmits
+/// the fin until it is answered. Any other packet ends the linger, e.g.
+/// the syn of the next transfer, which its sender has to retransmit. The
+/// session is done, so a failure only ends the linger.
+fn linger(mut cur_fsm: RcvFsm, ctx: &mut (impl ProtocolIoContext + ProtocolEventSource)) {
+    if ctx.start_connection_timer().is_err() {
+        return;
+    }
+    while let Ok(event) = ctx.wait_for_ack_or_timeout() {
+        match lingers_on(&event) {
+            Some(true) => match handle_event(cur_fsm, event, ctx) {
+                Ok(next) if ctx.restart_connection_timer().is_ok() => cur_fsm = next,
+                _ => break,
+            },
+            Some(false) => continue,
+            None => break,
+        }
+    }
+    _ = ctx.stop_connection_timer();
+}
+
+/// `Some(true)` for a fin to answer while lingering, `Some(false)` for a
+/// corrupt packet to skip, `None` for any other event, which ends it
+fn lingers_on(event: &RcvEvent) -> Option<bool> {
+    match event {
+        RcvEvent::RecvPck(Some(rcvpkt), _) if rcvpkt.notcorrupt() => {
+            rcvpkt.is_FIN().then_some(true)
+        }
+        RcvEvent::RecvPck(_, _) => Some(false),
+        RcvEvent::ConnectionTimeout => None,
+    }
+}
+
+/// run fsm until a session got closed or the ctx would block
+///
+/// # Return
+/// the fsm to resume from and `Poll::Ready` with the outcome once a
+/// session is closed, either by fin or by `ConnectionTimeout`
+pub fn poll_rcv_fsm(
+    mut cur_fsm: RcvFsm,
+    ctx: &mut (impl ProtocolIoContext + ProtocolEventSource),
+) -> Result<(RcvFsm, Poll<Result<()>>)> {
+    loop {
+        check_deadline(ctx)?;
+        let event = match get_next_event_for_current_state(&cur_fsm, ctx) {
+            Err(e) if e.is_would_block() => return Ok((cur_fsm, Poll::Pending)),
+            r => r?,
         };
+
+        let (next, closed) = step(cur_fsm, event, ctx)?;
+        cur_fsm = next;
+        if let Some(outcome) = closed {
+            return Ok((cur_fsm, Poll::Ready(outcome)));
+        }
     }
 }
 
-fn get_next_event_for_current_state(
-    wrapper: &mut FsmStateWrapper,
+#[cfg(feature = "async")]
+pub async fn run_rcv_fsm_loop_async(
+    mut cur_fsm: RcvFsm,
+    ctx: &mut (impl ProtocolIoContext + AsyncProtocolEventSource),
+) -> Result<()> {
+    loop {
+        check_deadline(ctx)?;
+        let event = match cur_fsm.state() {
+            // awaiting new pck
+            RcvState::WaitForConnection | RcvState::End => ctx.wait_for_pck_no_timeout().await?,
+            RcvState::WaitForPkt { .. } => ctx.wait_for_ack_or_timeout().await?,
+        };
+
+        let closed;
+        (cur_fsm, closed) = step(cur_fsm, event, ctx)?;
+        if let Some(outcome) = closed {
+            if outcome.is_ok() {
+                linger_async(cur_fsm, ctx).await;
+            }
+            return outcome;
+        }
+    }
+}
+
+/// see `linger`, the async receive returns once its session is closed too
+#[cfg(feature = "async")]
+async fn linger_async(
+    mut cur_fsm: RcvFsm,
+    ctx: &mut (impl ProtocolIoContext + AsyncProtocolEventSource),
+) {
+    if ctx.start_connection_timer().is_err() {
+        return;
+    }
+    while let Ok(event) = ctx.wait_for_ack_or_timeout().await {
+        match lingers_on(&event) {
+            Some(true) => match handle_event(cur_fsm, event, ctx) {
+                Ok(next) if ctx.restart_connection_timer().is_ok() => cur_fsm = next,
+                _ => break,
+            },
+            Some(false) => continue,
+            None => break,
+        }
+    }
+    _ = ctx.stop_connection_timer();
+}
+
+/// handle one event and check if it closed the session
+///
+/// # Return
+/// the next fsm and the outcome of the session if it got closed,
+/// either by fin or by `ConnectionTimeout`
+pub fn step(
+    cur_fsm: RcvFsm,
+    event: RcvEvent,
     ctx: &mut impl ProtocolIoContext,
-) -> io::Result<RcvEvent> {
-    match wrapper {
+) -> Result<(RcvFsm, Option<Result<()>>)> {
+    let in_session = cur_fsm.in_session();
+    let next = handle_event(cur_fsm, event, ctx)?;
+
+    if next.is_end() {
+        return Ok((next, Some(Ok(()))));
+    }
+    // any other way out of a session is its connection timeout
+    if in_session && !next.in_session() {
+        return Ok((next, Some(Err(SecSnailError::ConnectionTimeout))));
+    }
+    Ok((next, None))
+}
+
+/// feed a single event into the fsm, e.g. a syn accepted by the application
+pub fn handle_event(
+    cur_fsm: RcvFsm,
+    event: RcvEvent,
+    ctx: &mut impl ProtocolIoContext,
+) -> Result<RcvFsm> {
+    ctx.on_event(&event);
+    let (state, event_name) = (cur_fsm.name(), event.name());
+    let pck = event.packet();
+    let _span = tracing::debug_span!(
+        "transition",
+        state = cur_fsm.name(),
+        event = event.name(),
+        pkt = pck.map(tracing::field::display),
+    )
+    .entered();
+
+    let next = cur_fsm.goto(event, ctx)?;
+    tracing::trace!(next = next.name(), "state changed");
+    ctx.on_transition(state, event_name, next.name());
+    Ok(next)
+}
+
+/// fails once the deadline of the open session is reached
+fn check_deadline(ctx: &impl ProtocolIoContext) -> Result<()> {
+    match ctx.deadline() {
+        Some(deadline) if ctx.now() >= deadline => Err(SecSnailError::DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
+
+fn get_next_event_for_current_state(
+    fsm: &RcvFsm,
+    ctx: &mut (impl ProtocolIoContext + ProtocolEventSource),
+) -> Result<RcvEvent> {
+    match fsm.state() {
         // blocking until new pck recvd
-        FsmStateWrapper::WaitForConnection(_) => ctx.wait_for_pck_no_timeout(),
+        RcvState::WaitForConnection | RcvState::End => ctx.wait_for_pck_no_timeout(),
 
         // check if data is available
-        FsmStateWrapper::WaitForPkt(_) => ctx.wait_for_ack_or_timeout(),
+        RcvState::WaitForPkt { .. } => ctx.wait_for_ack_or_timeout(),
     }
 }
@@ -1,11 +1,11 @@
-use std::{
-    io::{self},
-    net::SocketAddr,
-};
+use std::{net::SocketAddr, time::Instant};
+
+use crate::error::{Result, SecSnailError};
 
 use super::super::pck::Flag;
 
 use super::super::pck::Packet;
+use super::{wait_for_connection, wait_for_pkt};
 
 pub enum RcvEvent {
     ConnectionTimeout,
@@ -13,137 +13,424 @@ pub enum RcvEvent {
     RecvPck(Option<Packet>, SocketAddr),
 }
 
-// Connection / start
-#[derive(Clone)]
-pub struct RcvStateWaitForConnection {}
+impl RcvEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RcvEvent::ConnectionTimeout => "connection_timeout",
+            RcvEvent::RecvPck(Some(_), _) => "rcv_pkt",
+            RcvEvent::RecvPck(None, _) => "rcv_corrupt",
+        }
+    }
 
-impl RcvStateWaitForConnection {
-    pub fn new() -> Self {
-        Self {}
+    pub fn packet(&self) -> Option<&Packet> {
+        match self {
+            RcvEvent::RecvPck(pck, _) => pck.as_ref(),
+            RcvEvent::ConnectionTimeout => None,
+        }
     }
 }
 
-// Wait for Pkt
+/// state of the receiving side, every variant carries the data of its state
 #[derive(Clone)]
-pub struct RcvStateWaitForPkt {
-    /// last sent packet
-    sndpkt: Packet,
-}
-
-impl RcvStateWaitForPkt {
-    pub fn new(sndpkt: Packet) -> Self {
-        Self { sndpkt }
-    }
-
-    pub fn sndpkt(&self) -> &Packet {
-        &self.sndpkt
-    }
+pub enum RcvState {
+    WaitForConnection,
+    /// in the session opened by `syn` of `peer`
+    WaitForPkt {
+        /// last sent packet
+        sndpkt: Packet,
+        syn: Packet,
+        peer: SocketAddr,
+        /// false while only the syn is acknowledged
+        acked_data: bool,
+    },
+    /// the session got closed by its fin, a new syn opens the next one
+    End,
 }
 
-// fsm
-
-#[derive(Clone, Copy)]
-struct Config {}
-
-impl Config {
-    pub fn new() -> Self {
-        Self {}
+impl RcvState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RcvState::WaitForConnection => "wait_for_connection",
+            RcvState::WaitForPkt { .. } => "wait_for_pkt",
+            RcvState::End => "end",
+        }
     }
 }
 
-pub struct RcvFsm<State: Clone> {
-    _state: State,
-    _config: Config,
+pub struct RcvFsm {
+    state: RcvState,
 }
 
-impl<State: Clone> RcvFsm<State> {
-    pub fn new(state: State) -> Self {
+impl RcvFsm {
+    /// fsm start entry point
+    pub fn init() -> RcvFsm {
         RcvFsm {
-            _state: state,
-            _config: Config::new(),
+            state: RcvState::WaitForConnection,
         }
     }
 
-    /// inmutable refrence
-    pub fn state(&self) -> &State {
-        &self._state
+    pub fn state(&self) -> &RcvState {
+        &self.state
     }
 
-    pub fn to_wait_for_connection(&self) -> RcvFsm<RcvStateWaitForConnection> {
-        RcvFsm {
-            _state: RcvStateWaitForConnection::new(),
-            _config: self._config,
-        }
-    }
-
-    pub fn to_wait_for_pkt(&self, sndpkt: Packet) -> RcvFsm<RcvStateWaitForPkt> {
-        RcvFsm {
-            _state: RcvStateWaitForPkt::new(sndpkt),
-            _config: self._config,
-        }
+    pub fn name(&self) -> &'static str {
+        self.state.name()
     }
-}
 
-// wrap trait on all states
-pub trait FsmWrap {
-    fn wrap(self) -> FsmStateWrapper;
-}
-
-impl FsmWrap for RcvFsm<RcvStateWaitForConnection> {
-    fn wrap(self) -> FsmStateWrapper {
-        FsmStateWrapper::WaitForConnection(self)
+    /// a session is open
+    pub fn in_session(&self) -> bool {
+        matches!(self.state, RcvState::WaitForPkt { .. })
     }
-}
 
-impl FsmWrap for RcvFsm<RcvStateWaitForPkt> {
-    fn wrap(self) -> FsmStateWrapper {
-        FsmStateWrapper::WaitForPkt(self)
+    /// the last session got closed by its fin
+    pub fn is_end(&self) -> bool {
+        matches!(self.state, RcvState::End)
     }
-}
-
-// fsm entry point
 
-impl RcvFsm<RcvStateWaitForConnection> {
-    /// fsm start entry point
-    pub fn init() -> RcvFsm<RcvStateWaitForConnection> {
-        RcvFsm::new(RcvStateWaitForConnection::new())
+    /// handle `e` in the current state, the transitions of every state are
+    /// in the module named after it
+    pub fn goto(self, e: RcvEvent, ctx: &mut dyn ProtocolIoContext) -> Result<RcvFsm> {
+        let state = match self.state {
+            // a stray fin of the closed session is still answered, as long
+            // as the socket is read, see `driver::run_rcv_fsm_loop_lingering`
+            RcvState::WaitForConnection | RcvState::End => wait_for_connection::goto(e, ctx)?,
+            RcvState::WaitForPkt {
+                sndpkt,
+                syn,
+                peer,
+                acked_data,
+            } => wait_for_pkt::goto(sndpkt, syn, peer, acked_data, e, ctx)?,
+        };
+        Ok(RcvFsm { state })
     }
 }
 
-pub enum FsmStateWrapper {
-    WaitForConnection(RcvFsm<RcvStateWaitForConnection>),
-    WaitForPkt(RcvFsm<RcvStateWaitForPkt>),
+/// blocking event source of the driver loop
+pub trait ProtocolEventSource {
+    fn wait_for_ack_or_timeout(&mut self) -> Result<RcvEvent>; // Gibt ein FSM Event zurück (RecvAck, Timeout, Corrupt)
+    fn wait_for_pck_no_timeout(&mut self) -> Result<RcvEvent>;
 }
 
-pub trait StateRouter {
-    // Gibt immer den Wrapper-Typ zurück, egal wie der tatsächliche Folgezustand heißt.
-    // &mut dyn ProtocolIoContext muss dabei sein, um I/O zu ermöglichen.
-    fn goto(self, e: RcvEvent, ctx: &mut dyn ProtocolIoContext) -> io::Result<FsmStateWrapper>;
+/// async event source of the async driver loop
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait AsyncProtocolEventSource {
+    async fn wait_for_ack_or_timeout(&mut self) -> Result<RcvEvent>;
+    async fn wait_for_pck_no_timeout(&mut self) -> Result<RcvEvent>;
 }
 
 pub trait ProtocolIoContext {
-    /// set snd_addr, rcv any other packet will be ignored
-    fn set_snd_addr(&mut self, snd_addr: SocketAddr);
+    /// set snd_addr and the transfer id of its syn, rcv any other packet
+    /// will be ignored and replies carry the transfer id
+    fn set_snd_addr(&mut self, snd_addr: SocketAddr, transfer_id: u8);
     fn extract_data<'a>(&mut self, rcvpkt: &'a Packet) -> &'a [u8];
-    fn extract_file_name(&mut self, rcvpkt: &Packet) -> io::Result<String>;
-    fn append(&mut self, data: &[u8]) -> io::Result<()>;
-    fn wait_for_ack_or_timeout(&mut self) -> io::Result<RcvEvent>; // Gibt ein FSM Event zurück (RecvAck, Timeout, Corrupt)
-    fn wait_for_pck_no_timeout(&mut self) -> io::Result<RcvEvent>;
+    fn extract_file_name(&mut self, rcvpkt: &Packet) -> Result<String>;
+    fn append(&mut self, data: &[u8]) -> Result<()>;
 
-    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> io::Result<Packet>;
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet>;
+    /// ack of a syn, carries the offset if a partial file is resumed
+    fn make_syn_ack(&mut self, seq_n: u8) -> Result<Packet>;
 
     /// create start_timer instant and set read timeout to timeout Duration
-    fn start_connection_timer(&mut self) -> io::Result<()>;
-    fn stop_connection_timer(&mut self) -> io::Result<()>;
-    fn restart_connection_timer(&mut self) -> io::Result<()>;
+    fn start_connection_timer(&mut self) -> Result<()>;
+    fn stop_connection_timer(&mut self) -> Result<()>;
+    fn restart_connection_timer(&mut self) -> Result<()>;
 
-    fn close_file(&mut self) -> io::Result<()>;
-    fn open_file(&mut self, filename: &str) -> io::Result<()>;
+    fn close_file(&mut self) -> Result<()>;
+    /// drop the open file of a session which did not finish
+    fn discard_file(&mut self) -> Result<()>;
+    fn open_file(&mut self, filename: &str) -> Result<()>;
 
-    fn udt_send(&mut self, pck: &Packet) -> io::Result<()>;
+    fn udt_send(&mut self, pck: &Packet) -> Result<()>;
 
     /// Track amount of data transmitted
     fn get_data_counter(&self) -> usize;
     fn increase_data_counter(&mut self, n: usize);
     fn reset_data_counter(&mut self);
+    /// Track acks sent again for duplicate packets
+    fn increase_ack_retransmit_counter(&mut self);
+
+    /// wall-clock limit of the open session, checked by the driver loops
+    fn deadline(&self) -> Option<Instant>;
+
+    /// clock of all timers and the deadline, virtual in a simulation
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// called by the drivers with every event before the fsm handles it
+    fn on_event(&mut self, _event: &RcvEvent) {}
+
+    /// called by the drivers after the fsm took the edge from `state` on
+    /// `event` to `next`
+    fn on_transition(&mut self, _state: &'static str, _event: &'static str, _next: &'static str) {}
+
+    /// `event` has no transition in `state`, the fsm stays in its state
+    /// unless this fails, which aborts the session
+    fn protocol_violation(&mut self, state: &'static str, event: &RcvEvent) -> Result<()> {
+        Err(SecSnailError::undefined_transition(
+            state,
+            event.name(),
+            event.packet(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::u8_to_bool;
+
+    /// records sent packets and the file operations
+    #[derive(Default)]
+    struct MockCtx {
+        sent: Vec<Packet>,
+        opened: Option<String>,
+        closed: bool,
+        discarded: bool,
+        data: Vec<u8>,
+        ack_retransmits: usize,
+    }
+
+    impl ProtocolIoContext for MockCtx {
+        fn set_snd_addr(&mut self, _snd_addr: SocketAddr, _transfer_id: u8) {}
+        fn extract_data<'a>(&mut self, rcvpkt: &'a Packet) -> &'a [u8] {
+            rcvpkt.payload()
+        }
+        fn extract_file_name(&mut self, rcvpkt: &Packet) -> Result<String> {
+            Ok(String::from_utf8_lossy(rcvpkt.payload()).into_owned())
+        }
+        fn append(&mut self, data: &[u8]) -> Result<()> {
+            self.data.extend_from_slice(data);
+            Ok(())
+        }
+        fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+            Ok(Packet::new(u8_to_bool(seq_n), f, vec![])?)
+        }
+        fn make_syn_ack(&mut self, seq_n: u8) -> Result<Packet> {
+            self.make_pkt(seq_n, Flag::ACK)
+        }
+        fn start_connection_timer(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn stop_connection_timer(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn restart_connection_timer(&mut self) -> Result<()> {
+            Ok(())
+        }
+        fn close_file(&mut self) -> Result<()> {
+            self.closed = true;
+            Ok(())
+        }
+        fn discard_file(&mut self) -> Result<()> {
+            self.discarded = true;
+            Ok(())
+        }
+        fn open_file(&mut self, filename: &str) -> Result<()> {
+            self.opened = Some(filename.to_string());
+            Ok(())
+        }
+        fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+            self.sent.push(pck.clone());
+            Ok(())
+        }
+        fn get_data_counter(&self) -> usize {
+            self.data.len()
+        }
+        fn increase_data_counter(&mut self, _n: usize) {}
+        fn reset_data_counter(&mut self) {
+            self.data.clear();
+        }
+        fn increase_ack_retransmit_counter(&mut self) {
+            self.ack_retransmits += 1;
+        }
+        fn deadline(&self) -> Option<Instant> {
+            None
+        }
+    }
+
+    const PEER: SocketAddr = SocketAddr::V4(std::net::SocketAddrV4::new(
+        std::net::Ipv4Addr::LOCALHOST,
+        4000,
+    ));
+    const OTHER: SocketAddr = SocketAddr::V4(std::net::SocketAddrV4::new(
+        std::net::Ipv4Addr::LOCALHOST,
+        4001,
+    ));
+
+    fn pkt(n: u8, f: Flag, payload: &[u8]) -> Packet {
+        Packet::new(u8_to_bool(n), f, payload.to_vec()).unwrap()
+    }
+
+    fn syn() -> Packet {
+        pkt(0, Flag::SYN, b"snail.txt")
+    }
+
+    /// in the session of `PEER`, `sndpkt` acked the syn or data
+    fn session(sndpkt: Packet, acked_data: bool) -> RcvFsm {
+        RcvFsm {
+            state: RcvState::WaitForPkt {
+                sndpkt,
+                syn: syn(),
+                peer: PEER,
+                acked_data,
+            },
+        }
+    }
+
+    fn rcv(pck: Packet, from: SocketAddr) -> RcvEvent {
+        RcvEvent::RecvPck(Some(pck), from)
+    }
+
+    #[test]
+    fn transition_table() {
+        let syn_ack = || pkt(0, Flag::ACK, b"");
+        let data_ack = || pkt(1, Flag::ACK, b"");
+        // state, event, next state, sent packet
+        let table: Vec<(RcvFsm, RcvEvent, &str, Option<Flag>)> = vec![
+            (
+                RcvFsm::init(),
+                rcv(syn(), PEER),
+                "wait_for_pkt",
+                Some(Flag::ACK),
+            ),
+            (
+                RcvFsm::init(),
+                rcv(pkt(1, Flag::Data, b"x"), PEER),
+                "wait_for_connection",
+                None,
+            ),
+            (
+                RcvFsm::init(),
+                rcv(pkt(1, Flag::FIN, b""), PEER),
+                "wait_for_connection",
+                Some(Flag::FINACK),
+            ),
+            (
+                RcvFsm::init(),
+                RcvEvent::RecvPck(None, PEER),
+                "wait_for_connection",
+                None,
+            ),
+            (
+                session(syn_ack(), false),
+                rcv(syn(), PEER),
+                "wait_for_pkt",
+                Some(Flag::ACK),
+            ),
+            (
+                session(syn_ack(), false),
+                rcv(syn(), OTHER),
+                "wait_for_pkt",
+                Some(Flag::ACK),
+            ),
+            (
+                session(data_ack(), true),
+                rcv(syn(), PEER),
+                "wait_for_pkt",
+                None,
+            ),
+            (
+                session(syn_ack(), false),
+                rcv(pkt(1, Flag::Data, b"x"), PEER),
+                "wait_for_pkt",
+                Some(Flag::ACK),
+            ),
+            (
+                session(data_ack(), true),
+                rcv(pkt(1, Flag::Data, b"x"), PEER),
+                "wait_for_pkt",
+                Some(Flag::ACK),
+            ),
+            (
+                session(data_ack(), true),
+                rcv(pkt(0, Flag::FIN, b""), PEER),
+                "end",
+                Some(Flag::FINACK),
+            ),
+            (
+                RcvFsm {
+                    state: RcvState::End,
+                },
+                rcv(pkt(0, Flag::FIN, b""), PEER),
+                "wait_for_connection",
+                Some(Flag::FINACK),
+            ),
+            (
+                session(data_ack(), true),
+                RcvEvent::ConnectionTimeout,
+                "wait_for_connection",
+                None,
+            ),
+            (
+                session(data_ack(), true),
+                RcvEvent::RecvPck(None, PEER),
+                "wait_for_pkt",
+                None,
+            ),
+        ];
+        for (fsm, event, next, sent) in table {
+            let from = fsm.name();
+            let event_name = event.name();
+            let mut ctx = MockCtx::default();
+            let to = fsm.goto(event, &mut ctx).unwrap();
+            assert_eq!(to.name(), next, "{from} on {event_name}");
+            assert_eq!(
+                ctx.sent.last().map(Packet::flag),
+                sent,
+                "{from} on {event_name}"
+            );
+        }
+    }
+
+    #[test]
+    fn session_lifecycle() {
+        let mut ctx = MockCtx::default();
+        let fsm = RcvFsm::init().goto(rcv(syn(), PEER), &mut ctx).unwrap();
+        assert_eq!(ctx.opened.as_deref(), Some("snail.txt"));
+        let fsm = fsm
+            .goto(rcv(pkt(1, Flag::Data, b"slow"), PEER), &mut ctx)
+            .unwrap();
+        assert!(matches!(
+            fsm.state(),
+            RcvState::WaitForPkt {
+                acked_data: true,
+                ..
+            }
+        ));
+        // duplicate data is acked again, not appended
+        let fsm = fsm
+            .goto(rcv(pkt(1, Flag::Data, b"slow"), PEER), &mut ctx)
+            .unwrap();
+        assert_eq!(ctx.ack_retransmits, 1);
+        let fsm = fsm
+            .goto(rcv(pkt(0, Flag::FIN, b""), PEER), &mut ctx)
+            .unwrap();
+        assert!(fsm.is_end() && !fsm.in_session());
+        assert!(ctx.closed && !ctx.discarded);
+        assert_eq!(ctx.data, b"slow");
+    }
+
+    #[test]
+    fn undefined_transition_is_violation() {
+        let mut ctx = MockCtx::default();
+        let r = session(pkt(0, Flag::ACK, b""), false)
+            .goto(rcv(pkt(1, Flag::ACK, b""), PEER), &mut ctx);
+        assert!(matches!(r, Err(SecSnailError::ProtocolViolation(_))));
+        let r = RcvFsm::init().goto(RcvEvent::ConnectionTimeout, &mut ctx);
+        assert!(matches!(r, Err(SecSnailError::ProtocolViolation(_))));
+        assert!(ctx.sent.is_empty());
+    }
+
+    #[test]
+    fn new_syn_discards_session() {
+        let mut ctx = MockCtx::default();
+        let fsm = session(pkt(1, Flag::ACK, b""), true)
+            .goto(rcv(pkt(0, Flag::SYN, b"other.txt"), OTHER), &mut ctx)
+            .unwrap();
+        assert!(ctx.discarded);
+        assert_eq!(ctx.opened.as_deref(), Some("other.txt"));
+        assert!(matches!(fsm.state(), RcvState::WaitForPkt { peer, .. } if *peer == OTHER));
+    }
 }
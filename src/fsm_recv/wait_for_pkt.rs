@@ -1,75 +1,117 @@
-use std::io;
+use crate::error::Result;
+
+use std::net::SocketAddr;
 
 use crate::{
-    fsm_recv::fsm::{FsmStateWrapper, FsmWrap, RcvEvent, RcvFsm, RcvStateWaitForPkt, StateRouter},
-    pck::Flag,
+    fsm_recv::fsm::{ProtocolIoContext, RcvEvent, RcvState},
+    pck::{Flag, Packet},
 };
 
-use super::*;
+use super::wait_for_connection;
+
+pub(super) fn goto(
+    sndpkt: Packet,
+    syn: Packet,
+    peer: SocketAddr,
+    acked_data: bool,
+    e: RcvEvent,
+    ctx: &mut dyn ProtocolIoContext,
+) -> Result<RcvState> {
+    let stay = |sndpkt, syn| RcvState::WaitForPkt {
+        sndpkt,
+        syn,
+        peer,
+        acked_data,
+    };
+    match e {
+        // packet corrupt (could not be parsed)
+        RcvEvent::RecvPck(None, _) => Ok(stay(sndpkt, syn)),
+        // edge 14a: retransmitted syn, its ack got lost => resend ack
+        RcvEvent::RecvPck(Some(rcvpkt), snd_addr)
+            if rcvpkt.notcorrupt() && snd_addr == peer && rcvpkt == syn && !acked_data =>
+        {
+            ctx.udt_send(&sndpkt)?;
+            ctx.increase_ack_retransmit_counter();
+            ctx.restart_connection_timer()?;
+            Ok(stay(sndpkt, syn))
+        }
+
+        // edge 14b: new syn of a restarted or another sender
+        //
+        // abandon the open file and handle the syn like edge 2, a
+        // duplicate of the opening syn is left to edge 8
+        RcvEvent::RecvPck(Some(rcvpkt), snd_addr)
+            if rcvpkt.notcorrupt()
+                && rcvpkt.is_SYN()
+                && 0 == rcvpkt.n()
+                && (snd_addr != peer || rcvpkt != syn) =>
+        {
+            tracing::info!(
+                bytes = ctx.get_data_counter(),
+                peer = %snd_addr,
+                "session restarted by new syn"
+            );
+            ctx.stop_connection_timer()?;
+            ctx.discard_file()?;
+            wait_for_connection::goto(RcvEvent::RecvPck(Some(rcvpkt), snd_addr), ctx)
+        }
 
-impl StateRouter for RcvFsm<RcvStateWaitForPkt> {
-    fn goto(
-        self,
-        e: RcvEvent,
-        ctx: &mut dyn fsm::ProtocolIoContext,
-    ) -> io::Result<FsmStateWrapper> {
-        match e {
-            // packet corrupt (could not be parsed)
-            RcvEvent::RecvPck(None, _) => Ok(self.wrap()),
-            // edge 8: rcvpkt corrupt (checksum) oder syn
-            RcvEvent::RecvPck(Some(rcvpkt), _) if rcvpkt.corrupt() || rcvpkt.is_SYN() => {
-                Ok(self.wrap())
-            }
+        // edge 8: rcvpkt corrupt (checksum) oder syn
+        RcvEvent::RecvPck(Some(rcvpkt), _) if rcvpkt.corrupt() || rcvpkt.is_SYN() => {
+            Ok(stay(sndpkt, syn))
+        }
 
-            // edge 9: rcvpkt (data) with wrong n => resend ack (last sndpkt)
-            RcvEvent::RecvPck(Some(rcvpkt), _)
-                if rcvpkt.notcorrupt()
-                    && rcvpkt.n() == self.state().sndpkt().n()
-                    && rcvpkt.is_not_SYN() =>
-            {
-                ctx.udt_send(self.state().sndpkt())?;
-                ctx.restart_connection_timer()?;
-                Ok(self.wrap())
-            }
+        // edge 9: rcvpkt (data) with wrong n => resend ack (last sndpkt)
+        RcvEvent::RecvPck(Some(rcvpkt), _)
+            if rcvpkt.notcorrupt() && rcvpkt.n() == sndpkt.n() && rcvpkt.is_not_SYN() =>
+        {
+            ctx.udt_send(&sndpkt)?;
+            ctx.increase_ack_retransmit_counter();
+            ctx.restart_connection_timer()?;
+            Ok(stay(sndpkt, syn))
+        }
 
-            // edge 10: rcvpkt (data) with correct n
-            RcvEvent::RecvPck(Some(rcvpkt), _)
-                if rcvpkt.notcorrupt()
-                    && rcvpkt.n() != self.state().sndpkt().n()
-                    && rcvpkt.is_Data() =>
-            {
-                let data = ctx.extract_data(&rcvpkt);
-                ctx.append(data)?;
-                ctx.increase_data_counter(data.len());
-                let sndpkt = ctx.make_pkt(rcvpkt.n(), Flag::ACK)?;
-                ctx.udt_send(&sndpkt)?;
-                ctx.restart_connection_timer()?;
-                Ok(self.to_wait_for_pkt(sndpkt).wrap())
-            }
+        // edge 10: rcvpkt (data) with correct n
+        RcvEvent::RecvPck(Some(rcvpkt), _)
+            if rcvpkt.notcorrupt() && rcvpkt.n() != sndpkt.n() && rcvpkt.is_Data() =>
+        {
+            let data = ctx.extract_data(&rcvpkt);
+            ctx.append(data)?;
+            ctx.increase_data_counter(data.len());
+            let sndpkt = ctx.make_pkt(rcvpkt.n(), Flag::ACK)?;
+            ctx.udt_send(&sndpkt)?;
+            ctx.restart_connection_timer()?;
+            Ok(RcvState::WaitForPkt {
+                sndpkt,
+                syn,
+                peer,
+                acked_data: true,
+            })
+        }
 
-            // edge 11: connection timeout
-            RcvEvent::ConnectionTimeout => {
-                println!("Connection Timeout after {} Bytes", ctx.get_data_counter());
-                ctx.close_file()?;
-                Ok(self.to_wait_for_connection().wrap())
-            }
+        // edge 11: connection timeout
+        RcvEvent::ConnectionTimeout => {
+            tracing::info!(bytes = ctx.get_data_counter(), "connection timeout");
+            ctx.discard_file()?;
+            Ok(RcvState::WaitForConnection)
+        }
 
-            // edge 12: fin rcvpkt with correct n
-            RcvEvent::RecvPck(Some(rcvpkt), _)
-                if rcvpkt.notcorrupt()
-                    && rcvpkt.n() != self.state().sndpkt().n()
-                    && rcvpkt.is_FIN() =>
-            {
-                println!("Connection Closed after {} Bytes", ctx.get_data_counter());
-                let sndpkt = ctx.make_pkt(rcvpkt.n(), Flag::FINACK)?;
-                ctx.udt_send(&sndpkt)?;
-                ctx.stop_connection_timer()?;
-                ctx.close_file()?;
-                Ok(self.to_wait_for_connection().wrap())
-            }
+        // edge 12: fin rcvpkt with correct n
+        RcvEvent::RecvPck(Some(rcvpkt), _)
+            if rcvpkt.notcorrupt() && rcvpkt.n() != sndpkt.n() && rcvpkt.is_FIN() =>
+        {
+            tracing::info!(bytes = ctx.get_data_counter(), "connection closed");
+            let sndpkt = ctx.make_pkt(rcvpkt.n(), Flag::FINACK)?;
+            ctx.udt_send(&sndpkt)?;
+            ctx.stop_connection_timer()?;
+            ctx.close_file()?;
+            Ok(RcvState::End)
+        }
 
-            // ..undefined
-            _ => unreachable!("undefined transisions"),
+        // ..undefined, e.g. an ack or rst of a confused sender
+        _ => {
+            ctx.protocol_violation("wait_for_pkt", &e)?;
+            Ok(stay(sndpkt, syn))
         }
     }
 }
@@ -1,57 +1,166 @@
-use super::fsm::FsmStateWrapper;
-use super::fsm::FsmWrap;
-use std::{io, time::Duration, time::Instant};
+use std::{task::Poll, time::Duration};
 
+use crate::error::{Result, SecSnailError};
+
+#[cfg(feature = "async")]
+use super::fsm::AsyncProtocolEventSource;
+use super::fsm::ProtocolEventSource;
 use super::fsm::ProtocolIoContext;
 use super::fsm::SndEvent;
 use super::fsm::SndFsm;
-use super::fsm::StateRouter;
+use super::fsm::SndState;
 
 pub fn run_snd_fsm_loop(
-    ctx: &mut impl ProtocolIoContext,
+    ctx: &mut (impl ProtocolIoContext + ProtocolEventSource),
     max_retransmits: u8,
-) -> io::Result<(usize, Duration)> {
+) -> Result<(usize, Duration)> {
     // connection handshake via SYN and file name pkt
-    let mut cur_fsm_wrap = SndFsm::init(max_retransmits).wrap();
+    let mut cur_fsm = SndFsm::init(max_retransmits);
 
-    let start_time = Instant::now();
+    let start_time = ctx.now();
 
-    // run fsm
+    // run fsm, a blocking ctx never reports a pending event
     loop {
-        if let FsmStateWrapper::End = cur_fsm_wrap {
+        let (next_fsm, progress) = poll_snd_fsm(cur_fsm, ctx)?;
+        if progress.is_ready() {
             break;
         }
+        cur_fsm = next_fsm;
+    }
 
-        let event = get_next_event_for_current_state(&mut cur_fsm_wrap, ctx)?;
+    Ok((
+        ctx.get_data_counter(),
+        ctx.now().saturating_duration_since(start_time),
+    ))
+}
 
-        cur_fsm_wrap = match cur_fsm_wrap {
-            FsmStateWrapper::Start(fsm) => fsm.goto(event, ctx)?,
-            FsmStateWrapper::Wait(fsm) => fsm.goto(event, ctx)?,
-            FsmStateWrapper::Send(fsm) => fsm.goto(event, ctx)?,
+/// run fsm until the end state is reached or the ctx would block
+///
+/// # Return
+/// the fsm to resume from and `Poll::Ready` once the transfer is done
+pub fn poll_snd_fsm(
+    mut cur_fsm: SndFsm,
+    ctx: &mut (impl ProtocolIoContext + ProtocolEventSource),
+) -> Result<(SndFsm, Poll<()>)> {
+    loop {
+        if cur_fsm.is_end() {
+            return Ok((cur_fsm, Poll::Ready(())));
+        }
+        check_deadline(ctx)?;
 
-            // end state gets handled above
-            FsmStateWrapper::End => unreachable!(),
+        let event = match get_next_event_for_current_state(&cur_fsm, ctx) {
+            Err(e) if e.is_would_block() => return Ok((cur_fsm, Poll::Pending)),
+            r => r?,
         };
+
+        cur_fsm = handle_event(cur_fsm, event, ctx)?;
     }
+}
 
-    Ok((ctx.get_data_counter(), start_time.elapsed()))
+/// handle the next event of the fsm only, so a driver can pause the
+/// transfer between two events, e.g. to send a more urgent file
+///
+/// # Return
+/// like `poll_snd_fsm`, `Poll::Pending` after the event was handled
+pub fn step_snd_fsm(
+    cur_fsm: SndFsm,
+    ctx: &mut (impl ProtocolIoContext + ProtocolEventSource),
+) -> Result<(SndFsm, Poll<()>)> {
+    if cur_fsm.is_end() {
+        return Ok((cur_fsm, Poll::Ready(())));
+    }
+    check_deadline(ctx)?;
+    let event = match get_next_event_for_current_state(&cur_fsm, ctx) {
+        Err(e) if e.is_would_block() => return Ok((cur_fsm, Poll::Pending)),
+        r => r?,
+    };
+    let next = handle_event(cur_fsm, event, ctx)?;
+    let progress = match next.is_end() {
+        true => Poll::Ready(()),
+        false => Poll::Pending,
+    };
+    Ok((next, progress))
 }
 
-fn get_next_event_for_current_state(
-    wrapper: &mut FsmStateWrapper,
+#[cfg(feature = "async")]
+pub async fn run_snd_fsm_loop_async(
+    ctx: &mut (impl ProtocolIoContext + AsyncProtocolEventSource),
+    max_retransmits: u8,
+) -> Result<(usize, Duration)> {
+    // connection handshake via SYN and file name pkt
+    let mut cur_fsm = SndFsm::init(max_retransmits);
+
+    let start_time = ctx.now();
+
+    // run fsm
+    loop {
+        if !cur_fsm.is_end() {
+            check_deadline(ctx)?;
+        }
+        let event = match cur_fsm.state() {
+            SndState::End => break,
+
+            // awaiting event or timeout
+            SndState::Wait { .. } => ctx.wait_for_ack_or_timeout().await?,
+
+            SndState::Send { .. } => SndEvent::DataAvailable(ctx.data_available_paced().await?),
+            SndState::Start => SndEvent::InitSYN,
+        };
+
+        cur_fsm = handle_event(cur_fsm, event, ctx)?;
+    }
+
+    Ok((
+        ctx.get_data_counter(),
+        ctx.now().saturating_duration_since(start_time),
+    ))
+}
+
+fn handle_event(
+    cur_fsm: SndFsm,
+    event: SndEvent,
     ctx: &mut impl ProtocolIoContext,
-) -> io::Result<SndEvent> {
-    match wrapper {
+) -> Result<SndFsm> {
+    ctx.on_event(&event);
+    let (state, event_name) = (cur_fsm.name(), event.name());
+    let pck = event.packet();
+    let _span = tracing::debug_span!(
+        "transition",
+        state = cur_fsm.name(),
+        event = event.name(),
+        pkt = pck.map(tracing::field::display),
+    )
+    .entered();
+
+    let next = cur_fsm.goto(event, ctx)?;
+    tracing::trace!(next = next.name(), "state changed");
+    ctx.on_transition(state, event_name, next.name());
+    Ok(next)
+}
+
+/// fails once the transfer deadline of the ctx is reached
+fn check_deadline(ctx: &impl ProtocolIoContext) -> Result<()> {
+    match ctx.deadline() {
+        Some(deadline) if ctx.now() >= deadline => Err(SecSnailError::DeadlineExceeded),
+        _ => Ok(()),
+    }
+}
+
+fn get_next_event_for_current_state(
+    fsm: &SndFsm,
+    ctx: &mut (impl ProtocolIoContext + ProtocolEventSource),
+) -> Result<SndEvent> {
+    match fsm.state() {
         // blocking until event or timeout occured
-        FsmStateWrapper::Wait(_) => ctx.wait_for_ack_or_timeout(),
+        SndState::Wait { .. } => ctx.wait_for_ack_or_timeout(),
 
         // check if data ist available
-        FsmStateWrapper::Send(_) => Ok(SndEvent::DataAvailable(ctx.data_available()?)),
+        SndState::Send { .. } => Ok(SndEvent::DataAvailable(ctx.data_available()?)),
 
         // init event for handshake
-        FsmStateWrapper::Start(_) => Ok(SndEvent::InitSYN),
+        SndState::Start => Ok(SndEvent::InitSYN),
 
-        FsmStateWrapper::End => {
+        SndState::End => {
             unreachable!("Never call Event on end state in snd fsm");
         }
     }
@@ -1,8 +1,12 @@
-use std::io;
+use std::time::Instant;
+
+use crate::error::{Result, SecSnailError};
+use crate::meta::decode_resume_offset;
 
 use super::super::pck::Flag;
 
 use super::super::pck::Packet;
+use super::{send, start, wait};
 
 pub enum SndEvent {
     InitSYN,
@@ -11,210 +15,365 @@ pub enum SndEvent {
     DataAvailable(bool),
 }
 
-// start
-#[derive(Clone)]
-pub struct SndStateStart {
-    n: u8,
-}
-
-impl SndStateStart {
-    pub fn new(n: u8) -> Self {
-        Self { n }
-    }
-    pub fn n(&self) -> u8 {
-        self.n
-    }
-}
-
-// wait
-#[derive(Clone)]
-pub struct SndStateWait {
-    n: u8,
-    retransmit_counter: u8,
-    /// last sent packet
-    sndpkt: Packet,
-}
-
-impl SndStateWait {
-    pub fn new(n: u8, sndpkt: Packet) -> Self {
-        Self {
-            n,
-            retransmit_counter: 0,
-            sndpkt,
+impl SndEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SndEvent::InitSYN => "init_syn",
+            SndEvent::Timeout => "timeout",
+            SndEvent::RecvPck(Some(_)) => "rcv_pkt",
+            SndEvent::RecvPck(None) => "rcv_corrupt",
+            SndEvent::DataAvailable(true) => "data_available",
+            SndEvent::DataAvailable(false) => "no_data_available",
         }
     }
-    pub fn n(&self) -> u8 {
-        self.n
-    }
-
-    pub fn retransmit_counter(&self) -> u8 {
-        self.retransmit_counter
-    }
 
-    pub fn sndpkt(&self) -> &Packet {
-        &self.sndpkt
+    pub fn packet(&self) -> Option<&Packet> {
+        match self {
+            SndEvent::RecvPck(pck) => pck.as_ref(),
+            _ => None,
+        }
     }
 }
 
-// send
+/// state of the sending side, every variant carries the data of its state
 #[derive(Clone)]
-pub struct SndStateSend {
-    pub n: u8,
+pub enum SndState {
+    Start,
+    /// waiting for the ack of `sndpkt` with sequence number `n`
+    Wait {
+        n: u8,
+        retransmit_counter: u8,
+        /// last sent packet
+        sndpkt: Packet,
+    },
+    /// ready to send the next packet with sequence number `n`
+    Send {
+        n: u8,
+    },
+    End,
 }
 
-impl SndStateSend {
-    pub fn new(n: u8) -> Self {
-        Self { n }
-    }
-    pub fn n(&self) -> u8 {
-        self.n
+impl SndState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SndState::Start => "start",
+            SndState::Wait { .. } => "wait",
+            SndState::Send { .. } => "send",
+            SndState::End => "end",
+        }
     }
 }
 
-// end
-#[derive(Clone)]
-pub struct SndStateEnd;
-
-// fsm
-#[derive(Clone, Copy)]
-struct Config {
+pub struct SndFsm {
+    state: SndState,
     max_retransmits: u8,
 }
 
-impl Config {
-    pub fn new(max_retransmits: u8) -> Self {
-        Self { max_retransmits }
-    }
-}
-
-pub struct SndFsm<State: Clone> {
-    _state: State,
-    _config: Config,
-}
-
-impl<State: Clone> SndFsm<State> {
-    pub fn new(state: State, max_retransmits: u8) -> Self {
+impl SndFsm {
+    /// fsm start entry point
+    pub fn init(max_retransmits: u8) -> SndFsm {
         SndFsm {
-            _state: state,
-            _config: Config::new(max_retransmits),
+            state: SndState::Start,
+            max_retransmits,
         }
     }
 
-    pub fn max_retransmits(&self) -> u8 {
-        self._config.max_retransmits
+    pub fn state(&self) -> &SndState {
+        &self.state
     }
 
-    /// immutable reference
-    pub fn state(&self) -> &State {
-        &self._state
+    pub fn name(&self) -> &'static str {
+        self.state.name()
     }
 
-    pub fn to_send(&self, n: u8) -> SndFsm<SndStateSend> {
-        SndFsm {
-            _state: SndStateSend::new(n),
-            _config: self._config,
-        }
+    pub fn is_end(&self) -> bool {
+        matches!(self.state, SndState::End)
     }
 
-    pub fn to_wait(&self, n: u8, sndpkt: Packet) -> SndFsm<SndStateWait> {
-        SndFsm {
-            _state: SndStateWait::new(n, sndpkt),
-            _config: self._config,
-        }
+    /// handle `e` in the current state, the transitions of every state are
+    /// in the module named after it
+    pub fn goto(self, e: SndEvent, ctx: &mut dyn ProtocolIoContext) -> Result<SndFsm> {
+        let state = match self.state {
+            SndState::Start => start::goto(e, ctx)?,
+            SndState::Send { n } => send::goto(n, e, ctx)?,
+            SndState::Wait {
+                n,
+                retransmit_counter,
+                sndpkt,
+            } => wait::goto(n, retransmit_counter, sndpkt, self.max_retransmits, e, ctx)?,
+
+            // end state gets handled by the driver loops
+            SndState::End => {
+                ctx.protocol_violation("end", &e)?;
+                SndState::End
+            }
+        };
+        Ok(SndFsm { state, ..self })
     }
+}
 
-    pub fn to_end(&self) -> SndFsm<SndStateEnd> {
-        SndFsm {
-            _state: SndStateEnd,
-            _config: self._config,
-        }
-    }
+/// blocking event source of the driver loop
+pub trait ProtocolEventSource {
+    /// updates timer if timeout occured before re listening for incoming packet with udp socket
+    /// only accepts packets with configured recv_addr in ctx
+    fn wait_for_ack_or_timeout(&mut self) -> Result<SndEvent>; // Gibt ein FSM Event zurück (RecvAck, Timeout, Corrupt)
 }
 
-pub trait FsmWrap {
-    fn wrap(self) -> FsmStateWrapper;
+/// async event source of the async driver loop
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait AsyncProtocolEventSource {
+    /// only accepts packets with configured recv_addr in ctx
+    async fn wait_for_ack_or_timeout(&mut self) -> Result<SndEvent>;
+
+    /// like `ProtocolIoContext::data_available`, but waits out the gap
+    /// before the next data packet first
+    async fn data_available_paced(&mut self) -> Result<bool>;
 }
 
-// impl wrap on all states
-impl FsmWrap for SndFsm<SndStateStart> {
-    fn wrap(self) -> FsmStateWrapper {
-        FsmStateWrapper::Start(self)
+pub trait ProtocolIoContext {
+    fn data_available(&mut self) -> Result<bool>;
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet>;
+
+    /// create start_timer instant and set read timeout to timeout Duration
+    fn start_timer(&mut self) -> Result<()>;
+    fn stop_timer(&mut self) -> Result<()>;
+    fn udt_send(&mut self, pck: &Packet) -> Result<()>;
+
+    /// Track amount of data transmitted
+    fn get_data_counter(&self) -> usize;
+    fn increase_data_counter(&mut self, n: usize);
+
+    /// wall-clock limit of the whole transfer, checked by the driver loops
+    fn deadline(&self) -> Option<Instant>;
+
+    /// handle the payload of the ack of the syn, an offset to resume from
+    /// if resume was offered or the verdict of a verifying syn
+    fn syn_acked(&mut self, payload: &[u8]) -> Result<()> {
+        match decode_resume_offset(payload)? {
+            0 => Ok(()),
+            offset => Err(SecSnailError::ProtocolViolation(format!(
+                "receiver resumed at {offset} but no resume was offered"
+            ))),
+        }
     }
-}
 
-impl FsmWrap for SndFsm<SndStateWait> {
-    fn wrap(self) -> FsmStateWrapper {
-        FsmStateWrapper::Wait(self)
+    /// handle the payload of the finack, the entries a receiver misses if
+    /// the transfer was the manifest of a sync
+    fn fin_acked(&mut self, _payload: &[u8]) -> Result<()> {
+        Ok(())
     }
-}
 
-impl FsmWrap for SndFsm<SndStateSend> {
-    fn wrap(self) -> FsmStateWrapper {
-        FsmStateWrapper::Send(self)
+    /// clock of all timers and the deadline, virtual in a simulation
+    fn now(&self) -> Instant {
+        Instant::now()
     }
-}
 
-impl FsmWrap for SndFsm<SndStateEnd> {
-    fn wrap(self) -> FsmStateWrapper {
-        FsmStateWrapper::End
+    /// called by the drivers with every event before the fsm handles it
+    fn on_event(&mut self, _event: &SndEvent) {}
+
+    /// called by the drivers after the fsm took the edge from `state` on
+    /// `event` to `next`
+    fn on_transition(&mut self, _state: &'static str, _event: &'static str, _next: &'static str) {}
+
+    /// `event` has no transition in `state`, the fsm stays in its state
+    /// unless this fails, which aborts the transfer
+    fn protocol_violation(&mut self, state: &'static str, event: &SndEvent) -> Result<()> {
+        Err(SecSnailError::undefined_transition(
+            state,
+            event.name(),
+            event.packet(),
+        ))
     }
 }
 
-// inc retransmit
-impl SndFsm<SndStateWait> {
-    pub fn inc_retransmit(&self) -> Self {
-        let s = SndStateWait {
-            retransmit_counter: self.state().retransmit_counter() + 1,
-            ..self.state().clone()
-        };
-        SndFsm::new(s, self.max_retransmits())
+pub fn next_n(n: u8) -> u8 {
+    match n {
+        0 => 1,
+        _ => 0,
     }
 }
 
-// fsm entriy point
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::u8_to_bool;
+
+    /// records sent packets, never blocks
+    #[derive(Default)]
+    struct MockCtx {
+        data_available: bool,
+        sent: Vec<Packet>,
+        timer_running: bool,
+        data_counter: usize,
+    }
 
-impl SndFsm<SndStateStart> {
-    // Dies ist der "Einstiegspunkt" in die State Machine
-    /// fsm start entry point
-    pub fn init(max_retransmits: u8) -> SndFsm<SndStateStart> {
-        SndFsm::new(SndStateStart::new(0), max_retransmits)
+    impl ProtocolIoContext for MockCtx {
+        fn data_available(&mut self) -> Result<bool> {
+            Ok(self.data_available)
+        }
+        fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+            let payload = match f {
+                Flag::Data => vec![7; 10],
+                _ => vec![],
+            };
+            Ok(Packet::new(u8_to_bool(seq_n), f, payload)?)
+        }
+        fn start_timer(&mut self) -> Result<()> {
+            self.timer_running = true;
+            Ok(())
+        }
+        fn stop_timer(&mut self) -> Result<()> {
+            self.timer_running = false;
+            Ok(())
+        }
+        fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+            self.sent.push(pck.clone());
+            Ok(())
+        }
+        fn get_data_counter(&self) -> usize {
+            self.data_counter
+        }
+        fn increase_data_counter(&mut self, n: usize) {
+            self.data_counter += n;
+        }
+        fn deadline(&self) -> Option<Instant> {
+            None
+        }
     }
-}
 
-pub enum FsmStateWrapper {
-    Start(SndFsm<SndStateStart>),
-    Wait(SndFsm<SndStateWait>),
-    Send(SndFsm<SndStateSend>),
-    End,
-}
+    fn pkt(n: u8, f: Flag) -> Packet {
+        Packet::new(u8_to_bool(n), f, vec![]).unwrap()
+    }
 
-pub trait StateRouter {
-    // Gibt immer den Wrapper-Typ zurück, egal wie der tatsächliche Folgezustand heißt.
-    // &mut dyn ProtocolIoContext muss dabei sein, um I/O zu ermöglichen.
-    fn goto(self, e: SndEvent, ctx: &mut dyn ProtocolIoContext) -> io::Result<FsmStateWrapper>;
-}
+    fn fsm(state: SndState) -> SndFsm {
+        SndFsm {
+            state,
+            max_retransmits: 2,
+        }
+    }
 
-pub trait ProtocolIoContext {
-    /// updates timer if timeout occured before re listening for incoming packet with udp socket
-    /// only accepts packets with configured recv_addr in ctx
-    fn wait_for_ack_or_timeout(&mut self) -> io::Result<SndEvent>; // Gibt ein FSM Event zurück (RecvAck, Timeout, Corrupt)
+    fn wait(n: u8, retransmit_counter: u8, sndpkt: Packet) -> SndFsm {
+        fsm(SndState::Wait {
+            n,
+            retransmit_counter,
+            sndpkt,
+        })
+    }
 
-    fn data_available(&mut self) -> io::Result<bool>;
-    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> io::Result<Packet>;
+    fn rcv(n: u8, f: Flag) -> SndEvent {
+        SndEvent::RecvPck(Some(pkt(n, f)))
+    }
 
-    /// create start_timer instant and set read timeout to timeout Duration
-    fn start_timer(&mut self) -> io::Result<()>;
-    fn stop_timer(&mut self) -> io::Result<()>;
-    fn udt_send(&mut self, pck: &Packet) -> io::Result<()>;
+    #[test]
+    fn transition_table() {
+        // state, event, data available, next state, sent packet
+        let table: Vec<(SndFsm, SndEvent, bool, &str, Option<Flag>)> = vec![
+            (
+                SndFsm::init(2),
+                SndEvent::InitSYN,
+                false,
+                "wait",
+                Some(Flag::SYN),
+            ),
+            (
+                fsm(SndState::Send { n: 1 }),
+                SndEvent::DataAvailable(true),
+                true,
+                "wait",
+                Some(Flag::Data),
+            ),
+            (
+                fsm(SndState::Send { n: 1 }),
+                SndEvent::DataAvailable(false),
+                false,
+                "wait",
+                Some(Flag::FIN),
+            ),
+            (
+                fsm(SndState::Send { n: 1 }),
+                SndEvent::RecvPck(None),
+                true,
+                "send",
+                None,
+            ),
+            (
+                wait(0, 0, pkt(0, Flag::SYN)),
+                SndEvent::Timeout,
+                false,
+                "wait",
+                Some(Flag::SYN),
+            ),
+            (
+                wait(0, 0, pkt(0, Flag::SYN)),
+                rcv(0, Flag::ACK),
+                false,
+                "send",
+                None,
+            ),
+            (
+                wait(1, 0, pkt(1, Flag::Data)),
+                rcv(0, Flag::ACK),
+                true,
+                "wait",
+                None,
+            ),
+            (
+                wait(1, 0, pkt(1, Flag::Data)),
+                SndEvent::RecvPck(None),
+                true,
+                "wait",
+                None,
+            ),
+            (
+                wait(1, 0, pkt(1, Flag::FIN)),
+                rcv(1, Flag::FINACK),
+                false,
+                "end",
+                None,
+            ),
+        ];
+        for (fsm, event, data_available, next, sent) in table {
+            let from = fsm.name();
+            let mut ctx = MockCtx {
+                data_available,
+                ..MockCtx::default()
+            };
+            let event_name = event.name();
+            let to = fsm.goto(event, &mut ctx).unwrap();
+            assert_eq!(to.name(), next, "{from} on {event_name}");
+            assert_eq!(
+                ctx.sent.last().map(Packet::flag),
+                sent,
+                "{from} on {event_name}"
+            );
+        }
+    }
 
-    /// Track amount of data transmitted
-    fn get_data_counter(&self) -> usize;
-    fn increase_data_counter(&mut self, n: usize);
-}
+    #[test]
+    fn retransmits_until_limit() {
+        let mut ctx = MockCtx::default();
+        let next = wait(0, 1, pkt(0, Flag::SYN))
+            .goto(SndEvent::Timeout, &mut ctx)
+            .unwrap();
+        assert!(matches!(
+            next.state(),
+            SndState::Wait {
+                retransmit_counter: 2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            next.goto(SndEvent::Timeout, &mut ctx),
+            Err(SecSnailError::MaxRetransmitsExceeded)
+        ));
+        assert_eq!(ctx.sent.len(), 1);
+    }
 
-pub fn next_n(n: u8) -> u8 {
-    match n {
-        0 => 1,
-        _ => 0,
+    #[test]
+    fn rst_rejects() {
+        let mut ctx = MockCtx::default();
+        let r = wait(0, 0, pkt(0, Flag::SYN)).goto(rcv(0, Flag::RST), &mut ctx);
+        assert!(matches!(r, Err(SecSnailError::Rejected(_))));
     }
 }
@@ -1,45 +1,44 @@
-use std::io;
+use crate::error::Result;
 
 use crate::{
-    fsm_send::fsm::{FsmWrap, SndEvent, SndFsm, SndStateSend},
-    pck::Flag,
+    fsm_send::fsm::{ProtocolIoContext, SndEvent, SndState},
+    pck::{Flag, Packet},
 };
 
-use super::{
-    fsm::{FsmStateWrapper, StateRouter},
-    *,
-};
-
-impl StateRouter for SndFsm<SndStateSend> {
-    fn goto(
-        self,
-        e: SndEvent,
-        ctx: &mut dyn fsm::ProtocolIoContext,
-    ) -> io::Result<FsmStateWrapper> {
-        let n = self.state().n();
-        match e {
-            // edge 4: data available
-            SndEvent::DataAvailable(true) => {
-                let sndpck = ctx.make_pkt(n, Flag::Data)?;
-                ctx.increase_data_counter(sndpck.payload().len());
-                ctx.udt_send(&sndpck)?;
-                ctx.start_timer()?;
-                Ok(self.to_wait(n, sndpck).wrap())
-            }
+pub(super) fn goto(n: u8, e: SndEvent, ctx: &mut dyn ProtocolIoContext) -> Result<SndState> {
+    match e {
+        // edge 4: data available
+        SndEvent::DataAvailable(true) => {
+            let sndpck = ctx.make_pkt(n, Flag::Data)?;
+            ctx.increase_data_counter(sndpck.payload().len());
+            ctx.udt_send(&sndpck)?;
+            ctx.start_timer()?;
+            Ok(wait_for(n, sndpck))
+        }
 
-            // edge 5: file end / no data available
-            SndEvent::DataAvailable(false) => {
-                let sndpck = ctx.make_pkt(n, Flag::FIN)?;
-                ctx.udt_send(&sndpck)?;
-                ctx.start_timer()?;
-                Ok(self.to_wait(n, sndpck).wrap())
-            }
+        // edge 5: file end / no data available
+        SndEvent::DataAvailable(false) => {
+            let sndpck = ctx.make_pkt(n, Flag::FIN)?;
+            ctx.udt_send(&sndpck)?;
+            ctx.start_timer()?;
+            Ok(wait_for(n, sndpck))
+        }
 
-            // edge 6: rcv pck
-            SndEvent::RecvPck(_) => Ok(self.wrap()),
+        // edge 6: rcv pck
+        SndEvent::RecvPck(_) => Ok(SndState::Send { n }),
 
-            // ..undefined
-            _ => unreachable!("undefined transisions"),
+        // ..undefined
+        _ => {
+            ctx.protocol_violation("send", &e)?;
+            Ok(SndState::Send { n })
         }
     }
 }
+
+fn wait_for(n: u8, sndpkt: Packet) -> SndState {
+    SndState::Wait {
+        n,
+        retransmit_counter: 0,
+        sndpkt,
+    }
+}
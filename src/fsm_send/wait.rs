@@ -1,59 +1,81 @@
-use std::io;
-
-use crate::fsm_send::fsm::{
-    FsmStateWrapper, FsmWrap, SndEvent, SndFsm, SndStateWait, StateRouter, next_n,
-};
-
-use super::*;
-
-impl StateRouter for SndFsm<SndStateWait> {
-    fn goto(
-        self,
-        e: SndEvent,
-        ctx: &mut dyn fsm::ProtocolIoContext,
-    ) -> io::Result<FsmStateWrapper> {
-        let n = self.state().n();
-        match e {
-            // edge 2a: timeout < max_retrans
-            SndEvent::Timeout if self.state().retransmit_counter() < self.max_retransmits() => {
-                ctx.udt_send(self.state().sndpkt())?;
-                ctx.start_timer()?;
-                Ok(self.inc_retransmit().wrap())
-            }
+use crate::error::{Result, SecSnailError};
 
-            // edge 2b: timeout > max_retrans
-            SndEvent::Timeout => Ok(self.to_end().wrap()),
+use crate::fsm_send::fsm::{ProtocolIoContext, SndEvent, SndState, next_n};
+use crate::pck::Packet;
 
-            // edge 3: valid ack
-            SndEvent::RecvPck(Some(rcvpkt))
-                if rcvpkt.notcorrupt() && rcvpkt.is_ACK() && n == rcvpkt.n() =>
-            {
-                ctx.stop_timer()?;
-                Ok(self.to_send(next_n(n)).wrap())
-            }
+pub(super) fn goto(
+    n: u8,
+    retransmit_counter: u8,
+    sndpkt: Packet,
+    max_retransmits: u8,
+    e: SndEvent,
+    ctx: &mut dyn ProtocolIoContext,
+) -> Result<SndState> {
+    let stay = |sndpkt| SndState::Wait {
+        n,
+        retransmit_counter,
+        sndpkt,
+    };
+    match e {
+        // edge 2a: timeout < max_retrans
+        SndEvent::Timeout if retransmit_counter < max_retransmits => {
+            ctx.udt_send(&sndpkt)?;
+            ctx.start_timer()?;
+            Ok(SndState::Wait {
+                n,
+                retransmit_counter: retransmit_counter + 1,
+                sndpkt,
+            })
+        }
 
-            // edge 7: recv fin ack and not data available
-            SndEvent::RecvPck(Some(rcvpkt))
-                if rcvpkt.notcorrupt()
-                    && rcvpkt.is_FINACK()
-                    && n == rcvpkt.n()
-                    && !ctx.data_available()? =>
-            {
-                Ok(self.to_end().wrap())
+        // edge 2b: timeout > max_retrans
+        SndEvent::Timeout => Err(SecSnailError::MaxRetransmitsExceeded),
+
+        // edge 3: valid ack
+        SndEvent::RecvPck(Some(rcvpkt))
+            if rcvpkt.notcorrupt() && rcvpkt.is_ACK() && n == rcvpkt.n() =>
+        {
+            ctx.stop_timer()?;
+            // ack of the syn, carries an offset if the receiver resumes
+            if sndpkt.is_SYN() {
+                ctx.syn_acked(rcvpkt.payload())?;
             }
+            Ok(SndState::Send { n: next_n(n) })
+        }
 
-            // corrupt packet (could not be parsed)
-            SndEvent::RecvPck(None) => Ok(self.wrap()),
+        // edge 7: recv fin ack and not data available
+        SndEvent::RecvPck(Some(rcvpkt))
+            if rcvpkt.notcorrupt()
+                && rcvpkt.is_FINACK()
+                && n == rcvpkt.n()
+                && !ctx.data_available()? =>
+        {
+            ctx.fin_acked(rcvpkt.payload())?;
+            Ok(SndState::End)
+        }
 
-            // edge 8: corrupt/wrong ack -> wait for timeout from driver loop
-            SndEvent::RecvPck(Some(rcvpkt))
-                if rcvpkt.corrupt() || (rcvpkt.is_ACK() && n != rcvpkt.n()) =>
-            {
-                Ok(self.wrap())
-            }
+        // transfer rejected by receiver
+        SndEvent::RecvPck(Some(rcvpkt)) if rcvpkt.notcorrupt() && rcvpkt.is_RST() => {
+            ctx.stop_timer()?;
+            Err(SecSnailError::Rejected(
+                String::from_utf8_lossy(rcvpkt.payload()).into_owned(),
+            ))
+        }
+
+        // corrupt packet (could not be parsed)
+        SndEvent::RecvPck(None) => Ok(stay(sndpkt)),
+
+        // edge 8: corrupt/wrong ack -> wait for timeout from driver loop
+        SndEvent::RecvPck(Some(rcvpkt))
+            if rcvpkt.corrupt() || (rcvpkt.is_ACK() && n != rcvpkt.n()) =>
+        {
+            Ok(stay(sndpkt))
+        }
 
-            // ..undefined
-            _ => unreachable!("undefined transition"),
+        // ..undefined, e.g. a syn or data of a confused peer
+        _ => {
+            ctx.protocol_violation("wait", &e)?;
+            Ok(stay(sndpkt))
         }
     }
 }
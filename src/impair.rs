@@ -0,0 +1,194 @@
+//! Simulation of an unreliable channel on the sending side.
+//!
+//! Every datagram leaving a socket passes through an `Impairment` which
+//! may drop, corrupt (single bit flip), duplicate or delay it.
+//!
+//! Losses are either independent with `loss_p`, or come in bursts after
+//! the two-state Gilbert-Elliott model.
+
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+/// two-state burst loss model, the channel alternates between a good
+/// and a bad state with its own loss probability each
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GilbertElliott {
+    /// probability of the transition from the good into the bad state
+    pub p_good_bad: f64,
+    /// probability of the transition from the bad into the good state
+    pub p_bad_good: f64,
+    /// loss probability in the good state
+    pub loss_good: f64,
+    /// loss probability in the bad state
+    pub loss_bad: f64,
+}
+
+impl GilbertElliott {
+    /// simple Gilbert model, every packet in the bad state gets lost
+    pub fn new(p_good_bad: f64, p_bad_good: f64) -> Self {
+        Self {
+            p_good_bad,
+            p_bad_good,
+            loss_good: 0.0,
+            loss_bad: 1.0,
+        }
+    }
+
+    pub(crate) fn probabilities(&self) -> [(&'static str, f64); 4] {
+        [
+            ("p_good_bad", self.p_good_bad),
+            ("p_bad_good", self.p_bad_good),
+            ("loss_good", self.loss_good),
+            ("loss_bad", self.loss_bad),
+        ]
+    }
+}
+
+/// probabilities of the simulated unreliable channel
+#[derive(Debug, Default)]
+pub struct Impairment {
+    /// independent loss, ignored if `burst` is set
+    pub loss_p: f64,
+    pub burst: Option<GilbertElliott>,
+    /// burst model is in its bad state
+    pub in_bad_state: AtomicBool,
+    pub error_p: f64,
+    pub dup_p: f64,
+    /// fixed delay of every datagram
+    pub delay: Duration,
+    /// upper bound of a uniformly distributed delay on top of `delay`
+    pub jitter: Duration,
+    /// drawn from instead of the thread rng once seeded
+    pub rng: Option<Mutex<StdRng>>,
+}
+
+impl Impairment {
+    /// impair the datagram in a reusable send buffer, a bit error is
+    /// flipped in place
+    ///
+    /// # Return
+    /// how often to put `pkt` on the wire, 0 if it got lost
+    pub fn apply_in_place(&self, pkt: &mut [u8]) -> usize {
+        match &self.rng {
+            Some(rng) => self.apply_in_place_with(&mut *rng.lock().unwrap(), pkt),
+            None => self.apply_in_place_with(&mut rand::rng(), pkt),
+        }
+    }
+
+    /// delay of the next datagram, see `sample_delay`
+    pub fn next_delay(&self) -> Duration {
+        match &self.rng {
+            Some(rng) => self.sample_delay(&mut *rng.lock().unwrap()),
+            None => self.sample_delay(&mut rand::rng()),
+        }
+    }
+
+    /// make all following decisions reproducible, the burst model
+    /// starts over in its good state
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = Some(Mutex::new(StdRng::seed_from_u64(seed)));
+        self.in_bad_state = false.into();
+    }
+
+    /// delay of a single datagram, `delay` plus a random share of `jitter`
+    pub fn sample_delay<R: Rng + ?Sized>(&self, rng: &mut R) -> Duration {
+        let jitter = match self.jitter.is_zero() {
+            true => Duration::ZERO,
+            false => Duration::from_nanos(rng.random_range(0..=self.jitter.as_nanos() as u64)),
+        };
+        self.delay + jitter
+    }
+
+    /// datagrams to put on the wire, empty if the packet got lost, every
+    /// decision is drawn from `rng`
+    pub fn apply_with<R: Rng + ?Sized>(&self, rng: &mut R, pkt: &[u8]) -> Vec<Vec<u8>> {
+        let mut pkt = pkt.to_vec();
+        let copies = self.apply_in_place_with(rng, &mut pkt);
+        vec![pkt; copies]
+    }
+
+    /// like `apply_in_place`, but every decision is drawn from `rng`
+    pub fn apply_in_place_with<R: Rng + ?Sized>(&self, rng: &mut R, pkt: &mut [u8]) -> usize {
+        // Simulate Packet loss
+        if self.lose(rng) {
+            return 0;
+        }
+
+        // Simulate Packet Error
+        if rng.random_bool(self.error_p) {
+            let mask: u8 = 1 << rng.random_range(0..8);
+            let l = pkt.len();
+            pkt[rng.random_range(0..l)] ^= mask;
+        }
+
+        // Simulate Packet Duplication
+        match rng.random_bool(self.dup_p) {
+            true => 2,
+            false => 1,
+        }
+    }
+
+    fn lose<R: Rng + ?Sized>(&self, rng: &mut R) -> bool {
+        let Some(ge) = &self.burst else {
+            return rng.random_bool(self.loss_p);
+        };
+        let bad = self.in_bad_state.load(Ordering::Relaxed);
+        let lost = rng.random_bool(if bad { ge.loss_bad } else { ge.loss_good });
+        let switch = rng.random_bool(if bad { ge.p_bad_good } else { ge.p_good_bad });
+        self.in_bad_state.store(bad ^ switch, Ordering::Relaxed);
+        lost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_decisions_repeat() {
+        let run = || {
+            let mut impairment = Impairment {
+                loss_p: 0.3,
+                error_p: 0.3,
+                dup_p: 0.3,
+                ..Impairment::default()
+            };
+            impairment.seed(42);
+            (0..200)
+                .map(|i| {
+                    let mut pkt = [i as u8; 8];
+                    (impairment.apply_in_place(&mut pkt), pkt)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn burst_losses() {
+        let impairment = Impairment {
+            burst: Some(GilbertElliott::new(0.02, 0.25)),
+            ..Impairment::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let lost: Vec<bool> = (0..100_000)
+            .map(|_| impairment.apply_with(&mut rng, &[0]).is_empty())
+            .collect();
+
+        // stationary loss rate p_gb / (p_gb + p_bg) of about 7.4 %
+        let rate = lost.iter().filter(|l| **l).count() as f64 / lost.len() as f64;
+        assert!((0.06..0.09).contains(&rate), "loss rate {rate}");
+
+        // mean burst length 1 / p_bg = 4
+        let bursts = lost.windows(2).filter(|w| !w[0] && w[1]).count();
+        let mean_burst = lost.iter().filter(|l| **l).count() as f64 / bursts as f64;
+        assert!((3.0..5.0).contains(&mean_burst), "mean burst {mean_burst}");
+    }
+}
@@ -13,8 +13,33 @@
 //! Art credit: Hayley Jane Wakenshaw
 //! ```
 
+// the drivers of the fsms only serve the sockets
+#![cfg_attr(not(feature = "net"), allow(dead_code))]
+
+#[cfg(feature = "net")]
+mod delta;
+#[cfg(feature = "net")]
+mod discovery;
+#[cfg(feature = "net")]
+mod disk;
+pub mod error;
+#[cfg(feature = "net")]
+mod feedback;
 mod fsm_recv;
 mod fsm_send;
-mod pck;
+#[cfg(feature = "net")]
+mod impair;
+#[cfg(feature = "net")]
+mod manifest;
+mod meta;
+pub mod pck;
+pub mod proto;
+#[cfg(feature = "net")]
+pub mod sim;
+#[cfg(feature = "net")]
 pub mod sock;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "net")]
+mod timestamp;
 mod util;
@@ -0,0 +1,166 @@
+//! Snail Transfer Protocol – manifest of a sync
+//!
+//! A syncing sender first transfers the manifest of the files below a
+//! directory, announced with the manifest flag of the SYN, a line per file:
+//!
+//! ```text
+//! <SHA-256 as hex> <size> <path relative to the directory, `/` separated>
+//! ```
+//!
+//! The receiver answers the FIN of the manifest with a FINACK carrying a
+//! bitmap of the entries it misses or holds a stale copy of, entry `i` is
+//! bit `i % 8` (least significant first) of byte `i / 8`. Only those files
+//! are sent afterwards, each announced under its relative path. A manifest
+//! holds at most `MAX_MANIFEST_ENTRIES`, a larger tree is synced in chunks.
+
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use crate::{
+    error::{Result, SecSnailError},
+    meta::{Digest, check_relative_path, file_digest, nfc, utf8_file_name},
+    pck::{HEADER_LEN, MAX_PAYLOAD_SIZE, Packet},
+};
+
+/// name a manifest is announced under, hidden if stored by a receiver
+/// which does not know manifests
+pub const MANIFEST_NAME: &str = ".secsnail-manifest";
+
+/// entries the bitmap in a single finack can answer
+pub const MAX_MANIFEST_ENTRIES: usize = 8 * (MAX_PAYLOAD_SIZE - HEADER_LEN);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// relative to the synced directory, `/` separated
+    pub path: String,
+    pub size: u64,
+    pub digest: Digest,
+}
+
+impl ManifestEntry {
+    /// entry of `file` below `dir`, bytes of its path which are no UTF-8 are
+    /// percent-encoded
+    pub fn of_file(dir: &Path, file: &Path) -> Result<ManifestEntry> {
+        let relative = file.strip_prefix(dir).unwrap_or(file);
+        let components: Vec<_> = relative.iter().map(utf8_file_name).collect();
+        let path = nfc(&components.join("/"));
+        check_relative_path(&path)?;
+        let file = File::open(file)?;
+        Ok(ManifestEntry {
+            path,
+            size: file.metadata()?.len(),
+            digest: file_digest(file)?,
+        })
+    }
+
+    /// whether there is no file at `path` with the size and SHA-256 of the
+    /// entry
+    pub fn is_missing_at(&self, path: &Path) -> bool {
+        match fs::metadata(path) {
+            Ok(m) if m.is_file() && m.len() == self.size => File::open(path)
+                .and_then(file_digest)
+                .map_or(true, |d| d != self.digest),
+            _ => true,
+        }
+    }
+}
+
+pub fn encode_manifest(entries: &[ManifestEntry]) -> Vec<u8> {
+    let mut buf = String::new();
+    for entry in entries {
+        let digest: String = entry.digest.iter().map(|b| format!("{b:02x}")).collect();
+        buf.push_str(&format!("{digest} {} {}\n", entry.size, entry.path));
+    }
+    buf.into_bytes()
+}
+
+pub fn decode_manifest(data: &[u8]) -> Result<Vec<ManifestEntry>> {
+    let corrupt = || SecSnailError::ProtocolViolation("malformed manifest".to_string());
+    let data = str::from_utf8(data).map_err(|_| corrupt())?;
+    let entries = data
+        .lines()
+        .map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let (Some(digest), Some(size), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(corrupt());
+            };
+            check_relative_path(path)?;
+            Ok(ManifestEntry {
+                path: nfc(path),
+                size: size.parse().map_err(|_| corrupt())?,
+                digest: decode_hex(digest).ok_or_else(corrupt)?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if entries.len() > MAX_MANIFEST_ENTRIES {
+        return Err(SecSnailError::ProtocolViolation(format!(
+            "manifest of {} entries exceeds {MAX_MANIFEST_ENTRIES}",
+            entries.len()
+        )));
+    }
+    Ok(entries)
+}
+
+fn decode_hex(s: &str) -> Option<Digest> {
+    let mut digest = [0; 32];
+    if s.len() != 2 * digest.len() {
+        return None;
+    }
+    for (i, b) in digest.iter_mut().enumerate() {
+        *b = u8::from_str_radix(s.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// payload of the finack of a manifest, `missing` holds a flag per entry
+pub fn encode_missing(missing: &[bool]) -> Vec<u8> {
+    let mut bitmap = vec![0; missing.len().div_ceil(8)];
+    for (i, _) in missing.iter().enumerate().filter(|(_, m)| **m) {
+        bitmap[i / 8] |= 1 << (i % 8);
+    }
+    bitmap
+}
+
+/// indices of the entries a receiver misses, of a manifest of `entries`
+pub fn decode_missing(payload: &[u8], entries: usize) -> Result<Vec<usize>> {
+    if payload.len() != entries.div_ceil(8) || payload.len() > Packet::max_pck_payload_size() {
+        return Err(SecSnailError::ProtocolViolation(format!(
+            "receiver answered a manifest of {entries} entries with {} bytes",
+            payload.len()
+        )));
+    }
+    Ok((0..entries)
+        .filter(|i| payload[i / 8] & (1 << (i % 8)) != 0)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_and_bitmap_roundtrip() {
+        let entries: Vec<_> = (0..10u8)
+            .map(|i| ManifestEntry {
+                path: format!("shells/snail {i}.txt"),
+                size: u64::from(i) * 1000,
+                digest: [i; 32],
+            })
+            .collect();
+        assert_eq!(
+            decode_manifest(&encode_manifest(&entries)).unwrap(),
+            entries
+        );
+        assert!(decode_manifest(b"00 1 snail.txt\n").is_err());
+
+        let missing: Vec<_> = (0..10).map(|i| i % 3 == 0).collect();
+        let bitmap = encode_missing(&missing);
+        assert_eq!(bitmap.len(), 2);
+        assert_eq!(decode_missing(&bitmap, 10).unwrap(), [0, 3, 6, 9]);
+        assert!(decode_missing(&[], 10).is_err());
+    }
+}
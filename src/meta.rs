@@ -0,0 +1,619 @@
+//! Snail Transfer Protocol – SYN metadata
+//!
+//! The payload of a SYN packet announces the transfer to the receiver.
+//!
+//! ```text
+//!  ┌───────────────────┬──────┬────────────────────┬───────────┬──────────────────┐
+//!  │ File Name (UTF-8) │ 0x00 │ File Size (64 bit) │ Flags (8) │ Digest (256 bit) │
+//!  └───────────────────┴──────┴────────────────────┴───────────┴──────────────────┘
+//! ```
+//!
+//! The separator and file size are optional, so a SYN holding only the
+//! file name (as sent by 1.0 senders) is still accepted. The flags are only
+//! sent by a sender which offers to resume, bit 0 set, which only verifies
+//! its file, bit 1 set and followed by the SHA-256 of the file, which
+//! asks for a trusted link without checksums, bit 2 set, which sends the
+//! manifest of a sync, bit 3 set, or which announces a path relative to
+//! the target directory of the receiver, bit 4 set and the components of
+//! the path separated by `/`. Bit 5 offers to stamp the data packets with
+//! the time they were sent, see `timestamp`. Bit 6 announces the signature
+//! or the missing blocks of a delta transfer, see `delta`.
+//!
+//! An empty file name announces an anonymous file, which the receiver
+//! names itself, e.g. by the time it arrived and its sender.
+//!
+//! A receiver holding a partial file of an interrupted transfer answers
+//! such a SYN with an ACK carrying the offset (64 bit) to resume from. A
+//! receiver accepting a trusted link or timestamps appends a flags byte
+//! with bit 0 or bit 1 set to the offset, which is sent even if it is 0. A verifying sender is
+//! answered with a single byte, 1 if the receiver holds an identical file,
+//! 0 if its file differs and 2 if it holds none.
+
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    fmt,
+    io::{self, Read},
+    str::FromStr,
+};
+
+use sha2::{Digest as _, Sha256};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::{Result, SecSnailError};
+
+const SEPARATOR: u8 = 0x00;
+const FLAG_RESUME: u8 = 0b0000_0001;
+const FLAG_VERIFY: u8 = 0b0000_0010;
+const FLAG_TRUSTED: u8 = 0b0000_0100;
+const FLAG_MANIFEST: u8 = 0b0000_1000;
+const FLAG_PATH: u8 = 0b0001_0000;
+const FLAG_TIMESTAMPS: u8 = 0b0010_0000;
+const FLAG_DELTA: u8 = 0b0100_0000;
+/// flags of the ack of a syn, the receiver accepted a trusted link or
+/// timestamps
+const ACK_FLAG_TRUSTED: u8 = 0b0000_0001;
+const ACK_FLAG_TIMESTAMPS: u8 = 0b0000_0010;
+
+/// SHA-256 of a file
+pub type Digest = [u8; 32];
+
+/// longest file name in bytes, the limit of common file systems
+pub const MAX_FILE_NAME_LEN: usize = 255;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SynMeta {
+    pub file_name: String,
+    pub file_size: Option<u64>,
+    /// sender can skip data the receiver already holds, requires `file_size`
+    pub resume: bool,
+    /// SHA-256 of a file which is only compared, not sent, requires `file_size`
+    pub digest: Option<Digest>,
+    /// skip checksums after the syn if the receiver agrees, requires `file_size`
+    pub trusted: bool,
+    /// the data is the manifest of a sync, see `manifest`, requires `file_size`
+    pub manifest: bool,
+    /// `file_name` is a relative path, see `check_relative_path`, requires `file_size`
+    pub relative_path: bool,
+    /// stamp data packets if the receiver agrees, see `timestamp`, requires `file_size`
+    pub timestamps: bool,
+    /// the data is the signature or the missing blocks of a file, see
+    /// `delta`, requires `file_size`
+    pub delta: bool,
+}
+
+impl SynMeta {
+    /// the name is announced in NFC, whatever form the file system of the
+    /// sender holds it in
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = nfc(&self.file_name).into_bytes();
+        if let Some(size) = self.file_size {
+            buf.push(SEPARATOR);
+            buf.extend_from_slice(&size.to_be_bytes());
+            let mut flags = 0;
+            if self.resume {
+                flags |= FLAG_RESUME;
+            }
+            if self.digest.is_some() {
+                flags |= FLAG_VERIFY;
+            }
+            if self.trusted {
+                flags |= FLAG_TRUSTED;
+            }
+            if self.manifest {
+                flags |= FLAG_MANIFEST;
+            }
+            if self.relative_path {
+                flags |= FLAG_PATH;
+            }
+            if self.timestamps {
+                flags |= FLAG_TIMESTAMPS;
+            }
+            if self.delta {
+                flags |= FLAG_DELTA;
+            }
+            if flags != 0 {
+                buf.push(flags);
+            }
+            if let Some(digest) = self.digest {
+                buf.extend_from_slice(&digest);
+            }
+        }
+        buf
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<SynMeta> {
+        let (name, size) = match payload.iter().position(|b| *b == SEPARATOR) {
+            Some(i) => (&payload[..i], Some(&payload[i + 1..])),
+            None => (payload, None),
+        };
+
+        let file_name = match str::from_utf8(name) {
+            Ok(v) => nfc(v),
+            Err(e) => {
+                return Err(SecSnailError::InvalidFilename(format!(
+                    "Invalid UTF-8 sequence: {}",
+                    e
+                )));
+            }
+        };
+
+        let (size, flags, digest) = match size {
+            Some(b) if b.len() == 9 + 32 => (Some(&b[..8]), b[8], Some(&b[9..])),
+            Some(b) if b.len() == 9 => (Some(&b[..8]), b[8], None),
+            size => (size, 0, None),
+        };
+        match flags & FLAG_PATH != 0 {
+            true => check_relative_path(&file_name)?,
+            // anonymous
+            false if file_name.is_empty() => {}
+            false => check_file_name(&file_name)?,
+        }
+        let digest = match (flags & FLAG_VERIFY != 0, digest) {
+            (true, Some(b)) => Some(b.try_into().unwrap()),
+            (false, None) => None,
+            _ => return Err(SecSnailError::CorruptPacket("syn metadata digest mismatch")),
+        };
+        let file_size = match size {
+            Some(b) => Some(u64::from_be_bytes(b.try_into().map_err(|_| {
+                SecSnailError::CorruptPacket("syn metadata file size is not 64 bit")
+            })?)),
+            None => None,
+        };
+
+        Ok(SynMeta {
+            file_name,
+            file_size,
+            resume: flags & FLAG_RESUME != 0,
+            digest,
+            trusted: flags & FLAG_TRUSTED != 0,
+            manifest: flags & FLAG_MANIFEST != 0,
+            relative_path: flags & FLAG_PATH != 0,
+            timestamps: flags & FLAG_TIMESTAMPS != 0,
+            delta: flags & FLAG_DELTA != 0,
+        })
+    }
+}
+
+/// payload of the ack of a syn, empty unless resuming at `offset`
+pub fn encode_resume_offset(offset: u64) -> Vec<u8> {
+    match offset {
+        0 => Vec::new(),
+        offset => offset.to_be_bytes().to_vec(),
+    }
+}
+
+pub fn decode_resume_offset(payload: &[u8]) -> Result<u64> {
+    match payload {
+        [] => Ok(0),
+        b => Ok(u64::from_be_bytes(b.try_into().map_err(|_| {
+            SecSnailError::CorruptPacket("resume offset is not 64 bit")
+        })?)),
+    }
+}
+
+/// payload of the ack of a syn which accepts a trusted link
+pub fn encode_trusted_ack(offset: u64) -> Vec<u8> {
+    encode_syn_ack(SynAck {
+        offset,
+        trusted: true,
+        timestamps: false,
+    })
+}
+
+/// answer of a receiver to a syn which sends a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SynAck {
+    /// bytes to resume after
+    pub offset: u64,
+    /// accepted a trusted link
+    pub trusted: bool,
+    /// accepted timestamps
+    pub timestamps: bool,
+}
+
+pub fn encode_syn_ack(ack: SynAck) -> Vec<u8> {
+    let mut flags = 0;
+    if ack.trusted {
+        flags |= ACK_FLAG_TRUSTED;
+    }
+    if ack.timestamps {
+        flags |= ACK_FLAG_TIMESTAMPS;
+    }
+    match flags {
+        0 => encode_resume_offset(ack.offset),
+        flags => {
+            let mut buf = ack.offset.to_be_bytes().to_vec();
+            buf.push(flags);
+            buf
+        }
+    }
+}
+
+pub fn decode_syn_ack(payload: &[u8]) -> Result<SynAck> {
+    let (offset, flags) = match payload {
+        [offset @ .., flags] if payload.len() == 9 => (offset, *flags),
+        b => (b, 0),
+    };
+    Ok(SynAck {
+        offset: decode_resume_offset(offset)?,
+        trusted: flags & ACK_FLAG_TRUSTED != 0,
+        timestamps: flags & ACK_FLAG_TIMESTAMPS != 0,
+    })
+}
+
+/// SHA-256 of everything `reader` yields, compared by a verifying sender
+pub fn file_digest(mut reader: impl Read) -> io::Result<Digest> {
+    let mut digest = Sha256::new();
+    let mut buf = [0; 8192];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(digest.finalize().into()),
+            n => digest.update(&buf[..n]),
+        }
+    }
+}
+
+/// answer of a receiver to a verifying sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Verdict {
+    /// holds a file of the same name, size and SHA-256
+    Identical,
+    /// holds a file of the same name with other content
+    Differs,
+    /// holds no file of the name
+    Missing,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Identical => write!(f, "identical"),
+            Verdict::Differs => write!(f, "differs"),
+            Verdict::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// payload of the ack of a verifying syn
+pub fn encode_verdict(verdict: Verdict) -> Vec<u8> {
+    match verdict {
+        Verdict::Differs => vec![0],
+        Verdict::Identical => vec![1],
+        Verdict::Missing => vec![2],
+    }
+}
+
+pub fn decode_verdict(payload: &[u8]) -> Result<Verdict> {
+    match payload {
+        [0] => Ok(Verdict::Differs),
+        [1] => Ok(Verdict::Identical),
+        [2] => Ok(Verdict::Missing),
+        _ => Err(SecSnailError::CorruptPacket("verdict is not a known byte")),
+    }
+}
+
+/// the name of a received file is joined onto the target directory, so it
+/// must be a single plain path component
+pub fn check_file_name(name: &str) -> Result<()> {
+    let reason = if name.is_empty() {
+        "empty"
+    } else if name.len() > MAX_FILE_NAME_LEN {
+        "longer than 255 bytes"
+    } else if name == "." || name == ".." {
+        "refers to a directory"
+    } else if name.contains(['/', '\\']) {
+        "contains a path separator"
+    } else if name.chars().any(char::is_control) {
+        "contains a control character"
+    } else {
+        return Ok(());
+    };
+    Err(SecSnailError::InvalidFilename(format!("{name:?} {reason}")))
+}
+
+/// a path below the target directory, plain file names separated by `/`
+pub fn check_relative_path(path: &str) -> Result<()> {
+    path.split('/').try_for_each(check_file_name)
+}
+
+/// `name` as UTF-8, every byte of it which is no UTF-8 percent-encoded,
+/// e.g. a Latin-1 `é` as `%E9`
+pub fn utf8_file_name(name: &OsStr) -> String {
+    let mut utf8 = String::new();
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        utf8.push_str(chunk.valid());
+        for b in chunk.invalid() {
+            utf8.push_str(&format!("%{b:02X}"));
+        }
+    }
+    utf8
+}
+
+/// `name` in Unicode normalization form C, composed as Linux and Windows
+/// usually store names, unlike macOS
+pub fn nfc(name: &str) -> String {
+    name.nfc().collect()
+}
+
+/// how a receiver stores announced names with characters outside the
+/// portable set of `A-Z`, `a-z`, `0-9`, `.`, `_` and `-`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NamePolicy {
+    /// store names as announced
+    #[default]
+    Unicode,
+    /// replace other characters by an ascii transliteration, e.g. `ü` by
+    /// `u`, and anything left by `_`
+    Transliterate,
+    /// percent-encode the UTF-8 bytes of other characters
+    Escape,
+}
+
+fn is_portable(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+}
+
+impl NamePolicy {
+    /// `name`, a single path component, under this policy
+    pub fn apply(self, name: &str) -> Cow<'_, str> {
+        if name.chars().all(is_portable) {
+            return Cow::Borrowed(name);
+        }
+        match self {
+            NamePolicy::Unicode => Cow::Borrowed(name),
+            NamePolicy::Transliterate => Cow::Owned(
+                deunicode::deunicode(name)
+                    .chars()
+                    .map(|c| if is_portable(c) { c } else { '_' })
+                    .collect(),
+            ),
+            NamePolicy::Escape => {
+                let mut safe = String::new();
+                for c in name.chars() {
+                    match is_portable(c) {
+                        true => safe.push(c),
+                        false => {
+                            for b in c.encode_utf8(&mut [0; 4]).bytes() {
+                                safe.push_str(&format!("%{b:02X}"));
+                            }
+                        }
+                    }
+                }
+                Cow::Owned(safe)
+            }
+        }
+    }
+}
+
+impl FromStr for NamePolicy {
+    type Err = SecSnailError;
+
+    fn from_str(s: &str) -> Result<NamePolicy> {
+        match s {
+            "unicode" => Ok(NamePolicy::Unicode),
+            "transliterate" => Ok(NamePolicy::Transliterate),
+            "escape" => Ok(NamePolicy::Escape),
+            _ => Err(SecSnailError::InvalidConfig(format!(
+                "'{s}' is no name policy, expected unicode, transliterate or escape"
+            ))),
+        }
+    }
+}
+
+/// device names windows reserves, with any extension
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// `name` altered to a name windows can store: reserved characters and
+/// trailing dots or spaces become `_`, a reserved device name gets a `_`
+/// appended to its stem, e.g. `con.txt` becomes `con_.txt`
+pub fn windows_file_name(name: &str) -> Cow<'_, str> {
+    const RESERVED_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+    let trimmed = name.trim_end_matches(['.', ' ']);
+    let stem = trimmed.split('.').next().unwrap_or(trimmed);
+    let reserved = WINDOWS_RESERVED
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(stem.trim_end()));
+    if trimmed.len() == name.len() && !reserved && !name.contains(RESERVED_CHARS) {
+        return Cow::Borrowed(name);
+    }
+    // all replaced characters are ascii, the stem keeps its length
+    let mut safe = trimmed.replace(RESERVED_CHARS, "_");
+    if reserved {
+        safe.insert(stem.len(), '_');
+    }
+    safe.extend(std::iter::repeat_n('_', name.len() - trimmed.len()));
+    Cow::Owned(safe)
+}
+
+/// path a receiver stores the announced relative `path` under, altered by
+/// `policy` and on windows by `windows_file_name`
+///
+/// fails if the altered name is no plain file name, e.g. a transliterated
+/// `‥` which became `..`
+pub fn stored_path(path: &str, policy: NamePolicy) -> Result<String> {
+    let components = path
+        .split('/')
+        .map(|name| {
+            let name = policy.apply(name);
+            check_file_name(&name)?;
+            Ok(match cfg!(windows) {
+                true => windows_file_name(&name).into_owned(),
+                false => name.into_owned(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let meta = SynMeta {
+            file_name: "snail.txt".to_string(),
+            file_size: Some(4711),
+            resume: false,
+            digest: None,
+            trusted: false,
+            manifest: false,
+            relative_path: false,
+            timestamps: false,
+            delta: false,
+        };
+        assert_eq!(SynMeta::decode(&meta.encode()).unwrap(), meta);
+
+        let resumable = SynMeta {
+            resume: true,
+            ..meta
+        };
+        assert_eq!(resumable.encode().len(), 9 + 1 + 9);
+        assert_eq!(SynMeta::decode(&resumable.encode()).unwrap(), resumable);
+
+        let verifying = SynMeta {
+            digest: Some([0x5a; 32]),
+            ..resumable
+        };
+        assert_eq!(verifying.encode().len(), 9 + 1 + 9 + 32);
+        assert_eq!(SynMeta::decode(&verifying.encode()).unwrap(), verifying);
+
+        let trusted = SynMeta {
+            trusted: true,
+            digest: None,
+            ..verifying
+        };
+        assert_eq!(SynMeta::decode(&trusted.encode()).unwrap(), trusted);
+
+        let nested = SynMeta {
+            file_name: "shells/snail.txt".to_string(),
+            relative_path: true,
+            timestamps: false,
+            ..trusted
+        };
+        assert_eq!(SynMeta::decode(&nested.encode()).unwrap(), nested);
+        let escaping = SynMeta {
+            file_name: "../snail.txt".to_string(),
+            ..nested
+        };
+        assert!(SynMeta::decode(&escaping.encode()).is_err());
+    }
+
+    #[test]
+    fn verdict_and_digest() {
+        for verdict in [Verdict::Identical, Verdict::Differs, Verdict::Missing] {
+            assert_eq!(decode_verdict(&encode_verdict(verdict)).unwrap(), verdict);
+        }
+        assert!(decode_verdict(&[]).is_err());
+        assert!(decode_verdict(&[3]).is_err());
+        // check value of SHA-256
+        assert_eq!(
+            file_digest(&b"abc"[..]).unwrap()[..4],
+            [0xba, 0x78, 0x16, 0xbf]
+        );
+    }
+
+    #[test]
+    fn resume_offset() {
+        assert!(encode_resume_offset(0).is_empty());
+        assert_eq!(decode_resume_offset(&encode_resume_offset(0)).unwrap(), 0);
+        assert_eq!(
+            decode_resume_offset(&encode_resume_offset(508)).unwrap(),
+            508
+        );
+        assert!(decode_resume_offset(&[1, 2]).is_err());
+        let ack = decode_syn_ack(&encode_resume_offset(508)).unwrap();
+        assert_eq!(
+            (ack.offset, ack.trusted, ack.timestamps),
+            (508, false, false)
+        );
+        let ack = decode_syn_ack(&encode_trusted_ack(0)).unwrap();
+        assert_eq!((ack.offset, ack.trusted, ack.timestamps), (0, true, false));
+        let ack = SynAck {
+            offset: 7,
+            trusted: false,
+            timestamps: true,
+        };
+        assert_eq!(decode_syn_ack(&encode_syn_ack(ack)).unwrap(), ack);
+    }
+
+    #[test]
+    fn decode_name_only() {
+        let meta = SynMeta::decode(b"snail.txt").unwrap();
+        assert_eq!(meta.file_name, "snail.txt");
+        assert_eq!(meta.file_size, None);
+    }
+
+    #[test]
+    fn decode_invalid_size() {
+        assert!(SynMeta::decode(b"snail.txt\0\x01").is_err());
+    }
+
+    #[test]
+    fn rejects_unsafe_names() {
+        for name in [
+            ".",
+            "..",
+            "../../.bashrc",
+            "/etc/passwd",
+            "dir\\file",
+            "bell\u{7}",
+            "new\nline",
+        ] {
+            let r = SynMeta::decode(name.as_bytes());
+            assert!(
+                matches!(r, Err(SecSnailError::InvalidFilename(_))),
+                "{name:?} accepted"
+            );
+        }
+        assert!(SynMeta::decode("a".repeat(256).as_bytes()).is_err());
+        assert!(SynMeta::decode("a".repeat(255).as_bytes()).is_ok());
+        assert!(SynMeta::decode("..hidden snail.tar.gz".as_bytes()).is_ok());
+        assert_eq!(SynMeta::decode(b"").unwrap().file_name, "");
+        assert!(SynMeta::decode(b"\0\0\0\0\0\0\0\0\x05\x10").is_err());
+    }
+
+    #[test]
+    fn portable_names() {
+        for (name, safe) in [
+            ("snail.txt", "snail.txt"),
+            ("CON", "CON_"),
+            ("nul.tar.gz", "nul_.tar.gz"),
+            ("com1 .txt", "com1 _.txt"),
+            ("console.txt", "console.txt"),
+            ("shell.", "shell_"),
+            ("a:b?.txt ", "a_b_.txt_"),
+        ] {
+            assert_eq!(windows_file_name(name), safe);
+        }
+        assert_eq!(utf8_file_name(OsStr::new("schnecke.txt")), "schnecke.txt");
+
+        // `ü` decomposed, as macOS stores it
+        let decomposed = "mu\u{308}de Schnecke.txt";
+        let meta = SynMeta::decode(decomposed.as_bytes()).unwrap();
+        assert_eq!(meta.file_name, "m\u{fc}de Schnecke.txt");
+        assert_eq!(
+            SynMeta::decode(&meta.encode()).unwrap().file_name,
+            meta.file_name
+        );
+        for (policy, stored) in [
+            (NamePolicy::Unicode, "m\u{fc}de Schnecke.txt"),
+            (NamePolicy::Transliterate, "mude_Schnecke.txt"),
+            (NamePolicy::Escape, "m%C3%BCde%20Schnecke.txt"),
+        ] {
+            assert_eq!(policy.apply(&meta.file_name), stored);
+        }
+        assert!(stored_path("a/\u{2025}", NamePolicy::Transliterate).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let latin1 = OsStr::from_bytes(b"caf\xe9 \xe2\x82\xac.txt");
+            assert_eq!(utf8_file_name(latin1), "caf%E9 €.txt");
+        }
+    }
+}
@@ -0,0 +1,285 @@
+//! Bytes-in/bytes-out drivers of the sans-IO core.
+//!
+//! The files live in memory and the timers are checked against the
+//! instants handed to `tick`, so a transfer runs without sockets, files or
+//! a real clock, e.g. in a fuzzer or a property test.
+//!
+//! The instants come from a clock injected by the embedder, any
+//! `Timestamp`. Where `Instant::now` is not available, e.g. on
+//! `wasm32-unknown-unknown`, the time since an own epoch as a `Duration`
+//! does, such as `performance.now()` in a browser.
+
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::error::Result;
+
+use super::{Action, RecvInput, SendInput, SnailReceiver, SnailSender};
+
+/// point in time of the clock of the embedder
+pub trait Timestamp: Copy + Ord {
+    /// `self` plus `d`
+    fn after(self, d: Duration) -> Self;
+}
+
+impl Timestamp for Instant {
+    fn after(self, d: Duration) -> Self {
+        self + d
+    }
+}
+
+/// time since an epoch of the embedder
+impl Timestamp for Duration {
+    fn after(self, d: Duration) -> Self {
+        self + d
+    }
+}
+
+/// expiry of the running timer after `actions`
+fn update_timer<T: Timestamp>(timer: &mut Option<T>, action: &Action, now: T) {
+    match action {
+        Action::StartTimer(d) => *timer = Some(now.after(*d)),
+        Action::StopTimer => *timer = None,
+        _ => {}
+    }
+}
+
+/// sends a file held in memory
+pub struct BytesSender<T = Instant> {
+    sender: SnailSender,
+    data: Vec<u8>,
+    offset: usize,
+    timer: Option<T>,
+    outbox: VecDeque<Vec<u8>>,
+}
+
+impl<T: Timestamp> BytesSender<T> {
+    /// start the transfer of `data` under `file_name`, the syn is ready
+    /// in `poll_transmit`
+    pub fn new(file_name: &str, data: Vec<u8>, now: T) -> Result<Self> {
+        let sender = SnailSender::new(file_name, Some(data.len() as u64))?;
+        BytesSender::with_sender(sender, data, now)
+    }
+
+    /// start the transfer of `data` with a configured `sender`
+    pub fn with_sender(sender: SnailSender, data: Vec<u8>, now: T) -> Result<Self> {
+        let mut s = BytesSender {
+            sender,
+            data,
+            offset: 0,
+            timer: None,
+            outbox: VecDeque::new(),
+        };
+        s.run(SendInput::Start, now)?;
+        Ok(s)
+    }
+
+    /// a datagram of the receiver arrived
+    pub fn recv(&mut self, datagram: &[u8], now: T) -> Result<()> {
+        self.run(SendInput::Datagram(datagram.to_vec()), now)
+    }
+
+    /// advance the clock, an expired timer retransmits
+    pub fn tick(&mut self, now: T) -> Result<()> {
+        match self.timer {
+            Some(t) if t <= now => {
+                self.timer = None;
+                self.run(SendInput::Timeout, now)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// next datagram to put on the wire
+    pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+        self.outbox.pop_front()
+    }
+
+    /// when `tick` has to be called next
+    pub fn next_timeout(&self) -> Option<T> {
+        self.timer
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.sender.is_finished()
+    }
+
+    fn run(&mut self, input: SendInput, now: T) -> Result<()> {
+        let mut inputs = VecDeque::from([input]);
+        while let Some(input) = inputs.pop_front() {
+            for action in self.sender.handle(input)? {
+                update_timer(&mut self.timer, &action, now);
+                match action {
+                    Action::EmitPacket(pck) => self.outbox.push_back(pck),
+                    Action::ReadData { max } => {
+                        let end = self.data.len().min(self.offset + max);
+                        inputs.push_back(SendInput::Data(self.data[self.offset..end].to_vec()));
+                        self.offset = end;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// receives files into memory
+pub struct BytesReceiver<T = Instant> {
+    receiver: SnailReceiver,
+    timer: Option<T>,
+    outbox: VecDeque<(Vec<u8>, SocketAddr)>,
+    /// name and data of the open file
+    open: Option<(String, Vec<u8>)>,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+impl<T: Timestamp> BytesReceiver<T> {
+    pub fn new() -> Self {
+        BytesReceiver::with_receiver(SnailReceiver::new())
+    }
+
+    pub fn with_receiver(receiver: SnailReceiver) -> Self {
+        BytesReceiver {
+            receiver,
+            timer: None,
+            outbox: VecDeque::new(),
+            open: None,
+            files: Vec::new(),
+        }
+    }
+
+    /// a datagram of `src` arrived
+    ///
+    /// an error aborted the open session
+    pub fn recv(&mut self, datagram: &[u8], src: SocketAddr, now: T) -> Result<()> {
+        self.run(RecvInput::Datagram(datagram.to_vec(), src), Some(src), now)
+    }
+
+    /// advance the clock, an expired timer aborts the open session
+    pub fn tick(&mut self, now: T) -> Result<()> {
+        match self.timer {
+            Some(t) if t <= now => {
+                self.timer = None;
+                self.run(RecvInput::Timeout, None, now)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// next datagram to put on the wire and its destination
+    pub fn poll_transmit(&mut self) -> Option<(Vec<u8>, SocketAddr)> {
+        self.outbox.pop_front()
+    }
+
+    /// when `tick` has to be called next
+    pub fn next_timeout(&self) -> Option<T> {
+        self.timer
+    }
+
+    /// name and data of all files received completely since the last call
+    pub fn take_files(&mut self) -> Vec<(String, Vec<u8>)> {
+        std::mem::take(&mut self.files)
+    }
+
+    fn run(&mut self, input: RecvInput, src: Option<SocketAddr>, now: T) -> Result<()> {
+        let actions = match self.receiver.handle(input) {
+            Ok(actions) => actions,
+            Err(e) => {
+                self.open = None;
+                self.timer = None;
+                return Err(e);
+            }
+        };
+        for action in actions {
+            update_timer(&mut self.timer, &action, now);
+            match action {
+                // a timeout never replies
+                Action::EmitPacket(pck) => self.outbox.extend(src.map(|src| (pck, src))),
+                Action::OpenFile(name) => self.open = Some((name, Vec::new())),
+                Action::WriteData(data) => {
+                    if let Some((_, buf)) = &mut self.open {
+                        buf.extend(data);
+                    }
+                }
+                Action::CloseFile => self.files.extend(self.open.take()),
+                Action::DiscardFile => self.open = None,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Timestamp> Default for BytesReceiver<T> {
+    fn default() -> Self {
+        BytesReceiver::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::DEFAULT_SND_TIMEOUT_MS;
+
+    #[test]
+    fn transfer_over_lossy_wire() {
+        let file: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mut now = Instant::now();
+        let mut sender = BytesSender::new("snail.bin", file.clone(), now).unwrap();
+        let mut receiver = BytesReceiver::new();
+
+        // every third datagram of either side gets lost
+        let mut sent = 0;
+        while !sender.is_finished() {
+            while let Some(datagram) = sender.poll_transmit() {
+                sent += 1;
+                if sent % 3 != 0 {
+                    receiver.recv(&datagram, peer, now).unwrap();
+                }
+            }
+            while let Some((datagram, dst)) = receiver.poll_transmit() {
+                assert_eq!(dst, peer);
+                sent += 1;
+                if sent % 3 != 0 {
+                    sender.recv(&datagram, now).unwrap();
+                }
+            }
+            if let Some(t) = sender.next_timeout() {
+                now = now.max(t);
+                sender.tick(now).unwrap();
+                receiver.tick(now).unwrap();
+            }
+        }
+        assert_eq!(receiver.take_files(), vec![("snail.bin".to_string(), file)]);
+    }
+
+    #[test]
+    fn clock_of_durations() {
+        let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let mut sender = BytesSender::new("snail.bin", vec![1; 700], Duration::ZERO).unwrap();
+        let mut receiver = BytesReceiver::new();
+
+        // the syn gets lost, so it is sent again once the timer expired
+        assert!(sender.poll_transmit().is_some());
+        let timeout = sender.next_timeout().unwrap();
+        assert_eq!(timeout, Duration::from_millis(DEFAULT_SND_TIMEOUT_MS));
+        sender.tick(timeout).unwrap();
+        while !sender.is_finished() {
+            while let Some(datagram) = sender.poll_transmit() {
+                receiver.recv(&datagram, peer, timeout).unwrap();
+            }
+            while let Some((datagram, _)) = receiver.poll_transmit() {
+                sender.recv(&datagram, timeout).unwrap();
+            }
+        }
+        assert_eq!(
+            receiver.take_files(),
+            vec![("snail.bin".to_string(), vec![1; 700])]
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Entry points of fuzz targets.
+//!
+//! Every function takes the input of a fuzzer and only panics on a bug,
+//! e.g. a transition the state machines assumed to be unreachable. The
+//! targets in `fuzz/` hand them to cargo-fuzz:
+//!
+//! ```text
+//! cargo +nightly fuzz run receiver
+//! ```
+
+use crate::pck::{DecodeMode, Packet, PacketView};
+
+use super::{Action, RecvInput, SendInput, SnailReceiver, SnailSender};
+
+/// decode raw bytes, an intact packet encodes its fields the same way again
+/// and a view of the bytes sees the same packet, a strict decode only
+/// accepts the exact encoding of the packet
+pub fn decode(data: &[u8]) {
+    let strict = PacketView::parse_with(data, DecodeMode::Strict);
+    let Ok(pck) = Packet::decode(data.to_vec()) else {
+        assert!(PacketView::parse(data).is_err());
+        assert!(strict.is_err());
+        return;
+    };
+    assert_eq!(PacketView::parse(data).unwrap(), pck.view());
+    if let Ok(view) = strict {
+        assert_eq!(view, pck.view());
+        assert_eq!(pck.encode(), data);
+    }
+    let _ = pck.describe();
+    if pck.corrupt() {
+        return;
+    }
+    if let Ok(again) = Packet::new(pck.n() != 0, pck.flag(), pck.payload().to_vec()) {
+        assert!(again.notcorrupt());
+        assert_eq!(&again.encode()[..4], &data[..4]);
+    }
+}
+
+/// hand `inputs` to a receiver, it writes into an open file only
+pub fn receiver(inputs: Vec<RecvInput>) {
+    let mut receiver = SnailReceiver::new();
+    let mut open = false;
+    for input in inputs {
+        let Ok(actions) = receiver.handle(input) else {
+            open = false;
+            continue;
+        };
+        for action in actions {
+            match action {
+                Action::OpenFile(_) => open = true,
+                Action::WriteData(_) => assert!(open, "write without an open file"),
+                Action::CloseFile | Action::DiscardFile => open = false,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// hand `inputs` to a sender of a file named `snail.bin`, it never asks
+/// for more than a packet holds
+pub fn sender(inputs: Vec<SendInput>) {
+    let Ok(mut sender) = SnailSender::new("snail.bin", None) else {
+        return;
+    };
+    for input in inputs {
+        let Ok(actions) = sender.handle(input) else {
+            return;
+        };
+        for action in actions {
+            if let Action::ReadData { max } = action {
+                assert!(max <= Packet::max_pck_payload_size());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arbitrary::{Arbitrary, Unstructured};
+
+    /// run `target` on inputs of pseudo random bytes
+    fn run<'a, T: Arbitrary<'a>>(data: &'a mut [u8], seed: u64, target: fn(T)) {
+        let mut state = seed;
+        for b in data.iter_mut() {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            *b = (state >> 56) as u8;
+        }
+        if let Ok(input) = T::arbitrary_take_rest(Unstructured::new(data)) {
+            target(input);
+        }
+    }
+
+    #[test]
+    fn entry_points_survive_random_inputs() {
+        for seed in 0..2000 {
+            run(&mut [0; 64], seed, |data: Vec<u8>| decode(&data));
+            run(&mut [0; 4096], seed, receiver);
+            run(&mut [0; 4096], seed, sender);
+            // intact packets get past the checksum into the fsms
+            run(&mut [0; 4096], seed, |pcks: Vec<(Packet, bool)>| {
+                let peer = "10.0.0.1:4000".parse().unwrap();
+                receiver(
+                    pcks.into_iter()
+                        .map(|(pck, timeout)| match timeout {
+                            true => RecvInput::Timeout,
+                            false => RecvInput::Datagram(pck.encode().to_vec(), peer),
+                        })
+                        .collect(),
+                );
+            });
+            run(&mut [0; 4096], seed, |pcks: Vec<(Packet, u8)>| {
+                sender(
+                    pcks.into_iter()
+                        .map(|(pck, input)| match input % 4 {
+                            0 => SendInput::Datagram(pck.encode().to_vec()),
+                            1 => SendInput::Timeout,
+                            2 => SendInput::Data(pck.payload().to_vec()),
+                            _ => SendInput::Start,
+                        })
+                        .collect(),
+                );
+            });
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! I/O-free protocol core.
+//!
+//! `SnailSender` and `SnailReceiver` run the same state machines as the
+//! sockets, but never touch a socket, file or clock. Every input is handed
+//! to `handle`, which answers with the actions the embedder has to carry
+//! out, e.g. put a packet on the wire or arm a timer. This way an own event
+//! loop, async runtime or a transport other than UDP can reuse the protocol,
+//! `SecSnailSocket` is just one driver on top of the state machines.
+//!
+//! The drivers in `bytes` carry out all actions in memory, they only take
+//! raw datagrams and clock ticks. `vectors` generates conformance test
+//! vectors of the wire format.
+//!
+//! ```
+//! use std::collections::VecDeque;
+//! use secsnail::proto::{Action, RecvInput, SendInput, SnailReceiver, SnailSender};
+//!
+//! let file = b"slow and steady".to_vec();
+//! let peer = "127.0.0.1:4000".parse().unwrap();
+//! let mut sender = SnailSender::new("snail.txt", Some(file.len() as u64)).unwrap();
+//! let mut receiver = SnailReceiver::new();
+//! let (mut received, mut offset) = (Vec::new(), 0);
+//!
+//! // a lossless wire, so the timers never expire
+//! let mut inputs = VecDeque::from([SendInput::Start]);
+//! while let Some(input) = inputs.pop_front() {
+//!     for action in sender.handle(input).unwrap() {
+//!         match action {
+//!             Action::EmitPacket(pck) => {
+//!                 for reply in receiver.handle(RecvInput::Datagram(pck, peer)).unwrap() {
+//!                     match reply {
+//!                         Action::EmitPacket(pck) => inputs.push_back(SendInput::Datagram(pck)),
+//!                         Action::WriteData(data) => received.extend(data),
+//!                         _ => {}
+//!                     }
+//!                 }
+//!             }
+//!             Action::ReadData { max } => {
+//!                 let end = file.len().min(offset + max);
+//!                 inputs.push_back(SendInput::Data(file[offset..end].to_vec()));
+//!                 offset = end;
+//!             }
+//!             _ => {}
+//!         }
+//!     }
+//! }
+//! assert!(sender.is_finished());
+//! assert_eq!(received, file);
+//! ```
+
+pub mod bytes;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+mod receiver;
+mod sender;
+pub mod vectors;
+
+use std::time::Duration;
+
+pub use receiver::{RecvInput, SnailReceiver};
+pub use sender::{SendInput, SnailSender};
+
+pub const DEFAULT_MAX_RETRANSMITS: u8 = 100;
+
+pub const DEFAULT_SND_TIMEOUT_MS: u64 = 10;
+pub const DEFAULT_RCV_TIMEOUT_MS: u64 = 5000;
+
+/// what the embedder has to do after `handle`, in the given order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// put the encoded packet on the wire, a receiver replies to the
+    /// source of the handled datagram
+    EmitPacket(Vec<u8>),
+    /// (re)arm the single timer, its expiry is handed back as `Timeout`
+    StartTimer(Duration),
+    StopTimer,
+    /// sender needs the next chunk of at most `max` bytes of the file as
+    /// `SendInput::Data`, an empty chunk ends the file
+    ReadData {
+        max: usize,
+    },
+    /// receiver got a syn for a file with this name, data follows
+    OpenFile(String),
+    /// receiver got the next chunk of the open file
+    WriteData(Vec<u8>),
+    /// receiver got the whole open file
+    CloseFile,
+    /// receiver dropped the open file of an aborted session
+    DiscardFile,
+    /// sender got the fin acknowledged, the transfer is done
+    Finished,
+}
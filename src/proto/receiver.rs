@@ -0,0 +1,235 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::Result,
+    fsm_recv::fsm::{ProtocolIoContext, RcvEvent, RcvFsm, RcvState},
+    meta::{SynMeta, encode_resume_offset},
+    pck::{Flag, Packet},
+    util::u8_to_bool,
+};
+
+use super::{Action, DEFAULT_RCV_TIMEOUT_MS};
+
+/// input of a `SnailReceiver`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum RecvInput {
+    /// datagram and its source
+    Datagram(Vec<u8>, SocketAddr),
+    /// the timer of the last `Action::StartTimer` expired
+    Timeout,
+}
+
+/// receiving side, one session after another, see the module docs
+pub struct SnailReceiver {
+    fsm: Option<RcvFsm>,
+    ctx: RecvActions,
+}
+
+/// collects the actions of a transition
+struct RecvActions {
+    connection_timeout: Duration,
+    /// transfer id of the open session, echoed by every reply
+    transfer_id: u8,
+    data_counter: usize,
+    actions: Vec<Action>,
+}
+
+impl SnailReceiver {
+    pub fn new() -> Self {
+        SnailReceiver {
+            fsm: Some(RcvFsm::init()),
+            ctx: RecvActions {
+                connection_timeout: Duration::from_millis(DEFAULT_RCV_TIMEOUT_MS),
+                transfer_id: 0,
+                data_counter: 0,
+                actions: Vec::new(),
+            },
+        }
+    }
+
+    /// duration of every `Action::StartTimer`, a session without a
+    /// packet for this long is aborted
+    pub fn with_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.ctx.connection_timeout = timeout;
+        self
+    }
+
+    /// handle `input` in the current state
+    ///
+    /// # Return
+    /// the actions to carry out, an error aborts the open session, whose
+    /// file is to be discarded, and the receiver waits for the next syn
+    pub fn handle(&mut self, input: RecvInput) -> Result<Vec<Action>> {
+        let fsm = self.fsm.take().unwrap_or_else(RcvFsm::init);
+        let event = match input {
+            RecvInput::Datagram(buf, src) => RcvEvent::RecvPck(Packet::decode(buf).ok(), src),
+            RecvInput::Timeout => RcvEvent::ConnectionTimeout,
+        };
+
+        match fsm.goto(event, &mut self.ctx) {
+            Ok(next) => {
+                self.fsm = Some(next);
+                Ok(std::mem::take(&mut self.ctx.actions))
+            }
+            Err(e) => {
+                self.ctx.actions.clear();
+                Err(e)
+            }
+        }
+    }
+
+    /// source of the syn of the open session
+    pub fn peer(&self) -> Option<SocketAddr> {
+        match self.fsm.as_ref().map(RcvFsm::state) {
+            Some(RcvState::WaitForPkt { peer, .. }) => Some(*peer),
+            _ => None,
+        }
+    }
+
+    /// file bytes received in the open or last session
+    pub fn bytes_received(&self) -> usize {
+        self.ctx.data_counter
+    }
+}
+
+impl Default for SnailReceiver {
+    fn default() -> Self {
+        SnailReceiver::new()
+    }
+}
+
+impl ProtocolIoContext for RecvActions {
+    // replies go to the source of the handled datagram
+    fn set_snd_addr(&mut self, _snd_addr: SocketAddr, transfer_id: u8) {
+        self.transfer_id = transfer_id;
+    }
+
+    fn extract_data<'a>(&mut self, rcvpkt: &'a Packet) -> &'a [u8] {
+        rcvpkt.payload()
+    }
+
+    fn extract_file_name(&mut self, rcvpkt: &Packet) -> Result<String> {
+        Ok(SynMeta::decode(rcvpkt.payload())?.file_name)
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.actions.push(Action::WriteData(data.to_vec()));
+        Ok(())
+    }
+
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+        Ok(Packet::new(u8_to_bool(seq_n), f, vec![])?.with_transfer_id(self.transfer_id))
+    }
+
+    fn make_syn_ack(&mut self, seq_n: u8) -> Result<Packet> {
+        Ok(
+            Packet::new(u8_to_bool(seq_n), Flag::ACK, encode_resume_offset(0))?
+                .with_transfer_id(self.transfer_id),
+        )
+    }
+
+    fn start_connection_timer(&mut self) -> Result<()> {
+        self.actions
+            .push(Action::StartTimer(self.connection_timeout));
+        Ok(())
+    }
+
+    fn stop_connection_timer(&mut self) -> Result<()> {
+        self.actions.push(Action::StopTimer);
+        Ok(())
+    }
+
+    fn restart_connection_timer(&mut self) -> Result<()> {
+        self.start_connection_timer()
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        self.actions.push(Action::CloseFile);
+        Ok(())
+    }
+
+    fn discard_file(&mut self) -> Result<()> {
+        self.actions.push(Action::DiscardFile);
+        Ok(())
+    }
+
+    fn open_file(&mut self, filename: &str) -> Result<()> {
+        self.actions.push(Action::OpenFile(filename.to_string()));
+        Ok(())
+    }
+
+    fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+        self.actions.push(Action::EmitPacket(pck.encode().to_vec()));
+        Ok(())
+    }
+
+    fn get_data_counter(&self) -> usize {
+        self.data_counter
+    }
+
+    fn increase_data_counter(&mut self, n: usize) {
+        self.data_counter += n;
+    }
+
+    fn reset_data_counter(&mut self) {
+        self.data_counter = 0;
+    }
+
+    fn increase_ack_retransmit_counter(&mut self) {}
+
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::SecSnailError;
+
+    const PEER: SocketAddr = SocketAddr::V4(std::net::SocketAddrV4::new(
+        std::net::Ipv4Addr::LOCALHOST,
+        4000,
+    ));
+
+    fn datagram(n: bool, f: Flag, payload: &[u8]) -> RecvInput {
+        let pck = Packet::new(n, f, payload.to_vec()).unwrap();
+        RecvInput::Datagram(pck.encode().to_vec(), PEER)
+    }
+
+    #[test]
+    fn timeout_discards_session() {
+        let mut receiver = SnailReceiver::new().with_connection_timeout(Duration::from_secs(1));
+        let actions = receiver
+            .handle(datagram(false, Flag::SYN, b"snail.txt"))
+            .unwrap();
+        assert_eq!(actions[0], Action::OpenFile("snail.txt".to_string()));
+        assert_eq!(actions[2], Action::StartTimer(Duration::from_secs(1)));
+        assert_eq!(receiver.peer(), Some(PEER));
+
+        let actions = receiver
+            .handle(datagram(true, Flag::Data, b"slow"))
+            .unwrap();
+        assert_eq!(actions[0], Action::WriteData(b"slow".to_vec()));
+        assert_eq!(
+            receiver.handle(RecvInput::Timeout).unwrap(),
+            vec![Action::DiscardFile]
+        );
+        assert_eq!(receiver.peer(), None);
+
+        // a timeout without a session is a protocol violation
+        assert!(matches!(
+            receiver.handle(RecvInput::Timeout),
+            Err(SecSnailError::ProtocolViolation(_))
+        ));
+        assert!(
+            receiver
+                .handle(datagram(false, Flag::SYN, b"next.txt"))
+                .is_ok()
+        );
+    }
+}
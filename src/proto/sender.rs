@@ -0,0 +1,200 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    error::{Result, SecSnailError},
+    fsm_send::fsm::{ProtocolIoContext, SndEvent, SndFsm, SndState},
+    meta::{SynMeta, check_file_name},
+    pck::{Flag, Packet},
+    util::u8_to_bool,
+};
+
+use super::{Action, DEFAULT_MAX_RETRANSMITS, DEFAULT_SND_TIMEOUT_MS};
+
+/// input of a `SnailSender`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum SendInput {
+    /// open the transfer with a syn
+    Start,
+    /// datagram of the receiver
+    Datagram(Vec<u8>),
+    /// the timer of the last `Action::StartTimer` expired
+    Timeout,
+    /// chunk asked for by `Action::ReadData`, empty at the end of the file
+    Data(Vec<u8>),
+}
+
+/// sending side of a single transfer, see the module docs
+pub struct SnailSender {
+    /// `None` once the transfer failed
+    fsm: Option<SndFsm>,
+    ctx: SendActions,
+}
+
+/// collects the actions of a transition
+struct SendActions {
+    file_name: String,
+    file_size: Option<u64>,
+    timeout: Duration,
+    /// chunk handed in by the last `SendInput::Data`
+    chunk: Option<Vec<u8>>,
+    data_counter: usize,
+    actions: Vec<Action>,
+}
+
+impl SnailSender {
+    /// transfer of `file_name`, the receiver learns `file_size` in advance if given
+    pub fn new(file_name: &str, file_size: Option<u64>) -> Result<Self> {
+        check_file_name(file_name)?;
+        Ok(SnailSender {
+            fsm: Some(SndFsm::init(DEFAULT_MAX_RETRANSMITS)),
+            ctx: SendActions {
+                file_name: file_name.to_string(),
+                file_size,
+                timeout: Duration::from_millis(DEFAULT_SND_TIMEOUT_MS),
+                chunk: None,
+                data_counter: 0,
+                actions: Vec::new(),
+            },
+        })
+    }
+
+    /// duration of every `Action::StartTimer`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.ctx.timeout = timeout;
+        self
+    }
+
+    /// expired timers of a packet before the transfer fails
+    pub fn with_max_retransmits(mut self, max_retransmits: u8) -> Self {
+        self.fsm = Some(SndFsm::init(max_retransmits));
+        self
+    }
+
+    /// handle `input` in the current state
+    ///
+    /// # Return
+    /// the actions to carry out, an error fails the whole transfer
+    pub fn handle(&mut self, input: SendInput) -> Result<Vec<Action>> {
+        let fsm = self.fsm.take().ok_or(SecSnailError::NoActiveTransfer)?;
+        let event = match input {
+            SendInput::Start => SndEvent::InitSYN,
+            SendInput::Datagram(buf) => SndEvent::RecvPck(Packet::decode(buf).ok()),
+            SendInput::Timeout => SndEvent::Timeout,
+            SendInput::Data(chunk) => {
+                let available = !chunk.is_empty();
+                self.ctx.chunk = Some(chunk);
+                SndEvent::DataAvailable(available)
+            }
+        };
+
+        // a packet in the send state does not ask for data again
+        let sending = matches!(fsm.state(), SndState::Send { .. });
+        let next = fsm.goto(event, &mut self.ctx)?;
+        match next.state() {
+            SndState::Send { .. } if !sending => self.ctx.actions.push(Action::ReadData {
+                max: Packet::max_pck_payload_size(),
+            }),
+            // the fsm leaves the timer of the fin running
+            SndState::End => self
+                .ctx
+                .actions
+                .extend([Action::StopTimer, Action::Finished]),
+            _ => {}
+        }
+        self.fsm = Some(next);
+        Ok(std::mem::take(&mut self.ctx.actions))
+    }
+
+    /// the fin got acknowledged
+    pub fn is_finished(&self) -> bool {
+        self.fsm.as_ref().is_some_and(SndFsm::is_end)
+    }
+
+    /// file bytes put into data packets so far
+    pub fn bytes_sent(&self) -> usize {
+        self.ctx.data_counter
+    }
+}
+
+impl ProtocolIoContext for SendActions {
+    fn data_available(&mut self) -> Result<bool> {
+        Ok(self.chunk.as_ref().is_some_and(|c| !c.is_empty()))
+    }
+
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+        let payload = match f {
+            Flag::Data => self.chunk.take().unwrap_or_default(),
+            Flag::SYN => SynMeta {
+                file_name: self.file_name.clone(),
+                file_size: self.file_size,
+                resume: false,
+                digest: None,
+                trusted: false,
+                manifest: false,
+                relative_path: false,
+                timestamps: false,
+                delta: false,
+            }
+            .encode(),
+            _ => vec![],
+        };
+        Ok(Packet::new(u8_to_bool(seq_n), f, payload)?)
+    }
+
+    fn start_timer(&mut self) -> Result<()> {
+        self.actions.push(Action::StartTimer(self.timeout));
+        Ok(())
+    }
+
+    fn stop_timer(&mut self) -> Result<()> {
+        self.actions.push(Action::StopTimer);
+        Ok(())
+    }
+
+    fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+        self.actions.push(Action::EmitPacket(pck.encode().to_vec()));
+        Ok(())
+    }
+
+    fn get_data_counter(&self) -> usize {
+        self.data_counter
+    }
+
+    fn increase_data_counter(&mut self, n: usize) {
+        self.data_counter += n;
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retransmits_until_limit() {
+        let mut sender = SnailSender::new("snail.txt", None)
+            .unwrap()
+            .with_timeout(Duration::from_millis(5))
+            .with_max_retransmits(1);
+        let actions = sender.handle(SendInput::Start).unwrap();
+        let Some(Action::EmitPacket(syn)) = actions.first().cloned() else {
+            panic!("syn expected, got {actions:?}");
+        };
+        assert_eq!(actions[1], Action::StartTimer(Duration::from_millis(5)));
+
+        let actions = sender.handle(SendInput::Timeout).unwrap();
+        assert_eq!(actions[0], Action::EmitPacket(syn));
+        assert!(matches!(
+            sender.handle(SendInput::Timeout),
+            Err(SecSnailError::MaxRetransmitsExceeded)
+        ));
+        assert!(matches!(
+            sender.handle(SendInput::Start),
+            Err(SecSnailError::NoActiveTransfer)
+        ));
+    }
+}
@@ -0,0 +1,221 @@
+//! Conformance test vectors.
+//!
+//! Canonical encodings of every packet type and the datagrams of a small
+//! reference transfer, so another implementation of the protocol, e.g. of
+//! another student group or in another language, can check its encoder,
+//! decoder and state machines against this one. `write_vectors` prints
+//! them in a line based format, `secsnail vectors` does the same:
+//!
+//! ```text
+//! packet <name> <encoded packet as hex>
+//! sender|receiver <datagram as hex>
+//! ```
+//!
+//! Lines starting with `#` are comments.
+
+use std::{io::Write, net::SocketAddr, time::Duration};
+
+use crate::{
+    error::Result,
+    meta::{
+        SynMeta, Verdict, encode_resume_offset, encode_trusted_ack, encode_verdict, file_digest,
+    },
+    pck::{Flag, Packet},
+};
+
+use super::bytes::{BytesReceiver, BytesSender};
+
+/// file name of the reference transfer
+pub const TRACE_FILE_NAME: &str = "snail.txt";
+/// size of the reference transfer, three data packets
+pub const TRACE_FILE_SIZE: usize = 1200;
+
+/// a packet with its name in the vectors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketVector {
+    pub name: &'static str,
+    pub packet: Packet,
+}
+
+/// side which put a datagram of the reference transfer on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Sender,
+    Receiver,
+}
+
+impl Side {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Side::Sender => "sender",
+            Side::Receiver => "receiver",
+        }
+    }
+}
+
+/// content of the reference transfer, byte `i` is `i % 251`
+pub fn trace_file() -> Vec<u8> {
+    (0..TRACE_FILE_SIZE).map(|i| (i % 251) as u8).collect()
+}
+
+/// every packet type, with each kind of payload a syn or ack carries
+pub fn packet_vectors() -> Result<Vec<PacketVector>> {
+    let data = trace_file();
+    let syn = |resume, digest| SynMeta {
+        file_name: TRACE_FILE_NAME.to_string(),
+        file_size: Some(TRACE_FILE_SIZE as u64),
+        resume,
+        digest,
+        trusted: false,
+        manifest: false,
+        relative_path: false,
+        timestamps: false,
+        delta: false,
+    };
+    let digest = file_digest(data.as_slice())?;
+    let vectors = [
+        ("syn-name-only", false, Flag::SYN, TRACE_FILE_NAME.into()),
+        ("syn", false, Flag::SYN, syn(false, None).encode()),
+        ("syn-resume", false, Flag::SYN, syn(true, None).encode()),
+        (
+            "syn-verify",
+            false,
+            Flag::SYN,
+            syn(false, Some(digest)).encode(),
+        ),
+        (
+            "syn-trusted",
+            false,
+            Flag::SYN,
+            SynMeta {
+                trusted: true,
+                ..syn(false, None)
+            }
+            .encode(),
+        ),
+        ("ack-0", false, Flag::ACK, Vec::new()),
+        ("ack-1", true, Flag::ACK, Vec::new()),
+        ("ack-resume", false, Flag::ACK, encode_resume_offset(512)),
+        ("ack-trusted", false, Flag::ACK, encode_trusted_ack(0)),
+        (
+            "ack-identical",
+            false,
+            Flag::ACK,
+            encode_verdict(Verdict::Identical),
+        ),
+        (
+            "ack-differs",
+            false,
+            Flag::ACK,
+            encode_verdict(Verdict::Differs),
+        ),
+        (
+            "ack-missing",
+            false,
+            Flag::ACK,
+            encode_verdict(Verdict::Missing),
+        ),
+        ("data-0", false, Flag::Data, b"slow and steady".to_vec()),
+        (
+            "data-1-full",
+            true,
+            Flag::Data,
+            data[..Packet::max_pck_payload_size()].to_vec(),
+        ),
+        ("data-empty", true, Flag::Data, Vec::new()),
+        ("fin", true, Flag::FIN, Vec::new()),
+        ("finack", true, Flag::FINACK, Vec::new()),
+        ("rst", false, Flag::RST, b"receiver is busy".to_vec()),
+    ];
+    vectors
+        .into_iter()
+        .map(|(name, n, flag, payload)| {
+            Ok(PacketVector {
+                name,
+                packet: Packet::new(n, flag, payload)?,
+            })
+        })
+        .collect()
+}
+
+/// datagrams of the transfer of `trace_file` over a lossless wire, in the
+/// order they are put on the wire
+pub fn reference_trace() -> Result<Vec<(Side, Vec<u8>)>> {
+    let peer: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+    let now = Duration::ZERO;
+    let mut sender = BytesSender::new(TRACE_FILE_NAME, trace_file(), now)?;
+    let mut receiver = BytesReceiver::new();
+    let mut trace = Vec::new();
+    while !sender.is_finished() {
+        let Some(datagram) = sender.poll_transmit() else {
+            break;
+        };
+        receiver.recv(&datagram, peer, now)?;
+        trace.push((Side::Sender, datagram));
+        while let Some((datagram, _)) = receiver.poll_transmit() {
+            sender.recv(&datagram, now)?;
+            trace.push((Side::Receiver, datagram));
+        }
+    }
+    Ok(trace)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// write the packet vectors and the reference trace, see the module docs
+pub fn write_vectors(mut wrt: impl Write) -> Result<()> {
+    writeln!(wrt, "# secsnail conformance vectors")?;
+    writeln!(wrt, "# packet <name> <encoded packet as hex>")?;
+    for vector in packet_vectors()? {
+        writeln!(
+            wrt,
+            "packet {} {}",
+            vector.name,
+            hex(vector.packet.encode())
+        )?;
+    }
+    writeln!(
+        wrt,
+        "# transfer of {TRACE_FILE_NAME}, {TRACE_FILE_SIZE} bytes with byte i being i % 251, \
+         over a lossless wire"
+    )?;
+    writeln!(wrt, "# sender|receiver <datagram as hex>")?;
+    for (side, datagram) in reference_trace()? {
+        writeln!(wrt, "{} {}", side.name(), hex(&datagram))?;
+    }
+    wrt.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vectors_decode_to_their_packets() {
+        for vector in packet_vectors().unwrap() {
+            let decoded = Packet::decode(vector.packet.encode().to_vec()).unwrap();
+            assert_eq!(decoded, vector.packet, "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn reference_trace_is_canonical() {
+        let trace = reference_trace().unwrap();
+        let flags: Vec<_> = trace
+            .iter()
+            .map(|(side, d)| (*side, Packet::decode(d.clone()).unwrap().flag()))
+            .collect();
+        assert_eq!(flags.len(), 10);
+        assert_eq!(flags[0], (Side::Sender, Flag::SYN));
+        assert_eq!(flags[9], (Side::Receiver, Flag::FINACK));
+        assert_eq!(trace, reference_trace().unwrap());
+
+        let mut out = Vec::new();
+        write_vectors(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains(&format!("sender {}", hex(&trace[0].1))));
+    }
+}
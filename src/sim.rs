@@ -0,0 +1,327 @@
+//! Deterministic network simulation.
+//!
+//! All endpoints of a `SimNetwork` share a virtual clock and a seeded rng,
+//! which decides about loss, corruption and duplication of every datagram.
+//! Time only advances when both sides of a transfer wait, straight to the
+//! next delivery or expiring timer, so a transfer under heavy loss runs in
+//! milliseconds and is replayed bit-for-bit with the same seed.
+//!
+//! ```
+//! use secsnail::{sim::SimNetwork, sock::SecSnailSocket};
+//! # let dir = std::env::temp_dir().join("secsnail-sim-doc");
+//! # std::fs::create_dir_all(&dir).unwrap();
+//! # std::fs::write(dir.join("file.txt"), b"slow and steady").unwrap();
+//!
+//! let net = SimNetwork::new(42).loss_p(0.2);
+//! let mut sender = SecSnailSocket::builder()
+//!     .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+//!     .unwrap();
+//! let mut receiver = SecSnailSocket::builder()
+//!     .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+//!     .unwrap();
+//!
+//! let report = net
+//!     .run_transfer(&mut sender, &mut receiver, dir.join("file.txt"), dir.join("out"))
+//!     .unwrap();
+//! ```
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap},
+    io,
+    net::SocketAddr,
+    path::Path,
+    rc::Rc,
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+use rand::{SeedableRng, rngs::StdRng};
+
+use crate::{
+    error::Result,
+    impair::{GilbertElliott, Impairment},
+    sock::{DatagramTransport, SecSnailSocket, SendReport},
+};
+
+/// one-way delay of every datagram unless configured otherwise
+pub const DEFAULT_SIM_DELAY: Duration = Duration::from_millis(1);
+
+/// datagram handed to the simulated network
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimEvent {
+    /// virtual time since the start of the simulation
+    pub at: Duration,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    /// datagrams put on the wire, empty if it got lost, two if duplicated
+    pub delivered: Vec<Vec<u8>>,
+}
+
+struct SimState {
+    start: Instant,
+    elapsed: Duration,
+    rng: StdRng,
+    impairment: Impairment,
+    /// datagrams in flight by delivery time and send order
+    in_flight: BTreeMap<(Duration, u64), (SocketAddr, SocketAddr, Vec<u8>)>,
+    sent: u64,
+    /// expiry of the read timeout of every endpoint
+    wakeups: HashMap<SocketAddr, Duration>,
+    log: Vec<SimEvent>,
+}
+
+/// virtual network with a seeded rng and clock, see module docs
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Rc<RefCell<SimState>>,
+}
+
+impl SimNetwork {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(SimState {
+                start: Instant::now(),
+                elapsed: Duration::ZERO,
+                rng: StdRng::seed_from_u64(seed),
+                impairment: Impairment {
+                    delay: DEFAULT_SIM_DELAY,
+                    ..Impairment::default()
+                },
+                in_flight: BTreeMap::new(),
+                sent: 0,
+                wakeups: HashMap::new(),
+                log: Vec::new(),
+            })),
+        }
+    }
+
+    /// probability of a datagram getting lost
+    pub fn loss_p(self, p: f64) -> Self {
+        self.state.borrow_mut().impairment.loss_p = p;
+        self
+    }
+
+    /// lose datagrams in bursts after the Gilbert-Elliott model, replaces `loss_p`
+    pub fn burst_loss(self, model: GilbertElliott) -> Self {
+        self.state.borrow_mut().impairment.burst = Some(model);
+        self
+    }
+
+    /// probability of a single bit flip in a datagram
+    pub fn error_p(self, p: f64) -> Self {
+        self.state.borrow_mut().impairment.error_p = p;
+        self
+    }
+
+    /// probability of a datagram getting duplicated
+    pub fn dup_p(self, p: f64) -> Self {
+        self.state.borrow_mut().impairment.dup_p = p;
+        self
+    }
+
+    /// one-way delay of every datagram
+    pub fn delay(self, delay: Duration) -> Self {
+        self.state.borrow_mut().impairment.delay = delay;
+        self
+    }
+
+    /// upper bound of a uniformly distributed delay on top of `delay`
+    pub fn jitter(self, jitter: Duration) -> Self {
+        self.state.borrow_mut().impairment.jitter = jitter;
+        self
+    }
+
+    /// transport of a new endpoint with address `addr`
+    pub fn endpoint(&self, addr: SocketAddr) -> SimTransport {
+        SimTransport {
+            addr,
+            state: Rc::clone(&self.state),
+            read_timeout: Cell::new(None),
+            nonblocking: Cell::new(false),
+        }
+    }
+
+    /// virtual time since the start of the simulation
+    pub fn elapsed(&self) -> Duration {
+        self.state.borrow().elapsed
+    }
+
+    /// every datagram sent so far, in order
+    pub fn log(&self) -> Vec<SimEvent> {
+        self.state.borrow().log.clone()
+    }
+
+    /// send the file at `path` from `sender` to `receiver`, which stores it in `target_dir`
+    ///
+    /// both sockets are switched to non-blocking mode and polled in turn
+    ///
+    /// # Return
+    /// report of the sender, durations in virtual time
+    pub fn run_transfer<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        sender: &mut SecSnailSocket<SimTransport>,
+        receiver: &mut SecSnailSocket<SimTransport>,
+        path: P,
+        target_dir: Q,
+    ) -> Result<SendReport> {
+        sender.set_nonblocking(true)?;
+        receiver.set_nonblocking(true)?;
+        receiver.start_recv_file(target_dir)?;
+        sender.start_send_file(path, receiver.local_addr()?)?;
+
+        loop {
+            // the receiver keeps answering retransmitted fins after its session closed
+            _ = receiver.poll_recv_progress()?;
+            if let Poll::Ready(done) = sender.poll_send_progress()? {
+                return Ok(done);
+            }
+            if !self.advance() {
+                return Err(io::Error::other("simulation stalled without pending events").into());
+            }
+        }
+    }
+
+    /// jump to the next delivery or expiring read timeout
+    ///
+    /// # Return
+    /// false if nothing is pending anymore
+    fn advance(&self) -> bool {
+        let mut state = self.state.borrow_mut();
+        let next_delivery = state.in_flight.keys().next().map(|(at, _)| *at);
+        let next_wakeup = state.wakeups.values().min().copied();
+        let Some(next) = next_delivery.into_iter().chain(next_wakeup).min() else {
+            return false;
+        };
+        state.elapsed = state.elapsed.max(next);
+        let now = state.elapsed;
+        state.wakeups.retain(|_, at| *at > now);
+        true
+    }
+}
+
+/// endpoint of a `SimNetwork`
+pub struct SimTransport {
+    addr: SocketAddr,
+    state: Rc<RefCell<SimState>>,
+    read_timeout: Cell<Option<Duration>>,
+    nonblocking: Cell<bool>,
+}
+
+impl DatagramTransport for SimTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let state = &mut *self.state.borrow_mut();
+        let delivered = state.impairment.apply_with(&mut state.rng, buf);
+        for datagram in &delivered {
+            let at = state.elapsed + state.impairment.sample_delay(&mut state.rng);
+            state.sent += 1;
+            state
+                .in_flight
+                .insert((at, state.sent), (self.addr, addr, datagram.clone()));
+        }
+        state.log.push(SimEvent {
+            at: state.elapsed,
+            src: self.addr,
+            dst: addr,
+            delivered,
+        });
+        Ok(buf.len())
+    }
+
+    /// a blocking receive without datagram in flight would wait forever,
+    /// as the clock only advances in `SimNetwork::run_transfer`
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut state = self.state.borrow_mut();
+        let now = state.elapsed;
+        let key = state
+            .in_flight
+            .iter()
+            .find(|((at, _), (_, dst, _))| *at <= now && *dst == self.addr)
+            .map(|(key, _)| *key);
+        let Some((src, _, datagram)) = key.and_then(|key| state.in_flight.remove(&key)) else {
+            if !self.nonblocking.get() {
+                return Err(io::Error::other("blocking receive on a simulated network"));
+            }
+            // wake up once the read timeout expires
+            if let Some(timeout) = self.read_timeout.get() {
+                state.wakeups.insert(self.addr, now + timeout);
+            }
+            return Err(io::ErrorKind::WouldBlock.into());
+        };
+        let n = datagram.len().min(buf.len());
+        buf[..n].copy_from_slice(&datagram[..n]);
+        Ok((n, src))
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.nonblocking.set(nonblocking);
+        Ok(())
+    }
+
+    fn now(&self) -> Instant {
+        let state = self.state.borrow();
+        state.start + state.elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sock::TransferStats;
+    use std::{fs, path::PathBuf};
+
+    fn transfer(seed: u64, dir: &Path) -> (usize, Duration, Vec<SimEvent>, TransferStats) {
+        let net = SimNetwork::new(seed)
+            .loss_p(0.2)
+            .error_p(0.05)
+            .dup_p(0.05)
+            .jitter(Duration::from_millis(2));
+        let mut sender = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+
+        let report = net
+            .run_transfer(
+                &mut sender,
+                &mut receiver,
+                dir.join("snail.txt"),
+                dir.join("out"),
+            )
+            .unwrap();
+        let stats = sender.last_transfer_stats().unwrap();
+        (report.bytes, report.duration, net.log(), stats)
+    }
+
+    #[test]
+    fn replays_lossy_transfer() {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("secsnail-{}-sim", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let content: Vec<u8> = (0..20_000u32).map(|i| (i % 239) as u8).collect();
+        fs::write(dir.join("snail.txt"), &content).unwrap();
+
+        let first = transfer(7, &dir);
+        assert_eq!(first.0, content.len());
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+        assert!(first.2.iter().any(|e| e.delivered.is_empty()));
+        assert!(first.3.retransmissions > 0);
+        assert_eq!(first.3.timeouts, first.3.retransmissions);
+        assert_eq!(first.3.payload_bytes, content.len());
+
+        assert_eq!(transfer(7, &dir), first);
+        assert_ne!(transfer(8, &dir).2, first.2);
+    }
+}
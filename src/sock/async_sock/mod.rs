@@ -0,0 +1,1040 @@
+//! Async variant of the `SecSnailSocket`, independent of the async runtime.
+//!
+//! The FSM logic is shared with the blocking socket; only waiting for
+//! incoming packets and timers is done asynchronously. All runtime specific
+//! I/O is expressed through the `AsyncDatagramSocket` trait object, adapters
+//! for tokio and smol are shipped behind the features of the same name.
+//! File access stays buffered std I/O within the session, like in the
+//! blocking socket.
+
+use std::{
+    future::{Future, poll_fn},
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    path::{Path, PathBuf},
+    pin::{Pin, pin},
+    sync::{Arc, Mutex},
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+use tracing::Instrument;
+
+use crate::{
+    discovery,
+    error::{Result, SecSnailError},
+    fsm_recv::{self, driver::run_rcv_fsm_loop_async, fsm::RcvEvent},
+    fsm_send::{self, driver::run_snd_fsm_loop_async, fsm::SndEvent},
+    impair::Impairment,
+    pck::{Flag, Packet, PacketView},
+    util::u8_to_bool,
+};
+
+use super::{
+    CheckpointPolicy, DEFAULT_MAX_RETRANSMITS, DEFAULT_RCV_TIMEOUT_MS, DEFAULT_SND_TIMEOUT_MS,
+    DecodeMode, NamePolicy, OverwritePolicy, RecvResult, SecSnailSocket, SendReport, SocketStats,
+    Strictness, TransferReport, TransferStats, Transition,
+    capture::{Capture, Direction},
+    clamp_to_deadline, default_decode_mode,
+    delay::DelayLine,
+    expired,
+    fault::{self, Fault, FaultInjector},
+    filter::PeerFilter,
+    history::TransitionLog,
+    is_retryable,
+    journal::{Journal, finish_journaled, journal_send},
+    log_send_outcome, of_other_transfer,
+    pool::BufferPool,
+    prepare_target_dir,
+    quota::SenderQuota,
+    rcv_ctx::{PathResolver, RecvSession},
+    retry_backoff,
+    snd_ctx::SendSession,
+    trace::TraceLog,
+};
+
+#[cfg(feature = "smol")]
+mod smol_rt;
+#[cfg(feature = "tokio")]
+mod tokio_rt;
+#[cfg(feature = "smol")]
+pub use smol_rt::SmolUdpSocket;
+#[cfg(feature = "tokio")]
+pub use tokio_rt::TokioUdpSocket;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// udp socket of an async runtime
+pub trait AsyncDatagramSocket: Send + Sync {
+    /// adopt a bound std socket, called from within the runtime
+    fn from_std(sock: UdpSocket) -> io::Result<Self>
+    where
+        Self: Sized;
+
+    /// send without waiting, a full send buffer returns `WouldBlock`
+    fn try_send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8])
+    -> BoxFuture<'a, io::Result<(usize, SocketAddr)>>;
+
+    /// timer of the runtime, resolves once `deadline` is reached
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// # Examples
+///
+/// ```ignore
+/// # async fn run() -> secsnail::error::Result<()> {
+/// use secsnail::sock::{AsyncSecSnailSocket, DEFAULT_SECSNAIL_PORT, TokioUdpSocket};
+/// use std::net::SocketAddr;
+///
+/// let recv_addr: SocketAddr = format!("127.0.0.1:{DEFAULT_SECSNAIL_PORT}").parse().unwrap();
+///
+/// let mut secsnail_sock = AsyncSecSnailSocket::bind::<TokioUdpSocket>("0.0.0.0:3000")?;
+/// let (amt_bytes, dur) = secsnail_sock.send_file("file.txt", recv_addr).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncSecSnailSocket {
+    inner: Box<dyn AsyncDatagramSocket>,
+    snd_max_retransmits: u8,
+    snd_timeout_config: Duration,
+    rcv_timeout_config: Duration,
+    transfer_deadline: Option<Duration>,
+    max_recv_file_size: Option<u64>,
+    offer_resume: bool,
+    mmap_reads: bool,
+    read_ahead: usize,
+    min_packet_gap: Duration,
+    trusted_link: bool,
+    timestamps: bool,
+    congestion_threshold: Option<Duration>,
+    checkpoints: CheckpointPolicy,
+    sync_on_close: bool,
+    spool_dir: Option<PathBuf>,
+    overwrite_policy: OverwritePolicy,
+    name_policy: NamePolicy,
+    path_resolver: Option<PathResolver>,
+    /// see `SecSnailSocketBuilder::journal`
+    journal: Option<Arc<Journal>>,
+    strictness: Strictness,
+    decode_mode: DecodeMode,
+    peer_filter: PeerFilter,
+    quota: Option<Mutex<SenderQuota>>,
+    impairment: Impairment,
+    /// sends from a clone of the std socket, see `DelayLine`
+    delay_line: Option<DelayLine>,
+    /// see `SecSnailSocketBuilder::transfer_retry_policy`
+    transfer_retries: u32,
+    retry_backoff: Duration,
+    last_stats: Option<TransferStats>,
+    totals: SocketStats,
+    /// taken over from the blocking socket
+    capture: Option<Capture>,
+    trace: Option<TraceLog>,
+    history: Option<TransitionLog>,
+    faults: Option<Mutex<Box<dyn FaultInjector>>>,
+    recv_bufs: BufferPool,
+    send_bufs: BufferPool,
+}
+
+impl AsyncSecSnailSocket {
+    /// must be called within the runtime of `T`
+    pub fn bind<T: AsyncDatagramSocket + 'static>(
+        addr: impl ToSocketAddrs,
+    ) -> Result<AsyncSecSnailSocket> {
+        AsyncSecSnailSocket::from_blocking::<T>(SecSnailSocket::bind(addr)?)
+    }
+
+    /// take over socket and configuration of a blocking socket, e.g. one
+    /// created with `SecSnailSocket::builder()`
+    ///
+    /// must be called within the runtime of `T`
+    pub fn from_blocking<T: AsyncDatagramSocket + 'static>(
+        sock: SecSnailSocket,
+    ) -> Result<AsyncSecSnailSocket> {
+        sock.inner.set_nonblocking(true)?;
+        Ok(AsyncSecSnailSocket {
+            inner: Box::new(T::from_std(sock.inner)?),
+            snd_max_retransmits: sock.snd_max_retransmits,
+            snd_timeout_config: sock.snd_timeout_config,
+            rcv_timeout_config: sock.rcv_timeout_config,
+            transfer_deadline: sock.transfer_deadline,
+            max_recv_file_size: sock.max_recv_file_size,
+            offer_resume: sock.offer_resume,
+            mmap_reads: sock.mmap_reads,
+            read_ahead: sock.read_ahead,
+            min_packet_gap: sock.min_packet_gap,
+            trusted_link: sock.trusted_link,
+            timestamps: sock.timestamps,
+            congestion_threshold: sock.congestion_threshold,
+            checkpoints: sock.checkpoints,
+            sync_on_close: sock.sync_on_close,
+            spool_dir: sock.spool_dir.clone(),
+            overwrite_policy: sock.overwrite_policy,
+            name_policy: sock.name_policy,
+            path_resolver: sock.path_resolver,
+            journal: sock.journal,
+            strictness: sock.strictness,
+            decode_mode: sock.decode_mode,
+            peer_filter: sock.peer_filter,
+            quota: sock.quota,
+            impairment: sock.impairment,
+            delay_line: sock.delay_line,
+            transfer_retries: sock.transfer_retries,
+            retry_backoff: sock.retry_backoff,
+            last_stats: None,
+            totals: SocketStats::default(),
+            capture: sock.capture,
+            trace: sock.trace,
+            history: sock.history,
+            faults: sock.faults,
+            recv_bufs: BufferPool::default(),
+            send_bufs: BufferPool::default(),
+        })
+    }
+
+    /// custom transport with default configuration
+    pub fn from_transport(inner: Box<dyn AsyncDatagramSocket>) -> AsyncSecSnailSocket {
+        let decode_mode = default_decode_mode(inner.local_addr());
+        AsyncSecSnailSocket {
+            inner,
+            snd_max_retransmits: DEFAULT_MAX_RETRANSMITS,
+            snd_timeout_config: Duration::from_millis(DEFAULT_SND_TIMEOUT_MS),
+            rcv_timeout_config: Duration::from_millis(DEFAULT_RCV_TIMEOUT_MS),
+            transfer_deadline: None,
+            max_recv_file_size: None,
+            offer_resume: false,
+            mmap_reads: false,
+            read_ahead: 0,
+            min_packet_gap: Duration::ZERO,
+            trusted_link: false,
+            timestamps: false,
+            congestion_threshold: None,
+            checkpoints: CheckpointPolicy::default(),
+            sync_on_close: false,
+            spool_dir: None,
+            overwrite_policy: OverwritePolicy::default(),
+            name_policy: NamePolicy::default(),
+            path_resolver: None,
+            journal: None,
+            strictness: Strictness::default(),
+            decode_mode,
+            peer_filter: PeerFilter::default(),
+            quota: None,
+            impairment: Impairment::default(),
+            delay_line: None,
+            transfer_retries: 0,
+            retry_backoff: Duration::ZERO,
+            last_stats: None,
+            totals: SocketStats::default(),
+            capture: None,
+            trace: None,
+            history: None,
+            faults: None,
+            recv_bufs: BufferPool::default(),
+            send_bufs: BufferPool::default(),
+        }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.inner.local_addr()?)
+    }
+
+    /// see `SecSnailSocket::last_transfer_stats`
+    pub fn last_transfer_stats(&self) -> Option<TransferStats> {
+        self.last_stats
+    }
+
+    /// see `SecSnailSocket::stats_snapshot`
+    pub fn stats_snapshot(&self) -> SocketStats {
+        self.totals
+    }
+
+    /// see `SecSnailSocket::reset_stats`
+    pub fn reset_stats(&mut self) {
+        self.totals = SocketStats::default();
+    }
+
+    /// see `SecSnailSocket::set_fault_injector`
+    pub fn set_fault_injector<F: FaultInjector + 'static>(&mut self, injector: F) {
+        self.faults = Some(Mutex::new(Box::new(injector)));
+    }
+
+    /// see `SecSnailSocket::last_transfer_trace`
+    pub fn last_transfer_trace(&self) -> Vec<Transition> {
+        self.history
+            .as_ref()
+            .map(TransitionLog::transitions)
+            .unwrap_or_default()
+    }
+
+    /// send a file to `recv_addr`, started over after a timeout as the
+    /// retry policy of the builder allows
+    pub async fn send_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recv_addr: SocketAddr,
+    ) -> Result<SendReport> {
+        let path = path.as_ref();
+        let span = tracing::info_span!("send_file", file = %path.display(), peer = %recv_addr);
+        // the deadline covers all attempts
+        let start = Instant::now();
+        let mut attempt = 1;
+        let mut entry = None;
+        loop {
+            let r = self
+                .send_attempt(path, recv_addr, start, attempt, &mut entry, &span)
+                .await;
+            match r {
+                Err(e) if is_retryable(&e) && attempt <= self.transfer_retries as usize => {
+                    let backoff = retry_backoff(self.retry_backoff, attempt);
+                    span.in_scope(
+                        || tracing::warn!(attempt, error = %e, ?backoff, "retrying transfer"),
+                    );
+                    self.inner.sleep_until(Instant::now() + backoff).await;
+                    attempt += 1;
+                }
+                // the journal keeps a send which may succeed later
+                Err(e) if is_retryable(&e) => return Err(e),
+                r => {
+                    finish_journaled(self.journal.as_ref(), entry);
+                    return r;
+                }
+            }
+        }
+    }
+
+    async fn send_attempt(
+        &mut self,
+        path: &Path,
+        recv_addr: SocketAddr,
+        start: Instant,
+        attempt: usize,
+        entry: &mut Option<u64>,
+        span: &tracing::Span,
+    ) -> Result<SendReport> {
+        let session = SendSession::new(recv_addr, path, self.snd_timeout_config)?
+            .with_transfer_deadline(self.transfer_deadline, start)
+            .with_resume(self.offer_resume)
+            .with_read_ahead(self.read_ahead)
+            .with_min_gap(self.min_packet_gap)
+            .with_trusted_link(self.trusted_link)
+            .with_timestamps(self.timestamps)
+            .with_mmap(self.mmap_reads)?;
+        let session = journal_send(self.journal.as_ref(), session, path, entry)?;
+        let mut ctx = AsyncSendProtocolIoContext {
+            sock_ref: self,
+            session,
+        };
+        let ret = run_snd_fsm_loop_async(&mut ctx, self.snd_max_retransmits)
+            .instrument(span.clone())
+            .await;
+        let stats = TransferStats {
+            attempts: attempt,
+            ..ctx.session.stats()
+        };
+        let ret = ret.map(|(_, duration)| SendReport {
+            stats,
+            ..ctx.session.report(duration)
+        });
+        self.last_stats = Some(stats);
+        self.totals.record_send(&stats);
+        span.in_scope(|| log_send_outcome(&ret));
+        ret
+    }
+
+    /// wait for a single file and store it in `target_dir`
+    pub async fn recv_file<P: AsRef<Path>>(&mut self, target_dir: P) -> Result<TransferReport> {
+        let target_dir = target_dir.as_ref();
+        prepare_target_dir(target_dir)?;
+
+        let session = RecvSession::new(target_dir.to_path_buf(), self.rcv_timeout_config)
+            .with_transfer_deadline(self.transfer_deadline)
+            .with_max_file_size(self.max_recv_file_size)
+            .with_overwrite_policy(self.overwrite_policy)
+            .with_name_policy(self.name_policy)
+            .with_congestion_threshold(self.congestion_threshold)
+            .with_checkpoints(self.checkpoints)
+            .with_sync_on_close(self.sync_on_close)
+            .with_spool_dir(self.spool_dir.clone())
+            .with_path_resolver(self.path_resolver.clone())
+            .with_journal(self.journal.clone())
+            .with_trusted_link(self.trusted_link)
+            .with_timestamps(self.timestamps);
+        let mut ctx = AsyncRecvProtocolIoContext {
+            sock_ref: self,
+            session,
+        };
+        let span = tracing::info_span!("recv_file", dir = %target_dir.display());
+        let ret = run_rcv_fsm_loop_async(fsm_recv::fsm::RcvFsm::init(), &mut ctx)
+            .instrument(span)
+            .await;
+        let mut session = ctx.session;
+        let stats = session.stats();
+        self.last_stats = Some(stats);
+        self.totals.record_recv(&stats);
+        ret?;
+        Ok(session.take_report().expect("closed session has a report"))
+    }
+
+    // utils
+
+    async fn wait_for_incoming_or_timeout(
+        &self,
+        peer: Option<(SocketAddr, u8)>,
+        syn_from_any: bool,
+        timeout: Duration,
+        timer_start: Instant,
+        transfer_deadline: Option<Instant>,
+    ) -> Result<RecvResult> {
+        let (timeout, exceeds_deadline) =
+            clamp_to_deadline(timer_start, timeout, transfer_deadline);
+        let deadline = timer_start + timeout;
+
+        // waiting for correct ack or timeout
+        loop {
+            match self.timeout_at(deadline, self.rdt_recv()).await {
+                None => return expired(exceeds_deadline),
+                Some(Ok((src, resp_pck))) => {
+                    if of_other_transfer(peer, syn_from_any, src, resp_pck.as_ref()) {
+                        continue;
+                    }
+                    return Ok(RecvResult::RecvPkt(resp_pck, src));
+                }
+                Some(Err(e)) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// # Return
+    /// `None` if `deadline` is reached before `fut` completes
+    async fn timeout_at<T>(&self, deadline: Instant, fut: impl Future<Output = T>) -> Option<T> {
+        let mut fut = pin!(fut);
+        let mut sleep = self.inner.sleep_until(deadline);
+        poll_fn(|cx| {
+            if let Poll::Ready(v) = fut.as_mut().poll(cx) {
+                return Poll::Ready(Some(v));
+            }
+            sleep.as_mut().poll(cx).map(|_| None)
+        })
+        .await
+    }
+
+    fn trace_event(&self, fsm: &str, name: &str, pck: Option<&Packet>) {
+        if let Some(trace) = &self.trace {
+            trace.event(Instant::now(), fsm, name, pck);
+        }
+    }
+
+    fn trace_emit(&self, fsm: &str, pck: &Packet, peer: SocketAddr) {
+        if let Some(trace) = &self.trace {
+            trace.emit(Instant::now(), fsm, pck, peer);
+        }
+        if let Some(history) = &self.history {
+            history.emit(pck.flag());
+        }
+    }
+
+    fn record_transition(
+        &self,
+        fsm: &'static str,
+        state: &'static str,
+        event: &'static str,
+        next: &'static str,
+    ) {
+        if let Some(history) = &self.history {
+            history.record(Instant::now(), fsm, state, event, next);
+        }
+    }
+
+    /// trace `pck` of `fsm` and put it on the wire, unless the fault
+    /// injector drops it
+    fn emit(&self, fsm: &str, pck: &Packet, peer: SocketAddr) -> io::Result<()> {
+        tracing::trace!(fsm, flag = ?pck.flag(), n = pck.n(), len = pck.payload().len(), %peer, "packet sent");
+        self.trace_emit(fsm, pck, peer);
+        let fault = self
+            .faults
+            .as_ref()
+            .map_or(Fault::Pass, |f| f.lock().unwrap().on_emit(fsm, pck));
+        for _ in 0..fault::copies(fault) {
+            self.udt_send_faulty(pck, fault, peer)?;
+        }
+        Ok(())
+    }
+
+    fn udt_send(&self, sndpkt: &Packet, recv_addr: SocketAddr) -> io::Result<()> {
+        self.udt_send_faulty(sndpkt, Fault::Pass, recv_addr)
+    }
+
+    /// encode `pck` into a send buffer of the pool, corrupt it if `fault`
+    /// says so and put it on the wire
+    fn udt_send_faulty(&self, pck: &Packet, fault: Fault, recv_addr: SocketAddr) -> io::Result<()> {
+        let mut buf = self.send_bufs.take();
+        let len = pck.encode_into(&mut buf);
+        fault::corrupt(fault, &mut buf[..len]);
+        let r = self.udt_send_bytes(&mut buf[..len], recv_addr);
+        self.send_bufs.give(buf);
+        r
+    }
+
+    fn udt_send_bytes(&self, pkt: &mut [u8], recv_addr: SocketAddr) -> io::Result<()> {
+        for _ in 0..self.impairment.apply_in_place(pkt) {
+            if let Some(capture) = &self.capture {
+                capture.record(Direction::Outbound, recv_addr, pkt)?;
+            }
+            if let Some(delay_line) = &self.delay_line {
+                let delay = self.impairment.next_delay();
+                delay_line.send_at(Instant::now() + delay, pkt.to_vec(), recv_addr);
+                continue;
+            }
+            match self.inner.try_send_to(pkt, recv_addr) {
+                // full send buffer is handled like packet loss
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                r => _ = r?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn rdt_recv(&self) -> io::Result<(SocketAddr, Option<Packet>)> {
+        let mut buf = self.recv_bufs.take();
+        let r = self.recv_into(&mut buf).await;
+        self.recv_bufs.give(buf);
+        r
+    }
+
+    async fn recv_into(&self, buf: &mut [u8]) -> io::Result<(SocketAddr, Option<Packet>)> {
+        let (n, src) = loop {
+            let (n, src) = self.inner.recv_from(buf).await?;
+            if self.peer_filter.allows(src.ip()) {
+                break (n, src);
+            }
+            tracing::trace!(%src, "datagram of a filtered peer dropped");
+        };
+        if let Some(reply) = discovery::echo_reply(&buf[..n], src) {
+            // like an ack, a reply lost to a full send buffer is retried by the peer
+            match self.inner.try_send_to(&reply, src) {
+                Err(e) if e.kind() != io::ErrorKind::WouldBlock => return Err(e),
+                _ => return Ok((src, None)),
+            }
+        }
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Inbound, src, &buf[..n])?;
+        }
+        // parsed in place, only a decodable packet is copied out of `buf`
+        match PacketView::parse_with(&buf[..n], self.decode_mode) {
+            Ok(view) => {
+                tracing::trace!(flag = ?view.flag(), n = view.n(), len = view.payload().len(), %src, "packet received");
+                Ok((src, Some(view.to_packet())))
+            }
+            Err(e) => {
+                tracing::trace!(%src, error = %e, "undecodable datagram dropped");
+                Ok((src, None))
+            }
+        }
+    }
+}
+
+struct AsyncSendProtocolIoContext<'a> {
+    sock_ref: &'a AsyncSecSnailSocket,
+    session: SendSession,
+}
+
+impl<'a> fsm_send::fsm::AsyncProtocolEventSource for AsyncSendProtocolIoContext<'a> {
+    async fn wait_for_ack_or_timeout(&mut self) -> Result<SndEvent> {
+        let r = self
+            .sock_ref
+            .wait_for_incoming_or_timeout(
+                Some((self.session.recv_addr(), self.session.transfer_id())),
+                false,
+                self.session.timeout(),
+                self.session.timer_start(),
+                self.session.deadline(),
+            )
+            .await?;
+        match r {
+            RecvResult::RecvPkt(rcvpkt, _) => Ok(SndEvent::RecvPck(self.session.received(rcvpkt))),
+            RecvResult::Timeout => Ok(SndEvent::Timeout),
+        }
+    }
+
+    /// see `SendProtocolIoContext::data_available`, replies arriving in the
+    /// meantime wait in the socket
+    async fn data_available_paced(&mut self) -> Result<bool> {
+        let available = self.session.data_available()?;
+        if available && let Some(until) = self.session.next_send() {
+            let start = Instant::now();
+            self.sock_ref.inner.sleep_until(until).await;
+            self.session
+                .record_paced(Instant::now().saturating_duration_since(start));
+        }
+        Ok(available)
+    }
+}
+
+impl<'a> fsm_send::fsm::ProtocolIoContext for AsyncSendProtocolIoContext<'a> {
+    fn data_available(&mut self) -> Result<bool> {
+        self.session.data_available()
+    }
+
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+        self.session.make_pkt(seq_n, f, Instant::now())
+    }
+
+    fn start_timer(&mut self) -> Result<()> {
+        self.session.start_timer(Instant::now());
+        Ok(())
+    }
+
+    fn stop_timer(&mut self) -> Result<()> {
+        self.session.stop_timer();
+        Ok(())
+    }
+
+    fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+        self.sock_ref.emit("send", pck, self.session.recv_addr())?;
+        let now = self.now();
+        self.session.record_sent(pck, now);
+        Ok(())
+    }
+
+    fn get_data_counter(&self) -> usize {
+        self.session.data_counter()
+    }
+
+    fn increase_data_counter(&mut self, n: usize) {
+        self.session.increase_data_counter(n);
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.session.deadline()
+    }
+
+    fn syn_acked(&mut self, payload: &[u8]) -> Result<()> {
+        self.session.syn_acked(payload)
+    }
+
+    fn fin_acked(&mut self, payload: &[u8]) -> Result<()> {
+        self.session.fin_acked(payload)
+    }
+
+    fn on_event(&mut self, event: &SndEvent) {
+        let now = self.now();
+        self.session.record_event(event, now);
+        self.sock_ref
+            .trace_event("send", event.name(), event.packet());
+    }
+
+    fn protocol_violation(&mut self, state: &'static str, event: &SndEvent) -> Result<()> {
+        self.session.record_violation();
+        self.sock_ref
+            .strictness
+            .check("send", state, event.name(), event.packet())
+    }
+
+    fn on_transition(&mut self, state: &'static str, event: &'static str, next: &'static str) {
+        self.sock_ref.record_transition("send", state, event, next);
+    }
+}
+
+struct AsyncRecvProtocolIoContext<'a> {
+    sock_ref: &'a AsyncSecSnailSocket,
+    session: RecvSession<'static>,
+}
+
+impl AsyncRecvProtocolIoContext<'_> {
+    /// see `RecvProtocolIoContext::check_quota`
+    fn check_quota(&mut self) -> Result<()> {
+        match (&self.sock_ref.quota, self.session.snd_addr()) {
+            (Some(quota), Some(snd_addr)) => {
+                quota.lock().unwrap().check(snd_addr.ip(), Instant::now())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// see `RecvProtocolIoContext::reset_if_refused`
+    fn reset_if_refused<V>(&mut self, r: Result<V>) -> Result<V> {
+        if let (
+            Err(
+                e @ (SecSnailError::FileTooLarge { .. }
+                | SecSnailError::InsufficientSpace { .. }
+                | SecSnailError::QuotaExceeded { .. }
+                | SecSnailError::InvalidFilename(_)),
+            ),
+            Some(snd_addr),
+        ) = (&r, self.session.snd_addr())
+        {
+            tracing::info!(error = %e, "transfer refused");
+            let sndpkt = Packet::new(false, Flag::RST, e.to_string().into_bytes())?
+                .with_transfer_id(self.session.transfer_id());
+            self.sock_ref.udt_send(&sndpkt, snd_addr)?;
+        }
+        r
+    }
+}
+
+impl<'b> fsm_recv::fsm::AsyncProtocolEventSource for AsyncRecvProtocolIoContext<'b> {
+    /// never call this functino if snd_addr is not set
+    async fn wait_for_ack_or_timeout(&mut self) -> Result<RcvEvent> {
+        let r = self
+            .sock_ref
+            .wait_for_incoming_or_timeout(
+                self.session.sender(),
+                true,
+                self.session.connection_timeout(),
+                self.session.connection_timer_start(),
+                self.session.deadline(),
+            )
+            .await?;
+        match r {
+            RecvResult::RecvPkt(rcvpkt, rcv_addr) => {
+                Ok(self.session.received(RcvEvent::RecvPck(rcvpkt, rcv_addr)))
+            }
+            RecvResult::Timeout => Ok(RcvEvent::ConnectionTimeout),
+        }
+    }
+
+    async fn wait_for_pck_no_timeout(&mut self) -> Result<RcvEvent> {
+        let (src, rcv_pck) = self.sock_ref.rdt_recv().await?;
+        Ok(self.session.received(RcvEvent::RecvPck(rcv_pck, src)))
+    }
+}
+
+impl<'b> fsm_recv::fsm::ProtocolIoContext for AsyncRecvProtocolIoContext<'b> {
+    fn set_snd_addr(&mut self, snd_addr: SocketAddr, transfer_id: u8) {
+        self.session.set_snd_addr(snd_addr, transfer_id);
+    }
+
+    fn extract_data<'a>(&mut self, rcvpkt: &'a Packet) -> &'a [u8] {
+        self.session.extract_data(rcvpkt, Instant::now())
+    }
+
+    fn extract_file_name(&mut self, rcvpkt: &Packet) -> Result<String> {
+        let r = self
+            .check_quota()
+            .and_then(|_| self.session.extract_file_name(rcvpkt));
+        self.reset_if_refused(r)
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        let r = self.session.append(data);
+        if r.is_ok()
+            && let (Some(quota), Some(snd_addr)) = (&self.sock_ref.quota, self.session.snd_addr())
+        {
+            quota
+                .lock()
+                .unwrap()
+                .record(snd_addr.ip(), Instant::now(), data.len());
+        }
+        self.reset_if_refused(r)
+    }
+
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+        self.session.make_pkt(seq_n, f)
+    }
+
+    fn make_syn_ack(&mut self, seq_n: u8) -> Result<Packet> {
+        Ok(
+            Packet::new(u8_to_bool(seq_n), Flag::ACK, self.session.syn_ack_payload())?
+                .with_transfer_id(self.session.transfer_id()),
+        )
+    }
+
+    fn start_connection_timer(&mut self) -> Result<()> {
+        self.session.start_connection_timer(Instant::now());
+        Ok(())
+    }
+
+    fn stop_connection_timer(&mut self) -> Result<()> {
+        self.session.stop_connection_timer();
+        Ok(())
+    }
+
+    fn restart_connection_timer(&mut self) -> Result<()> {
+        self.start_connection_timer()
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        self.session.close_file(Instant::now())
+    }
+
+    fn discard_file(&mut self) -> Result<()> {
+        self.session.discard_file()
+    }
+
+    fn open_file(&mut self, filename: &str) -> Result<()> {
+        let r = self.session.open_file(filename, Instant::now());
+        self.reset_if_refused(r)
+    }
+
+    /// call only if snd_addr is set
+    fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+        let snd_addr = self.session.snd_addr().unwrap();
+        self.sock_ref.emit("recv", pck, snd_addr)?;
+        self.session.record_sent(pck);
+        Ok(())
+    }
+
+    fn get_data_counter(&self) -> usize {
+        self.session.data_counter()
+    }
+
+    fn increase_data_counter(&mut self, n: usize) {
+        self.session.increase_data_counter(n);
+    }
+
+    fn reset_data_counter(&mut self) {
+        self.session.reset_data_counter();
+    }
+
+    fn increase_ack_retransmit_counter(&mut self) {
+        self.session.increase_ack_retransmit_counter();
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.session.deadline()
+    }
+
+    fn on_event(&mut self, event: &RcvEvent) {
+        self.session.record_event(event);
+        self.sock_ref
+            .trace_event("recv", event.name(), event.packet());
+    }
+
+    fn protocol_violation(&mut self, state: &'static str, event: &RcvEvent) -> Result<()> {
+        self.session.record_violation();
+        self.sock_ref
+            .strictness
+            .check("recv", state, event.name(), event.packet())
+    }
+
+    fn on_transition(&mut self, state: &'static str, event: &'static str, next: &'static str) {
+        self.sock_ref.record_transition("recv", state, event, next);
+    }
+}
+
+#[cfg(all(test, any(feature = "tokio", feature = "smol")))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use std::path::PathBuf;
+
+    /// scratch dir with a file to send
+    fn setup(name: &str) -> (PathBuf, Vec<u8>) {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let content: Vec<u8> = (0..3000u32).map(|i| (i % 253) as u8).collect();
+        fs::write(dir.join("snail.txt"), &content).unwrap();
+        (dir, content)
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_send_and_recv() {
+        let (dir, content) = setup("tokio");
+
+        let mut receiver = AsyncSecSnailSocket::bind::<TokioUdpSocket>("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out_dir = dir.join("out");
+        let recv_task = tokio::spawn(async move { receiver.recv_file(out_dir).await });
+
+        let mut sender = AsyncSecSnailSocket::bind::<TokioUdpSocket>("127.0.0.1:0").unwrap();
+        let sent = sender
+            .send_file(dir.join("snail.txt"), recv_addr)
+            .await
+            .unwrap()
+            .bytes;
+        let report = recv_task.await.unwrap().unwrap();
+
+        assert_eq!(sent, content.len());
+        assert_eq!(report.bytes, content.len());
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_send_is_paced() {
+        let (dir, content) = setup("tokio-paced");
+
+        let mut receiver = AsyncSecSnailSocket::bind::<TokioUdpSocket>("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out_dir = dir.join("out");
+        let recv_task = tokio::spawn(async move { receiver.recv_file(out_dir).await });
+
+        let gap = Duration::from_millis(10);
+        let sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .min_packet_gap(gap)
+            .build()
+            .unwrap();
+        let mut sender = AsyncSecSnailSocket::from_blocking::<TokioUdpSocket>(sender).unwrap();
+        let report = sender
+            .send_file(dir.join("snail.txt"), recv_addr)
+            .await
+            .unwrap();
+        recv_task.await.unwrap().unwrap();
+
+        assert_eq!(report.bytes, content.len());
+        // every data packet after the first waits for the gap
+        assert!(report.stats.paced >= gap);
+        assert!(report.duration >= gap * 2);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_retry_until_receiver_listens() {
+        let (dir, content) = setup("tokio-retry");
+
+        let receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out_dir = dir.join("out");
+        let recv_task = tokio::spawn(async move {
+            // the first attempt times out before the receiver listens
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            let mut receiver = AsyncSecSnailSocket::from_blocking::<TokioUdpSocket>(receiver)?;
+            receiver.recv_file(out_dir).await
+        });
+
+        let sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .snd_timeout(Duration::from_millis(20))
+            .max_retransmits(2)
+            .transfer_retry_policy(10, Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let mut sender = AsyncSecSnailSocket::from_blocking::<TokioUdpSocket>(sender).unwrap();
+        let sent = sender
+            .send_file(dir.join("snail.txt"), recv_addr)
+            .await
+            .unwrap();
+        recv_task.await.unwrap().unwrap();
+
+        assert_eq!(sent.bytes, content.len());
+        assert!(sent.stats.attempts > 1);
+        assert_eq!(
+            sender.last_transfer_stats().unwrap().attempts,
+            sent.stats.attempts
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_journaled_receive_is_resumed() {
+        let (dir, content) = setup("tokio-journal");
+        let journal = dir.join("journal");
+
+        let receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .rcv_timeout(Duration::from_millis(200))
+            .journal(&journal)
+            .build()
+            .unwrap();
+        let mut receiver = AsyncSecSnailSocket::from_blocking::<TokioUdpSocket>(receiver).unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out_dir = dir.join("out");
+        let recv_task = tokio::spawn(async move {
+            let first = receiver.recv_file(&out_dir).await;
+            (first, receiver.recv_file(&out_dir).await)
+        });
+
+        // the sender dies after its first data packet
+        let sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .snd_timeout(Duration::from_millis(20))
+            .max_retransmits(2)
+            .build()
+            .unwrap();
+        let mut sender = AsyncSecSnailSocket::from_blocking::<TokioUdpSocket>(sender).unwrap();
+        let mut data = 0;
+        sender.set_fault_injector(move |_: &str, pck: &Packet| {
+            data += usize::from(pck.flag() == Flag::Data);
+            match data > 1 {
+                true => Fault::Drop,
+                false => Fault::Pass,
+            }
+        });
+        let src = dir.join("snail.txt");
+        assert!(sender.send_file(&src, recv_addr).await.is_err());
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let pending = Journal::open(&journal).unwrap().pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].name, "snail.txt");
+
+        let sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .offer_resume(true)
+            .build()
+            .unwrap();
+        let mut sender = AsyncSecSnailSocket::from_blocking::<TokioUdpSocket>(sender).unwrap();
+        sender.send_file(&src, recv_addr).await.unwrap();
+
+        let (first, second) = recv_task.await.unwrap();
+        assert!(matches!(first, Err(SecSnailError::ConnectionTimeout)));
+        assert!(second.unwrap().resumed_from > 0);
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+        assert!(Journal::open(&journal).unwrap().pending().is_empty());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn tokio_refuse_sender_over_quota() {
+        let (dir, content) = setup("tokio-quota");
+
+        let receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .sender_quota(2000, Duration::from_secs(60))
+            .build()
+            .unwrap();
+        let mut receiver = AsyncSecSnailSocket::from_blocking::<TokioUdpSocket>(receiver).unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out_dir = dir.join("out");
+        let recv_task = tokio::spawn(async move {
+            let first = receiver.recv_file(&out_dir).await;
+            (first, receiver.recv_file(&out_dir).await)
+        });
+
+        let mut sender = AsyncSecSnailSocket::bind::<TokioUdpSocket>("127.0.0.1:0").unwrap();
+        let src = dir.join("snail.txt");
+        assert!(sender.send_file(&src, recv_addr).await.is_ok());
+        let r = sender.send_file(&src, recv_addr).await;
+        assert!(matches!(r, Err(SecSnailError::Rejected(_))));
+
+        let (first, second) = recv_task.await.unwrap();
+        assert_eq!(first.unwrap().bytes, content.len());
+        assert!(matches!(
+            second,
+            Err(SecSnailError::QuotaExceeded { quota: 2000, .. })
+        ));
+    }
+
+    #[cfg(feature = "smol")]
+    #[test]
+    fn smol_send_and_recv() {
+        let (dir, content) = setup("smol");
+
+        smol::block_on(async {
+            let mut receiver = AsyncSecSnailSocket::bind::<SmolUdpSocket>("127.0.0.1:0").unwrap();
+            let recv_addr = receiver.local_addr().unwrap();
+            let out_dir = dir.join("out");
+            let recv_task = smol::spawn(async move { receiver.recv_file(out_dir).await });
+
+            let mut sender = AsyncSecSnailSocket::bind::<SmolUdpSocket>("127.0.0.1:0").unwrap();
+            let sent = sender
+                .send_file(dir.join("snail.txt"), recv_addr)
+                .await
+                .unwrap()
+                .bytes;
+            let report = recv_task.await.unwrap();
+
+            assert_eq!(sent, content.len());
+            assert_eq!(report.bytes, content.len());
+        });
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+    }
+}
@@ -0,0 +1,40 @@
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::Instant,
+};
+
+use smol::{Async, Timer};
+
+use super::{AsyncDatagramSocket, BoxFuture};
+
+/// `AsyncDatagramSocket` adapter for the smol runtime
+pub struct SmolUdpSocket(Async<UdpSocket>);
+
+impl AsyncDatagramSocket for SmolUdpSocket {
+    fn from_std(sock: UdpSocket) -> io::Result<Self> {
+        Ok(SmolUdpSocket(Async::new(sock)?))
+    }
+
+    fn try_send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        // the wrapped socket is non-blocking
+        self.0.get_ref().send_to(buf, addr)
+    }
+
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        Box::pin(self.0.recv_from(buf))
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            Timer::at(deadline).await;
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.get_ref().local_addr()
+    }
+}
@@ -0,0 +1,38 @@
+use std::{
+    io,
+    net::{self, SocketAddr},
+    time::Instant,
+};
+
+use tokio::net::UdpSocket;
+
+use super::{AsyncDatagramSocket, BoxFuture};
+
+/// `AsyncDatagramSocket` adapter for the tokio runtime
+pub struct TokioUdpSocket(UdpSocket);
+
+impl AsyncDatagramSocket for TokioUdpSocket {
+    fn from_std(sock: net::UdpSocket) -> io::Result<Self> {
+        sock.set_nonblocking(true)?;
+        Ok(TokioUdpSocket(UdpSocket::from_std(sock)?))
+    }
+
+    fn try_send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.0.try_send_to(buf, addr)
+    }
+
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, io::Result<(usize, SocketAddr)>> {
+        Box::pin(self.0.recv_from(buf))
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
@@ -0,0 +1,742 @@
+//! Builder for a fully configured `SecSnailSocket`.
+//!
+//! All configuration is validated once in `build()`, so a socket never
+//! starts a transfer with settings that would panic or misbehave at
+//! runtime (e.g. a loss probability of `1.5` or a zero read timeout).
+
+use std::{
+    fs, io,
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, atomic::AtomicBool},
+    time::Duration,
+};
+
+use socket2::SockRef;
+
+use crate::{
+    error::{Result, SecSnailError},
+    impair::{GilbertElliott, Impairment},
+};
+
+use super::{
+    CheckpointPolicy, DEFAULT_MAX_RETRANSMITS, DEFAULT_RCV_TIMEOUT_MS, DEFAULT_SECSNAIL_PORT,
+    DEFAULT_SND_TIMEOUT_MS, DatagramTransport, DecodeMode, IpNet, NamePolicy, OverwritePolicy,
+    SecSnailSocket, Strictness, default_decode_mode, delay::DelayLine, events::Subscribers,
+    filter::PeerFilter, journal::Journal, multicast::bind_reusable, pool::BufferPool,
+    quota::SenderQuota, rcv_ctx::PathResolver,
+};
+
+/// # Examples
+///
+/// ```no_run
+/// use secsnail::sock::SecSnailSocket;
+/// use std::time::Duration;
+///
+/// let secsnail_sock = SecSnailSocket::builder()
+///     .bind("0.0.0.0:3000")
+///     .snd_timeout(Duration::from_millis(20))
+///     .max_retransmits(10)
+///     .loss_p(0.1)
+///     .build()
+///     .unwrap();
+/// ```
+pub struct SecSnailSocketBuilder {
+    /// `None` binds the default port, or an ephemeral one if connected
+    addrs: Option<io::Result<Vec<SocketAddr>>>,
+    peer_addrs: Option<io::Result<Vec<SocketAddr>>>,
+    snd_max_retransmits: u8,
+    snd_timeout: Duration,
+    rcv_timeout: Duration,
+    transfer_deadline: Option<Duration>,
+    max_recv_file_size: Option<u64>,
+    transfer_retries: u32,
+    retry_backoff: Duration,
+    offer_resume: bool,
+    mmap_reads: bool,
+    read_ahead: usize,
+    min_packet_gap: Duration,
+    trusted_link: bool,
+    timestamps: bool,
+    congestion_threshold: Option<Duration>,
+    checkpoints: CheckpointPolicy,
+    sync_on_close: bool,
+    overwrite_policy: OverwritePolicy,
+    name_policy: NamePolicy,
+    path_resolver: Option<PathResolver>,
+    spool_dir: Option<PathBuf>,
+    journal: Option<PathBuf>,
+    strictness: Strictness,
+    decode_mode: Option<DecodeMode>,
+    discovery_name: Option<String>,
+    peer_filter: PeerFilter,
+    /// max bytes and window
+    quota: Option<(u64, Duration)>,
+    /// group and interface to join
+    multicast: Option<(Ipv4Addr, Ipv4Addr)>,
+    os_recv_buffer: Option<usize>,
+    os_send_buffer: Option<usize>,
+    error_p: f64,
+    loss_p: f64,
+    dup_p: f64,
+    burst: Option<GilbertElliott>,
+    rng_seed: Option<u64>,
+    delay: Duration,
+    jitter: Duration,
+}
+
+impl Default for SecSnailSocketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SecSnailSocketBuilder {
+    /// binds to `0.0.0.0:DEFAULT_SECSNAIL_PORT` unless `bind` or `connect` is called
+    pub fn new() -> Self {
+        Self {
+            addrs: None,
+            peer_addrs: None,
+            snd_max_retransmits: DEFAULT_MAX_RETRANSMITS,
+            snd_timeout: Duration::from_millis(DEFAULT_SND_TIMEOUT_MS),
+            rcv_timeout: Duration::from_millis(DEFAULT_RCV_TIMEOUT_MS),
+            transfer_deadline: None,
+            max_recv_file_size: None,
+            transfer_retries: 0,
+            retry_backoff: Duration::ZERO,
+            offer_resume: false,
+            mmap_reads: false,
+            read_ahead: 0,
+            min_packet_gap: Duration::ZERO,
+            trusted_link: false,
+            timestamps: false,
+            congestion_threshold: None,
+            checkpoints: CheckpointPolicy::default(),
+            sync_on_close: false,
+            overwrite_policy: OverwritePolicy::default(),
+            name_policy: NamePolicy::default(),
+            path_resolver: None,
+            spool_dir: None,
+            journal: None,
+            strictness: Strictness::default(),
+            decode_mode: None,
+            discovery_name: None,
+            peer_filter: PeerFilter::default(),
+            quota: None,
+            multicast: None,
+            os_recv_buffer: None,
+            os_send_buffer: None,
+            error_p: 0.0,
+            loss_p: 0.0,
+            dup_p: 0.0,
+            burst: None,
+            rng_seed: None,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// address resolution errors are reported by `build`
+    pub fn bind<A: ToSocketAddrs>(mut self, addr: A) -> Self {
+        self.addrs = Some(addr.to_socket_addrs().map(|addrs| addrs.collect()));
+        self
+    }
+
+    /// fix the peer, packets of other addresses are dropped by the os
+    ///
+    /// binds an ephemeral port unless `bind` is called
+    pub fn connect<A: ToSocketAddrs>(mut self, addr: A) -> Self {
+        self.peer_addrs = Some(addr.to_socket_addrs().map(|addrs| addrs.collect()));
+        self
+    }
+
+    /// retransmission timeout of the sender
+    pub fn snd_timeout(mut self, timeout: Duration) -> Self {
+        self.snd_timeout = timeout;
+        self
+    }
+
+    /// connection timeout of the receiver
+    pub fn rcv_timeout(mut self, timeout: Duration) -> Self {
+        self.rcv_timeout = timeout;
+        self
+    }
+
+    /// wall-clock limit of a whole transfer, after which it fails with
+    /// `SecSnailError::DeadlineExceeded`, unlimited unless set
+    ///
+    /// a send starts with the call, a receive with the syn of the sender
+    pub fn transfer_deadline(mut self, deadline: Duration) -> Self {
+        self.transfer_deadline = Some(deadline);
+        self
+    }
+
+    /// refuse files larger than `max` bytes with a rst, the sender fails
+    /// with `SecSnailError::Rejected` and the receiver with `FileTooLarge`
+    ///
+    /// the size announced in the syn is checked up front, a sender which
+    /// announced none or a wrong one is cut off once it exceeds the limit
+    pub fn max_recv_file_size(mut self, max: u64) -> Self {
+        self.max_recv_file_size = Some(max);
+        self
+    }
+
+    /// start a send over up to `retries` times after it failed with
+    /// `MaxRetransmitsExceeded` or `ConnectionTimeout`, waiting `backoff`
+    /// before the first retry and twice as long before every further one
+    ///
+    /// with `offer_resume` a retry continues at the bytes the receiver
+    /// kept, the attempts are counted in `last_transfer_stats`
+    pub fn transfer_retry_policy(mut self, retries: u32, backoff: Duration) -> Self {
+        self.transfer_retries = retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// offer the receiver to continue an interrupted transfer of the same
+    /// file name, it resumes if its `OverwritePolicy` is `Resume`
+    pub fn offer_resume(mut self, offer_resume: bool) -> Self {
+        self.offer_resume = offer_resume;
+        self
+    }
+
+    /// slice the payloads of a sent file out of a memory mapping instead
+    /// of reading them through a buffer, saves copies on large files
+    ///
+    /// off by default, a file truncated while it is sent kills the process
+    /// with `SIGBUS` instead of failing the transfer
+    pub fn mmap_reads(mut self, mmap_reads: bool) -> Self {
+        self.mmap_reads = mmap_reads;
+        self
+    }
+
+    /// skip the checksums of all packets after the syn, e.g. on loopback or
+    /// a lower layer which already guarantees integrity
+    ///
+    /// a sender asks for it in its syn, a receiver agrees in its ack if it
+    /// enables the mode too, otherwise the transfer is checked as usual,
+    /// see `TransferStats::trusted_link`; streams are always checked
+    pub fn trusted_link(mut self, trusted_link: bool) -> Self {
+        self.trusted_link = trusted_link;
+        self
+    }
+
+    /// stamp sent data packets with the time they were sent and echo the
+    /// stamps of received ones, negotiated like a trusted link
+    ///
+    /// the sender learns how long its packets spent queued on the way, see
+    /// `SendReport::queueing_delay`, for 4 bytes less file data per packet
+    pub fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    /// flag the ack of a data packet whose write took longer than
+    /// `threshold`, e.g. to a slow disk, never flagged unless set
+    ///
+    /// the sender waits a gap before its next data packets, which grows
+    /// with every flagged ack and shrinks again with every other, see
+    /// `TransferStats::congestion_signals`
+    pub fn congestion_threshold(mut self, threshold: Duration) -> Self {
+        self.congestion_threshold = Some(threshold);
+        self
+    }
+
+    /// flush and sync a received file to disk whenever `policy` says so,
+    /// e.g. every few megabytes or minutes of a long, slow transfer, a
+    /// journaled file notes every checkpoint, see `journal`
+    ///
+    /// a power failure loses only the bytes since the last checkpoint,
+    /// counted in `TransferStats::checkpoints`, by default files are only
+    /// synced by the operating system
+    pub fn checkpoints(mut self, policy: CheckpointPolicy) -> Self {
+        self.checkpoints = policy;
+        self
+    }
+
+    /// sync a received file to disk before its finack confirms it to the
+    /// sender, and its directory once it is renamed into place, so a
+    /// completed transfer survives a power failure right after it
+    ///
+    /// off by default, as a sync may take long on a slow disk
+    pub fn sync_on_close(mut self, sync_on_close: bool) -> Self {
+        self.sync_on_close = sync_on_close;
+        self
+    }
+
+    /// read up to `chunks` payloads of a sent file or stream ahead in a
+    /// background thread while waiting for acks, so a slow disk or network
+    /// share does not stall the wire, `0` reads every payload on demand
+    ///
+    /// off by default, ignored with `mmap_reads`
+    pub fn read_ahead(mut self, chunks: usize) -> Self {
+        self.read_ahead = chunks;
+        self
+    }
+
+    /// wait at least `gap` between two sent packets, so a link with a
+    /// short round trip does not overflow a small router queue, the time
+    /// waited is counted in `TransferStats::paced`
+    ///
+    /// zero by default
+    pub fn min_packet_gap(mut self, gap: Duration) -> Self {
+        self.min_packet_gap = gap;
+        self
+    }
+
+    /// keep or remove the partial file of an interrupted transfer
+    pub fn overwrite_policy(mut self, policy: OverwritePolicy) -> Self {
+        self.overwrite_policy = policy;
+        self
+    }
+
+    /// store received files with characters outside a portable set under
+    /// a transliterated or escaped name, names are always NFC
+    pub fn name_policy(mut self, policy: NamePolicy) -> Self {
+        self.name_policy = policy;
+        self
+    }
+
+    /// store every received file at the path `resolver` returns for its
+    /// sender, announced name and size, e.g. to sort files into a directory
+    /// per sender or per day
+    ///
+    /// a relative path is below the target directory, the name policy does
+    /// not apply, and missing directories are created
+    pub fn path_resolver<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(SocketAddr, &str, Option<u64>) -> PathBuf + Send + Sync + 'static,
+    {
+        self.path_resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// write received files into `dir` until they are complete and only
+    /// then move them into the target dir, so whatever watches the target
+    /// dir never sees a file being written
+    ///
+    /// `dir` is created by `build` if missing and may be on another file
+    /// system, a file is copied into place then
+    pub fn spool_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.spool_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// note sent and received files in the journal at `path` until they
+    /// are done, see `SecSnailSocket::resume_pending_transfers`
+    ///
+    /// opened by `build`, transfers a crashed process left are read from it
+    pub fn journal<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.journal = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// ignore or abort on packets the fsm has no transition for, e.g. an ack
+    /// arriving at a receiver
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// how received datagrams are checked before the fsm sees them, a
+    /// datagram failing a strict decode is dropped like a corrupt one
+    ///
+    /// defaults to `DecodeMode::Strict` on a socket bound to a public or
+    /// the unspecified address, lenient on loopback and private networks
+    pub fn decode_mode(mut self, mode: DecodeMode) -> Self {
+        self.decode_mode = Some(mode);
+        self
+    }
+
+    /// only accept datagrams from senders in `nets`, all others are dropped
+    /// silently, e.g. before a syn engages the fsm
+    ///
+    /// applies to every received datagram, so a sending socket must allow its receiver
+    pub fn allowed_senders(mut self, nets: Vec<IpNet>) -> Self {
+        self.peer_filter.allowed = Some(nets);
+        self
+    }
+
+    /// drop datagrams from senders in `nets` silently, checked after `allowed_senders`
+    pub fn denied_senders(mut self, nets: Vec<IpNet>) -> Self {
+        self.peer_filter.denied = nets;
+        self
+    }
+
+    /// refuse the syn of a sender which sent `max_bytes` or more within the
+    /// last `window`, it fails with `SecSnailError::Rejected`
+    ///
+    /// senders are told apart by ip address, a running transfer is never cut
+    /// off, the quota also applies to an `AsyncSecSnailSocket` taken over
+    /// with `from_blocking`
+    pub fn sender_quota(mut self, max_bytes: u64, window: Duration) -> Self {
+        self.quota = Some((max_bytes, window));
+        self
+    }
+
+    /// answer discovery probes of `SecSnailSocket::discover` with `name`
+    /// while receiving
+    pub fn discoverable<S: Into<String>>(mut self, name: S) -> Self {
+        self.discovery_name = Some(name.into());
+        self
+    }
+
+    /// size of the kernel's receive buffer, `SO_RCVBUF`, datagrams arriving
+    /// while it is full are dropped
+    ///
+    /// the kernel may round or cap `size`, e.g. Linux doubles it and caps
+    /// it at `net.core.rmem_max`, `SecSnailSocket::os_recv_buffer` returns
+    /// the actual size
+    pub fn os_recv_buffer(mut self, size: usize) -> Self {
+        self.os_recv_buffer = Some(size);
+        self
+    }
+
+    /// size of the kernel's send buffer, `SO_SNDBUF`, see `os_recv_buffer`
+    pub fn os_send_buffer(mut self, size: usize) -> Self {
+        self.os_send_buffer = Some(size);
+        self
+    }
+
+    /// join the multicast `group` on `interface`, the bound address is reused
+    pub fn join_multicast(mut self, group: Ipv4Addr, interface: Ipv4Addr) -> Self {
+        self.multicast = Some((group, interface));
+        self
+    }
+
+    pub fn max_retransmits(mut self, max: u8) -> Self {
+        self.snd_max_retransmits = max;
+        self
+    }
+
+    /// probability of a sent packet getting lost
+    pub fn loss_p(mut self, p: f64) -> Self {
+        self.loss_p = p;
+        self
+    }
+
+    /// probability of a single bit flip in a sent packet
+    pub fn error_p(mut self, p: f64) -> Self {
+        self.error_p = p;
+        self
+    }
+
+    /// probability of a sent packet getting duplicated
+    pub fn dup_p(mut self, p: f64) -> Self {
+        self.dup_p = p;
+        self
+    }
+
+    /// lose packets in bursts after the Gilbert-Elliott model, replaces `loss_p`
+    pub fn burst_loss(mut self, model: GilbertElliott) -> Self {
+        self.burst = Some(model);
+        self
+    }
+
+    /// seed the rng of the impairment, so the same losses, bit flips,
+    /// duplicates and delays are drawn in every run
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.rng_seed = Some(seed);
+        self
+    }
+
+    /// fixed delay of every sent datagram, datagrams are sent by a
+    /// background thread then
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// upper bound of a uniformly distributed delay on top of `delay`
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn build(mut self) -> Result<SecSnailSocket> {
+        self.validate()?;
+
+        let peer_addrs = self.peer_addrs.take().transpose()?;
+        let addrs = match (self.addrs.take(), &peer_addrs) {
+            (Some(addrs), _) => addrs?,
+            // ephemeral port of the same address family as the peer
+            (None, Some(peer_addrs)) => peer_addrs
+                .iter()
+                .map(|peer| match peer {
+                    SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+                    SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+                })
+                .collect(),
+            (None, None) => vec![SocketAddr::from(([0, 0, 0, 0], DEFAULT_SECSNAIL_PORT))],
+        };
+        let inner = match self.multicast {
+            Some((group, interface)) => {
+                let inner = bind_reusable(addrs[0])?;
+                inner.join_multicast_v4(&group, &interface)?;
+                inner
+            }
+            None => UdpSocket::bind(&addrs[..])?,
+        };
+        if let Some(peer_addrs) = peer_addrs {
+            inner.connect(&peer_addrs[..])?;
+        }
+        if let Some(size) = self.os_recv_buffer {
+            SockRef::from(&inner).set_recv_buffer_size(size)?;
+        }
+        if let Some(size) = self.os_send_buffer {
+            SockRef::from(&inner).set_send_buffer_size(size)?;
+        }
+        let peer = inner.peer_addr().ok();
+        let delay_line = match self.delay.is_zero() && self.jitter.is_zero() {
+            true => None,
+            false => Some(DelayLine::spawn(inner.try_clone()?, peer)?),
+        };
+
+        self.assemble(inner, peer, delay_line)
+    }
+
+    /// build a socket on top of another datagram transport
+    ///
+    /// the transport is already bound, so `bind`, `connect` and
+    /// `join_multicast` are rejected, as well as `delay` and `jitter`,
+    /// which need a udp socket to send from a background thread, and the
+    /// buffer sizes of the os socket
+    pub fn build_with_transport<T: DatagramTransport>(
+        self,
+        transport: T,
+    ) -> Result<SecSnailSocket<T>> {
+        self.validate()?;
+        if self.addrs.is_some() || self.peer_addrs.is_some() || self.multicast.is_some() {
+            return Err(invalid_config(
+                "bind, connect and join_multicast do not apply to a custom transport",
+            ));
+        }
+        if !self.delay.is_zero() || !self.jitter.is_zero() {
+            return Err(invalid_config(
+                "delay and jitter are not supported on a custom transport",
+            ));
+        }
+        if self.os_recv_buffer.is_some() || self.os_send_buffer.is_some() {
+            return Err(invalid_config(
+                "os buffer sizes do not apply to a custom transport",
+            ));
+        }
+        self.assemble(transport, None, None)
+    }
+
+    fn assemble<T: DatagramTransport>(
+        self,
+        inner: T,
+        peer: Option<SocketAddr>,
+        delay_line: Option<DelayLine>,
+    ) -> Result<SecSnailSocket<T>> {
+        let spool_dir = match &self.spool_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir)?;
+                Some(fs::canonicalize(dir)?)
+            }
+            None => None,
+        };
+        let journal = match &self.journal {
+            Some(path) => Some(Arc::new(Journal::open(path)?)),
+            None => None,
+        };
+        let mut impairment = Impairment {
+            loss_p: self.loss_p,
+            error_p: self.error_p,
+            dup_p: self.dup_p,
+            burst: self.burst,
+            delay: self.delay,
+            jitter: self.jitter,
+            ..Impairment::default()
+        };
+        if let Some(seed) = self.rng_seed {
+            impairment.seed(seed);
+        }
+        let decode_mode = self
+            .decode_mode
+            .unwrap_or_else(|| default_decode_mode(inner.local_addr()));
+
+        Ok(SecSnailSocket {
+            inner,
+            snd_max_retransmits: self.snd_max_retransmits,
+            snd_timeout_config: self.snd_timeout,
+            rcv_timeout_config: self.rcv_timeout,
+            transfer_deadline: self.transfer_deadline,
+            transfer_retries: self.transfer_retries,
+            retry_backoff: self.retry_backoff,
+            max_recv_file_size: self.max_recv_file_size,
+            offer_resume: self.offer_resume,
+            mmap_reads: self.mmap_reads,
+            read_ahead: self.read_ahead,
+            min_packet_gap: self.min_packet_gap,
+            trusted_link: self.trusted_link,
+            timestamps: self.timestamps,
+            congestion_threshold: self.congestion_threshold,
+            checkpoints: self.checkpoints,
+            sync_on_close: self.sync_on_close,
+            spool_dir,
+            overwrite_policy: self.overwrite_policy,
+            name_policy: self.name_policy,
+            path_resolver: self.path_resolver,
+            journal,
+            strictness: self.strictness,
+            decode_mode,
+            discovery_name: self.discovery_name,
+            peer_filter: self.peer_filter,
+            quota: self
+                .quota
+                .map(|(max_bytes, window)| Mutex::new(SenderQuota::new(max_bytes, window))),
+            impairment,
+            delay_line,
+            peer,
+            nonblocking: false,
+            pending_snd: None,
+            pending_rcv: None,
+            last_stats: None,
+            totals: Arc::default(),
+            active: Mutex::default(),
+            subscribers: Subscribers::default(),
+            capture: None,
+            trace: None,
+            history: None,
+            faults: None,
+            progress: None,
+            recv_bufs: BufferPool::default(),
+            send_bufs: BufferPool::default(),
+            stop: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    fn validate(&self) -> Result<()> {
+        let burst = self.burst.iter().flat_map(|ge| ge.probabilities());
+        check_probabilities(
+            [
+                ("loss_p", self.loss_p),
+                ("error_p", self.error_p),
+                ("dup_p", self.dup_p),
+            ]
+            .into_iter()
+            .chain(burst),
+        )?;
+
+        // a zero read timeout is rejected by the udp socket itself
+        if self.snd_timeout.is_zero() {
+            return Err(invalid_config("snd_timeout must be greater than zero"));
+        }
+        if self.rcv_timeout.is_zero() {
+            return Err(invalid_config("rcv_timeout must be greater than zero"));
+        }
+        if self.transfer_deadline.is_some_and(|d| d.is_zero()) {
+            return Err(invalid_config(
+                "transfer_deadline must be greater than zero",
+            ));
+        }
+        if self.quota.is_some_and(|(_, window)| window.is_zero()) {
+            return Err(invalid_config("quota window must be greater than zero"));
+        }
+
+        Ok(())
+    }
+}
+
+/// every named value has to be a probability in 0..=1
+fn check_probabilities<'a>(probabilities: impl IntoIterator<Item = (&'a str, f64)>) -> Result<()> {
+    for (name, p) in probabilities {
+        if !(0.0..=1.0).contains(&p) {
+            return Err(invalid_config(format!(
+                "{name} must be a probability in 0..=1, got {p}"
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn invalid_config<S: Into<String>>(msg: S) -> SecSnailError {
+    SecSnailError::InvalidConfig(msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_probabilities() {
+        assert!(SecSnailSocketBuilder::new().loss_p(1.5).validate().is_err());
+        assert!(
+            SecSnailSocketBuilder::new()
+                .error_p(-0.1)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            SecSnailSocketBuilder::new()
+                .dup_p(f64::NAN)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            SecSnailSocketBuilder::new()
+                .burst_loss(GilbertElliott::new(0.1, 2.0))
+                .validate()
+                .is_err()
+        );
+        assert!(
+            SecSnailSocketBuilder::new()
+                .loss_p(1.0)
+                .error_p(0.0)
+                .dup_p(0.5)
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_zero_timeouts() {
+        assert!(
+            SecSnailSocketBuilder::new()
+                .snd_timeout(Duration::ZERO)
+                .validate()
+                .is_err()
+        );
+        assert!(
+            SecSnailSocketBuilder::new()
+                .rcv_timeout(Duration::ZERO)
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_zero_deadline() {
+        assert!(
+            SecSnailSocketBuilder::new()
+                .transfer_deadline(Duration::ZERO)
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_zero_quota_window() {
+        assert!(
+            SecSnailSocketBuilder::new()
+                .sender_quota(1000, Duration::ZERO)
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn builds_with_configuration() {
+        let sock = SecSnailSocketBuilder::new()
+            .bind("127.0.0.1:0")
+            .max_retransmits(7)
+            .snd_timeout(Duration::from_millis(42))
+            .build()
+            .unwrap();
+
+        assert_eq!(sock.snd_max_retransmits, 7);
+        assert_eq!(sock.snd_timeout_config, Duration::from_millis(42));
+    }
+}
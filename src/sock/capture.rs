@@ -0,0 +1,259 @@
+//! Capture of all datagrams of a socket into a pcapng file.
+//!
+//! Every datagram is wrapped into synthetic ip and udp headers of the
+//! local and the remote address, so Wireshark shows the peers of each
+//! packet. The direction is stored in the flags of each packet block.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// raw ip packets, version taken from the first nibble
+const LINKTYPE_RAW: u16 = 101;
+const OPT_EPB_FLAGS: u16 = 2;
+
+const IP_PROTO_UDP: u8 = 17;
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Direction {
+    Inbound,
+    Outbound,
+}
+
+pub(super) struct Capture {
+    wrt: Mutex<BufWriter<File>>,
+    /// address of the capturing socket
+    local: SocketAddr,
+}
+
+impl Capture {
+    pub fn create<P: AsRef<Path>>(path: P, local: SocketAddr) -> io::Result<Capture> {
+        let mut wrt = BufWriter::new(File::create(path)?);
+
+        // section header, version 1.0 of unspecified length
+        let mut shb = Vec::new();
+        shb.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend(1u16.to_le_bytes());
+        shb.extend(0u16.to_le_bytes());
+        shb.extend((-1i64).to_le_bytes());
+        write_block(&mut wrt, SECTION_HEADER_BLOCK, &shb)?;
+
+        // single interface without snap length limit
+        let mut idb = Vec::new();
+        idb.extend(LINKTYPE_RAW.to_le_bytes());
+        idb.extend(0u16.to_le_bytes());
+        idb.extend(0u32.to_le_bytes());
+        write_block(&mut wrt, INTERFACE_DESCRIPTION_BLOCK, &idb)?;
+        wrt.flush()?;
+
+        Ok(Capture {
+            wrt: Mutex::new(wrt),
+            local,
+        })
+    }
+
+    /// record `datagram` exchanged with `remote`
+    pub fn record(
+        &self,
+        direction: Direction,
+        remote: SocketAddr,
+        datagram: &[u8],
+    ) -> io::Result<()> {
+        let (src, dst) = match direction {
+            Direction::Inbound => (remote, self.local),
+            Direction::Outbound => (self.local, remote),
+        };
+        let pkt = ip_udp_packet(src, dst, datagram);
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let mut epb = Vec::with_capacity(pkt.len() + 32);
+        epb.extend(0u32.to_le_bytes());
+        epb.extend(((ts >> 32) as u32).to_le_bytes());
+        epb.extend((ts as u32).to_le_bytes());
+        epb.extend((pkt.len() as u32).to_le_bytes());
+        epb.extend((pkt.len() as u32).to_le_bytes());
+        epb.extend(&pkt);
+        pad(&mut epb);
+        let flags: u32 = match direction {
+            Direction::Inbound => 1,
+            Direction::Outbound => 2,
+        };
+        epb.extend(OPT_EPB_FLAGS.to_le_bytes());
+        epb.extend(4u16.to_le_bytes());
+        epb.extend(flags.to_le_bytes());
+        // end of options
+        epb.extend([0; 4]);
+
+        let mut wrt = self.wrt.lock().unwrap();
+        write_block(&mut *wrt, ENHANCED_PACKET_BLOCK, &epb)?;
+        wrt.flush()
+    }
+}
+
+fn write_block<W: Write>(wrt: &mut W, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_len = (body.len() + 12) as u32;
+    wrt.write_all(&block_type.to_le_bytes())?;
+    wrt.write_all(&total_len.to_le_bytes())?;
+    wrt.write_all(body)?;
+    wrt.write_all(&total_len.to_le_bytes())
+}
+
+/// pad to a multiple of 32 bit
+fn pad(buf: &mut Vec<u8>) {
+    buf.resize(buf.len().next_multiple_of(4), 0);
+}
+
+/// ip packet of the address family of `dst` with an udp datagram
+fn ip_udp_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = (8 + payload.len()) as u16;
+    let mut udp = Vec::with_capacity(udp_len as usize);
+    udp.extend(src.port().to_be_bytes());
+    udp.extend(dst.port().to_be_bytes());
+    udp.extend(udp_len.to_be_bytes());
+    udp.extend([0, 0]);
+    udp.extend(payload);
+
+    match dst.ip() {
+        IpAddr::V4(dst_ip) => {
+            let src_ip = match src.ip() {
+                IpAddr::V4(ip) => ip,
+                IpAddr::V6(ip) => ip.to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            };
+            // the udp checksum is optional over ipv4
+            let mut ip = Vec::with_capacity(20 + udp.len());
+            ip.extend([0x45, 0]);
+            ip.extend((20 + udp_len).to_be_bytes());
+            ip.extend([0, 0, 0x40, 0, 64, IP_PROTO_UDP, 0, 0]);
+            ip.extend(src_ip.octets());
+            ip.extend(dst_ip.octets());
+            let checksum = internet_checksum(&ip);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+            ip.extend(udp);
+            ip
+        }
+        IpAddr::V6(dst_ip) => {
+            let src_ip = match src.ip() {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let checksum = udp_checksum_v6(src_ip, dst_ip, &udp);
+            udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+            let mut ip = Vec::with_capacity(40 + udp.len());
+            ip.extend([0x60, 0, 0, 0]);
+            ip.extend(udp_len.to_be_bytes());
+            ip.extend([IP_PROTO_UDP, 64]);
+            ip.extend(src_ip.octets());
+            ip.extend(dst_ip.octets());
+            ip.extend(udp);
+            ip
+        }
+    }
+}
+
+/// mandatory over ipv6, computed with the pseudo header
+fn udp_checksum_v6(src: Ipv6Addr, dst: Ipv6Addr, udp: &[u8]) -> u16 {
+    let mut pseudo = Vec::with_capacity(40 + udp.len());
+    pseudo.extend(src.octets());
+    pseudo.extend(dst.octets());
+    pseudo.extend((udp.len() as u32).to_be_bytes());
+    pseudo.extend([0, 0, 0, IP_PROTO_UDP]);
+    pseudo.extend(udp);
+    match internet_checksum(&pseudo) {
+        0 => 0xFFFF,
+        c => c,
+    }
+}
+
+/// ones' complement sum of rfc 1071
+fn internet_checksum(buf: &[u8]) -> u16 {
+    let mut sum: u32 = buf
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sock::SecSnailSocket;
+    use std::{fs, thread};
+
+    /// direction flags of all enhanced packet blocks
+    fn packet_directions(capture: &[u8]) -> Vec<u32> {
+        let mut flags = Vec::new();
+        let mut rest = capture;
+        while rest.len() >= 12 {
+            let block_type = u32::from_le_bytes(rest[0..4].try_into().unwrap());
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            if block_type == ENHANCED_PACKET_BLOCK {
+                // flag option directly after the padded packet data
+                let captured = u32::from_le_bytes(rest[20..24].try_into().unwrap()) as usize;
+                let opt = 28 + captured.next_multiple_of(4);
+                flags.push(u32::from_le_bytes(
+                    rest[opt + 4..opt + 8].try_into().unwrap(),
+                ));
+            }
+            rest = &rest[len..];
+        }
+        flags
+    }
+
+    #[test]
+    fn captures_both_directions() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-capture", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("snail.txt");
+        fs::write(&src, vec![7u8; 2500]).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        sender.set_capture_file(dir.join("snail.pcapng")).unwrap();
+        sender.send_file_to_blocking(&src, recv_addr).unwrap();
+        recv.join().unwrap();
+
+        let capture = fs::read(dir.join("snail.pcapng")).unwrap();
+        assert_eq!(&capture[0..4], &SECTION_HEADER_BLOCK.to_le_bytes());
+        let directions = packet_directions(&capture);
+        let stats = sender.last_transfer_stats().unwrap();
+        assert_eq!(
+            directions.iter().filter(|f| **f == 2).count(),
+            stats.packets_sent
+        );
+        assert_eq!(
+            directions.iter().filter(|f| **f == 1).count(),
+            stats.packets_sent
+        );
+    }
+
+    #[test]
+    fn ipv4_header_checksum() {
+        let pkt = ip_udp_packet(
+            "10.0.0.1:4000".parse().unwrap(),
+            "10.0.0.2:55055".parse().unwrap(),
+            b"snail",
+        );
+        assert_eq!(internet_checksum(&pkt[..20]), 0);
+    }
+}
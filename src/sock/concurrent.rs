@@ -0,0 +1,236 @@
+//! Concurrent sends from a single bound socket.
+//!
+//! Every transfer in flight gets a transfer id of its own, which its
+//! receiver echoes in every reply. The calling thread receives every
+//! datagram from the socket and hands it to the worker of its transfer id
+//! through a channel, like `serve_threaded` does for the sessions of a
+//! receiver, and a worker takes the next file once its transfer is done.
+
+use std::{
+    io,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        Mutex,
+        mpsc::{self, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{error::Result, fsm_send::driver::run_snd_fsm_loop, pck::MAX_TRANSFER_ID};
+
+use super::{
+    DatagramTransport, SecSnailSocket, SendOutcome, SendReport, TransferDirection,
+    log_send_outcome,
+    snd_ctx::{Inbox, SendProtocolIoContext},
+};
+
+/// how often the dispatching thread checks for finished workers while idle
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl<T: DatagramTransport + Sync> SecSnailSocket<T> {
+    /// send every file of `files` to its receiver, up to `MAX_TRANSFER_ID + 1`
+    /// at a time, e.g. several files to a receiver which `serve`s
+    ///
+    /// a receiver which runs a single session at a time, e.g.
+    /// `recv_file_blocking`, is restarted by every further file, a file which
+    /// failed is not retried
+    ///
+    /// # Return
+    /// the result of every file in the order of `files`, a file which failed
+    /// does not stop the others, fails only if the socket fails
+    pub fn send_concurrently_blocking<P: AsRef<Path> + Sync>(
+        &mut self,
+        files: &[(P, SocketAddr)],
+    ) -> Result<Vec<SendOutcome>> {
+        let workers = files.len().min(usize::from(MAX_TRANSFER_ID) + 1);
+        let queue = Mutex::new(files.iter().enumerate());
+        let mut results: Vec<Option<Result<SendReport>>> = files.iter().map(|_| None).collect();
+
+        let sock = &*self;
+        let mut last_stats = None;
+        let r: Result<()> = thread::scope(|scope| {
+            let (done_tx, done_rx) = mpsc::channel();
+            // dropping an inbox aborts the transfer of its worker
+            let mut inboxes: Vec<Sender<_>> = Vec::new();
+            for transfer_id in 0..workers as u8 {
+                let (inbox_tx, inbox) = mpsc::channel();
+                inboxes.push(inbox_tx);
+                let (queue, done_tx) = (&queue, done_tx.clone());
+                thread::Builder::new()
+                    .name(format!("secsnail-send-{transfer_id}"))
+                    .spawn_scoped(scope, move || {
+                        loop {
+                            let next = queue.lock().unwrap().next();
+                            let Some((i, (path, recv_addr))) = next else {
+                                return;
+                            };
+                            let r = sock.send_from_inbox(
+                                path.as_ref(),
+                                *recv_addr,
+                                transfer_id,
+                                &inbox,
+                            );
+                            _ = done_tx.send((i, r));
+                        }
+                    })?;
+            }
+
+            let mut done = 0;
+            let r = loop {
+                while let Ok((i, r)) = done_rx.try_recv() {
+                    last_stats = r
+                        .as_ref()
+                        .map_or(last_stats, |r: &SendReport| Some(r.stats));
+                    results[i] = Some(r);
+                    done += 1;
+                }
+                if done == files.len() {
+                    break Ok(());
+                }
+
+                if let Err(e) = sock.inner.set_read_timeout(Some(POLL_INTERVAL)) {
+                    break Err(e);
+                }
+                let (src, rcvpkt) = match sock.rdt_recv() {
+                    Ok(v) => v,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => break Err(e),
+                };
+                // a datagram which can not be decoded has no transfer id
+                if let Some(inbox) = rcvpkt
+                    .as_ref()
+                    .and_then(|p| inboxes.get(usize::from(p.transfer_id())))
+                {
+                    _ = inbox.send((src, rcvpkt));
+                }
+            };
+            if r.is_err() {
+                // no worker takes a further file
+                queue.lock().unwrap().by_ref().for_each(drop);
+            }
+            Ok(r?)
+        });
+        if last_stats.is_some() {
+            self.last_stats = last_stats;
+        }
+        r?;
+
+        Ok(files
+            .iter()
+            .zip(results)
+            .map(|((path, _), r)| {
+                let r = r.expect("every file was sent");
+                (path.as_ref().to_path_buf(), r)
+            })
+            .collect())
+    }
+
+    /// send `path` as transfer `transfer_id`, whose replies are dispatched
+    /// into `inbox`
+    fn send_from_inbox(
+        &self,
+        path: &Path,
+        recv_addr: SocketAddr,
+        transfer_id: u8,
+        inbox: &Inbox,
+    ) -> Result<SendReport> {
+        let _span = tracing::info_span!(
+            "send_file",
+            file = %path.display(),
+            peer = %recv_addr,
+            transfer_id
+        )
+        .entered();
+        let mut session = self
+            .new_send_session(path, recv_addr, self.inner.now())?
+            .with_transfer_id(transfer_id);
+        let mut ctx = SendProtocolIoContext::with_inbox(self, &mut session, inbox);
+        let ret = run_snd_fsm_loop(&mut ctx, self.snd_max_retransmits)
+            .map(|(_, duration)| session.report(duration));
+        let stats = session.stats();
+        self.totals.lock().unwrap().record_send(&stats);
+        self.notify_ended(
+            TransferDirection::Send,
+            Some(recv_addr),
+            transfer_id,
+            &stats,
+            ret.as_ref().err(),
+        );
+        log_send_outcome(&ret);
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, ops::ControlFlow, thread};
+
+    use crate::sock::{SecSnailSocket, SnailEvent};
+
+    #[test]
+    fn concurrent_sends_to_one_receiver() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-concurrent", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let events = receiver.subscribe();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            let mut received = Vec::new();
+            receiver
+                .serve(out, |report| {
+                    received.push(report.file_name);
+                    match received.len() {
+                        3 => ControlFlow::Break(()),
+                        _ => ControlFlow::Continue(()),
+                    }
+                })
+                .unwrap();
+            received
+        });
+
+        let files: Vec<_> = ["a.bin", "b.bin", "c.bin"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let content: Vec<u8> = (0..20_000u32)
+                    .map(|j| (j as usize * (i + 1) % 251) as u8)
+                    .collect();
+                fs::write(dir.join(name), content).unwrap();
+                (dir.join(name), recv_addr)
+            })
+            .collect();
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let results = sender.send_concurrently_blocking(&files).unwrap();
+
+        let mut received = recv.join().unwrap();
+        received.sort();
+        assert_eq!(received, ["a.bin", "b.bin", "c.bin"]);
+        for ((path, r), (file, _)) in results.into_iter().zip(&files) {
+            assert_eq!(&path, file);
+            assert_eq!(r.unwrap().bytes, 20_000);
+            let name = path.file_name().unwrap();
+            assert_eq!(
+                fs::read(dir.join("out").join(name)).unwrap(),
+                fs::read(&path).unwrap()
+            );
+        }
+        assert_eq!(sender.stats_snapshot().active, 0);
+
+        // all three files were open on the receiver at the same time
+        let events: Vec<_> = events.iter().collect();
+        let first_completed = events
+            .iter()
+            .position(|e| matches!(e, SnailEvent::Completed { .. }))
+            .unwrap();
+        let started = events[..first_completed]
+            .iter()
+            .filter(|e| matches!(e, SnailEvent::TransferStarted { .. }))
+            .count();
+        assert_eq!(started, 3);
+    }
+}
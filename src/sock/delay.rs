@@ -0,0 +1,88 @@
+//! Delayed sending of impaired datagrams.
+//!
+//! A `DelayLine` owns a clone of the udp socket and a background thread,
+//! which puts every datagram on the wire once its delay elapsed. With
+//! jitter, later datagrams may overtake earlier ones, like on a real link.
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Instant,
+};
+
+/// datagram with its send time, ordered by send time and then by submission
+type Delayed = Reverse<(Instant, u64, Vec<u8>, SocketAddr)>;
+
+pub(super) struct DelayLine {
+    tx: Sender<(Instant, Vec<u8>, SocketAddr)>,
+}
+
+impl DelayLine {
+    /// `peer` of a connected socket is sent to with `send`
+    pub fn spawn(sock: UdpSocket, peer: Option<SocketAddr>) -> io::Result<DelayLine> {
+        let (tx, rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("secsnail-delay".to_string())
+            .spawn(move || run(sock, peer, rx))?;
+        Ok(DelayLine { tx })
+    }
+
+    /// send `pkt` to `addr` at `at`
+    pub fn send_at(&self, at: Instant, pkt: Vec<u8>, addr: SocketAddr) {
+        // the thread only ends once the line is dropped
+        _ = self.tx.send((at, pkt, addr));
+    }
+}
+
+/// pending datagrams are still sent after the line got dropped
+fn run(sock: UdpSocket, peer: Option<SocketAddr>, rx: Receiver<(Instant, Vec<u8>, SocketAddr)>) {
+    let mut queue: BinaryHeap<Delayed> = BinaryHeap::new();
+    let mut submitted: u64 = 0;
+    let mut open = true;
+
+    while open || !queue.is_empty() {
+        let next = queue.peek().map(|Reverse((at, ..))| *at);
+        let wait = next.map(|at| at.saturating_duration_since(Instant::now()));
+        let received = match (wait, open) {
+            (Some(wait), true) => match rx.recv_timeout(wait) {
+                Ok(v) => Some(v),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => {
+                    open = false;
+                    None
+                }
+            },
+            (None, true) => match rx.recv() {
+                Ok(v) => Some(v),
+                Err(_) => {
+                    open = false;
+                    None
+                }
+            },
+            (Some(wait), false) => {
+                thread::sleep(wait);
+                None
+            }
+            (None, false) => break,
+        };
+        if let Some((at, pkt, addr)) = received {
+            submitted += 1;
+            queue.push(Reverse((at, submitted, pkt, addr)));
+        }
+
+        while let Some(Reverse((at, ..))) = queue.peek()
+            && *at <= Instant::now()
+        {
+            let Reverse((_, _, pkt, addr)) = queue.pop().unwrap();
+            // errors are handled like packet loss, there is nobody to report them to
+            _ = match peer {
+                Some(peer) if peer == addr => sock.send(&pkt),
+                _ => sock.send_to(&pkt, addr),
+            };
+        }
+    }
+}
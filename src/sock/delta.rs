@@ -0,0 +1,113 @@
+//! Delta transfer of a changed file, see `crate::delta`.
+
+use std::{fs::File, net::SocketAddr, path::Path};
+
+use crate::{
+    delta::{BlockReader, MIN_DELTA_SIZE, Signature},
+    error::{Result, SecSnailError},
+    meta::utf8_file_name,
+};
+
+use super::{DatagramTransport, SecSnailSocket, SendReport, snd_ctx::SendSession};
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// send the file at `path` to `recv_addr`, only the blocks which differ
+    /// from the copy of the same name the receiver holds, e.g. a backup
+    /// which changed a little since it was sent
+    ///
+    /// a receiver holding no copy gets the whole file, as does a small
+    /// file or a receiver which does not know delta transfers
+    ///
+    /// # Return
+    /// the report of the missing blocks, which are listed in
+    /// `SendReport::missing`
+    pub fn send_file_delta_blocking<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recv_addr: SocketAddr,
+    ) -> Result<SendReport> {
+        let path = path.as_ref();
+        let _span =
+            tracing::info_span!("send_delta", file = %path.display(), peer = %recv_addr).entered();
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if size < MIN_DELTA_SIZE {
+            return self.send_file_to_blocking(path, recv_addr);
+        }
+        let name = path.file_name().map(utf8_file_name).ok_or_else(|| {
+            SecSnailError::InvalidFilename(format!("{} has no file name", path.display()))
+        })?;
+        let signature = Signature::of_file(&name, size, &file)?;
+
+        let session = SendSession::from_signature(recv_addr, &signature, self.snd_timeout_config)?
+            .with_transfer_deadline(self.transfer_deadline, self.inner.now());
+        let Some(missing) = self.send_session(session, 1)?.missing else {
+            tracing::info!("receiver does not know delta transfers, sending the whole file");
+            return self.send_file_to_blocking(path, recv_addr);
+        };
+        tracing::info!(
+            blocks = signature.blocks.len(),
+            missing = missing.len(),
+            "receiver answered signature"
+        );
+
+        let len = signature.missing_len(&missing);
+        let blocks = BlockReader::new(File::open(path)?, &signature, missing.clone());
+        let session = SendSession::from_missing_blocks(
+            recv_addr,
+            blocks,
+            &name,
+            len,
+            self.snd_timeout_config,
+        )?
+        .with_transfer_deadline(self.transfer_deadline, self.inner.now())
+        .with_min_gap(self.min_packet_gap)
+        .with_trusted_link(self.trusted_link);
+        let report = self.send_session(session, 1)?;
+        Ok(SendReport {
+            missing: Some(missing),
+            ..report
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, thread};
+
+    #[test]
+    fn only_changed_blocks_are_sent() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-delta", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("out")).unwrap();
+        let old: Vec<u8> = (0..200_000u32).map(|i| (i * 13 % 251) as u8).collect();
+        let mut new = old.clone();
+        new.splice(1000..1000, b"a snail crawled in".iter().copied());
+        new[150_000..150_100].fill(0);
+        fs::write(dir.join("out/backup.bin"), &old).unwrap();
+        fs::write(dir.join("backup.bin"), &new).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        // the signature, then the missing blocks
+        let recv = thread::spawn(move || {
+            (0..2)
+                .map(|_| receiver.recv_file_blocking(&out).unwrap())
+                .collect::<Vec<_>>()
+        });
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let report = sender
+            .send_file_delta_blocking(dir.join("backup.bin"), recv_addr)
+            .unwrap();
+        let reports = recv.join().unwrap();
+
+        assert_eq!(fs::read(dir.join("out/backup.bin")).unwrap(), new);
+        assert!(report.missing.unwrap().len() <= 5);
+        assert!(report.bytes < new.len() / 10);
+        assert_eq!(reports[1].file_name, "backup.bin");
+        let left: Vec<_> = fs::read_dir(dir.join("out")).unwrap().collect();
+        assert_eq!(left.len(), 1);
+    }
+}
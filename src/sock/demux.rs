@@ -0,0 +1,181 @@
+//! Concurrent receiving on a single bound socket.
+//!
+//! Every transfer gets its own receiving fsm and session. Incoming packets
+//! are dispatched by their source address and transfer id, so several
+//! senders, or a sender with concurrent transfers from a single socket,
+//! see `send_concurrently_blocking`, can transfer files to the same port
+//! at the same time.
+
+use std::{collections::HashMap, io, net::SocketAddr, path::PathBuf, time::Instant};
+
+use crate::{
+    error::{Result, SecSnailError},
+    fsm_recv::{
+        driver::step,
+        fsm::{RcvEvent, RcvFsm},
+    },
+    pck::Packet,
+};
+
+use super::{
+    DatagramTransport, SecSnailSocket, TransferReport,
+    rcv_ctx::{RecvProtocolIoContext, RecvSession},
+};
+
+/// peer and transfer id a session receives from
+pub(super) type SessionKey = (SocketAddr, u8);
+
+/// outcome of a closed session and the peer it belonged to
+pub(super) type SessionOutcome = (SessionKey, Result<TransferReport>);
+
+pub(super) struct RecvDemux {
+    target_dir: PathBuf,
+    sessions: HashMap<SessionKey, (RcvFsm, RecvSession<'static>)>,
+}
+
+impl RecvDemux {
+    pub fn new(target_dir: PathBuf) -> Self {
+        Self {
+            target_dir,
+            sessions: HashMap::new(),
+        }
+    }
+
+    /// wait for the next packet or expired timer and handle it
+    ///
+    /// # Return
+    /// outcomes of all sessions closed by this step, errors of a single
+    /// session are part of its outcome
+    pub fn step<T: DatagramTransport>(
+        &mut self,
+        sock: &mut SecSnailSocket<T>,
+    ) -> Result<Vec<SessionOutcome>> {
+        let timeout = match self.next_wakeup() {
+            Some(t) => match t.checked_duration_since(sock.inner.now()) {
+                Some(d) if !d.is_zero() => Some(d),
+                _ => return self.handle_expired(sock),
+            },
+            None => None,
+        };
+        sock.inner.set_read_timeout(timeout)?;
+
+        match sock.rdt_recv() {
+            Ok((peer, rcvpkt)) => Ok(self.dispatch(sock, peer, rcvpkt).into_iter().collect()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && timeout.is_some() => {
+                self.handle_expired(sock)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// no session is open
+    pub fn is_idle(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// feed a packet into the session of `peer` and its transfer id, a syn
+    /// opens a new one unless the socket is shut down
+    fn dispatch<T: DatagramTransport>(
+        &mut self,
+        sock: &mut SecSnailSocket<T>,
+        peer: SocketAddr,
+        rcvpkt: Option<Packet>,
+    ) -> Option<SessionOutcome> {
+        let key = (peer, rcvpkt.as_ref().map_or(0, Packet::transfer_id));
+        let (fsm, session) = match self.sessions.remove(&key) {
+            Some(s) => s,
+            None if sock.check_shutdown().is_err()
+                && rcvpkt.as_ref().is_some_and(Packet::is_SYN) =>
+            {
+                return None;
+            }
+            None => (
+                RcvFsm::init(),
+                RecvSession::new(self.target_dir.clone(), sock.rcv_timeout_config)
+                    .with_transfer_deadline(sock.transfer_deadline)
+                    .with_max_file_size(sock.max_recv_file_size)
+                    .with_overwrite_policy(sock.overwrite_policy)
+                    .with_name_policy(sock.name_policy)
+                    .with_congestion_threshold(sock.congestion_threshold)
+                    .with_checkpoints(sock.checkpoints)
+                    .with_sync_on_close(sock.sync_on_close)
+                    .with_spool_dir(sock.spool_dir.clone())
+                    .with_path_resolver(sock.path_resolver.clone())
+                    .with_journal(sock.journal.clone())
+                    .with_trusted_link(sock.trusted_link)
+                    .with_timestamps(sock.timestamps),
+            ),
+        };
+        self.feed(sock, key, fsm, session, RcvEvent::RecvPck(rcvpkt, peer))
+    }
+
+    /// handle a single event of the session of `key`
+    fn feed<T: DatagramTransport>(
+        &mut self,
+        sock: &mut SecSnailSocket<T>,
+        key: SessionKey,
+        fsm: RcvFsm,
+        mut session: RecvSession<'static>,
+        event: RcvEvent,
+    ) -> Option<SessionOutcome> {
+        let (peer, transfer_id) = key;
+        let _span = tracing::info_span!("recv_file", %peer, transfer_id).entered();
+        let mut ctx = RecvProtocolIoContext::new(sock, &mut session);
+        let (next, closed) = match step(fsm, event, &mut ctx) {
+            Ok(v) => v,
+            Err(e) => return Some((key, Err(e))),
+        };
+
+        match closed {
+            Some(outcome) => Some((
+                key,
+                outcome.map(|_| session.take_report().expect("closed session has a report")),
+            )),
+            None => {
+                // only sessions opened by a syn are kept, e.g. not a stray fin
+                if next.in_session() {
+                    self.sessions.insert(key, (next, session));
+                }
+                None
+            }
+        }
+    }
+
+    /// earliest connection timer or transfer deadline of all sessions
+    fn next_wakeup(&self) -> Option<Instant> {
+        self.sessions
+            .values()
+            .flat_map(|(_, s)| [s.connection_timer_end(), s.deadline()])
+            .flatten()
+            .min()
+    }
+
+    fn handle_expired<T: DatagramTransport>(
+        &mut self,
+        sock: &mut SecSnailSocket<T>,
+    ) -> Result<Vec<SessionOutcome>> {
+        let now = sock.inner.now();
+        let expired: Vec<SessionKey> = self
+            .sessions
+            .iter()
+            .filter(|(_, (_, s))| {
+                [s.connection_timer_end(), s.deadline()]
+                    .into_iter()
+                    .flatten()
+                    .any(|t| t <= now)
+            })
+            .map(|(key, _)| *key)
+            .collect();
+
+        let mut outcomes = Vec::new();
+        for key in expired {
+            let (fsm, session) = self.sessions.remove(&key).unwrap();
+            if session.deadline().is_some_and(|d| d <= now) {
+                outcomes.push((key, Err(SecSnailError::DeadlineExceeded)));
+                continue;
+            }
+            outcomes.extend(self.feed(sock, key, fsm, session, RcvEvent::ConnectionTimeout));
+        }
+        Ok(outcomes)
+    }
+}
@@ -0,0 +1,232 @@
+//! Transfer lifecycle events for observers on other threads.
+//!
+//! `SecSnailSocket::subscribe` hands out a channel which receives an event
+//! whenever a transfer of the socket starts, puts a packet on the wire or
+//! ends, so a GUI or monitoring agent does not run code on the protocol
+//! thread like a progress callback does. Events are sent without blocking,
+//! a subscriber which dropped its receiver is forgotten.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    time::Instant,
+};
+
+use crate::{error::SecSnailError, pck::Packet};
+
+use super::{
+    DatagramTransport, Progress, SecSnailSocket, TransferDirection, TransferStats,
+    snd_ctx::SendSession,
+};
+
+/// what happens to a transfer of the socket, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnailEvent {
+    /// a send put its syn on the wire or a receive opened its file
+    TransferStarted {
+        direction: TransferDirection,
+        peer: SocketAddr,
+        name: String,
+    },
+    /// a send put a new packet on the wire
+    Progress {
+        peer: SocketAddr,
+        progress: Progress,
+    },
+    /// a send put a packet on the wire again
+    Retransmit {
+        peer: SocketAddr,
+        retransmissions: usize,
+    },
+    Completed {
+        direction: TransferDirection,
+        peer: SocketAddr,
+        stats: TransferStats,
+    },
+    /// `peer` is `None` if a receive failed before a sender was known
+    Failed {
+        direction: TransferDirection,
+        peer: Option<SocketAddr>,
+        error: String,
+    },
+}
+
+/// channels of the subscribers, behind a mutex for `serve_threaded`
+#[derive(Debug, Default)]
+pub(super) struct Subscribers(Mutex<Vec<Sender<SnailEvent>>>);
+
+impl Subscribers {
+    /// `event` is only built if anyone listens
+    fn notify(&self, event: impl FnOnce() -> SnailEvent) {
+        let mut subscribers = self.0.lock().unwrap();
+        if subscribers.is_empty() {
+            return;
+        }
+        let event = event();
+        subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// a channel which receives the events of all further transfers of
+    /// the socket, see the module docs
+    pub fn subscribe(&self) -> Receiver<SnailEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.0.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// count `pck` put on the wire at `now` by `session` and report it to
+    /// the progress callback and the subscribers
+    pub(super) fn record_sent_packet(&self, session: &mut SendSession, pck: &Packet, now: Instant) {
+        let retransmissions = session.progress().retransmissions;
+        session.record_sent(pck, now);
+        let progress = session.progress();
+        self.report_progress(progress);
+        let peer = session.recv_addr();
+        let retransmitted = progress.retransmissions > retransmissions;
+        if pck.is_SYN() && !retransmitted {
+            self.set_active(TransferDirection::Send, peer, session.transfer_id(), true);
+        }
+        self.subscribers.notify(|| match retransmitted {
+            true => SnailEvent::Retransmit {
+                peer,
+                retransmissions: progress.retransmissions,
+            },
+            false if pck.is_SYN() => SnailEvent::TransferStarted {
+                direction: TransferDirection::Send,
+                peer,
+                name: session.file_name().to_string(),
+            },
+            false => SnailEvent::Progress { peer, progress },
+        });
+    }
+
+    /// a receive from `peer` and its transfer id opened the file announced
+    /// as `name`
+    pub(super) fn notify_recv_started(&self, peer: SocketAddr, transfer_id: u8, name: &str) {
+        self.set_active(TransferDirection::Recv, peer, transfer_id, true);
+        self.subscribers.notify(|| SnailEvent::TransferStarted {
+            direction: TransferDirection::Recv,
+            peer,
+            name: name.to_string(),
+        });
+    }
+
+    /// a transfer with `peer` and its transfer id ended with `stats`,
+    /// failed if there is an `error`
+    pub(super) fn notify_ended(
+        &self,
+        direction: TransferDirection,
+        peer: Option<SocketAddr>,
+        transfer_id: u8,
+        stats: &TransferStats,
+        error: Option<&SecSnailError>,
+    ) {
+        if let Some(peer) = peer {
+            self.set_active(direction, peer, transfer_id, false);
+        }
+        self.subscribers.notify(|| match (error, peer) {
+            (None, Some(peer)) => SnailEvent::Completed {
+                direction,
+                peer,
+                stats: *stats,
+            },
+            (error, peer) => SnailEvent::Failed {
+                direction,
+                peer,
+                error: error.map_or_else(|| "no sender".to_string(), |e| e.to_string()),
+            },
+        });
+    }
+
+    /// a transfer with `peer` started or ended, a transfer which ends
+    /// without having started is not counted
+    fn set_active(
+        &self,
+        direction: TransferDirection,
+        peer: SocketAddr,
+        transfer_id: u8,
+        started: bool,
+    ) {
+        let key = (direction, peer, transfer_id);
+        let mut active = self.active.lock().unwrap();
+        match started {
+            true => active.insert(key),
+            false => active.remove(&key),
+        };
+        self.totals.lock().unwrap().active = active.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, thread};
+
+    #[test]
+    fn subscriber_sees_the_whole_transfer() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-events", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let content: Vec<u8> = (0..2000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.bin"), &content).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let received = receiver.subscribe();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let sent = sender.subscribe();
+        let report = sender
+            .send_file_to_blocking(dir.join("snail.bin"), recv_addr)
+            .unwrap();
+        recv.join().unwrap();
+        drop(sender);
+
+        let sent: Vec<_> = sent.iter().collect();
+        assert_eq!(
+            sent[0],
+            SnailEvent::TransferStarted {
+                direction: TransferDirection::Send,
+                peer: recv_addr,
+                name: "snail.bin".to_string(),
+            }
+        );
+        let progress = sent
+            .iter()
+            .filter(|e| matches!(e, SnailEvent::Progress { .. }))
+            .count();
+        assert_eq!(
+            progress,
+            report.stats.packets_sent - 1 - report.stats.retransmissions
+        );
+        assert_eq!(
+            sent.last(),
+            Some(&SnailEvent::Completed {
+                direction: TransferDirection::Send,
+                peer: recv_addr,
+                stats: report.stats,
+            })
+        );
+
+        let received: Vec<_> = received.try_iter().collect();
+        assert!(matches!(
+            received[0],
+            SnailEvent::TransferStarted {
+                direction: TransferDirection::Recv,
+                ..
+            }
+        ));
+        assert!(matches!(
+            received.last(),
+            Some(SnailEvent::Completed {
+                direction: TransferDirection::Recv,
+                ..
+            })
+        ));
+    }
+}
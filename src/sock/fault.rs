@@ -0,0 +1,153 @@
+//! Deterministic faults of emitted packets.
+//!
+//! A `FaultInjector` decides about every packet an fsm emits, before the
+//! impairment of the socket. Tests use it to hit edge cases of the protocol
+//! on purpose, e.g. a lost FINACK, instead of hoping for a seed which
+//! produces it.
+//!
+//! ```
+//! use secsnail::{
+//!     pck::{Flag, Packet},
+//!     sock::{Fault, SecSnailSocket},
+//! };
+//!
+//! let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+//! // drop the 3rd ack
+//! let mut acks = 0;
+//! receiver.set_fault_injector(move |_fsm: &str, pck: &Packet| {
+//!     if pck.flag() == Flag::ACK {
+//!         acks += 1;
+//!         if acks == 3 {
+//!             return Fault::Drop;
+//!         }
+//!     }
+//!     Fault::Pass
+//! });
+//! ```
+
+use crate::pck::Packet;
+
+/// what happens to an emitted packet
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fault {
+    #[default]
+    Pass,
+    Drop,
+    /// a bit of the encoded packet is flipped, so its checksum fails
+    Corrupt,
+    /// sent twice
+    Duplicate,
+}
+
+/// decides about the packets emitted by the fsms of a socket
+pub trait FaultInjector: Send {
+    /// `pck` is emitted by the fsm `fsm`, `send` or `recv`
+    fn on_emit(&mut self, fsm: &str, pck: &Packet) -> Fault;
+}
+
+impl<F: FnMut(&str, &Packet) -> Fault + Send> FaultInjector for F {
+    fn on_emit(&mut self, fsm: &str, pck: &Packet) -> Fault {
+        self(fsm, pck)
+    }
+}
+
+/// how often a packet is put on the wire after `fault`
+pub(super) fn copies(fault: Fault) -> usize {
+    match fault {
+        Fault::Drop => 0,
+        Fault::Duplicate => 2,
+        Fault::Pass | Fault::Corrupt => 1,
+    }
+}
+
+/// flip a bit of the encoded packet in `buf` if `fault` corrupts it
+pub(super) fn corrupt(fault: Fault, buf: &mut [u8]) {
+    if let (Fault::Corrupt, Some(b)) = (fault, buf.last_mut()) {
+        *b ^= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pck::Flag,
+        sim::{SimNetwork, SimTransport},
+        sock::SecSnailSocket,
+    };
+    use std::{fs, path::PathBuf};
+
+    type SimSocket = SecSnailSocket<SimTransport>;
+
+    /// both sockets of a simulated network with a 3000 byte file to send
+    fn setup(name: &str) -> (SimNetwork, SimSocket, SimSocket, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("snail.txt"), vec![5u8; 3000]).unwrap();
+        let net = SimNetwork::new(1);
+        let sender = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let receiver = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+        (net, sender, receiver, dir)
+    }
+
+    #[test]
+    fn lost_finack_is_answered_again() {
+        let (net, mut sender, mut receiver, dir) = setup("fault-finack");
+        let mut dropped = false;
+        receiver.set_fault_injector(move |_: &str, pck: &Packet| match pck.flag() {
+            Flag::FINACK if !dropped => {
+                dropped = true;
+                Fault::Drop
+            }
+            _ => Fault::Pass,
+        });
+        net.run_transfer(
+            &mut sender,
+            &mut receiver,
+            dir.join("snail.txt"),
+            dir.join("out"),
+        )
+        .unwrap();
+        assert_eq!(sender.last_transfer_stats().unwrap().retransmissions, 1);
+        assert_eq!(
+            fs::read(dir.join("out/snail.txt")).unwrap(),
+            vec![5u8; 3000]
+        );
+    }
+
+    #[test]
+    fn corrupt_and_duplicate_data() {
+        let (net, mut sender, mut receiver, dir) = setup("fault-data");
+        let mut data = 0;
+        sender.set_fault_injector(move |_: &str, pck: &Packet| {
+            if !pck.is_Data() {
+                return Fault::Pass;
+            }
+            data += 1;
+            match data {
+                2 => Fault::Corrupt,
+                4 => Fault::Duplicate,
+                _ => Fault::Pass,
+            }
+        });
+        net.run_transfer(
+            &mut sender,
+            &mut receiver,
+            dir.join("snail.txt"),
+            dir.join("out"),
+        )
+        .unwrap();
+        let stats = receiver.last_transfer_stats().unwrap();
+        assert_eq!(stats.corrupt_dropped, 1);
+        assert_eq!(stats.duplicates_received, 1);
+        assert_eq!(
+            fs::read(dir.join("out/snail.txt")).unwrap(),
+            vec![5u8; 3000]
+        );
+    }
+}
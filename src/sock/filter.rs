@@ -0,0 +1,132 @@
+//! Allow- and denylists of the peers a socket talks to.
+
+use std::{fmt, net::IpAddr, str::FromStr};
+
+use crate::error::SecSnailError;
+
+/// ip network in cidr notation, e.g. `10.0.0.0/8`, a plain address is a single host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNet {
+    /// fails if `prefix_len` exceeds the bits of `addr`
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<IpNet, SecSnailError> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max {
+            return Err(SecSnailError::InvalidConfig(format!(
+                "prefix length {prefix_len} of {addr} exceeds {max}"
+            )));
+        }
+        Ok(IpNet { addr, prefix_len })
+    }
+
+    /// ipv4-mapped ipv6 addresses of a dual-stack socket match ipv4 networks
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let (bytes, bits) = ((prefix_len / 8) as usize, prefix_len % 8);
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+    let mask = !(0xFFu8 >> bits);
+    bits == 0 || a[bytes] & mask == b[bytes] & mask
+}
+
+impl FromStr for IpNet {
+    type Err = SecSnailError;
+
+    fn from_str(s: &str) -> Result<IpNet, SecSnailError> {
+        let invalid = || SecSnailError::InvalidConfig(format!("'{s}' is no ip network"));
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len.parse().map_err(|_| invalid())?)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+        let host_len = if addr.is_ipv4() { 32 } else { 128 };
+        IpNet::new(addr, prefix_len.unwrap_or(host_len))
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// `None` allows every peer which is not denied
+#[derive(Debug, Clone, Default)]
+pub(super) struct PeerFilter {
+    pub allowed: Option<Vec<IpNet>>,
+    pub denied: Vec<IpNet>,
+}
+
+impl PeerFilter {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let allowed = match &self.allowed {
+            Some(nets) => nets.iter().any(|net| net.contains(ip)),
+            None => true,
+        };
+        allowed && !self.denied.iter().any(|net| net.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_contains() {
+        let net: IpNet = "10.1.0.0/20".parse().unwrap();
+        assert!(net.contains(ip("10.1.15.255")));
+        assert!(!net.contains(ip("10.1.16.0")));
+        assert!(net.contains(ip("::ffff:10.1.2.3")));
+        assert!(!net.contains(ip("fe80::1")));
+
+        let host: IpNet = "fe80::1".parse().unwrap();
+        assert_eq!(host.to_string(), "fe80::1/128");
+        assert!(host.contains(ip("fe80::1")));
+        assert!(!host.contains(ip("fe80::2")));
+
+        assert!(
+            "0.0.0.0/0"
+                .parse::<IpNet>()
+                .unwrap()
+                .contains(ip("8.8.8.8"))
+        );
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!("snail".parse::<IpNet>().is_err());
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let filter = PeerFilter {
+            allowed: Some(vec!["192.168.0.0/16".parse().unwrap()]),
+            denied: vec!["192.168.1.13".parse().unwrap()],
+        };
+        assert!(filter.allows(ip("192.168.1.12")));
+        assert!(!filter.allows(ip("192.168.1.13")));
+        assert!(!filter.allows(ip("10.0.0.1")));
+        assert!(PeerFilter::default().allows(ip("10.0.0.1")));
+    }
+}
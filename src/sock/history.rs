@@ -0,0 +1,145 @@
+//! Transition history of the fsms of a socket.
+//!
+//! Every edge taken by an fsm is recorded as a `Transition`, the names of
+//! the states and events are those of the protocol diagram. The history of
+//! the last transfer is kept, a new syn starts over.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::pck::Flag;
+
+/// callback of `SecSnailSocket::set_transition_callback`
+type TransitionCallback = Arc<dyn Fn(&Transition) + Send + Sync>;
+
+/// a single edge taken by an fsm
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    /// since the start of the transfer, on the clock of the transport
+    pub at: Duration,
+    /// `send` or `recv`
+    pub fsm: &'static str,
+    pub state: &'static str,
+    pub event: &'static str,
+    pub next: &'static str,
+    /// flags of the packets sent by the transition, e.g. a retransmitted `Data`
+    pub emitted: Vec<Flag>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    start: Option<Instant>,
+    transitions: Vec<Transition>,
+    /// packets emitted by the transition in progress
+    emitted: Vec<Flag>,
+}
+
+pub(super) struct TransitionLog {
+    recorded: Mutex<Recorded>,
+    /// transitions are only streamed, not kept
+    callback: Option<TransitionCallback>,
+}
+
+impl TransitionLog {
+    pub fn new(callback: Option<TransitionCallback>) -> TransitionLog {
+        TransitionLog {
+            recorded: Mutex::new(Recorded::default()),
+            callback,
+        }
+    }
+
+    /// the transition in progress emitted a packet with `flag`
+    pub fn emit(&self, flag: Flag) {
+        self.recorded.lock().unwrap().emitted.push(flag);
+    }
+
+    /// `fsm` took the edge from `state` on `event` to `next`
+    pub fn record(
+        &self,
+        now: Instant,
+        fsm: &'static str,
+        state: &'static str,
+        event: &'static str,
+        next: &'static str,
+    ) {
+        let mut recorded = self.recorded.lock().unwrap();
+        // a sender starts with its syn, a receiver once it accepts one
+        let opens = match fsm {
+            "send" => state == "start",
+            _ => state != "wait_for_pkt" && next == "wait_for_pkt",
+        };
+        if opens || recorded.start.is_none() {
+            recorded.start = Some(now);
+            recorded.transitions.clear();
+        }
+        let transition = Transition {
+            at: now.saturating_duration_since(recorded.start.unwrap_or(now)),
+            fsm,
+            state,
+            event,
+            next,
+            emitted: std::mem::take(&mut recorded.emitted),
+        };
+        match &self.callback {
+            Some(callback) => {
+                drop(recorded);
+                callback(&transition);
+            }
+            None => recorded.transitions.push(transition),
+        }
+    }
+
+    pub fn transitions(&self) -> Vec<Transition> {
+        self.recorded.lock().unwrap().transitions.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{sim::SimNetwork, sock::SecSnailSocket};
+    use std::fs;
+
+    #[test]
+    fn records_edges_of_last_transfer() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-history", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("snail.txt"), vec![3u8; 3000]).unwrap();
+
+        let net = SimNetwork::new(5).loss_p(0.2);
+        let mut sender = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+        sender.set_transition_recording(true);
+        receiver.set_transition_recording(true);
+        for _ in 0..2 {
+            net.run_transfer(
+                &mut sender,
+                &mut receiver,
+                dir.join("snail.txt"),
+                dir.join("out"),
+            )
+            .unwrap();
+        }
+
+        let snd = sender.last_transfer_trace();
+        assert_eq!(
+            (snd[0].state, snd[0].event, snd[0].next),
+            ("start", "init_syn", "wait")
+        );
+        assert_eq!(snd[0].emitted, vec![crate::pck::Flag::SYN]);
+        assert_eq!(snd.iter().filter(|t| t.event == "init_syn").count(), 1);
+        assert!(snd.iter().any(|t| t.event == "timeout"));
+        assert_eq!(snd.last().unwrap().next, "end");
+
+        let rcv = receiver.last_transfer_trace();
+        assert_eq!(rcv[0].state, "wait_for_connection");
+        assert!(rcv.windows(2).all(|w| w[0].at <= w[1].at));
+        assert_eq!(rcv.last().unwrap().emitted, vec![crate::pck::Flag::FINACK]);
+    }
+}
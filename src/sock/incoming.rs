@@ -0,0 +1,93 @@
+//! Iterator of received files.
+
+use std::{
+    net::UdpSocket,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Result, SecSnailError};
+
+use super::{DatagramTransport, SecSnailSocket, TransferReport, is_sender_failure};
+
+/// endless iterator of the files received one after another, see
+/// `SecSnailSocket::incoming`
+///
+/// failures of a single sender, e.g. a timed out session, are skipped,
+/// all other errors are yielded, ends once the socket is shut down
+pub struct Incoming<'a, T = UdpSocket> {
+    sock: &'a mut SecSnailSocket<T>,
+    target_dir: PathBuf,
+}
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// receive files into `target_dir`, a report per completed file
+    ///
+    /// ```no_run
+    /// use secsnail::sock::SecSnailSocket;
+    /// let mut sock = SecSnailSocket::bind_default_port().unwrap();
+    /// for report in sock.incoming("./received") {
+    ///     let report = report.unwrap();
+    ///     println!("{} from {}", report.file_name, report.peer);
+    /// }
+    /// ```
+    pub fn incoming<P: AsRef<Path>>(&mut self, target_dir: P) -> Incoming<'_, T> {
+        Incoming {
+            sock: self,
+            target_dir: target_dir.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl<T: DatagramTransport> Iterator for Incoming<'_, T> {
+    type Item = Result<TransferReport>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.sock.recv_file_blocking(&self.target_dir) {
+                Err(e) if is_sender_failure(&e) => continue,
+                Err(SecSnailError::Shutdown) => return None,
+                r => return Some(r),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread};
+
+    use crate::sock::SecSnailSocket;
+
+    #[test]
+    fn one_report_per_file() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-incoming", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, len) in [("a.bin", 100), ("b.bin", 3000)] {
+            fs::write(dir.join(name), vec![1; len]).unwrap();
+        }
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let src = dir.clone();
+        let sender = thread::spawn(move || {
+            let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+            for name in ["a.bin", "b.bin"] {
+                sock.send_file_to_blocking(src.join(name), recv_addr)
+                    .unwrap();
+            }
+        });
+
+        let received: Vec<(String, usize)> = receiver
+            .incoming(dir.join("out"))
+            .take(2)
+            .map(|r| r.map(|report| (report.file_name, report.bytes)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        sender.join().unwrap();
+        assert_eq!(
+            received,
+            [("a.bin".to_string(), 100), ("b.bin".to_string(), 3000)]
+        );
+    }
+}
@@ -0,0 +1,432 @@
+//! Journal of pending transfers.
+//!
+//! A socket with a journal notes every file it starts to send or receive in
+//! a small text file, a line per transfer, and removes the line once the
+//! transfer is done:
+//!
+//! ```text
+//! <id> <send|recv> <peer> <bytes acknowledged> <announced name> <local path>
+//! ```
+//!
+//! Name and path are percent-encoded where they hold a space, `%` or a line
+//! break. The bytes are written every `CHECKPOINT` bytes, by writing a new
+//! journal and renaming it over the old one, so a crash leaves either of
+//! them complete. Lines left after a crash are the transfers to pick up:
+//! a sender sends its files again with an offer to resume, a receiver
+//! resumes the partial files it lists, whatever its overwrite policy.
+//!
+//! No digest state is kept, a resumed file is only as intact as its
+//! partial file, like a resume without journal.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::error::{Result, SecSnailError};
+
+use super::{DatagramTransport, SecSnailSocket, SendOutcome, is_retryable, snd_ctx::SendSession};
+
+/// bytes between two writes of the progress of a transfer
+const CHECKPOINT: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransferDirection {
+    Send,
+    Recv,
+}
+
+/// a transfer of the journal which did not finish
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingTransfer {
+    pub id: u64,
+    pub direction: TransferDirection,
+    pub peer: SocketAddr,
+    /// acknowledged bytes as of the last checkpoint
+    pub bytes: u64,
+    /// name announced to the receiver
+    pub name: String,
+    /// file sent, or partial file received into
+    pub path: PathBuf,
+}
+
+impl fmt::Display for PendingTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            TransferDirection::Send => "send",
+            TransferDirection::Recv => "recv",
+        };
+        write!(
+            f,
+            "{} {direction} {} {} {} {}",
+            self.id,
+            self.peer,
+            self.bytes,
+            escape(&self.name),
+            escape(&self.path.to_string_lossy())
+        )
+    }
+}
+
+impl PendingTransfer {
+    fn parse(line: &str) -> Option<PendingTransfer> {
+        let mut fields = line.split(' ');
+        let mut next = || fields.next();
+        Some(PendingTransfer {
+            id: next()?.parse().ok()?,
+            direction: match next()? {
+                "send" => TransferDirection::Send,
+                "recv" => TransferDirection::Recv,
+                _ => return None,
+            },
+            peer: next()?.parse().ok()?,
+            bytes: next()?.parse().ok()?,
+            name: unescape(next()?)?,
+            path: unescape(next()?)?.into(),
+        })
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            ' ' | '%' | '\n' | '\r' => out.push_str(&format!("%{:02X}", c as u8)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(s: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b => out.push(b),
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    pending: BTreeMap<u64, PendingTransfer>,
+    next_id: u64,
+}
+
+/// see the module docs
+#[derive(Debug)]
+pub(super) struct Journal {
+    path: PathBuf,
+    entries: Mutex<Entries>,
+}
+
+impl Journal {
+    /// the journal at `path`, with the transfers a previous run left
+    pub fn open(path: &Path) -> Result<Journal> {
+        let mut entries = Entries::default();
+        match fs::read_to_string(path) {
+            Ok(journal) => {
+                for line in journal.lines().filter(|l| !l.is_empty()) {
+                    let entry = PendingTransfer::parse(line).ok_or_else(|| {
+                        SecSnailError::InvalidConfig(format!(
+                            "malformed line in journal {}: {line}",
+                            path.display()
+                        ))
+                    })?;
+                    entries.next_id = entries.next_id.max(entry.id + 1);
+                    entries.pending.insert(entry.id, entry);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(Journal {
+            path: path.to_path_buf(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// note a transfer of the file at `path`, which replaces a pending one
+    /// of the same direction and path
+    pub fn begin(
+        &self,
+        direction: TransferDirection,
+        peer: SocketAddr,
+        name: &str,
+        path: &Path,
+    ) -> Result<u64> {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .pending
+            .retain(|_, t| t.direction != direction || t.path != path);
+        let id = entries.next_id;
+        entries.next_id += 1;
+        entries.pending.insert(
+            id,
+            PendingTransfer {
+                id,
+                direction,
+                peer,
+                bytes: 0,
+                name: name.to_string(),
+                path: path.to_path_buf(),
+            },
+        );
+        self.write(&entries)?;
+        Ok(id)
+    }
+
+    /// `bytes` of transfer `id` are acknowledged, every `CHECKPOINT` bytes
+    /// `flush` makes them durable first and the journal is written
+    pub fn progress(
+        &self,
+        id: u64,
+        bytes: u64,
+        flush: impl FnOnce() -> io::Result<()>,
+    ) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.pending.get_mut(&id) else {
+            return Ok(());
+        };
+        if bytes < entry.bytes + CHECKPOINT {
+            return Ok(());
+        }
+        flush()?;
+        entry.bytes = bytes;
+        self.write(&entries)
+    }
+
+    /// `bytes` of transfer `id` are durable, the journal is written at once
+    pub fn checkpoint(&self, id: u64, bytes: u64) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let Some(entry) = entries.pending.get_mut(&id) else {
+            return Ok(());
+        };
+        entry.bytes = bytes;
+        self.write(&entries)
+    }
+
+    /// transfer `id` is done or will not be picked up again
+    pub fn finish(&self, id: u64) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.pending.remove(&id).is_some() {
+            self.write(&entries)?;
+        }
+        Ok(())
+    }
+
+    pub fn pending(&self) -> Vec<PendingTransfer> {
+        let entries = self.entries.lock().unwrap();
+        entries.pending.values().cloned().collect()
+    }
+
+    /// whether a transfer into the partial file at `path` is pending
+    pub fn holds(&self, direction: TransferDirection, path: &Path) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .pending
+            .values()
+            .any(|t| t.direction == direction && t.path == path)
+    }
+
+    /// replace the journal, a crash leaves the old or the new one
+    fn write(&self, entries: &Entries) -> Result<()> {
+        let mut journal = String::new();
+        for entry in entries.pending.values() {
+            journal.push_str(&format!("{entry}\n"));
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, journal)?;
+        fs::File::open(&tmp)?.sync_all()?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// transfers of the journal which did not finish, empty without one
+    pub fn pending_transfers(&self) -> Vec<PendingTransfer> {
+        self.journal.as_ref().map_or_else(Vec::new, |j| j.pending())
+    }
+
+    /// send the pending files of the journal again, each offering to resume
+    /// at the bytes its receiver kept
+    ///
+    /// pending receptions need nothing, their partial files are resumed
+    /// once their senders send them again
+    ///
+    /// # Return
+    /// the result of every file sent again, a file which failed for good,
+    /// e.g. as it was removed meanwhile, leaves the journal
+    pub fn resume_pending_transfers(&mut self) -> Vec<SendOutcome> {
+        let mut results = Vec::new();
+        for entry in self.pending_transfers() {
+            if entry.direction != TransferDirection::Send {
+                continue;
+            }
+            let _span = tracing::info_span!(
+                "resume_send",
+                file = %entry.path.display(),
+                peer = %entry.peer,
+                bytes = entry.bytes
+            )
+            .entered();
+            let r = self.send_path_with_retries(&entry.path, entry.peer, |session| {
+                Ok(session.with_remote_name(&entry.name)?.with_resume(true))
+            });
+            // a send which did not start, e.g. of a removed file, never will
+            if r.as_ref().is_err_and(|e| !is_retryable(e)) {
+                finish_journaled(self.journal.as_ref(), Some(entry.id));
+            }
+            results.push((entry.path, r));
+        }
+        results
+    }
+}
+
+/// note the send of `path` in `journal`, once for all attempts, with the
+/// name `session` announces
+pub(super) fn journal_send(
+    journal: Option<&Arc<Journal>>,
+    session: SendSession,
+    path: &Path,
+    entry: &mut Option<u64>,
+) -> Result<SendSession> {
+    let (Some(journal), Some(name)) = (journal, session.journaled_name()) else {
+        return Ok(session);
+    };
+    let id = match *entry {
+        Some(id) => id,
+        None => *entry.insert(journal.begin(
+            TransferDirection::Send,
+            session.recv_addr(),
+            name,
+            &fs::canonicalize(path)?,
+        )?),
+    };
+    Ok(session.with_journal(journal.clone(), id))
+}
+
+/// the send noted as `entry` is done, or failed for good
+pub(super) fn finish_journaled(journal: Option<&Arc<Journal>>, entry: Option<u64>) {
+    if let (Some(journal), Some(id)) = (journal, entry)
+        && let Err(e) = journal.finish(id)
+    {
+        tracing::warn!(error = %e, "failed to update the journal");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::UdpSocket, thread, time::Duration};
+
+    #[test]
+    fn journal_survives_a_restart() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-journal", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("journal");
+        let peer = "127.0.0.1:55055".parse().unwrap();
+
+        let journal = Journal::open(&path).unwrap();
+        let a = journal
+            .begin(
+                TransferDirection::Send,
+                peer,
+                "100% snail.txt",
+                Path::new("/tmp/a b"),
+            )
+            .unwrap();
+        let b = journal
+            .begin(
+                TransferDirection::Recv,
+                peer,
+                "shell.bin",
+                Path::new("/tmp/.shell"),
+            )
+            .unwrap();
+        journal.progress(a, 1000, || Ok(())).unwrap();
+        journal.progress(a, 2 * CHECKPOINT, || Ok(())).unwrap();
+        journal.finish(b).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&path).unwrap();
+        let pending = journal.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].name, "100% snail.txt");
+        assert_eq!(pending[0].path, Path::new("/tmp/a b"));
+        assert_eq!(pending[0].bytes, 2 * CHECKPOINT);
+        assert!(journal.holds(TransferDirection::Send, Path::new("/tmp/a b")));
+        // a new attempt replaces the entry
+        let c = journal
+            .begin(
+                TransferDirection::Send,
+                peer,
+                "100% snail.txt",
+                Path::new("/tmp/a b"),
+            )
+            .unwrap();
+        assert_eq!(journal.pending().len(), 1);
+        assert_eq!(journal.pending()[0].id, c);
+    }
+
+    #[test]
+    fn send_is_resumed_after_a_restart() {
+        let dir =
+            std::env::temp_dir().join(format!("secsnail-{}-resume-journal", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("snail.txt"), b"sent after a crash").unwrap();
+        let journal = dir.join("journal");
+
+        // nobody answers yet, the send stays pending
+        let absent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = absent.local_addr().unwrap();
+        let mut sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .snd_timeout(Duration::from_millis(20))
+            .max_retransmits(2)
+            .journal(&journal)
+            .build()
+            .unwrap();
+        assert!(
+            sender
+                .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+                .is_err()
+        );
+        drop((sender, absent));
+
+        let mut sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .journal(&journal)
+            .build()
+            .unwrap();
+        let pending = sender.pending_transfers();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].name, "snail.txt");
+        assert_eq!(pending[0].peer, recv_addr);
+
+        let mut receiver = SecSnailSocket::bind(recv_addr).unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+        let results = sender.resume_pending_transfers();
+        assert!(results[0].1.is_ok());
+        assert_eq!(recv.join().unwrap().file_name, "snail.txt");
+        assert_eq!(
+            fs::read(dir.join("out/snail.txt")).unwrap(),
+            b"sent after a crash"
+        );
+        assert!(sender.pending_transfers().is_empty());
+        assert!(Journal::open(&journal).unwrap().pending().is_empty());
+    }
+}
@@ -0,0 +1,394 @@
+//! Accept API for receiving files one by one.
+//!
+//! Instead of storing every incoming file blindly, a `SecSnailListener`
+//! hands each announced transfer to the application, which decides
+//! whether and where to store it, or rejects it.
+
+use std::{
+    io::Write,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::Result,
+    fsm_recv::{
+        driver::{handle_event, run_rcv_fsm_loop_lingering},
+        fsm::{RcvEvent, RcvFsm},
+    },
+    meta::{SynMeta, check_file_name},
+    pck::{Flag, Packet},
+};
+
+use super::{
+    DatagramTransport, SecSnailSocket, ShutdownHandle, TransferReport, prepare_target_dir,
+    rcv_ctx::{RecvProtocolIoContext, RecvSession},
+};
+
+/// reason sent to the sender of a rejected transfer
+const REJECT_REASON: &str = "rejected by receiver";
+
+pub struct SecSnailListener<T = UdpSocket> {
+    sock: SecSnailSocket<T>,
+}
+
+impl SecSnailListener {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<SecSnailListener> {
+        Ok(Self::from_socket(SecSnailSocket::bind(addr)?))
+    }
+}
+
+impl<T: DatagramTransport> SecSnailListener<T> {
+    /// listen on an already configured socket, e.g. built by `SecSnailSocket::builder`
+    pub fn from_socket(sock: SecSnailSocket<T>) -> SecSnailListener<T> {
+        SecSnailListener { sock }
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.sock.inner.local_addr()?)
+    }
+
+    /// stop accepting from another thread, see `ShutdownHandle::shutdown`
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.sock.shutdown_handle()
+    }
+
+    /// block until a sender announces a new file
+    ///
+    /// packets which do not start a transfer are handled like in the
+    /// `WaitForConnection` state, e.g. a retransmitted fin is finack(ed)
+    pub fn accept(&mut self) -> Result<(IncomingTransfer<'_, T>, SocketAddr)> {
+        loop {
+            self.sock.check_shutdown()?;
+            self.sock.inner.set_read_timeout(None)?;
+            let (peer, rcvpkt) = self.sock.rdt_recv()?;
+            self.sock.check_shutdown()?;
+
+            match rcvpkt {
+                Some(syn) if syn.notcorrupt() && syn.is_SYN() && 0 == syn.n() => {
+                    // a syn with unreadable metadata is ignored like a corrupt one
+                    let Ok(meta) = SynMeta::decode(syn.payload()) else {
+                        continue;
+                    };
+                    let transfer = IncomingTransfer {
+                        sock: &mut self.sock,
+                        syn,
+                        peer,
+                        meta,
+                    };
+                    return Ok((transfer, peer));
+                }
+                rcvpkt => {
+                    let mut session =
+                        RecvSession::new(PathBuf::new(), self.sock.rcv_timeout_config);
+                    let mut ctx = RecvProtocolIoContext::new(&self.sock, &mut session);
+                    handle_event(RcvFsm::init(), RcvEvent::RecvPck(rcvpkt, peer), &mut ctx)?;
+                }
+            }
+        }
+    }
+}
+
+/// a file announced by a sender, not acknowledged until it is accepted
+pub struct IncomingTransfer<'a, T = UdpSocket> {
+    sock: &'a mut SecSnailSocket<T>,
+    syn: Packet,
+    peer: SocketAddr,
+    meta: SynMeta,
+}
+
+impl<'a, T: DatagramTransport> IncomingTransfer<'a, T> {
+    /// file name announced by the sender, a single path component, empty
+    /// if the sender is anonymous
+    pub fn file_name(&self) -> &str {
+        &self.meta.file_name
+    }
+
+    /// whether the sender announced no name, `save_to` then names the file
+    /// by its arrival time and sender, `save_as` by the application
+    pub fn is_anonymous(&self) -> bool {
+        self.meta.file_name.is_empty()
+    }
+
+    /// file size announced by the sender, `None` for senders without size
+    pub fn file_size(&self) -> Option<u64> {
+        self.meta.file_size
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// receive the file into `target_dir` under its announced name
+    ///
+    pub fn save_to<P: AsRef<Path>>(self, target_dir: P) -> Result<TransferReport> {
+        let target_dir = target_dir.as_ref();
+        prepare_target_dir(target_dir)?;
+        let session = RecvSession::new(target_dir.to_path_buf(), self.sock.rcv_timeout_config)
+            .with_transfer_deadline(self.sock.transfer_deadline)
+            .with_max_file_size(self.sock.max_recv_file_size)
+            .with_overwrite_policy(self.sock.overwrite_policy)
+            .with_name_policy(self.sock.name_policy)
+            .with_congestion_threshold(self.sock.congestion_threshold)
+            .with_checkpoints(self.sock.checkpoints)
+            .with_sync_on_close(self.sock.sync_on_close)
+            .with_spool_dir(self.sock.spool_dir.clone())
+            .with_path_resolver(self.sock.path_resolver.clone())
+            .with_journal(self.sock.journal.clone())
+            .with_trusted_link(self.sock.trusted_link)
+            .with_timestamps(self.sock.timestamps);
+        self.receive(session)
+    }
+
+    /// receive the file into `target_dir` under `name` instead of the
+    /// announced one, e.g. to name the file of an anonymous sender
+    pub fn save_as<P: AsRef<Path>>(self, target_dir: P, name: &str) -> Result<TransferReport> {
+        check_file_name(name)?;
+        let target_dir = target_dir.as_ref();
+        prepare_target_dir(target_dir)?;
+        let session = RecvSession::new(target_dir.to_path_buf(), self.sock.rcv_timeout_config)
+            .with_transfer_deadline(self.sock.transfer_deadline)
+            .with_max_file_size(self.sock.max_recv_file_size)
+            .with_overwrite_policy(self.sock.overwrite_policy)
+            .with_name_policy(self.sock.name_policy)
+            .with_congestion_threshold(self.sock.congestion_threshold)
+            .with_checkpoints(self.sock.checkpoints)
+            .with_sync_on_close(self.sock.sync_on_close)
+            .with_spool_dir(self.sock.spool_dir.clone())
+            .with_path_resolver(self.sock.path_resolver.clone())
+            .with_journal(self.sock.journal.clone())
+            .with_trusted_link(self.sock.trusted_link)
+            .with_timestamps(self.sock.timestamps)
+            .with_file_name(name);
+        self.receive(session)
+    }
+
+    /// receive the file into `writer`
+    ///
+    pub fn write_to<W: Write + Send + 'a>(self, writer: W) -> Result<TransferReport> {
+        let session = RecvSession::with_writer(Box::new(writer), self.sock.rcv_timeout_config)
+            .with_transfer_deadline(self.sock.transfer_deadline)
+            .with_max_file_size(self.sock.max_recv_file_size)
+            .with_overwrite_policy(self.sock.overwrite_policy)
+            .with_name_policy(self.sock.name_policy)
+            .with_congestion_threshold(self.sock.congestion_threshold)
+            .with_checkpoints(self.sock.checkpoints)
+            .with_sync_on_close(self.sock.sync_on_close)
+            .with_spool_dir(self.sock.spool_dir.clone())
+            .with_path_resolver(self.sock.path_resolver.clone())
+            .with_journal(self.sock.journal.clone())
+            .with_trusted_link(self.sock.trusted_link)
+            .with_timestamps(self.sock.timestamps);
+        self.receive(session)
+    }
+
+    /// refuse the transfer, the sender fails with `SecSnailError::Rejected`
+    pub fn reject(self) -> Result<()> {
+        tracing::info!(file = %self.meta.file_name, peer = %self.peer, "transfer rejected");
+        let sndpkt = Packet::new(false, Flag::RST, REJECT_REASON.as_bytes().to_vec())?
+            .with_transfer_id(self.syn.transfer_id());
+        self.sock.udt_send(&sndpkt, self.peer)?;
+        Ok(())
+    }
+
+    fn receive(self, mut session: RecvSession<'_>) -> Result<TransferReport> {
+        let _span = tracing::info_span!(
+            "recv_file",
+            file = %self.meta.file_name,
+            peer = %self.peer,
+        )
+        .entered();
+        let mut ctx = RecvProtocolIoContext::new(self.sock, &mut session);
+
+        // replay the accepted syn, which acks it and opens the file
+        let cur_fsm_wrap = handle_event(
+            RcvFsm::init(),
+            RcvEvent::RecvPck(Some(self.syn), self.peer),
+            &mut ctx,
+        )?;
+        let ret = run_rcv_fsm_loop_lingering(cur_fsm_wrap, &mut ctx)
+            .map(|_| session.take_report().expect("closed session has a report"));
+        self.sock.record_recv(
+            Some(self.peer),
+            session.transfer_id(),
+            session.stats(),
+            ret.as_ref().err(),
+        );
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::SecSnailError, sock::SendReport};
+    use std::{fs, thread};
+
+    fn spawn_sender(
+        name: &str,
+        content: &[u8],
+        recv_addr: SocketAddr,
+    ) -> thread::JoinHandle<Result<SendReport>> {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("snail.txt");
+        fs::write(&src, content).unwrap();
+
+        thread::spawn(move || {
+            let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+            sock.send_file_to_blocking(src, recv_addr)
+        })
+    }
+
+    #[test]
+    fn accept_and_save() {
+        let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let mut listener = SecSnailListener::bind("127.0.0.1:0").unwrap();
+        let sender = spawn_sender("accept-save", &content, listener.local_addr().unwrap());
+
+        let out = std::env::temp_dir().join(format!("secsnail-{}-accept-out", std::process::id()));
+        let (transfer, _) = listener.accept().unwrap();
+        assert_eq!(transfer.file_name(), "snail.txt");
+        assert_eq!(transfer.file_size(), Some(content.len() as u64));
+        let report = transfer.save_to(&out).unwrap();
+        assert_eq!(report.bytes, content.len());
+        assert_eq!(report.path, Some(out.join("snail.txt")));
+        assert_eq!(report.stats.payload_bytes, content.len());
+        assert_eq!(report.stats.retransmissions, 0);
+
+        assert_eq!(sender.join().unwrap().unwrap().bytes, content.len());
+        assert_eq!(fs::read(out.join("snail.txt")).unwrap(), content);
+    }
+
+    #[test]
+    fn anonymous_sender_is_named_by_receiver() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-anonymous", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("secret-plans.txt"), b"nobody knows").unwrap();
+        let mut listener = SecSnailListener::bind("127.0.0.1:0").unwrap();
+        let recv_addr = listener.local_addr().unwrap();
+        let src = dir.join("secret-plans.txt");
+        let sender = thread::spawn(move || {
+            let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+            let peer = sock.local_addr().unwrap();
+            for _ in 0..2 {
+                sock.send_file_anonymous_blocking(&src, recv_addr).unwrap();
+            }
+            peer
+        });
+
+        let out = dir.join("out");
+        let (transfer, _) = listener.accept().unwrap();
+        assert!(transfer.is_anonymous());
+        let report = transfer.save_as(&out, "plans.txt").unwrap();
+        assert_eq!(report.file_name, "plans.txt");
+        let (transfer, _) = listener.accept().unwrap();
+        let report = transfer.save_to(&out).unwrap();
+
+        let peer = sender.join().unwrap();
+        assert!(report.file_name.starts_with("anonymous-"));
+        assert!(
+            report
+                .file_name
+                .ends_with(&format!("-127.0.0.1-{}", peer.port()))
+        );
+        assert_eq!(fs::read(out.join("plans.txt")).unwrap(), b"nobody knows");
+        assert_eq!(
+            fs::read(out.join(&report.file_name)).unwrap(),
+            b"nobody knows"
+        );
+    }
+
+    #[test]
+    fn accept_into_writer() {
+        let content = b"slow and steady".to_vec();
+        let mut listener = SecSnailListener::bind("127.0.0.1:0").unwrap();
+        let sender = spawn_sender("accept-writer", &content, listener.local_addr().unwrap());
+
+        let mut buf = Vec::new();
+        let (transfer, _) = listener.accept().unwrap();
+        transfer.write_to(&mut buf).unwrap();
+
+        sender.join().unwrap().unwrap();
+        assert_eq!(buf, content);
+    }
+
+    #[test]
+    fn slow_writer_flags_congestion() {
+        /// a disk which takes its time for every write
+        struct SlowWriter(Vec<u8>);
+        impl Write for SlowWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                thread::sleep(std::time::Duration::from_millis(10));
+                self.0.write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let content: Vec<u8> = (0..40_000u32).map(|i| (i % 251) as u8).collect();
+        let sock = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .congestion_threshold(std::time::Duration::from_millis(2))
+            .build()
+            .unwrap();
+        let mut listener = SecSnailListener::from_socket(sock);
+        let sender = spawn_sender("congestion", &content, listener.local_addr().unwrap());
+
+        let mut writer = SlowWriter(Vec::new());
+        let (transfer, _) = listener.accept().unwrap();
+        let received = transfer.write_to(&mut writer).unwrap();
+
+        let sent = sender.join().unwrap().unwrap();
+        assert_eq!(writer.0, content);
+        assert!(received.stats.congestion_signals > 0);
+        assert_eq!(
+            sent.stats.congestion_signals,
+            received.stats.congestion_signals
+        );
+    }
+
+    #[test]
+    fn lost_finack_is_answered_after_save() {
+        let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let mut sock = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .rcv_timeout(std::time::Duration::from_millis(300))
+            .build()
+            .unwrap();
+        let mut dropped = false;
+        sock.set_fault_injector(move |_: &str, pck: &Packet| match pck.flag() {
+            Flag::FINACK if !dropped => {
+                dropped = true;
+                crate::sock::Fault::Drop
+            }
+            _ => crate::sock::Fault::Pass,
+        });
+        let mut listener = SecSnailListener::from_socket(sock);
+        let sender = spawn_sender("linger", &content, listener.local_addr().unwrap());
+
+        let out = std::env::temp_dir().join(format!("secsnail-{}-linger-out", std::process::id()));
+        let (transfer, _) = listener.accept().unwrap();
+        assert_eq!(transfer.save_to(&out).unwrap().bytes, content.len());
+        let sent = sender.join().unwrap().unwrap();
+        assert_eq!(sent.stats.retransmissions, 1);
+        assert_eq!(fs::read(out.join("snail.txt")).unwrap(), content);
+    }
+
+    #[test]
+    fn reject() {
+        let mut listener = SecSnailListener::bind("127.0.0.1:0").unwrap();
+        let sender = spawn_sender("reject", b"unwanted", listener.local_addr().unwrap());
+
+        let (transfer, _) = listener.accept().unwrap();
+        transfer.reject().unwrap();
+
+        assert!(matches!(
+            sender.join().unwrap(),
+            Err(SecSnailError::Rejected(_))
+        ));
+    }
+}
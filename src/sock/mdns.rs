@@ -0,0 +1,143 @@
+//! mDNS/DNS-SD advertisement of receiving sockets.
+//!
+//! A receiver registers itself as `<name>._secsnail._udp.local.`, clients
+//! browse for this service type instead of broadcasting a probe, or
+//! resolve a single receiver by its name.
+
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::{discovery::DiscoveredPeer, error::Result};
+
+use super::SecSnailSocket;
+
+pub const MDNS_SERVICE_TYPE: &str = "_secsnail._udp.local.";
+
+/// registered mDNS service, withdrawn on drop
+pub struct MdnsAdvertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Drop for MdnsAdvertisement {
+    fn drop(&mut self) {
+        _ = self.daemon.unregister(&self.fullname);
+        _ = self.daemon.shutdown();
+    }
+}
+
+impl SecSnailSocket {
+    /// advertise this socket as `name` on the local network
+    pub fn advertise_mdns(&self, name: &str) -> Result<MdnsAdvertisement> {
+        let port = self.inner.local_addr()?.port();
+        let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+        let info = ServiceInfo::new(
+            MDNS_SERVICE_TYPE,
+            name,
+            &format!("{name}.local."),
+            "",
+            port,
+            None,
+        )
+        .map_err(mdns_error)?
+        .enable_addr_auto();
+
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info).map_err(mdns_error)?;
+        Ok(MdnsAdvertisement { daemon, fullname })
+    }
+
+    /// browse for all receivers advertised via mDNS within `timeout`
+    pub fn discover_mdns(timeout: Duration) -> Result<Vec<DiscoveredPeer>> {
+        let mut peers = Vec::new();
+        browse(timeout, |peer, _| {
+            if !peers.contains(&peer) {
+                peers.push(peer);
+            }
+            false
+        })?;
+        Ok(peers)
+    }
+
+    /// resolve the receiver advertised as `name`, either by its instance
+    /// name or by its host name `<name>.local`
+    pub fn resolve_mdns(name: &str, timeout: Duration) -> Result<SocketAddr> {
+        let name = name.trim_end_matches('.');
+        let mut found = None;
+        browse(timeout, |peer, host| {
+            let host = host.trim_end_matches('.');
+            if peer.name == name || host == name || host.strip_suffix(".local") == Some(name) {
+                found = Some(peer.addr);
+            }
+            found.is_some()
+        })?;
+        found.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no secsnail receiver '{name}' found via mdns"),
+            )
+            .into()
+        })
+    }
+}
+
+/// call `f` with every resolved receiver and its host name until it
+/// returns true or `timeout` expired
+fn browse<F: FnMut(DiscoveredPeer, &str) -> bool>(timeout: Duration, mut f: F) -> Result<()> {
+    let daemon = ServiceDaemon::new().map_err(mdns_error)?;
+    let events = daemon.browse(MDNS_SERVICE_TYPE).map_err(mdns_error)?;
+    let deadline = Instant::now() + timeout;
+
+    while let Ok(event) = events.recv_deadline(deadline) {
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+        // prefer ipv4, like the broadcast discovery
+        let Some(ip) = info
+            .get_addresses()
+            .iter()
+            .min_by_key(|ip| ip.is_ipv6())
+            .copied()
+        else {
+            continue;
+        };
+        let peer = DiscoveredPeer {
+            name: instance_name(info.get_fullname()).to_string(),
+            addr: SocketAddr::new(ip, info.get_port()),
+        };
+        if f(peer, info.get_hostname()) {
+            break;
+        }
+    }
+
+    _ = daemon.shutdown();
+    Ok(())
+}
+
+/// `<name>._secsnail._udp.local.` to `<name>`
+fn instance_name(fullname: &str) -> &str {
+    fullname
+        .strip_suffix(MDNS_SERVICE_TYPE)
+        .and_then(|n| n.strip_suffix('.'))
+        .unwrap_or(fullname)
+}
+
+fn mdns_error(e: mdns_sd::Error) -> crate::error::SecSnailError {
+    io::Error::other(e).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_name_of_fullname() {
+        assert_eq!(instance_name("lab-pc._secsnail._udp.local."), "lab-pc");
+        assert_eq!(instance_name("other"), "other");
+    }
+}
@@ -0,0 +1,152 @@
+//! Prometheus text exposition of the counters of a socket.
+//!
+//! `SecSnailSocket::metrics_text` renders the counters of
+//! `SecSnailSocket::stats_snapshot` in the text format a Prometheus server
+//! scrapes, a `MetricsHandle` does so from another thread while the socket
+//! serves. Counters start over after `SecSnailSocket::reset_stats`, which
+//! Prometheus treats like a restart.
+
+use std::{
+    fmt::Write,
+    sync::{Arc, Mutex},
+};
+
+use super::{DatagramTransport, SecSnailSocket, SocketStats, TransferStats};
+
+/// renders the counters of a socket from any thread, see
+/// `SecSnailSocket::metrics_handle`
+#[derive(Debug, Clone)]
+pub struct MetricsHandle {
+    totals: Arc<Mutex<SocketStats>>,
+}
+
+impl MetricsHandle {
+    /// see `SecSnailSocket::metrics_text`
+    pub fn metrics_text(&self) -> String {
+        prometheus_text(&self.totals.lock().unwrap())
+    }
+}
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// counters of all transfers in the Prometheus text format, see the
+    /// module docs
+    pub fn metrics_text(&self) -> String {
+        prometheus_text(&self.stats_snapshot())
+    }
+
+    /// render `metrics_text` from another thread, e.g. an http listener
+    pub fn metrics_handle(&self) -> MetricsHandle {
+        MetricsHandle {
+            totals: self.totals.clone(),
+        }
+    }
+}
+
+/// name, help and value of a counter
+type Counter = (&'static str, &'static str, fn(&TransferStats) -> f64);
+
+/// counters of both directions
+const COUNTERS: &[Counter] = &[
+    (
+        "packets_sent",
+        "Packets sent, including retransmissions.",
+        |s| s.packets_sent as f64,
+    ),
+    (
+        "bytes_on_wire",
+        "Bytes of all sent packets including header.",
+        |s| s.bytes_on_wire as f64,
+    ),
+    ("payload_bytes", "File bytes transferred.", |s| {
+        s.payload_bytes as f64
+    }),
+    ("retransmissions", "Packets sent again.", |s| {
+        s.retransmissions as f64
+    }),
+    (
+        "corrupt_dropped",
+        "Received datagrams dropped for a bad checksum or format.",
+        |s| s.corrupt_dropped as f64,
+    ),
+    (
+        "timeouts",
+        "Expired retransmission or connection timers.",
+        |s| s.timeouts as f64,
+    ),
+    (
+        "paced_seconds",
+        "Time the sender held data packets back.",
+        |s| s.paced.as_secs_f64(),
+    ),
+];
+
+fn prometheus_text(stats: &SocketStats) -> String {
+    let mut buf = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, values: &[(&str, f64)]| {
+        _ = writeln!(buf, "# HELP secsnail_{name} {help}");
+        _ = writeln!(buf, "# TYPE secsnail_{name} {kind}");
+        for (labels, value) in values {
+            _ = writeln!(buf, "secsnail_{name}{labels} {value}");
+        }
+    };
+    metric(
+        "active_transfers",
+        "gauge",
+        "Transfers started but not ended yet.",
+        &[("", stats.active as f64)],
+    );
+    metric(
+        "transfers_total",
+        "counter",
+        "Transfers ended, also failed ones.",
+        &[
+            (r#"{direction="send"}"#, stats.sends as f64),
+            (r#"{direction="recv"}"#, stats.receives as f64),
+        ],
+    );
+    for (name, help, value) in COUNTERS {
+        metric(
+            &format!("{name}_total"),
+            "counter",
+            help,
+            &[
+                (r#"{direction="send"}"#, value(&stats.sent)),
+                (r#"{direction="recv"}"#, value(&stats.received)),
+            ],
+        );
+    }
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, thread};
+
+    #[test]
+    fn counters_are_exposed() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-metrics", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("snail.txt"), b"measured snail").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let metrics = receiver.metrics_handle();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+            .unwrap();
+        recv.join().unwrap();
+
+        let text = sender.metrics_text();
+        assert!(text.contains("# TYPE secsnail_transfers_total counter\n"));
+        assert!(text.contains("secsnail_transfers_total{direction=\"send\"} 1\n"));
+        assert!(text.contains("secsnail_payload_bytes_total{direction=\"send\"} 14\n"));
+        assert!(text.contains("secsnail_active_transfers 0\n"));
+        let text = metrics.metrics_text();
+        assert!(text.contains("secsnail_transfers_total{direction=\"recv\"} 1\n"));
+        assert!(text.contains("secsnail_payload_bytes_total{direction=\"recv\"} 14\n"));
+    }
+}
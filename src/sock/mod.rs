@@ -0,0 +1,2736 @@
+//! The `SecSnailSocket` represents a bidirectional UDP transport endpoint
+//! used by both sending and receiving finite state machines (FSM).
+//!
+//! Each transfer (either sending or receiving) owns a temporary
+//! protocol I/O context (`SendProtocolIoContext`, `RecvProtocolIoContext`)
+//! which drives the FSM logic on top of the same socket.
+//!
+//! The datagrams themselves go through a `DatagramTransport`, which is a
+//! plain `UdpSocket` unless another transport is given to the builder.
+//!
+//! A single transfer runs either blocking or driven step by step in
+//! non-blocking mode via the `poll_*` functions. `serve` receives from
+//! several senders at the same time, see `demux`.
+
+use std::{
+    collections::{HashMap, HashSet, hash_map::Entry},
+    fs::{self, File},
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::Poll,
+    thread,
+    time::{Duration, Instant},
+};
+
+use socket2::SockRef;
+
+use crate::{
+    discovery,
+    error::{Result, SecSnailError},
+    fsm_recv::{
+        self,
+        driver::{poll_rcv_fsm, run_rcv_fsm_loop_lingering},
+    },
+    impair::Impairment,
+    meta::utf8_file_name,
+    pck::MAX_PAYLOAD_SIZE,
+};
+
+use super::fsm_send::driver::{poll_snd_fsm, run_snd_fsm_loop};
+use super::pck::{Packet, PacketView};
+use crate::fsm_send;
+
+#[cfg(feature = "async")]
+mod async_sock;
+mod builder;
+mod capture;
+mod concurrent;
+mod delay;
+mod delta;
+mod demux;
+mod events;
+mod fault;
+mod filter;
+mod history;
+mod incoming;
+mod journal;
+mod listener;
+#[cfg(feature = "mdns")]
+mod mdns;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod multicast;
+mod pool;
+mod prefetch;
+mod queue;
+mod quota;
+mod rcv_ctx;
+mod reflexive;
+#[cfg(feature = "rendezvous")]
+mod rendezvous;
+mod report;
+mod shutdown;
+mod snd_ctx;
+mod sync;
+mod trace;
+mod transfers;
+mod transport;
+mod workers;
+pub use crate::discovery::DiscoveredPeer;
+pub use crate::impair::GilbertElliott;
+pub use crate::meta::{NamePolicy, Verdict};
+pub use crate::pck::DecodeMode;
+pub use crate::timestamp::QueueingDelay;
+#[cfg(feature = "smol")]
+pub use async_sock::SmolUdpSocket;
+#[cfg(feature = "tokio")]
+pub use async_sock::TokioUdpSocket;
+#[cfg(feature = "async")]
+pub use async_sock::{AsyncDatagramSocket, AsyncSecSnailSocket, BoxFuture};
+pub use builder::SecSnailSocketBuilder;
+use capture::{Capture, Direction};
+use delay::DelayLine;
+use demux::RecvDemux;
+pub use events::SnailEvent;
+use events::Subscribers;
+pub use fault::{Fault, FaultInjector};
+pub use filter::IpNet;
+use filter::PeerFilter;
+pub use history::Transition;
+use history::TransitionLog;
+pub use incoming::Incoming;
+use journal::{Journal, finish_journaled, journal_send};
+pub use journal::{PendingTransfer, TransferDirection};
+pub use listener::{IncomingTransfer, SecSnailListener};
+#[cfg(feature = "mdns")]
+pub use mdns::{MDNS_SERVICE_TYPE, MdnsAdvertisement};
+#[cfg(feature = "metrics")]
+pub use metrics::MetricsHandle;
+use pool::BufferPool;
+pub use queue::SendQueue;
+use quota::SenderQuota;
+pub use rcv_ctx::{CheckpointPolicy, OverwritePolicy};
+use rcv_ctx::{PathResolver, RecvProtocolIoContext, RecvSession};
+#[cfg(feature = "rendezvous")]
+pub use rendezvous::{DEFAULT_RENDEZVOUS_PORT, RendezvousServer};
+pub use report::{
+    Progress, SendOutcome, SendReport, SocketStats, TransferReport, TransferStats, outcomes_to_json,
+};
+pub use shutdown::ShutdownHandle;
+use snd_ctx::{SendProtocolIoContext, SendSession};
+use trace::TraceLog;
+pub use transfers::{TransferHandle, TransferStatus, TransferWorker};
+pub use transport::DatagramTransport;
+
+pub use crate::proto::{DEFAULT_MAX_RETRANSMITS, DEFAULT_RCV_TIMEOUT_MS, DEFAULT_SND_TIMEOUT_MS};
+
+pub const DEFAULT_FIRST_N: u8 = 0;
+pub const DEFAULT_SECSNAIL_PORT: u16 = 55055;
+
+enum RecvResult {
+    RecvPkt(Option<Packet>, SocketAddr),
+    Timeout,
+}
+
+/// send transfer suspended between two `poll_send_progress` calls
+struct PendingSend {
+    fsm: fsm_send::fsm::SndFsm,
+    session: SendSession,
+    start_time: Instant,
+}
+
+/// reception suspended between two `poll_recv_progress` calls
+struct PendingRecv {
+    fsm: fsm_recv::fsm::RcvFsm,
+    session: RecvSession<'static>,
+}
+
+/// callback of `SecSnailSocket::set_progress_callback`
+type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// # Examples
+///
+/// ## Sending a file
+/// ```no_run
+/// use secsnail::sock::{DEFAULT_SECSNAIL_PORT, SecSnailSocket};
+/// use std::net::SocketAddr;
+/// let recv_addr: SocketAddr = format!("{}:{}", "127.0.0.1", DEFAULT_SECSNAIL_PORT)
+/// .parse()
+/// .expect("Unable to parse socket address");
+///
+/// let mut secsnail_sock = SecSnailSocket::builder()
+///     .connect(recv_addr)
+///     .max_retransmits(10)
+///     .build()
+///     .unwrap();
+///
+/// let report = secsnail_sock.send_file_blocking("file.txt").unwrap();
+/// println!("{} bytes at {} byte/s", report.bytes, report.goodput());
+/// ```
+///
+/// ## Receiving a file
+/// ```no_run
+/// use secsnail::sock::SecSnailSocket;
+/// let mut secsnail_sock = SecSnailSocket::bind_default_port().unwrap();
+///
+/// let report = secsnail_sock.recv_file_blocking("./test").unwrap();
+/// println!("{} from {}", report.file_name, report.peer);
+/// ```
+pub struct SecSnailSocket<T = UdpSocket> {
+    inner: T,
+    snd_max_retransmits: u8,
+    snd_timeout_config: Duration,
+    rcv_timeout_config: Duration,
+    transfer_deadline: Option<Duration>,
+    /// a failed send is started over this often
+    transfer_retries: u32,
+    /// wait before the first retry, doubled for every further one
+    retry_backoff: Duration,
+    max_recv_file_size: Option<u64>,
+    /// offer receivers to resume a partial file
+    offer_resume: bool,
+    /// read sent files through a memory mapping
+    mmap_reads: bool,
+    /// payloads read ahead by a background thread
+    read_ahead: usize,
+    /// see `SecSnailSocketBuilder::min_packet_gap`
+    min_packet_gap: Duration,
+    /// skip checksums after the syn if the peer agrees
+    trusted_link: bool,
+    /// stamp data packets if the peer agrees, see `SecSnailSocketBuilder::timestamps`
+    timestamps: bool,
+    /// writes of received data taking longer flag congestion in the ack
+    congestion_threshold: Option<Duration>,
+    /// when received files are synced to disk
+    checkpoints: CheckpointPolicy,
+    /// see `SecSnailSocketBuilder::sync_on_close`
+    sync_on_close: bool,
+    /// see `SecSnailSocketBuilder::spool_dir`
+    spool_dir: Option<PathBuf>,
+    overwrite_policy: OverwritePolicy,
+    name_policy: NamePolicy,
+    /// destination of received files instead of their announced names
+    path_resolver: Option<PathResolver>,
+    /// pending transfers, see `SecSnailSocketBuilder::journal`
+    journal: Option<Arc<Journal>>,
+    strictness: Strictness,
+    /// checks of received datagrams, strict on a public interface unless set
+    decode_mode: DecodeMode,
+    /// answer discovery probes while receiving
+    discovery_name: Option<String>,
+    /// datagrams of other peers are dropped before the fsm sees them
+    peer_filter: PeerFilter,
+    /// shared by the workers of `serve_threaded`
+    quota: Option<Mutex<SenderQuota>>,
+    impairment: Impairment,
+    /// holds back datagrams if the impairment delays them
+    delay_line: Option<DelayLine>,
+    /// set by `connect`
+    peer: Option<SocketAddr>,
+    nonblocking: bool,
+    pending_snd: Option<PendingSend>,
+    /// behind a mutex to keep the socket `Sync`, see `serve_threaded`
+    pending_rcv: Option<Mutex<PendingRecv>>,
+    last_stats: Option<TransferStats>,
+    /// counters of all transfers, shared with a `MetricsHandle`
+    totals: Arc<Mutex<SocketStats>>,
+    /// transfers started but not ended, see `SocketStats::active`
+    active: Mutex<HashSet<(TransferDirection, SocketAddr, u8)>>,
+    /// see `subscribe`
+    subscribers: Subscribers,
+    /// pcapng file of all sent and received datagrams
+    capture: Option<Capture>,
+    /// line per fsm event and emitted packet
+    trace: Option<TraceLog>,
+    history: Option<TransitionLog>,
+    faults: Option<Mutex<Box<dyn FaultInjector>>>,
+    progress: Option<ProgressCallback>,
+    recv_bufs: BufferPool,
+    send_bufs: BufferPool,
+    /// set by a `ShutdownHandle`
+    stop: Arc<AtomicBool>,
+}
+
+impl SecSnailSocket {
+    pub fn bind_default_port() -> Result<SecSnailSocket> {
+        SecSnailSocket::bind(format!("0.0.0.0:{DEFAULT_SECSNAIL_PORT}"))
+    }
+
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<SecSnailSocket> {
+        SecSnailSocket::builder().bind(addr).build()
+    }
+
+    /// bind an ephemeral port and fix `addr` as peer
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<SecSnailSocket> {
+        SecSnailSocket::builder().connect(addr).build()
+    }
+
+    /// broadcast a discovery probe to the default port of the local network
+    ///
+    /// # Return
+    /// all discoverable receivers which answered within `timeout`
+    pub fn discover(timeout: Duration) -> Result<Vec<DiscoveredPeer>> {
+        SecSnailSocket::discover_at(
+            SocketAddr::from(([255, 255, 255, 255], DEFAULT_SECSNAIL_PORT)),
+            timeout,
+        )
+    }
+
+    /// like `discover`, but probe `addr`, e.g. a subnet broadcast or a single host
+    pub fn discover_at(addr: SocketAddr, timeout: Duration) -> Result<Vec<DiscoveredPeer>> {
+        let unspecified = match addr {
+            SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        let sock = UdpSocket::bind(unspecified)?;
+        sock.set_broadcast(true)?;
+        sock.send_to(&discovery::probe(), addr)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut peers: Vec<DiscoveredPeer> = Vec::new();
+        let mut buf = vec![0; MAX_PAYLOAD_SIZE];
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            if remaining.is_zero() {
+                break;
+            }
+            sock.set_read_timeout(Some(remaining))?;
+            let (n, src) = match sock.recv_from(&mut buf) {
+                Ok(v) => v,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(peer) = discovery::decode_reply(&buf[..n], src)
+                && !peers.contains(&peer)
+            {
+                peers.push(peer);
+            }
+        }
+        Ok(peers)
+    }
+
+    /// configure and validate all socket parameters up front
+    pub fn builder() -> SecSnailSocketBuilder {
+        SecSnailSocketBuilder::new()
+    }
+
+    pub fn os_recv_buffer(&self) -> Result<usize> {
+        Ok(SockRef::from(&self.inner).recv_buffer_size()?)
+    }
+
+    pub fn os_send_buffer(&self) -> Result<usize> {
+        Ok(SockRef::from(&self.inner).send_buffer_size()?)
+    }
+}
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    // socket blocking functionality
+
+    /// send a file to the connected peer, see `connect`
+    pub fn send_file_blocking<P: AsRef<Path>>(&mut self, path: P) -> Result<SendReport> {
+        let recv_addr = self.peer_addr()?;
+        self.send_file_to_blocking(path, recv_addr)
+    }
+
+    /// send a file to `recv_addr`, also on an unconnected socket
+    pub fn send_file_to_blocking<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recv_addr: SocketAddr,
+    ) -> Result<SendReport> {
+        let path = path.as_ref();
+        let _span =
+            tracing::info_span!("send_file", file = %path.display(), peer = %recv_addr).entered();
+        self.send_path_with_retries(path, recv_addr, Ok)
+    }
+
+    /// like `send_file_to_blocking`, but the receiver stores the file
+    /// under `remote_name` instead of the local file name
+    pub fn send_file_as_blocking<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        remote_name: &str,
+        recv_addr: SocketAddr,
+    ) -> Result<SendReport> {
+        let path = path.as_ref();
+        let _span = tracing::info_span!(
+            "send_file",
+            file = %path.display(),
+            remote_name,
+            peer = %recv_addr
+        )
+        .entered();
+        self.send_path_with_retries(path, recv_addr, |session| {
+            session.with_remote_name(remote_name)
+        })
+    }
+
+    /// like `send_file_to_blocking`, but announce no name, the receiver
+    /// names the file itself, e.g. by the time it arrived
+    pub fn send_file_anonymous_blocking<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recv_addr: SocketAddr,
+    ) -> Result<SendReport> {
+        let path = path.as_ref();
+        let _span = tracing::info_span!(
+            "send_file",
+            file = %path.display(),
+            anonymous = true,
+            peer = %recv_addr
+        )
+        .entered();
+        self.send_path_with_retries(path, recv_addr, |session| Ok(session.with_anonymous()))
+    }
+
+    /// send everything `reader` yields, e.g. stdin, to `recv_addr`, the
+    /// receiver stores it under `remote_name`
+    ///
+    /// the size is not announced, so a stream can not be resumed, nor is it
+    /// retried after a failure
+    pub fn send_reader_blocking<R: Read + Send + 'static>(
+        &mut self,
+        reader: R,
+        remote_name: &str,
+        recv_addr: SocketAddr,
+    ) -> Result<SendReport> {
+        let _span = tracing::info_span!("send_file", remote_name, peer = %recv_addr).entered();
+        let session =
+            SendSession::from_reader(recv_addr, reader, remote_name, self.snd_timeout_config)?
+                .with_transfer_deadline(self.transfer_deadline, self.inner.now())
+                .with_read_ahead(self.read_ahead)
+                .with_min_gap(self.min_packet_gap);
+        self.send_session(session, 1)
+    }
+
+    /// send `path`, started over after a timeout as the retry policy allows,
+    /// `rename` announces every attempt under another name
+    fn send_path_with_retries(
+        &mut self,
+        path: &Path,
+        recv_addr: SocketAddr,
+        rename: impl Fn(SendSession) -> Result<SendSession>,
+    ) -> Result<SendReport> {
+        // the deadline covers all attempts
+        let start = self.inner.now();
+        let mut attempt = 1;
+        let mut entry = None;
+        loop {
+            let session = rename(self.new_send_session(path, recv_addr, start)?)?;
+            let session = journal_send(self.journal.as_ref(), session, path, &mut entry)?;
+            match self.send_session(session, attempt) {
+                Err(e) if is_retryable(&e) && attempt <= self.transfer_retries as usize => {
+                    let backoff = retry_backoff(self.retry_backoff, attempt);
+                    tracing::warn!(attempt, error = %e, ?backoff, "retrying transfer");
+                    thread::sleep(backoff);
+                    attempt += 1;
+                }
+                // the journal keeps a send which may succeed later
+                Err(e) if is_retryable(&e) => return Err(e),
+                r => {
+                    finish_journaled(self.journal.as_ref(), entry);
+                    return r;
+                }
+            }
+        }
+    }
+
+    fn new_send_session(
+        &self,
+        path: &Path,
+        recv_addr: SocketAddr,
+        start: Instant,
+    ) -> Result<SendSession> {
+        SendSession::new(recv_addr, path, self.snd_timeout_config)?
+            .with_transfer_deadline(self.transfer_deadline, start)
+            .with_resume(self.offer_resume)
+            .with_read_ahead(self.read_ahead)
+            .with_min_gap(self.min_packet_gap)
+            .with_trusted_link(self.trusted_link)
+            .with_timestamps(self.timestamps)
+            .with_mmap(self.mmap_reads)
+    }
+
+    fn send_session(&mut self, mut session: SendSession, attempt: usize) -> Result<SendReport> {
+        let max_transmits = self.snd_max_retransmits;
+        let mut ctx = SendProtocolIoContext::new(self, &mut session);
+        let ret = run_snd_fsm_loop(&mut ctx, max_transmits);
+        let stats = TransferStats {
+            attempts: attempt,
+            ..session.stats()
+        };
+        let ret = ret.map(|(_, duration)| SendReport {
+            stats,
+            ..session.report(duration)
+        });
+        self.record_send(
+            session.recv_addr(),
+            session.transfer_id(),
+            stats,
+            ret.as_ref().err(),
+        );
+        log_send_outcome(&ret);
+        ret
+    }
+
+    /// send every file matching the glob `pattern` to `recv_addr`, one
+    /// after another in alphabetical order
+    ///
+    /// # Return
+    /// the result of every matched file, a file which failed does not stop
+    /// the others, fails only for an invalid pattern
+    pub fn send_matching_blocking(
+        &mut self,
+        pattern: &str,
+        recv_addr: SocketAddr,
+    ) -> Result<Vec<SendOutcome>> {
+        let paths = glob::glob(pattern).map_err(|e| {
+            SecSnailError::InvalidConfig(format!("invalid pattern '{pattern}': {e}"))
+        })?;
+        let mut results = Vec::new();
+        for entry in paths {
+            let (path, r) = match entry {
+                Ok(path) if path.is_dir() => continue,
+                Ok(path) => {
+                    let r = self.send_file_to_blocking(&path, recv_addr);
+                    (path, r)
+                }
+                Err(e) => (e.path().to_path_buf(), Err(io::Error::from(e).into())),
+            };
+            results.push((path, r));
+        }
+        Ok(results)
+    }
+
+    /// send every file below `dir` to `recv_addr`, walking subdirectories
+    /// depth first in alphabetical order
+    ///
+    /// a file is announced under its own name, the receiver stores all of
+    /// them in its target directory
+    ///
+    /// # Return
+    /// the result of every file, a file or subdirectory which failed does
+    /// not stop the others, fails only if `dir` can not be read
+    pub fn send_dir_blocking<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        recv_addr: SocketAddr,
+    ) -> Result<Vec<SendOutcome>> {
+        let mut files = Vec::new();
+        let mut results = Vec::new();
+        walk_dir(dir.as_ref(), &mut files, &mut results)?;
+        reject_case_collisions(&mut files, &mut results, |path| {
+            path.file_name().map(utf8_file_name).unwrap_or_default()
+        });
+        for path in files {
+            let r = self.send_file_to_blocking(&path, recv_addr);
+            results.push((path, r));
+        }
+        Ok(results)
+    }
+
+    /// ask `recv_addr` whether it already holds a file identical to the one
+    /// at `path`, without sending its data
+    ///
+    /// the receiver compares the name, size and SHA-256 of the file it would
+    /// store the file under
+    pub fn verify_file_blocking<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recv_addr: SocketAddr,
+    ) -> Result<Verdict> {
+        self.verify_path(path.as_ref(), None, recv_addr)
+    }
+
+    /// like `verify_file_blocking`, but compares with the file the receiver
+    /// holds under `remote_name`
+    pub fn verify_file_as_blocking<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        remote_name: &str,
+        recv_addr: SocketAddr,
+    ) -> Result<Verdict> {
+        self.verify_path(path.as_ref(), Some(remote_name), recv_addr)
+    }
+
+    fn verify_path(
+        &mut self,
+        path: &Path,
+        remote_name: Option<&str>,
+        recv_addr: SocketAddr,
+    ) -> Result<Verdict> {
+        let _span =
+            tracing::info_span!("verify_file", file = %path.display(), peer = %recv_addr).entered();
+        let mut session = self
+            .new_send_session(path, recv_addr, self.inner.now())?
+            .with_verify()?;
+        if let Some(name) = remote_name {
+            session = session.with_remote_name(name)?;
+        }
+        let report = self.send_session(session, 1)?;
+        report.verdict.ok_or_else(|| {
+            SecSnailError::ProtocolViolation("receiver did not verify the file".to_string())
+        })
+    }
+
+    /// send the file at `path` to every receiver in `recv_addrs`, one after
+    /// another
+    ///
+    /// # Return
+    /// the result of every receiver, a receiver which failed does not stop
+    /// the others
+    pub fn send_file_to_all_blocking<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recv_addrs: &[SocketAddr],
+    ) -> HashMap<SocketAddr, Result<SendReport>> {
+        let path = path.as_ref();
+        recv_addrs
+            .iter()
+            .map(|&recv_addr| (recv_addr, self.send_file_to_blocking(path, recv_addr)))
+            .collect()
+    }
+
+    /// wait for a single file and store it in `target_dir`, returns once
+    /// its session reached the end and a lost finack could no longer be
+    /// asked for, see `serve` to receive until stopped
+    ///
+    /// # Return
+    /// report of the received file, a session which timed out fails with
+    /// `SecSnailError::ConnectionTimeout`
+    pub fn recv_file_blocking<P: AsRef<Path>>(&mut self, target_dir: P) -> Result<TransferReport> {
+        let _span =
+            tracing::info_span!("recv_file", dir = %target_dir.as_ref().display()).entered();
+        let mut session = self.new_recv_session(target_dir.as_ref())?;
+        let mut ctx = RecvProtocolIoContext::new(self, &mut session);
+        let ret = run_rcv_fsm_loop_lingering(fsm_recv::fsm::RcvFsm::init(), &mut ctx)
+            .map(|_| session.take_report().expect("closed session has a report"));
+        let peer = ret.as_ref().map_or(session.snd_addr(), |r| Some(r.peer));
+        self.record_recv(
+            peer,
+            session.transfer_id(),
+            session.stats(),
+            ret.as_ref().err(),
+        );
+        ret
+    }
+
+    /// stop receiving from another thread, see `ShutdownHandle::shutdown`
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.stop.clone(), self.inner.local_addr().ok())
+    }
+
+    /// receive files into `target_dir` until `handler` breaks or the
+    /// socket is shut down
+    ///
+    /// files of different senders are received concurrently, `handler`
+    /// is called after each completed file, sessions which timed out
+    /// or exceeded the transfer deadline are skipped
+    pub fn serve<P, F>(&mut self, target_dir: P, mut handler: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(TransferReport) -> ControlFlow<()>,
+    {
+        let target_dir = target_dir.as_ref();
+        prepare_target_dir(target_dir)?;
+
+        let mut demux = RecvDemux::new(target_dir.to_path_buf());
+        loop {
+            for ((peer, transfer_id), outcome) in demux.step(self)? {
+                let report = match outcome {
+                    Err(e) if is_sender_failure(&e) => {
+                        let stats = TransferStats::default();
+                        let dir = TransferDirection::Recv;
+                        self.notify_ended(dir, Some(peer), transfer_id, &stats, Some(&e));
+                        continue;
+                    }
+                    r => r?,
+                };
+                self.record_recv(Some(report.peer), transfer_id, report.stats, None);
+                if handler(report).is_break() {
+                    return Ok(());
+                }
+            }
+            if self.check_shutdown().is_err() && demux.is_idle() {
+                return Ok(());
+            }
+        }
+    }
+
+    // socket non-blocking functionality
+
+    /// in non-blocking mode the `poll_*` functions return `Poll::Pending`
+    /// instead of waiting for the next datagram
+    pub fn set_nonblocking(&mut self, nonblocking: bool) -> Result<()> {
+        self.inner.set_nonblocking(nonblocking)?;
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    /// start a send transfer which is driven by `poll_send_progress`
+    pub fn start_send_file<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        recv_addr: SocketAddr,
+    ) -> Result<()> {
+        let session = self.new_send_session(path.as_ref(), recv_addr, self.inner.now())?;
+        self.pending_snd = Some(PendingSend {
+            fsm: fsm_send::fsm::SndFsm::init(self.snd_max_retransmits),
+            session,
+            start_time: self.inner.now(),
+        });
+        Ok(())
+    }
+
+    /// handle all events of the started send transfer which are ready
+    ///
+    /// # Return
+    /// `Poll::Ready` with the report once the transfer is done,
+    /// `Poll::Pending` if it has to be polled again
+    pub fn poll_send_progress(&mut self) -> Result<Poll<SendReport>> {
+        let mut pending = self
+            .pending_snd
+            .take()
+            .ok_or(SecSnailError::NoActiveTransfer)?;
+
+        let _span = tracing::info_span!("send_file", peer = %pending.session.recv_addr()).entered();
+        let mut ctx = SendProtocolIoContext::new(self, &mut pending.session);
+        let (fsm, progress) = match poll_snd_fsm(pending.fsm, &mut ctx) {
+            Ok(v) => v,
+            Err(e) => {
+                let session = &pending.session;
+                self.record_send(
+                    session.recv_addr(),
+                    session.transfer_id(),
+                    session.stats(),
+                    Some(&e),
+                );
+                return Err(e);
+            }
+        };
+
+        if progress.is_ready() {
+            let session = &pending.session;
+            self.record_send(
+                session.recv_addr(),
+                session.transfer_id(),
+                session.stats(),
+                None,
+            );
+            let duration = self
+                .inner
+                .now()
+                .saturating_duration_since(pending.start_time);
+            return Ok(Poll::Ready(pending.session.report(duration)));
+        }
+
+        pending.fsm = fsm;
+        self.pending_snd = Some(pending);
+        Ok(Poll::Pending)
+    }
+
+    /// start listening for files which are received by `poll_recv_progress`
+    pub fn start_recv_file<P: AsRef<Path>>(&mut self, target_dir: P) -> Result<()> {
+        let session = self.new_recv_session(target_dir.as_ref())?;
+        self.pending_rcv = Some(Mutex::new(PendingRecv {
+            fsm: fsm_recv::fsm::RcvFsm::init(),
+            session,
+        }));
+        Ok(())
+    }
+
+    /// handle all events of the receiving side which are ready
+    ///
+    /// # Return
+    /// `Poll::Ready` with the amount of received bytes once a session is
+    /// closed, the socket keeps listening for the next one afterwards,
+    /// also after a session failed, e.g. with `ConnectionTimeout`, while
+    /// an i/o error ends the receive and is returned as is
+    pub fn poll_recv_progress(&mut self) -> Result<Poll<usize>> {
+        let mut pending = self
+            .pending_rcv
+            .take()
+            .ok_or(SecSnailError::NoActiveTransfer)?
+            .into_inner()
+            .unwrap();
+
+        let _span = tracing::info_span!("recv_file").entered();
+        let mut ctx = RecvProtocolIoContext::new(self, &mut pending.session);
+        let (fsm, progress) = match poll_rcv_fsm(pending.fsm, &mut ctx) {
+            Ok((fsm, progress)) => (Some(fsm), progress),
+            // only the session failed, the socket waits for the next one
+            Err(e) if is_sender_failure(&e) || matches!(e, SecSnailError::Rejected(_)) => {
+                (Some(fsm_recv::fsm::RcvFsm::init()), Poll::Ready(Err(e)))
+            }
+            // the socket itself failed, listening ends
+            Err(e) => (None, Poll::Ready(Err(e))),
+        };
+
+        let data_counter = pending.session.data_counter();
+        if let Poll::Ready(outcome) = &progress {
+            let peer = pending.session.report_peer().or(pending.session.snd_addr());
+            let transfer_id = pending.session.transfer_id();
+            let stats = pending.session.stats();
+            self.record_recv(peer, transfer_id, stats, outcome.as_ref().err());
+            if outcome.is_err() {
+                // a file left open by the failed session is not completed
+                _ = pending.session.discard_file();
+            }
+        }
+        if let Some(fsm) = fsm {
+            pending.fsm = fsm;
+            self.pending_rcv = Some(Mutex::new(pending));
+        }
+
+        match progress {
+            Poll::Ready(outcome) => outcome.map(|_| Poll::Ready(data_counter)),
+            Poll::Pending => Ok(Poll::Pending),
+        }
+    }
+
+    fn new_recv_session(&self, target_dir: &Path) -> Result<RecvSession<'static>> {
+        prepare_target_dir(target_dir)?;
+        Ok(
+            RecvSession::new(target_dir.to_path_buf(), self.rcv_timeout_config)
+                .with_transfer_deadline(self.transfer_deadline)
+                .with_max_file_size(self.max_recv_file_size)
+                .with_overwrite_policy(self.overwrite_policy)
+                .with_name_policy(self.name_policy)
+                .with_congestion_threshold(self.congestion_threshold)
+                .with_checkpoints(self.checkpoints)
+                .with_sync_on_close(self.sync_on_close)
+                .with_spool_dir(self.spool_dir.clone())
+                .with_path_resolver(self.path_resolver.clone())
+                .with_journal(self.journal.clone())
+                .with_trusted_link(self.trusted_link)
+                .with_timestamps(self.timestamps),
+        )
+    }
+
+    // socket observation functions
+
+    /// write every sent and received datagram with timestamp and direction
+    /// into a new pcapng file at `path`, e.g. to inspect a transfer in Wireshark
+    ///
+    /// datagrams are recorded as they leave the impairment, so lost ones are missing
+    pub fn set_capture_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.capture = Some(Capture::create(path, self.inner.local_addr()?)?);
+        Ok(())
+    }
+
+    /// stop and close a capture started with `set_capture_file`
+    pub fn stop_capture(&mut self) {
+        self.capture = None;
+    }
+
+    /// write a line per event consumed and packet emitted by the fsms into `wrt`,
+    /// see the format below
+    ///
+    /// ```text
+    /// t_us=0 fsm=send event=init_syn
+    /// t_us=12 fsm=send emit=SYN n=0 len=8 peer=10.0.0.2:55055
+    /// t_us=1043 fsm=send event=rcv_pkt flag=ACK n=0 len=0 checksum=ok
+    /// ```
+    pub fn set_trace_writer<W: Write + Send + 'static>(&mut self, wrt: W) {
+        self.trace = Some(TraceLog::new(Box::new(wrt), self.inner.now()));
+    }
+
+    /// like `set_trace_writer` into a new file at `path`
+    pub fn set_trace_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        self.set_trace_writer(File::create(path)?);
+        Ok(())
+    }
+
+    pub fn stop_trace(&mut self) {
+        self.trace = None;
+    }
+
+    /// counters of the last finished transfer, also of a failed one
+    pub fn last_transfer_stats(&self) -> Option<TransferStats> {
+        self.last_stats
+    }
+
+    /// counters of all transfers since the socket was created or
+    /// `reset_stats`, e.g. to measure a phase of a benchmark
+    pub fn stats_snapshot(&self) -> SocketStats {
+        *self.totals.lock().unwrap()
+    }
+
+    /// zero the counters of `stats_snapshot`, `last_transfer_stats` and
+    /// the transfers in progress are kept
+    pub fn reset_stats(&self) {
+        let mut totals = self.totals.lock().unwrap();
+        *totals = SocketStats {
+            active: totals.active,
+            ..SocketStats::default()
+        };
+    }
+
+    /// a send to `peer` finished with `stats`, failed if there is an `error`
+    fn record_send(
+        &mut self,
+        peer: SocketAddr,
+        transfer_id: u8,
+        stats: TransferStats,
+        error: Option<&SecSnailError>,
+    ) {
+        self.last_stats = Some(stats);
+        self.totals.lock().unwrap().record_send(&stats);
+        self.notify_ended(
+            TransferDirection::Send,
+            Some(peer),
+            transfer_id,
+            &stats,
+            error,
+        );
+    }
+
+    /// a receive from `peer` finished with `stats`, failed if there is an
+    /// `error`
+    fn record_recv(
+        &mut self,
+        peer: Option<SocketAddr>,
+        transfer_id: u8,
+        stats: TransferStats,
+        error: Option<&SecSnailError>,
+    ) {
+        self.last_stats = Some(stats);
+        self.totals.lock().unwrap().record_recv(&stats);
+        self.notify_ended(TransferDirection::Recv, peer, transfer_id, &stats, error);
+    }
+
+    /// record every fsm transition, see `last_transfer_trace`
+    pub fn set_transition_recording(&mut self, record: bool) {
+        self.history = record.then(|| TransitionLog::new(None));
+    }
+
+    /// call `callback` with every fsm transition instead of keeping them
+    pub fn set_transition_callback<F: Fn(&Transition) + Send + Sync + 'static>(
+        &mut self,
+        callback: F,
+    ) {
+        self.history = Some(TransitionLog::new(Some(Arc::new(callback))));
+    }
+
+    /// call `callback` with the progress of a send after every packet it
+    /// puts on the wire, e.g. to render a progress bar
+    pub fn set_progress_callback<F: Fn(&Progress) + Send + Sync + 'static>(&mut self, callback: F) {
+        self.progress = Some(Arc::new(callback));
+    }
+
+    /// decide about every packet the fsms emit, e.g. to lose a FINACK on
+    /// purpose in a test, see `FaultInjector`
+    pub fn set_fault_injector<F: FaultInjector + 'static>(&mut self, injector: F) {
+        self.faults = Some(Mutex::new(Box::new(injector)));
+    }
+
+    /// transitions of the last transfer, while it is running also of the
+    /// current one, empty unless `set_transition_recording` is on
+    pub fn last_transfer_trace(&self) -> Vec<Transition> {
+        self.history
+            .as_ref()
+            .map(TransitionLog::transitions)
+            .unwrap_or_default()
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.inner.local_addr()?)
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        self.peer
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected).into())
+    }
+
+    // utils
+
+    fn wait_for_incoming_or_timeout(
+        &self,
+        peer: Option<(SocketAddr, u8)>,
+        syn_from_any: bool,
+        timeout: Duration,
+        timer_start: Instant,
+        transfer_deadline: Option<Instant>,
+    ) -> Result<RecvResult> {
+        let (timeout, exceeds_deadline) =
+            clamp_to_deadline(timer_start, timeout, transfer_deadline);
+
+        // waiting for correct ack or timeout
+        loop {
+            if self.update_udp_sock_timeout(timer_start, timeout)? {
+                return expired(exceeds_deadline);
+            }
+            match self.rdt_recv() {
+                Ok((src, resp_pck)) => {
+                    if of_other_transfer(peer, syn_from_any, src, resp_pck.as_ref()) {
+                        continue;
+                    }
+                    return Ok(RecvResult::RecvPkt(resp_pck, src));
+                }
+                // non-blocking socket without datagram before timer expired
+                Err(e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        && self.nonblocking
+                        && self.elapsed_since(timer_start) < timeout =>
+                {
+                    return Err(e.into());
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return expired(exceeds_deadline);
+                }
+                // a signal, e.g. the ctrl-c of a server, interrupts a receive
+                // with a timeout despite SA_RESTART, the retry only waits
+                // for what is left of the timer
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// update socket udp timer accordinly to remaing timeout
+    ///
+    /// # Return
+    /// true if timeout as reached, else false
+    fn update_udp_sock_timeout(&self, timer_start: Instant, timeout: Duration) -> io::Result<bool> {
+        // calc remaing timer time
+        let elapsed = self.elapsed_since(timer_start);
+        if elapsed >= timeout {
+            // reached timeout
+            return Ok(true);
+        }
+
+        let remaining = timeout - elapsed;
+        self.inner.set_read_timeout(Some(remaining))?;
+        Ok(false)
+    }
+
+    /// elapsed time on the clock of the transport
+    fn elapsed_since(&self, start: Instant) -> Duration {
+        self.inner.now().saturating_duration_since(start)
+    }
+
+    /// trace `pck` of `fsm` and put it on the wire, unless the fault
+    /// injector drops it
+    fn emit(&self, fsm: &str, pck: &Packet, peer: SocketAddr) -> io::Result<()> {
+        tracing::trace!(fsm, flag = ?pck.flag(), n = pck.n(), len = pck.payload().len(), %peer, "packet sent");
+        self.trace_emit(fsm, pck, peer);
+        let fault = self
+            .faults
+            .as_ref()
+            .map_or(Fault::Pass, |f| f.lock().unwrap().on_emit(fsm, pck));
+        for _ in 0..fault::copies(fault) {
+            self.udt_send_faulty(pck, fault, peer)?;
+        }
+        Ok(())
+    }
+
+    fn report_progress(&self, progress: Progress) {
+        if let Some(callback) = &self.progress {
+            callback(&progress);
+        }
+    }
+
+    fn udt_send(&self, sndpkt: &Packet, recv_addr: SocketAddr) -> io::Result<()> {
+        self.udt_send_faulty(sndpkt, Fault::Pass, recv_addr)
+    }
+
+    /// encode `pck` into a send buffer of the pool, corrupt it if `fault`
+    /// says so and put it on the wire
+    fn udt_send_faulty(&self, pck: &Packet, fault: Fault, recv_addr: SocketAddr) -> io::Result<()> {
+        let mut buf = self.send_bufs.take();
+        let len = pck.encode_into(&mut buf);
+        fault::corrupt(fault, &mut buf[..len]);
+        let r = self.udt_send_bytes(&mut buf[..len], recv_addr);
+        self.send_bufs.give(buf);
+        r
+    }
+
+    fn udt_send_bytes(&self, pkt: &mut [u8], recv_addr: SocketAddr) -> io::Result<()> {
+        for _ in 0..self.impairment.apply_in_place(pkt) {
+            if let Some(capture) = &self.capture {
+                capture.record(Direction::Outbound, recv_addr, pkt)?;
+            }
+            if let Some(delay_line) = &self.delay_line {
+                let delay = self.impairment.next_delay();
+                delay_line.send_at(self.inner.now() + delay, pkt.to_vec(), recv_addr);
+                continue;
+            }
+            // some platforms refuse send_to on a connected socket
+            let r = match self.peer {
+                Some(peer) if peer == recv_addr => self.inner.send(pkt),
+                _ => self.inner.send_to(pkt, recv_addr),
+            };
+            match r {
+                // full send buffer of a non-blocking socket is handled like packet loss
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                r => _ = r?,
+            }
+        }
+        Ok(())
+    }
+
+    /// fails with `Shutdown` once a `ShutdownHandle` stopped the socket
+    fn check_shutdown(&self) -> Result<()> {
+        match self.stop.load(Ordering::SeqCst) {
+            true => Err(SecSnailError::Shutdown),
+            false => Ok(()),
+        }
+    }
+
+    fn trace_event(&self, fsm: &str, name: &str, pck: Option<&Packet>) {
+        if let Some(trace) = &self.trace {
+            trace.event(self.inner.now(), fsm, name, pck);
+        }
+    }
+
+    fn trace_emit(&self, fsm: &str, pck: &Packet, peer: SocketAddr) {
+        if let Some(trace) = &self.trace {
+            trace.emit(self.inner.now(), fsm, pck, peer);
+        }
+        if let Some(history) = &self.history {
+            history.emit(pck.flag());
+        }
+    }
+
+    fn record_transition(
+        &self,
+        fsm: &'static str,
+        state: &'static str,
+        event: &'static str,
+        next: &'static str,
+    ) {
+        if let Some(history) = &self.history {
+            history.record(self.inner.now(), fsm, state, event, next);
+        }
+    }
+
+    fn answer_probe(&self, src: SocketAddr) -> io::Result<()> {
+        if let Some(name) = &self.discovery_name {
+            let reply = discovery::reply(name, self.inner.local_addr()?.port());
+            self.inner.send_to(&reply, src)?;
+        }
+        Ok(())
+    }
+
+    fn rdt_recv(&self) -> io::Result<(SocketAddr, Option<Packet>)> {
+        let mut buf = self.recv_bufs.take();
+        let r = self.recv_into(&mut buf);
+        self.recv_bufs.give(buf);
+        r
+    }
+
+    fn recv_into(&self, buf: &mut [u8]) -> io::Result<(SocketAddr, Option<Packet>)> {
+        let (n, src) = loop {
+            let (n, src) = self.inner.recv_from(buf)?;
+            if shutdown::is_wakeup(&buf[..n]) {
+                return Ok((src, None));
+            }
+            if self.peer_filter.allows(src.ip()) {
+                break (n, src);
+            }
+            tracing::trace!(%src, "datagram of a filtered peer dropped");
+        };
+        if discovery::is_probe(&buf[..n]) {
+            self.answer_probe(src)?;
+            return Ok((src, None));
+        }
+        if let Some(reply) = discovery::echo_reply(&buf[..n], src) {
+            self.inner.send_to(&reply, src)?;
+            return Ok((src, None));
+        }
+        if let Some(capture) = &self.capture {
+            capture.record(Direction::Inbound, src, &buf[..n])?;
+        }
+        // parsed in place, only a decodable packet is copied out of `buf`
+        match PacketView::parse_with(&buf[..n], self.decode_mode) {
+            Ok(view) => {
+                tracing::trace!(flag = ?view.flag(), n = view.n(), len = view.payload().len(), %src, "packet received");
+                Ok((src, Some(view.to_packet())))
+            }
+            Err(e) => {
+                tracing::trace!(%src, error = %e, "undecodable datagram dropped");
+                Ok((src, None))
+            }
+        }
+    }
+}
+
+/// how an fsm treats an event it has no transition for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// log and count the event, the fsm stays in its state
+    #[default]
+    Lenient,
+    /// abort the transfer with `ProtocolViolation`
+    Strict,
+}
+
+impl Strictness {
+    /// outcome of an undefined transition, counted by the caller
+    fn check(self, fsm: &str, state: &str, event: &str, pck: Option<&Packet>) -> Result<()> {
+        let e = SecSnailError::undefined_transition(state, event, pck);
+        tracing::warn!(fsm, "{e}");
+        match self {
+            Strictness::Lenient => Ok(()),
+            Strictness::Strict => Err(e),
+        }
+    }
+}
+
+/// failures of a single sender, which do not stop serving others
+fn is_sender_failure(e: &SecSnailError) -> bool {
+    matches!(
+        e,
+        SecSnailError::ConnectionTimeout
+            | SecSnailError::ProtocolViolation(_)
+            | SecSnailError::DeadlineExceeded
+            | SecSnailError::InvalidFilename(_)
+            | SecSnailError::FileTooLarge { .. }
+            | SecSnailError::InsufficientSpace { .. }
+            | SecSnailError::QuotaExceeded { .. }
+    )
+}
+
+/// failures of a send which a new attempt may overcome
+fn is_retryable(e: &SecSnailError) -> bool {
+    matches!(
+        e,
+        SecSnailError::MaxRetransmitsExceeded | SecSnailError::ConnectionTimeout
+    )
+}
+
+/// wait before retry `attempt`, `backoff` doubled for every further one
+fn retry_backoff(backoff: Duration, attempt: usize) -> Duration {
+    backoff.saturating_mul(1 << (attempt - 1).min(16))
+}
+
+/// shorten the timer to the transfer deadline if it would expire later
+///
+/// # Return
+/// the timeout to wait for and true if it ends at the transfer deadline
+fn clamp_to_deadline(
+    timer_start: Instant,
+    timeout: Duration,
+    transfer_deadline: Option<Instant>,
+) -> (Duration, bool) {
+    match transfer_deadline {
+        Some(deadline) if deadline < timer_start + timeout => {
+            (deadline.saturating_duration_since(timer_start), true)
+        }
+        _ => (timeout, false),
+    }
+}
+
+/// result of an expired wait, see `clamp_to_deadline`
+fn expired(exceeds_deadline: bool) -> Result<RecvResult> {
+    match exceeds_deadline {
+        true => Err(SecSnailError::DeadlineExceeded),
+        false => Ok(RecvResult::Timeout),
+    }
+}
+
+/// a packet from `src` belongs to another transfer than the one of `peer`
+/// and its transfer id, unless it is a syn which may restart the session
+fn of_other_transfer(
+    peer: Option<(SocketAddr, u8)>,
+    syn_from_any: bool,
+    src: SocketAddr,
+    rcvpkt: Option<&Packet>,
+) -> bool {
+    let Some((addr, transfer_id)) = peer else {
+        return false;
+    };
+    let restarts = syn_from_any && rcvpkt.is_some_and(|p| p.notcorrupt() && p.is_SYN());
+    let other = addr != src || rcvpkt.is_some_and(|p| p.transfer_id() != transfer_id);
+    other && !restarts
+}
+
+/// `rcvpkt` as received on a link which is `trusted`, only a syn is still
+/// verified there
+fn on_trusted_link(rcvpkt: Option<Packet>, trusted: bool) -> Option<Packet> {
+    rcvpkt.map(|pck| match trusted && !pck.is_SYN() {
+        true => pck.trusted(),
+        false => pck,
+    })
+}
+
+fn log_send_outcome(ret: &Result<SendReport>) {
+    match ret {
+        Ok(report) => tracing::info!(
+            bytes = report.bytes,
+            duration = ?report.duration,
+            mean_rtt = ?report.mean_rtt,
+            "file sent"
+        ),
+        Err(e) => tracing::warn!(error = %e, "send failed"),
+    }
+}
+
+/// create target dir of a reception if not existing yet
+fn prepare_target_dir(target_dir: &Path) -> Result<()> {
+    // check if path is a file
+    if let Ok(metadata) = fs::metadata(target_dir)
+        && metadata.is_file()
+    {
+        return Err(SecSnailError::InvalidConfig(format!(
+            "given dir path '{}' exists and is a file, only a target dir is expected.",
+            target_dir.display()
+        )));
+    }
+
+    fs::create_dir_all(target_dir)?;
+    Ok(())
+}
+
+/// strict decode for a socket reachable from the internet, see
+/// `SecSnailSocketBuilder::decode_mode`
+fn default_decode_mode(local_addr: io::Result<SocketAddr>) -> DecodeMode {
+    let public = match local_addr.map(|addr| addr.ip()) {
+        Ok(IpAddr::V4(ip)) => !(ip.is_loopback() || ip.is_private() || ip.is_link_local()),
+        Ok(IpAddr::V6(ip)) => {
+            !(ip.is_loopback() || ip.is_unicast_link_local() || ip.is_unique_local())
+        }
+        Err(_) => false,
+    };
+    match public {
+        true => DecodeMode::Strict,
+        false => DecodeMode::Lenient,
+    }
+}
+
+/// collect the files below `dir` into `files`, sorted, subdirectories which
+/// can not be read go to `failed`
+fn walk_dir(dir: &Path, files: &mut Vec<PathBuf>, failed: &mut Vec<SendOutcome>) -> Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        match path.is_dir() {
+            true => {
+                if let Err(e) = walk_dir(&path, files, failed) {
+                    failed.push((path, Err(e)));
+                }
+            }
+            false => files.push(path),
+        }
+    }
+    Ok(())
+}
+
+/// move every file whose name by `key` differs from the one of an earlier
+/// file only by case to `failed`, a receiver on a case-insensitive file
+/// system, e.g. on windows, would store both under the same name
+fn reject_case_collisions(
+    files: &mut Vec<PathBuf>,
+    failed: &mut Vec<SendOutcome>,
+    key: impl Fn(&Path) -> String,
+) {
+    let mut seen = HashMap::new();
+    files.retain(|path| {
+        let name = key(path);
+        match seen.entry(name.to_lowercase()) {
+            Entry::Vacant(e) => {
+                e.insert(name);
+                true
+            }
+            Entry::Occupied(e) if *e.get() == name => true,
+            Entry::Occupied(e) => {
+                let reason = format!("{name:?} differs from {:?} only by case", e.get());
+                failed.push((path.clone(), Err(SecSnailError::InvalidFilename(reason))));
+                false
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf, thread};
+
+    /// unique scratch dir per test, as tests run in parallel
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn nonblocking_recv_of_blocking_send() {
+        let dir = scratch_dir("nonblocking");
+        let src = dir.join("snail.txt");
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&src, &content).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.inner.local_addr().unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        receiver.start_recv_file(dir.join("out")).unwrap();
+
+        let sender = thread::spawn(move || {
+            let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+            sock.send_file_to_blocking(src, recv_addr).unwrap()
+        });
+
+        let received = loop {
+            if let Poll::Ready(n) = receiver.poll_recv_progress().unwrap() {
+                break n;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+
+        let sent = sender.join().unwrap().bytes;
+        assert_eq!(sent, content.len());
+        assert_eq!(received, content.len());
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+    }
+
+    #[test]
+    fn serve_until_break() {
+        let dir = scratch_dir("serve");
+        let src = dir.join("snail.txt");
+        fs::write(&src, b"one snail at a time").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.inner.local_addr().unwrap();
+
+        let sender = thread::spawn(move || {
+            let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+            for _ in 0..2 {
+                sock.send_file_to_blocking(&src, recv_addr).unwrap();
+            }
+        });
+
+        let mut reports = Vec::new();
+        receiver
+            .serve(dir.join("out"), |report| {
+                reports.push(report);
+                match reports.len() {
+                    2 => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                }
+            })
+            .unwrap();
+
+        sender.join().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r.file_name == "snail.txt"));
+    }
+
+    #[test]
+    fn serve_concurrent_senders() {
+        let dir = scratch_dir("serve-concurrent");
+        let content: Vec<u8> = (0..20_000u32).map(|i| (i % 249) as u8).collect();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.inner.local_addr().unwrap();
+
+        let senders: Vec<_> = ["a.bin", "b.bin"]
+            .into_iter()
+            .map(|name| {
+                let src = dir.join(name);
+                fs::write(&src, &content).unwrap();
+                thread::spawn(move || {
+                    let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+                    sock.send_file_to_blocking(src, recv_addr).unwrap()
+                })
+            })
+            .collect();
+
+        let mut received = Vec::new();
+        receiver
+            .serve(dir.join("out"), |report| {
+                received.push(report.file_name);
+                match received.len() {
+                    2 => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                }
+            })
+            .unwrap();
+
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        received.sort();
+        assert_eq!(received, ["a.bin", "b.bin"]);
+        for name in received {
+            assert_eq!(fs::read(dir.join("out").join(name)).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn send_matching_files() {
+        let dir = scratch_dir("matching");
+        for name in ["b.txt", "a.txt", "c.bin"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        fs::create_dir_all(dir.join("d.txt")).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            receiver
+                .incoming(out)
+                .take(2)
+                .map(|r| r.unwrap().file_name)
+                .collect::<Vec<_>>()
+        });
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let pattern = format!("{}/*.txt", dir.display());
+        let results = sender.send_matching_blocking(&pattern, recv_addr).unwrap();
+        let sent: Vec<_> = results
+            .iter()
+            .map(|(path, r)| (path.file_name().unwrap().to_str().unwrap(), r.is_ok()))
+            .collect();
+        assert_eq!(sent, [("a.txt", true), ("b.txt", true)]);
+        assert_eq!(recv.join().unwrap(), ["a.txt", "b.txt"]);
+
+        assert!(sender.send_matching_blocking("[", recv_addr).is_err());
+    }
+
+    #[test]
+    fn send_dir_recursively() {
+        let dir = scratch_dir("send-dir");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("sub/deeper")).unwrap();
+        for name in ["b.txt", "sub/a.txt", "sub/deeper/c.txt"] {
+            fs::write(src.join(name), name).unwrap();
+        }
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            receiver
+                .incoming(out)
+                .take(3)
+                .map(|r| r.unwrap().file_name)
+                .collect::<Vec<_>>()
+        });
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let results = sender.send_dir_blocking(&src, recv_addr).unwrap();
+        assert!(results.iter().all(|(_, r)| r.is_ok()));
+        assert_eq!(recv.join().unwrap(), ["b.txt", "a.txt", "c.txt"]);
+        assert_eq!(
+            fs::read(dir.join("out/c.txt")).unwrap(),
+            b"sub/deeper/c.txt"
+        );
+
+        assert!(
+            sender
+                .send_dir_blocking(dir.join("missing"), recv_addr)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn sync_dir_sends_missing_files() {
+        let dir = scratch_dir("sync-dir");
+        let src = dir.join("src");
+        let out = dir.join("out");
+        fs::create_dir_all(src.join("sub/deeper")).unwrap();
+        fs::create_dir_all(out.join("sub")).unwrap();
+        for name in ["b.txt", "sub/a.txt", "sub/deeper/c.txt"] {
+            fs::write(src.join(name), name).unwrap();
+        }
+        fs::write(out.join("b.txt"), "b.txt").unwrap();
+        fs::write(out.join("sub/a.txt"), "stale").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let target = out.clone();
+        let recv = thread::spawn(move || {
+            receiver
+                .incoming(target)
+                .take(3)
+                .map(|r| r.unwrap())
+                .map(|report| (report.file_name, report.missing))
+                .collect::<Vec<_>>()
+        });
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let results = sender.sync_dir_blocking(&src, recv_addr).unwrap();
+        let sent: Vec<_> = results
+            .into_iter()
+            .map(|(_, r)| r.unwrap().file_name)
+            .collect();
+        assert_eq!(sent, ["sub/a.txt", "sub/deeper/c.txt"]);
+        assert_eq!(
+            recv.join().unwrap(),
+            [
+                (".secsnail-manifest".to_string(), Some(2)),
+                ("sub/a.txt".to_string(), None),
+                ("sub/deeper/c.txt".to_string(), None),
+            ]
+        );
+        for name in ["b.txt", "sub/a.txt", "sub/deeper/c.txt"] {
+            assert_eq!(fs::read(out.join(name)).unwrap(), name.as_bytes());
+        }
+    }
+
+    #[test]
+    fn case_collisions_are_rejected() {
+        let mut files: Vec<PathBuf> = ["a/Snail.txt", "b/shell.txt", "b/snail.TXT", "c/shell.txt"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect();
+        let mut failed = Vec::new();
+        reject_case_collisions(&mut files, &mut failed, |p| {
+            p.file_name().map(utf8_file_name).unwrap()
+        });
+        assert_eq!(
+            files,
+            [
+                Path::new("a/Snail.txt"),
+                "b/shell.txt".as_ref(),
+                "c/shell.txt".as_ref()
+            ]
+        );
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].0, Path::new("b/snail.TXT"));
+        assert!(matches!(
+            failed[0].1,
+            Err(SecSnailError::InvalidFilename(_))
+        ));
+    }
+
+    #[test]
+    fn path_resolver_sorts_by_sender() {
+        let dir = scratch_dir("path-resolver");
+        fs::write(dir.join("snail.txt"), b"sorted snail").unwrap();
+
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .path_resolver(|peer, name, size| {
+                PathBuf::from(format!("port-{}", peer.port()))
+                    .join(format!("{}-{name}", size.unwrap()))
+            })
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+            .unwrap();
+        let expected = dir
+            .join("out")
+            .join(format!("port-{}", sender.local_addr().unwrap().port()))
+            .join("12-snail.txt");
+        assert_eq!(recv.join().unwrap().path, Some(expected.clone()));
+        assert_eq!(fs::read(expected).unwrap(), b"sorted snail");
+    }
+
+    #[test]
+    fn send_under_remote_name() {
+        let dir = scratch_dir("remote-name");
+        let src = dir.join("local.txt");
+        fs::write(&src, b"renamed snail").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        assert!(matches!(
+            sender.send_file_as_blocking(&src, "../escape.txt", recv_addr),
+            Err(SecSnailError::InvalidFilename(_))
+        ));
+        sender
+            .send_file_as_blocking(&src, "remote.txt", recv_addr)
+            .unwrap();
+        assert_eq!(recv.join().unwrap().file_name, "remote.txt");
+        assert_eq!(
+            fs::read(dir.join("out").join("remote.txt")).unwrap(),
+            b"renamed snail"
+        );
+    }
+
+    #[test]
+    fn send_from_reader() {
+        let dir = scratch_dir("reader");
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 241) as u8).collect();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let sent = sender
+            .send_reader_blocking(io::Cursor::new(content.clone()), "stream.bin", recv_addr)
+            .unwrap()
+            .bytes;
+        assert_eq!(sent, content.len());
+        assert_eq!(recv.join().unwrap().bytes, content.len());
+        assert_eq!(
+            fs::read(dir.join("out").join("stream.bin")).unwrap(),
+            content
+        );
+    }
+
+    #[test]
+    fn progress_of_send() {
+        let dir = scratch_dir("progress");
+        let src = dir.join("progress.bin");
+        fs::write(&src, vec![9u8; 2500]).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        sender.set_progress_callback(move |p: &Progress| log.lock().unwrap().push(*p));
+        sender.send_file_to_blocking(&src, recv_addr).unwrap();
+        recv.join().unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert!(seen.windows(2).all(|w| w[0].bytes <= w[1].bytes));
+        assert!(seen.iter().all(|p| p.total == Some(2500)));
+        assert_eq!(seen.last().unwrap().bytes, 2500);
+    }
+
+    #[test]
+    fn verify_without_sending() {
+        let dir = scratch_dir("verify");
+        let src = dir.join("snail.txt");
+        let out = dir.join("out");
+        fs::create_dir_all(&out).unwrap();
+        fs::write(&src, b"same snail").unwrap();
+        fs::write(out.join("snail.txt"), b"same snail").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let recv = thread::spawn(move || {
+            let reports: Vec<_> = receiver.incoming(out).take(3).collect();
+            reports
+        });
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let verdict = sender.verify_file_blocking(&src, recv_addr).unwrap();
+        assert_eq!(verdict, Verdict::Identical);
+        fs::write(&src, b"new snail!").unwrap();
+        let verdict = sender.verify_file_blocking(&src, recv_addr).unwrap();
+        assert_eq!(verdict, Verdict::Differs);
+        let verdict = sender
+            .verify_file_as_blocking(&src, "other.txt", recv_addr)
+            .unwrap();
+        assert_eq!(verdict, Verdict::Missing);
+
+        let verified: Vec<_> = recv
+            .join()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.unwrap().verified)
+            .collect();
+        assert_eq!(
+            verified,
+            [
+                Some(Verdict::Identical),
+                Some(Verdict::Differs),
+                Some(Verdict::Missing)
+            ]
+        );
+        // the differing file was not sent
+        assert_eq!(
+            fs::read(dir.join("out").join("snail.txt")).unwrap(),
+            b"same snail"
+        );
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn send_to_all_receivers() {
+        let dir = scratch_dir("fan-out");
+        let src = dir.join("snail.txt");
+        fs::write(&src, vec![3; 2500]).unwrap();
+
+        let receivers: Vec<_> = (0..2)
+            .map(|i| {
+                let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+                let addr = receiver.local_addr().unwrap();
+                let out = dir.join(format!("out-{i}"));
+                (
+                    addr,
+                    thread::spawn(move || receiver.recv_file_blocking(out)),
+                )
+            })
+            .collect();
+        let mut recv_addrs: Vec<_> = receivers.iter().map(|(addr, _)| *addr).collect();
+
+        let mut sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .snd_timeout(Duration::from_millis(20))
+            .max_retransmits(2)
+            .build()
+            .unwrap();
+        // nobody listens on the port of a closed socket
+        let closed = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        recv_addrs.push(closed.local_addr().unwrap());
+        drop(closed);
+        let results = sender.send_file_to_all_blocking(&src, &recv_addrs);
+
+        assert_eq!(results.len(), 3);
+        for (addr, recv) in receivers {
+            assert_eq!(results[&addr].as_ref().unwrap().bytes, 2500);
+            assert_eq!(recv.join().unwrap().unwrap().bytes, 2500);
+        }
+        assert!(results[&recv_addrs[2]].is_err());
+    }
+
+    #[test]
+    fn delayed_send() {
+        let dir = scratch_dir("delay");
+        let src = dir.join("snail.txt");
+        let content: Vec<u8> = (0..3000u32).map(|i| (i % 233) as u8).collect();
+        fs::write(&src, &content).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .snd_timeout(Duration::from_millis(200))
+            .delay(Duration::from_millis(10))
+            .jitter(Duration::from_millis(5))
+            .build()
+            .unwrap();
+        let report = sender.send_file_to_blocking(&src, recv_addr).unwrap();
+
+        // syn, data and fin are held back one after another
+        assert!(report.duration >= Duration::from_millis(30));
+        assert_eq!(report.bytes, content.len());
+        assert!(report.mean_rtt.unwrap() >= Duration::from_millis(10));
+        assert_eq!(recv.join().unwrap().bytes, content.len());
+    }
+
+    #[test]
+    fn interrupted_transfer_leaves_no_file() {
+        let dir = scratch_dir("interrupted");
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .rcv_timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        // sender vanishes after the first data packet
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let meta = crate::meta::SynMeta {
+            file_name: "snail.txt".to_string(),
+            file_size: Some(1000),
+            resume: false,
+            digest: None,
+            trusted: false,
+            manifest: false,
+            relative_path: false,
+            timestamps: false,
+            delta: false,
+        };
+        let syn = Packet::new(false, crate::pck::Flag::SYN, meta.encode()).unwrap();
+        let data = Packet::new(true, crate::pck::Flag::Data, vec![1; 500]).unwrap();
+        sender.send_to(syn.encode(), recv_addr).unwrap();
+        sender.send_to(data.encode(), recv_addr).unwrap();
+
+        let r = receiver.recv_file_blocking(dir.join("out"));
+        assert!(matches!(r, Err(SecSnailError::ConnectionTimeout)));
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn lost_finack_of_blocking_recv_is_answered() {
+        let dir = scratch_dir("lost-finack");
+        let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.txt"), &content).unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .rcv_timeout(Duration::from_millis(300))
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let mut dropped = false;
+        receiver.set_fault_injector(move |_: &str, pck: &Packet| match pck.flag() {
+            crate::pck::Flag::FINACK if !dropped => {
+                dropped = true;
+                Fault::Drop
+            }
+            _ => Fault::Pass,
+        });
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let sent = sender
+            .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+            .unwrap();
+        assert_eq!(sent.stats.retransmissions, 1);
+        assert_eq!(recv.join().unwrap().bytes, content.len());
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+    }
+
+    #[test]
+    fn strict_decode_drops_padded_datagrams() {
+        let local = |addr: &str| Ok(addr.parse().unwrap());
+        assert_eq!(default_decode_mode(local("0.0.0.0:0")), DecodeMode::Strict);
+        assert_eq!(default_decode_mode(local("[::]:0")), DecodeMode::Strict);
+        assert_eq!(
+            default_decode_mode(local("127.0.0.1:0")),
+            DecodeMode::Lenient
+        );
+        assert_eq!(
+            default_decode_mode(local("192.168.1.2:0")),
+            DecodeMode::Lenient
+        );
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .inner
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let pck = Packet::new(false, crate::pck::Flag::Data, b"snail".to_vec()).unwrap();
+        let padded = [pck.encode(), &[0; 3]].concat();
+        let oversized = [pck.encode(), &[0; MAX_PAYLOAD_SIZE]].concat();
+
+        sender.send_to(&padded, recv_addr).unwrap();
+        assert_eq!(receiver.rdt_recv().unwrap().1, Some(pck.clone()));
+        receiver.decode_mode = DecodeMode::Strict;
+        for datagram in [&padded, &oversized] {
+            sender.send_to(datagram, recv_addr).unwrap();
+            assert_eq!(receiver.rdt_recv().unwrap().1, None);
+        }
+        sender.send_to(pck.encode(), recv_addr).unwrap();
+        assert_eq!(receiver.rdt_recv().unwrap().1, Some(pck));
+    }
+
+    #[test]
+    fn stray_ack_per_strictness() {
+        let dir = scratch_dir("strictness");
+        let meta = crate::meta::SynMeta {
+            file_name: "snail.txt".to_string(),
+            file_size: Some(500),
+            resume: false,
+            digest: None,
+            trusted: false,
+            manifest: false,
+            relative_path: false,
+            timestamps: false,
+            delta: false,
+        };
+        let syn = Packet::new(false, crate::pck::Flag::SYN, meta.encode()).unwrap();
+        let ack = Packet::new(true, crate::pck::Flag::ACK, vec![]).unwrap();
+        let data = Packet::new(true, crate::pck::Flag::Data, vec![1; 500]).unwrap();
+        let fin = Packet::new(false, crate::pck::Flag::FIN, vec![]).unwrap();
+
+        for strictness in [Strictness::Lenient, Strictness::Strict] {
+            let mut receiver = SecSnailSocket::builder()
+                .bind("127.0.0.1:0")
+                .strictness(strictness)
+                .build()
+                .unwrap();
+            let recv_addr = receiver.local_addr().unwrap();
+            let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+            for pck in [&syn, &ack, &data, &fin] {
+                sender.send_to(pck.encode(), recv_addr).unwrap();
+            }
+
+            let r = receiver.recv_file_blocking(dir.join("out"));
+            match strictness {
+                Strictness::Lenient => {
+                    let report = r.unwrap();
+                    assert_eq!(report.bytes, 500);
+                    assert_eq!(report.stats.protocol_violations, 1);
+                }
+                Strictness::Strict => {
+                    assert!(matches!(r, Err(SecSnailError::ProtocolViolation(_))));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn new_syn_restarts_session() {
+        let dir = scratch_dir("restart");
+        let src = dir.join("snail.txt");
+        fs::write(&src, vec![7; 3000]).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        // crashed sender, its restart binds another port
+        let crashed = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let meta = crate::meta::SynMeta {
+            file_name: "snail.txt".to_string(),
+            file_size: Some(3000),
+            resume: false,
+            digest: None,
+            trusted: false,
+            manifest: false,
+            relative_path: false,
+            timestamps: false,
+            delta: false,
+        };
+        let syn = Packet::new(false, crate::pck::Flag::SYN, meta.encode()).unwrap();
+        let data = Packet::new(true, crate::pck::Flag::Data, vec![1; 500]).unwrap();
+        crashed.send_to(syn.encode(), recv_addr).unwrap();
+        crashed.send_to(data.encode(), recv_addr).unwrap();
+
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out));
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let start = Instant::now();
+        sender.send_file_to_blocking(&src, recv_addr).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(DEFAULT_RCV_TIMEOUT_MS));
+
+        let report = recv.join().unwrap().unwrap();
+        assert_eq!(report.peer, sender.local_addr().unwrap());
+        assert_eq!(report.bytes, 3000);
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), vec![7; 3000]);
+    }
+
+    #[test]
+    fn os_buffer_sizes() {
+        let sock = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .os_recv_buffer(1 << 16)
+            .os_send_buffer(1 << 16)
+            .build()
+            .unwrap();
+        // linux doubles the size for its bookkeeping
+        assert!(sock.os_recv_buffer().unwrap() >= 1 << 16);
+        assert!(sock.os_send_buffer().unwrap() >= 1 << 16);
+    }
+
+    #[test]
+    fn read_ahead_sends_file_and_stream() {
+        let dir = scratch_dir("read-ahead");
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.txt"), &content).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            (0..2)
+                .map(|_| receiver.recv_file_blocking(&out).unwrap().file_name)
+                .collect::<Vec<_>>()
+        });
+        let mut sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .read_ahead(4)
+            .build()
+            .unwrap();
+        sender
+            .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+            .unwrap();
+        sender
+            .send_reader_blocking(io::Cursor::new(content.clone()), "stream.txt", recv_addr)
+            .unwrap();
+
+        assert_eq!(recv.join().unwrap(), ["snail.txt", "stream.txt"]);
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+        assert_eq!(fs::read(dir.join("out/stream.txt")).unwrap(), content);
+    }
+
+    #[test]
+    fn trusted_link_needs_both_sides() {
+        let dir = scratch_dir("trusted");
+        let content: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.txt"), &content).unwrap();
+
+        let net = crate::sim::SimNetwork::new(9).loss_p(0.1);
+        let mut sender = SecSnailSocket::builder()
+            .trusted_link(true)
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+        for trusted in [false, true] {
+            receiver.trusted_link = trusted;
+            let report = net
+                .run_transfer(
+                    &mut sender,
+                    &mut receiver,
+                    dir.join("snail.txt"),
+                    dir.join("out"),
+                )
+                .unwrap();
+            assert_eq!(report.stats.trusted_link, trusted);
+            assert_eq!(
+                receiver.last_transfer_stats().unwrap().trusted_link,
+                trusted
+            );
+            assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn timestamps_measure_queueing_delay() {
+        let dir = scratch_dir("timestamps");
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.txt"), &content).unwrap();
+
+        let net = crate::sim::SimNetwork::new(4)
+            .loss_p(0.1)
+            .delay(Duration::from_millis(20))
+            .jitter(Duration::from_millis(10));
+        let mut sender = SecSnailSocket::builder()
+            .timestamps(true)
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+        for timestamps in [false, true] {
+            receiver.timestamps = timestamps;
+            let report = net
+                .run_transfer(
+                    &mut sender,
+                    &mut receiver,
+                    dir.join("snail.txt"),
+                    dir.join("out"),
+                )
+                .unwrap();
+            assert_eq!(report.bytes, content.len());
+            assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+            let Some(delay) = report.queueing_delay else {
+                assert!(!timestamps);
+                continue;
+            };
+            assert!(timestamps);
+            assert!((1..=10).contains(&delay.samples));
+            assert!(delay.max <= Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn receiver_checkpoints_into_the_journal() {
+        let dir = scratch_dir("checkpoints");
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.bin"), &content).unwrap();
+
+        let net = crate::sim::SimNetwork::new(5);
+        let mut sender = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .checkpoints(CheckpointPolicy {
+                bytes: Some(10_000),
+                interval: None,
+            })
+            .sync_on_close(true)
+            .journal(dir.join("journal"))
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+        net.run_transfer(
+            &mut sender,
+            &mut receiver,
+            dir.join("snail.bin"),
+            dir.join("out"),
+        )
+        .unwrap();
+        assert_eq!(fs::read(dir.join("out/snail.bin")).unwrap(), content);
+        let stats = receiver.last_transfer_stats().unwrap();
+        assert!((9..=10).contains(&stats.checkpoints));
+        assert!(receiver.pending_transfers().is_empty());
+    }
+
+    #[test]
+    fn spooled_file_appears_only_once_complete() {
+        let dir = scratch_dir("spool");
+        let content: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.bin"), &content).unwrap();
+
+        let net = crate::sim::SimNetwork::new(7);
+        let mut sender = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .spool_dir(dir.join("spool"))
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+        // data arriving in the target dir would have to go to the spool
+        sender.set_progress_callback({
+            let out = dir.join("out");
+            move |_| assert!(fs::read_dir(&out).is_ok_and(|mut d| d.next().is_none()))
+        });
+        net.run_transfer(
+            &mut sender,
+            &mut receiver,
+            dir.join("snail.bin"),
+            dir.join("out"),
+        )
+        .unwrap();
+        assert_eq!(fs::read(dir.join("out/snail.bin")).unwrap(), content);
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 1);
+        assert_eq!(fs::read_dir(dir.join("spool")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn min_packet_gap_paces_the_sender() {
+        let dir = scratch_dir("gap");
+        let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.bin"), &content).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+        let mut sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .min_packet_gap(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        let report = sender
+            .send_file_to_blocking(dir.join("snail.bin"), recv_addr)
+            .unwrap();
+        recv.join().unwrap();
+        let packets = content.len().div_ceil(Packet::max_pck_payload_size()) as u32;
+        assert!(report.stats.paced >= Duration::from_millis(5) * packets);
+        assert!(report.duration >= Duration::from_millis(10) * packets);
+        assert_eq!(fs::read(dir.join("out/snail.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn stats_add_up_until_reset() {
+        let dir = scratch_dir("totals");
+        fs::write(dir.join("snail.txt"), b"counted snail").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            for _ in 0..3 {
+                receiver.recv_file_blocking(&out).unwrap();
+            }
+            receiver.stats_snapshot()
+        });
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let mut packets = 0;
+        for _ in 0..2 {
+            let report = sender
+                .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+                .unwrap();
+            packets += report.stats.packets_sent;
+        }
+        let totals = sender.stats_snapshot();
+        assert_eq!((totals.sends, totals.receives), (2, 0));
+        assert_eq!(totals.sent.packets_sent, packets);
+        assert_eq!(totals.sent.payload_bytes, 26);
+
+        sender.reset_stats();
+        assert_eq!(sender.stats_snapshot(), SocketStats::default());
+        sender
+            .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+            .unwrap();
+        assert_eq!(sender.stats_snapshot().sent.payload_bytes, 13);
+        let received = recv.join().unwrap();
+        assert_eq!(received.receives, 3);
+        assert_eq!(received.received.payload_bytes, 39);
+    }
+
+    #[test]
+    fn mmap_reads_send_the_file() {
+        let dir = scratch_dir("mmap");
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("snail.txt"), &content).unwrap();
+        fs::write(dir.join("empty.txt"), []).unwrap();
+
+        let net = crate::sim::SimNetwork::new(3).loss_p(0.1);
+        let mut sender = SecSnailSocket::builder()
+            .mmap_reads(true)
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+        for name in ["snail.txt", "empty.txt"] {
+            net.run_transfer(&mut sender, &mut receiver, dir.join(name), dir.join("out"))
+                .unwrap();
+            assert_eq!(
+                fs::read(dir.join("out").join(name)).unwrap(),
+                fs::read(dir.join(name)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn existing_file_is_kept_and_received_one_renamed() {
+        let dir = scratch_dir("rename");
+        fs::create_dir_all(dir.join("out")).unwrap();
+        fs::write(dir.join("snail.txt"), b"new shell").unwrap();
+        fs::write(dir.join("out/snail.txt"), b"old shell").unwrap();
+
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .overwrite_policy(OverwritePolicy::RenameWithSuffix)
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            (0..2)
+                .map(|_| receiver.recv_file_blocking(&out).unwrap().path.unwrap())
+                .collect::<Vec<_>>()
+        });
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let stored: Vec<_> = (0..2)
+            .map(|_| {
+                sender
+                    .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+                    .unwrap()
+                    .stored_as
+            })
+            .collect();
+        assert_eq!(
+            stored,
+            [Some("snail (1).txt".into()), Some("snail (2).txt".into())]
+        );
+        assert_eq!(
+            recv.join().unwrap(),
+            [dir.join("out/snail (1).txt"), dir.join("out/snail (2).txt")]
+        );
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), b"old shell");
+        assert_eq!(
+            fs::read(dir.join("out/snail (2).txt")).unwrap(),
+            b"new shell"
+        );
+    }
+
+    #[test]
+    fn resume_interrupted_transfer() {
+        let dir = scratch_dir("resume");
+        let src = dir.join("snail.txt");
+        let content: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        fs::write(&src, &content).unwrap();
+
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .rcv_timeout(Duration::from_millis(50))
+            .overwrite_policy(OverwritePolicy::Resume)
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        // sender vanishes after the first half
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let meta = crate::meta::SynMeta {
+            file_name: "snail.txt".to_string(),
+            file_size: Some(1000),
+            resume: true,
+            digest: None,
+            trusted: false,
+            manifest: false,
+            relative_path: false,
+            timestamps: false,
+            delta: false,
+        };
+        let syn = Packet::new(false, crate::pck::Flag::SYN, meta.encode()).unwrap();
+        let data = Packet::new(true, crate::pck::Flag::Data, content[..500].to_vec()).unwrap();
+        sender.send_to(syn.encode(), recv_addr).unwrap();
+        sender.send_to(data.encode(), recv_addr).unwrap();
+        let r = receiver.recv_file_blocking(dir.join("out"));
+        assert!(matches!(r, Err(SecSnailError::ConnectionTimeout)));
+
+        // the receiver restarts with the default timeout on the same port
+        drop(receiver);
+        let mut receiver = SecSnailSocket::builder()
+            .bind(recv_addr)
+            .overwrite_policy(OverwritePolicy::Resume)
+            .build()
+            .unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out));
+        let mut sender = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .offer_resume(true)
+            .build()
+            .unwrap();
+        sender.send_file_to_blocking(&src, recv_addr).unwrap();
+
+        let report = recv.join().unwrap().unwrap();
+        assert_eq!(report.resumed_from, 500);
+        assert_eq!(report.bytes, 1000);
+        assert_eq!(report.stats.payload_bytes, 500);
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn refuse_announced_large_file() {
+        let dir = scratch_dir("too-large");
+        let src = dir.join("snail.txt");
+        fs::write(&src, vec![0; 5000]).unwrap();
+
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .max_recv_file_size(1000)
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out));
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let r = sender.send_file_to_blocking(&src, recv_addr);
+        assert!(matches!(r, Err(SecSnailError::Rejected(_))));
+        assert!(matches!(
+            recv.join().unwrap(),
+            Err(SecSnailError::FileTooLarge {
+                size: 5000,
+                max: 1000
+            })
+        ));
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn keep_listening_after_failed_session() {
+        let dir = scratch_dir("poll-failed");
+        let large = dir.join("large.txt");
+        fs::write(&large, vec![0; 5000]).unwrap();
+        let small = dir.join("small.txt");
+        fs::write(&small, b"still listening").unwrap();
+
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .max_recv_file_size(1000)
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        receiver.start_recv_file(dir.join("out")).unwrap();
+
+        let sender = thread::spawn(move || {
+            let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+            let first = sock.send_file_to_blocking(large, recv_addr);
+            (first, sock.send_file_to_blocking(small, recv_addr))
+        });
+        let mut outcomes = Vec::new();
+        while outcomes.len() < 2 {
+            match receiver.poll_recv_progress() {
+                Ok(Poll::Pending) => thread::sleep(Duration::from_millis(1)),
+                Ok(Poll::Ready(bytes)) => outcomes.push(Ok(bytes)),
+                Err(e) => outcomes.push(Err(e)),
+            }
+        }
+
+        let (first, second) = sender.join().unwrap();
+        assert!(matches!(first, Err(SecSnailError::Rejected(_))));
+        assert_eq!(second.unwrap().bytes, 15);
+        assert!(matches!(
+            outcomes[0],
+            Err(SecSnailError::FileTooLarge { size: 5000, .. })
+        ));
+        assert!(matches!(outcomes[1], Ok(15)));
+        assert_eq!(
+            fs::read(dir.join("out/small.txt")).unwrap(),
+            b"still listening"
+        );
+        assert!(!dir.join("out/large.txt").exists());
+    }
+
+    /// a backslash is no path separator on unix, but the receiver refuses it
+    #[cfg(unix)]
+    #[test]
+    fn refuse_invalid_file_name() {
+        let dir = scratch_dir("invalid-name");
+        let src = dir.join("snail\\..\\evil.txt");
+        fs::write(&src, b"slow").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out));
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let r = sender.send_file_to_blocking(&src, recv_addr);
+        assert!(matches!(r, Err(SecSnailError::Rejected(_))));
+        assert!(matches!(
+            recv.join().unwrap(),
+            Err(SecSnailError::InvalidFilename(_))
+        ));
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn cut_off_understated_file() {
+        let dir = scratch_dir("understated");
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .max_recv_file_size(100)
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        // sender claims a tiny file but keeps sending
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let meta = crate::meta::SynMeta {
+            file_name: "snail.txt".to_string(),
+            file_size: Some(10),
+            resume: false,
+            digest: None,
+            trusted: false,
+            manifest: false,
+            relative_path: false,
+            timestamps: false,
+            delta: false,
+        };
+        let syn = Packet::new(false, crate::pck::Flag::SYN, meta.encode()).unwrap();
+        let data = Packet::new(true, crate::pck::Flag::Data, vec![1; 500]).unwrap();
+        sender.send_to(syn.encode(), recv_addr).unwrap();
+        sender.send_to(data.encode(), recv_addr).unwrap();
+
+        let r = receiver.recv_file_blocking(dir.join("out"));
+        assert!(matches!(
+            r,
+            Err(SecSnailError::FileTooLarge { size: 500, .. })
+        ));
+
+        let mut buf = vec![0; MAX_PAYLOAD_SIZE];
+        let ack = sender.recv(&mut buf).unwrap();
+        assert!(Packet::decode(buf[..ack].to_vec()).unwrap().is_ACK());
+        let rst = sender.recv(&mut buf).unwrap();
+        assert!(Packet::decode(buf[..rst].to_vec()).unwrap().is_RST());
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn denied_sender_is_ignored() {
+        let dir = scratch_dir("denied");
+        let src = dir.join("snail.txt");
+        fs::write(&src, b"not for you").unwrap();
+
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .allowed_senders(vec!["127.0.0.0/8".parse().unwrap()])
+            .denied_senders(vec!["127.0.0.1".parse().unwrap()])
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        receiver.start_recv_file(dir.join("out")).unwrap();
+
+        let sender = thread::spawn(move || {
+            let mut sock = SecSnailSocket::builder()
+                .bind("127.0.0.1:0")
+                .snd_timeout(Duration::from_millis(20))
+                .max_retransmits(2)
+                .build()
+                .unwrap();
+            sock.send_file_to_blocking(src, recv_addr)
+        });
+        while !sender.is_finished() {
+            assert!(receiver.poll_recv_progress().unwrap().is_pending());
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert!(matches!(
+            sender.join().unwrap(),
+            Err(SecSnailError::MaxRetransmitsExceeded)
+        ));
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn refuse_sender_over_quota() {
+        let dir = scratch_dir("quota");
+        let src = dir.join("snail.txt");
+        fs::write(&src, vec![0; 3000]).unwrap();
+
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .sender_quota(2000, Duration::from_secs(60))
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            let first = receiver.recv_file_blocking(&out);
+            (first, receiver.recv_file_blocking(&out))
+        });
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        assert!(sender.send_file_to_blocking(&src, recv_addr).is_ok());
+        let r = sender.send_file_to_blocking(&src, recv_addr);
+        assert!(matches!(r, Err(SecSnailError::Rejected(_))));
+
+        let (first, second) = recv.join().unwrap();
+        assert_eq!(first.unwrap().bytes, 3000);
+        assert!(matches!(
+            second,
+            Err(SecSnailError::QuotaExceeded {
+                used: 3000,
+                quota: 2000
+            })
+        ));
+    }
+
+    #[test]
+    fn retry_until_receiver_listens() {
+        let dir = scratch_dir("retry");
+        let src = dir.join("snail.txt");
+        fs::write(&src, vec![7; 2000]).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let sender = thread::spawn(move || {
+            let mut sock = SecSnailSocket::builder()
+                .bind("127.0.0.1:0")
+                .snd_timeout(Duration::from_millis(20))
+                .max_retransmits(2)
+                .transfer_retry_policy(10, Duration::from_millis(10))
+                .build()
+                .unwrap();
+            sock.send_file_to_blocking(src, recv_addr)
+        });
+
+        // the first attempt times out before the receiver listens
+        thread::sleep(Duration::from_millis(150));
+        let report = receiver.recv_file_blocking(dir.join("out")).unwrap();
+        let sent = sender.join().unwrap().unwrap();
+        assert_eq!(sent.bytes, 2000);
+        assert_eq!(report.bytes, 2000);
+        assert!(sent.stats.attempts > 1);
+    }
+
+    #[test]
+    fn transfer_deadline_exceeded() {
+        let dir = scratch_dir("deadline");
+        let src = dir.join("snail.txt");
+        fs::write(&src, b"nobody is listening").unwrap();
+
+        // silent peer, the sender would retransmit the syn for seconds
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sock = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .max_retransmits(u8::MAX)
+            .transfer_deadline(Duration::from_millis(100))
+            .build()
+            .unwrap();
+
+        let start = Instant::now();
+        let r = sock.send_file_to_blocking(&src, silent.local_addr().unwrap());
+        assert!(matches!(r, Err(SecSnailError::DeadlineExceeded)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn connected_send() {
+        let dir = scratch_dir("connected");
+        let src = dir.join("snail.txt");
+        fs::write(&src, b"no address needed").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.inner.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sock = SecSnailSocket::connect(recv_addr).unwrap();
+        assert_eq!(sock.peer_addr().unwrap(), recv_addr);
+        sock.send_file_blocking(&src).unwrap();
+
+        assert_eq!(recv.join().unwrap().file_name, "snail.txt");
+    }
+
+    #[test]
+    fn send_without_peer() {
+        let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        assert!(sock.send_file_blocking("snail.txt").is_err());
+    }
+
+    #[test]
+    fn discover_receiver() {
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .discoverable("snail-lab")
+            .build()
+            .unwrap();
+        let recv_addr = receiver.inner.local_addr().unwrap();
+        let recv = thread::spawn(move || receiver.recv_file_blocking(scratch_dir("discover")));
+
+        let peers = SecSnailSocket::discover_at(recv_addr, Duration::from_millis(200)).unwrap();
+        assert_eq!(
+            peers,
+            [DiscoveredPeer {
+                name: "snail-lab".to_string(),
+                addr: recv_addr,
+            }]
+        );
+        drop(recv);
+    }
+
+    #[test]
+    fn poll_without_transfer() {
+        let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        assert!(matches!(
+            sock.poll_send_progress(),
+            Err(SecSnailError::NoActiveTransfer)
+        ));
+    }
+
+    #[test]
+    fn failed_poll_send_is_recorded() {
+        let dir = scratch_dir("poll-send-failed");
+        let src = dir.join("snail.txt");
+        fs::write(&src, b"nobody listens").unwrap();
+        // bound, but never answers
+        let dead = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dead_addr = dead.local_addr().unwrap();
+
+        let mut sock = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .snd_timeout(Duration::from_millis(20))
+            .max_retransmits(2)
+            .build()
+            .unwrap();
+        let events = sock.subscribe();
+        sock.set_nonblocking(true).unwrap();
+        sock.start_send_file(&src, dead_addr).unwrap();
+        let r = loop {
+            match sock.poll_send_progress() {
+                Ok(Poll::Pending) => thread::sleep(Duration::from_millis(1)),
+                r => break r,
+            }
+        };
+
+        assert!(matches!(r, Err(SecSnailError::MaxRetransmitsExceeded)));
+        assert_eq!(sock.stats_snapshot().active, 0);
+        assert_eq!(sock.last_transfer_stats().unwrap().retransmissions, 2);
+        assert!(events.try_iter().any(|e| matches!(
+            e,
+            SnailEvent::Failed {
+                direction: TransferDirection::Send,
+                peer: Some(peer),
+                ..
+            } if peer == dead_addr
+        )));
+    }
+}
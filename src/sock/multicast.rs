@@ -0,0 +1,258 @@
+//! One-to-many file distribution via ip multicast.
+//!
+//! The sender transmits every packet once to a multicast group. Each
+//! receiver acknowledges it by unicast like a regular transfer, and the
+//! sending fsm only sees an ack once all known receivers acknowledged the
+//! packet in flight. A timeout retransmits the packet to the whole group,
+//! receivers which already got it simply ack it again.
+//!
+//! Receivers are identified by the source address of their acks, so
+//! there is one receiver per host.
+
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    path::Path,
+    time::Instant,
+};
+
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+
+use crate::{
+    error::{Result, SecSnailError},
+    fsm_send::{
+        driver::run_snd_fsm_loop,
+        fsm::{ProtocolEventSource, ProtocolIoContext, SndEvent},
+    },
+    pck::{Flag, Packet},
+};
+
+use super::{RecvResult, SecSnailSocket, SendReport, snd_ctx::SendSession};
+
+impl SecSnailSocket {
+    /// bind the port of `group` and join it on `interface`
+    ///
+    /// the address is reused, so other sockets can join the group on the same port
+    pub fn bind_multicast(group: SocketAddrV4, interface: Ipv4Addr) -> Result<SecSnailSocket> {
+        SecSnailSocket::builder()
+            .bind((Ipv4Addr::UNSPECIFIED, group.port()))
+            .join_multicast(*group.ip(), interface)
+            .build()
+    }
+
+    /// interface to send multicast packets on
+    pub fn set_multicast_interface_v4(&self, interface: Ipv4Addr) -> Result<()> {
+        SockRef::from(&self.inner).set_multicast_if_v4(&interface)?;
+        Ok(())
+    }
+
+    /// send a file to all `receivers` which joined `group`
+    ///
+    /// the transfer completes once every receiver acknowledged every packet
+    pub fn send_file_multicast_blocking<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        group: SocketAddrV4,
+        receivers: &[SocketAddr],
+    ) -> Result<SendReport> {
+        if receivers.is_empty() {
+            return Err(SecSnailError::InvalidConfig(
+                "multicast transfer without receivers".to_string(),
+            ));
+        }
+
+        let path = path.as_ref();
+        let _span = tracing::info_span!(
+            "send_file_multicast",
+            file = %path.display(),
+            %group,
+            receivers = receivers.len(),
+        )
+        .entered();
+        let max_transmits = self.snd_max_retransmits;
+        let mut session = SendSession::new(group.into(), path, self.snd_timeout_config)?
+            .with_transfer_deadline(self.transfer_deadline, Instant::now());
+        let mut ctx = MulticastSendProtocolIoContext {
+            sock_ref: self,
+            session: &mut session,
+            receivers,
+            acked: HashSet::new(),
+            in_flight: None,
+        };
+        let ret =
+            run_snd_fsm_loop(&mut ctx, max_transmits).map(|(_, duration)| session.report(duration));
+        self.record_send(group.into(), 0, session.stats(), ret.as_ref().err());
+        super::log_send_outcome(&ret);
+        ret
+    }
+}
+
+/// bind with `SO_REUSEADDR`, which has to be set before binding
+pub(super) fn bind_reusable(addr: SocketAddr) -> Result<UdpSocket> {
+    let sock = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    sock.set_reuse_address(true)?;
+    sock.bind(&addr.into())?;
+    Ok(sock.into())
+}
+
+struct MulticastSendProtocolIoContext<'a> {
+    sock_ref: &'a mut SecSnailSocket,
+    session: &'a mut SendSession,
+    receivers: &'a [SocketAddr],
+    /// receivers which acknowledged the packet in flight
+    acked: HashSet<SocketAddr>,
+    in_flight: Option<Packet>,
+}
+
+impl MulticastSendProtocolIoContext<'_> {
+    /// ack or finack of a known receiver for the packet in flight
+    fn is_ack_of_in_flight(&self, rcvpkt: &Packet, src: SocketAddr) -> bool {
+        let Some(in_flight) = &self.in_flight else {
+            return false;
+        };
+        self.receivers.contains(&src)
+            && rcvpkt.notcorrupt()
+            && (rcvpkt.is_ACK() || rcvpkt.is_FINACK())
+            && rcvpkt.n() == in_flight.n()
+    }
+}
+
+impl ProtocolEventSource for MulticastSendProtocolIoContext<'_> {
+    /// only reports an ack once all receivers acknowledged the packet in flight
+    fn wait_for_ack_or_timeout(&mut self) -> Result<SndEvent> {
+        loop {
+            let r = self.sock_ref.wait_for_incoming_or_timeout(
+                None,
+                false,
+                self.session.timeout(),
+                self.session.timer_start(),
+                self.session.deadline(),
+            )?;
+            let (rcvpkt, src) = match r {
+                RecvResult::RecvPkt(Some(rcvpkt), src) => (rcvpkt, src),
+                RecvResult::RecvPkt(None, _) => continue,
+                RecvResult::Timeout => return Ok(SndEvent::Timeout),
+            };
+
+            // a single receiver refusing the file fails the transfer
+            if rcvpkt.notcorrupt() && rcvpkt.is_RST() && self.receivers.contains(&src) {
+                return Ok(SndEvent::RecvPck(Some(rcvpkt)));
+            }
+            if !self.is_ack_of_in_flight(&rcvpkt, src) {
+                continue;
+            }
+
+            self.acked.insert(src);
+            if self.acked.len() == self.receivers.len() {
+                return Ok(SndEvent::RecvPck(Some(rcvpkt)));
+            }
+        }
+    }
+}
+
+impl ProtocolIoContext for MulticastSendProtocolIoContext<'_> {
+    fn data_available(&mut self) -> Result<bool> {
+        self.session.data_available()
+    }
+
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+        self.session.make_pkt(seq_n, f, Instant::now())
+    }
+
+    fn start_timer(&mut self) -> Result<()> {
+        let timer_start = self.session.start_timer(Instant::now());
+        _ = self
+            .sock_ref
+            .update_udp_sock_timeout(timer_start, self.session.timeout())?;
+        Ok(())
+    }
+
+    fn stop_timer(&mut self) -> Result<()> {
+        self.session.stop_timer();
+        Ok(())
+    }
+
+    /// a new packet resets the acknowledgements, a retransmission keeps them
+    fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+        if self.in_flight.as_ref() != Some(pck) {
+            self.acked.clear();
+            self.in_flight = Some(pck.clone());
+        }
+        self.sock_ref.emit("send", pck, self.session.recv_addr())?;
+        let now = self.now();
+        self.sock_ref.record_sent_packet(self.session, pck, now);
+        Ok(())
+    }
+
+    fn get_data_counter(&self) -> usize {
+        self.session.data_counter()
+    }
+
+    fn increase_data_counter(&mut self, n: usize) {
+        self.session.increase_data_counter(n);
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.session.deadline()
+    }
+
+    fn on_event(&mut self, event: &SndEvent) {
+        let now = self.now();
+        self.session.record_event(event, now);
+        self.sock_ref
+            .trace_event("send", event.name(), event.packet());
+    }
+
+    fn protocol_violation(&mut self, state: &'static str, event: &SndEvent) -> Result<()> {
+        self.session.record_violation();
+        self.sock_ref
+            .strictness
+            .check("send", state, event.name(), event.packet())
+    }
+
+    fn on_transition(&mut self, state: &'static str, event: &'static str, next: &'static str) {
+        self.sock_ref.record_transition("send", state, event, next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, thread};
+
+    #[test]
+    fn send_via_group() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-multicast", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("snail.txt");
+        let content: Vec<u8> = (0..4000u32).map(|i| (i % 247) as u8).collect();
+        fs::write(&src, &content).unwrap();
+
+        // ephemeral port, the group is addressed with the port of the receiver
+        let group_ip = Ipv4Addr::new(239, 255, 55, 55);
+        let mut receiver =
+            SecSnailSocket::bind_multicast(SocketAddrV4::new(group_ip, 0), Ipv4Addr::LOCALHOST)
+                .unwrap();
+        let port = receiver.inner.local_addr().unwrap().port();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || receiver.recv_file_blocking(out).unwrap());
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .set_multicast_interface_v4(Ipv4Addr::LOCALHOST)
+            .unwrap();
+        let sent = sender
+            .send_file_multicast_blocking(
+                &src,
+                SocketAddrV4::new(group_ip, port),
+                &[SocketAddr::from((Ipv4Addr::LOCALHOST, port))],
+            )
+            .unwrap()
+            .bytes;
+
+        assert_eq!(sent, content.len());
+        assert_eq!(recv.join().unwrap().bytes, content.len());
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+    }
+}
@@ -0,0 +1,59 @@
+//! Datagram buffers of a socket.
+//!
+//! Every datagram is received into a buffer taken from the pool and given
+//! back right after, a decoded packet only copies its own bytes. So a
+//! dropped datagram, e.g. of a filtered peer, costs no allocation and a
+//! packet no more than its size, instead of a fresh zeroed buffer of
+//! `MAX_PAYLOAD_SIZE` bytes per datagram. Packets are sent the same way,
+//! encoded into a buffer of another pool, where the impairment corrupts
+//! them in place.
+
+use std::sync::Mutex;
+
+use crate::pck::MAX_PAYLOAD_SIZE;
+
+/// a byte more than a packet, so a strict decode sees an oversized
+/// datagram instead of its truncated start
+const BUF_LEN: usize = MAX_PAYLOAD_SIZE + 1;
+
+/// buffers kept for reuse, one per thread receiving on the socket at once
+const POOLED: usize = 8;
+
+#[derive(Default)]
+pub(super) struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// a buffer of `BUF_LEN` bytes, its content is undefined
+    pub fn take(&self) -> Vec<u8> {
+        self.free
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| vec![0; BUF_LEN])
+    }
+
+    pub fn give(&self, buf: Vec<u8>) {
+        let mut free = self.free.lock().unwrap();
+        if free.len() < POOLED {
+            free.push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool = BufferPool::default();
+        let buf = pool.take();
+        let ptr = buf.as_ptr();
+        pool.give(buf);
+        let again = pool.take();
+        assert_eq!(again.as_ptr(), ptr);
+        assert_eq!(again.len(), BUF_LEN);
+    }
+}
@@ -0,0 +1,100 @@
+//! Read-ahead of the data of a send transfer.
+//!
+//! A `Prefetch` owns a background thread, which reads payload sized chunks
+//! of the source into a bounded ring while the fsm waits for acks, so a
+//! slow disk or a network share only stalls the wire once the ring ran
+//! empty. The thread ends at the end of the source, on a read error, or
+//! once the prefetch is dropped and it finished its current read.
+
+use std::{
+    io::{self, Read},
+    sync::{
+        Mutex, PoisonError,
+        mpsc::{self, Receiver},
+    },
+    thread,
+};
+
+pub(super) struct Prefetch {
+    /// the mutex only keeps the socket `Sync` and is never locked
+    chunks: Mutex<Receiver<io::Result<Vec<u8>>>>,
+    /// chunk peeked by `data_available`
+    next: Option<Vec<u8>>,
+}
+
+impl Prefetch {
+    /// read chunks of `chunk` bytes of `reader`, up to `depth` ahead
+    pub fn spawn<R: Read + Send + 'static>(
+        mut reader: R,
+        chunk: usize,
+        depth: usize,
+    ) -> io::Result<Prefetch> {
+        let (tx, rx) = mpsc::sync_channel(depth);
+        thread::Builder::new()
+            .name("secsnail-prefetch".to_string())
+            .spawn(move || {
+                loop {
+                    let r = read_chunk(&mut reader, chunk);
+                    let last = !matches!(&r, Ok(buf) if !buf.is_empty());
+                    if tx.send(r).is_err() || last {
+                        break;
+                    }
+                }
+            })?;
+        Ok(Prefetch {
+            chunks: Mutex::new(rx),
+            next: None,
+        })
+    }
+
+    /// whether another chunk follows, waits for the reader thread
+    pub fn data_available(&mut self) -> io::Result<bool> {
+        if self.next.is_none() {
+            self.next = self.recv()?;
+        }
+        Ok(self.next.as_ref().is_some_and(|buf| !buf.is_empty()))
+    }
+
+    /// next chunk, empty at the end of the source
+    pub fn next_chunk(&mut self) -> io::Result<Vec<u8>> {
+        match self.next.take() {
+            Some(buf) => Ok(buf),
+            None => Ok(self.recv()?.unwrap_or_default()),
+        }
+    }
+
+    fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let chunks = self
+            .chunks
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        // the thread is gone after the last chunk
+        chunks.recv().ok().transpose()
+    }
+}
+
+/// up to `chunk` bytes, only fewer at the end of `reader`
+fn read_chunk(reader: &mut impl Read, chunk: usize) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(chunk);
+    reader.take(chunk as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_in_order_until_the_end() {
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let mut prefetch = Prefetch::spawn(io::Cursor::new(data.clone()), 300, 2).unwrap();
+        let mut read = Vec::new();
+        while prefetch.data_available().unwrap() {
+            let chunk = prefetch.next_chunk().unwrap();
+            assert!(chunk.len() == 300 || read.len() == 900);
+            read.extend(chunk);
+        }
+        assert_eq!(read, data);
+        assert!(prefetch.next_chunk().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+//! Prioritized send queue.
+//!
+//! Files are queued with a priority through a `SendQueue`, from any thread,
+//! while `SecSnailSocket::send_queue_blocking` sends them one after another,
+//! the highest priority first and files of equal priority in the order
+//! they were queued.
+//!
+//! A file queued for the receiver of the transfer in flight with a higher
+//! priority preempts it: the transfer is paused between two events, the
+//! urgent file is sent, whose syn makes the receiver abandon the paused
+//! file, and the paused one is sent again afterwards with an offer to
+//! resume. A receiver keeping partial files (`OverwritePolicy::Resume`)
+//! continues it where it stopped, any other starts it over. A file for
+//! another receiver waits for the transfer in flight, as the receiver of
+//! the paused file would not learn that it was paused.
+
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    task::Poll,
+};
+
+use crate::{
+    error::{Result, SecSnailError},
+    fsm_send::{driver::step_snd_fsm, fsm::SndFsm},
+};
+
+use super::{
+    DatagramTransport, SecSnailSocket, SendOutcome, SendReport, log_send_outcome,
+    snd_ctx::SendProtocolIoContext,
+    transfers::{TransferState, TransferStatus},
+};
+
+#[derive(Debug, Clone)]
+pub(super) struct QueuedFile {
+    pub path: PathBuf,
+    recv_addr: SocketAddr,
+    priority: u8,
+    /// order of equal priorities
+    seq: u64,
+    /// was paused, its receiver may hold a part of it
+    paused: bool,
+    /// state shared with the `TransferHandle` of the file, if any
+    pub state: Option<Arc<TransferState>>,
+}
+
+#[derive(Debug, Default)]
+struct Files {
+    files: Vec<QueuedFile>,
+    next_seq: u64,
+}
+
+impl Files {
+    /// most urgent file, the earliest queued one of the highest priority
+    fn next(&self) -> Option<usize> {
+        (0..self.files.len()).max_by_key(|i| {
+            let file = &self.files[*i];
+            (file.priority, std::cmp::Reverse(file.seq))
+        })
+    }
+}
+
+/// files waiting for `SecSnailSocket::send_queue_blocking`, clones share
+/// the queue, see the module docs
+#[derive(Debug, Clone, Default)]
+pub struct SendQueue {
+    files: Arc<Mutex<Files>>,
+}
+
+impl SendQueue {
+    pub fn new() -> SendQueue {
+        SendQueue::default()
+    }
+
+    /// queue the file at `path` for `recv_addr`, a higher `priority` is sent
+    /// earlier
+    pub fn push<P: AsRef<Path>>(&self, path: P, recv_addr: SocketAddr, priority: u8) {
+        self.push_with_state(path.as_ref(), recv_addr, priority, None);
+    }
+
+    pub(super) fn push_with_state(
+        &self,
+        path: &Path,
+        recv_addr: SocketAddr,
+        priority: u8,
+        state: Option<Arc<TransferState>>,
+    ) {
+        let mut files = self.files.lock().unwrap();
+        let seq = files.next_seq;
+        files.next_seq += 1;
+        files.files.push(QueuedFile {
+            path: path.to_path_buf(),
+            recv_addr,
+            priority,
+            seq,
+            paused: false,
+            state,
+        });
+    }
+
+    /// files waiting, not counting the one in flight
+    pub fn len(&self) -> usize {
+        self.files.lock().unwrap().files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn pop(&self) -> Option<QueuedFile> {
+        let mut files = self.files.lock().unwrap();
+        let i = files.next()?;
+        Some(files.files.remove(i))
+    }
+
+    /// a paused file keeps its place among the files of its priority
+    fn requeue(&self, file: QueuedFile) {
+        self.files.lock().unwrap().files.push(file);
+    }
+
+    /// whether a file waits which preempts `file`
+    fn preempts(&self, file: &QueuedFile) -> bool {
+        let files = self.files.lock().unwrap();
+        files
+            .files
+            .iter()
+            .any(|f| f.recv_addr == file.recv_addr && f.priority > file.priority)
+    }
+}
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// send the files of `queue` until it is empty, files queued meanwhile
+    /// are sent as well, see the module docs
+    ///
+    /// # Return
+    /// the result of every file in the order they were done, a file which
+    /// failed does not stop the others
+    pub fn send_queue_blocking(&mut self, queue: &SendQueue) -> Vec<SendOutcome> {
+        let mut results = Vec::new();
+        self.drain_queue(queue, |file, r| results.push((file.path, r)));
+        results
+    }
+
+    /// send the files of `queue` until it is empty, `done` is called with
+    /// every file once it is sent, failed or cancelled
+    pub(super) fn drain_queue<F>(&mut self, queue: &SendQueue, mut done: F)
+    where
+        F: FnMut(QueuedFile, Result<SendReport>),
+    {
+        while let Some(mut file) = queue.pop() {
+            if file.state.as_ref().is_some_and(|s| s.is_cancelled()) {
+                done(file, Err(SecSnailError::Cancelled));
+                continue;
+            }
+            if let Some(state) = &file.state {
+                state.set_status(TransferStatus::Sending);
+            }
+            let _span = tracing::info_span!(
+                "send_file",
+                file = %file.path.display(),
+                priority = file.priority,
+                peer = %file.recv_addr
+            )
+            .entered();
+            match self.send_preemptible(&file, queue) {
+                Ok(None) => {
+                    tracing::info!("transfer paused for a file of higher priority");
+                    file.paused = true;
+                    if let Some(state) = &file.state {
+                        state.set_status(TransferStatus::Paused);
+                    }
+                    queue.requeue(file);
+                }
+                Ok(Some(report)) => done(file, Ok(report)),
+                Err(e) => done(file, Err(e)),
+            }
+        }
+    }
+
+    /// # Return
+    /// `None` if the transfer was paused for a file of higher priority
+    fn send_preemptible(
+        &mut self,
+        file: &QueuedFile,
+        queue: &SendQueue,
+    ) -> Result<Option<SendReport>> {
+        let start = self.inner.now();
+        let mut session = self
+            .new_send_session(&file.path, file.recv_addr, start)?
+            .with_resume(file.paused || self.offer_resume);
+        let mut fsm = SndFsm::init(self.snd_max_retransmits);
+        let mut ctx = SendProtocolIoContext::new(self, &mut session);
+        let ret = loop {
+            match step_snd_fsm(fsm, &mut ctx) {
+                Ok((_, Poll::Ready(()))) => break Ok(()),
+                Ok((next, Poll::Pending)) => fsm = next,
+                Err(e) => break Err(e),
+            }
+            if let Some(state) = &file.state {
+                state.set_progress(ctx.progress());
+                if state.is_cancelled() {
+                    break Err(SecSnailError::Cancelled);
+                }
+            }
+            if queue.preempts(file) {
+                return Ok(None);
+            }
+        };
+        let ret = ret.map(|()| session.report(self.inner.now().saturating_duration_since(start)));
+        let transfer_id = session.transfer_id();
+        self.record_send(
+            file.recv_addr,
+            transfer_id,
+            session.stats(),
+            ret.as_ref().err(),
+        );
+        log_send_outcome(&ret);
+        ret.map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sock::OverwritePolicy;
+    use std::{
+        fs,
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn urgent_file_preempts_bulk_transfer() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-queue", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let bulk: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("bulk.bin"), &bulk).unwrap();
+        fs::write(dir.join("urgent.txt"), b"snail alarm").unwrap();
+
+        let mut receiver = SecSnailSocket::builder()
+            .bind("127.0.0.1:0")
+            .overwrite_policy(OverwritePolicy::Resume)
+            .build()
+            .unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            (0..2)
+                .map(|_| receiver.recv_file_blocking(&out).unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let queue = SendQueue::new();
+        queue.push(dir.join("bulk.bin"), recv_addr, 0);
+        let urgent = queue.clone();
+        let path = dir.join("urgent.txt");
+        let pushed = AtomicBool::new(false);
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        // queue the urgent file once the bulk transfer is under way
+        sender.set_progress_callback(move |p| {
+            if p.bytes > 50_000 && !pushed.swap(true, Ordering::Relaxed) {
+                urgent.push(&path, recv_addr, 9);
+            }
+        });
+        let results = sender.send_queue_blocking(&queue);
+        let order: Vec<_> = results
+            .iter()
+            .map(|(path, r)| (path.file_name().unwrap().to_owned(), r.is_ok()))
+            .collect();
+        assert_eq!(
+            order,
+            [("urgent.txt".into(), true), ("bulk.bin".into(), true)]
+        );
+
+        let reports = recv.join().unwrap();
+        assert_eq!(reports[0].file_name, "urgent.txt");
+        assert!(reports[1].resumed_from > 0);
+        assert_eq!(fs::read(dir.join("out/bulk.bin")).unwrap(), bulk);
+        assert!(queue.is_empty());
+    }
+}
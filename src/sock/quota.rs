@@ -0,0 +1,93 @@
+//! Per-sender quota of received bytes over a sliding time window.
+//!
+//! Received bytes are summed up in buckets of a fraction of the window, so
+//! the memory per sender stays bounded however many packets it sends.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use crate::error::{Result, SecSnailError};
+
+/// buckets per window
+const BUCKETS: u32 = 32;
+
+pub(super) struct SenderQuota {
+    max_bytes: u64,
+    window: Duration,
+    /// start and bytes of every bucket within the window, oldest first
+    received: HashMap<IpAddr, VecDeque<(Instant, u64)>>,
+}
+
+impl SenderQuota {
+    pub fn new(max_bytes: u64, window: Duration) -> SenderQuota {
+        SenderQuota {
+            max_bytes,
+            window,
+            received: HashMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, ip: IpAddr, now: Instant, bytes: usize) {
+        let bucket = self.window / BUCKETS;
+        let buckets = self.received.entry(ip).or_default();
+        match buckets.back_mut() {
+            Some((start, sum)) if now < *start + bucket => *sum += bytes as u64,
+            _ => buckets.push_back((now, bytes as u64)),
+        }
+    }
+
+    /// fails with `QuotaExceeded` if `ip` received its quota within the window
+    pub fn check(&mut self, ip: IpAddr, now: Instant) -> Result<()> {
+        let window = self.window;
+        self.received.retain(|_, buckets| {
+            while buckets
+                .front()
+                .is_some_and(|(start, _)| now.saturating_duration_since(*start) >= window)
+            {
+                buckets.pop_front();
+            }
+            !buckets.is_empty()
+        });
+
+        let used: u64 = self
+            .received
+            .get(&ip)
+            .map_or(0, |buckets| buckets.iter().map(|(_, sum)| sum).sum());
+        match used >= self.max_bytes {
+            true => Err(SecSnailError::QuotaExceeded {
+                used,
+                quota: self.max_bytes,
+            }),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quota_slides_with_window() {
+        let (a, b): (IpAddr, IpAddr) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let start = Instant::now();
+        let mut quota = SenderQuota::new(1000, Duration::from_secs(10));
+
+        for i in 0..4 {
+            quota.record(a, start + Duration::from_secs(i), 250);
+        }
+        assert!(matches!(
+            quota.check(a, start + Duration::from_secs(4)),
+            Err(SecSnailError::QuotaExceeded { used: 1000, .. })
+        ));
+        assert!(quota.check(b, start + Duration::from_secs(4)).is_ok());
+
+        // the first 250 bytes left the window
+        assert!(quota.check(a, start + Duration::from_secs(10)).is_ok());
+        assert!(quota.check(a, start + Duration::from_secs(14)).is_ok());
+        assert!(quota.received.is_empty());
+    }
+}
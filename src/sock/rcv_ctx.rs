@@ -0,0 +1,1243 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        mpsc::{Receiver, RecvTimeoutError},
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use memmap2::Mmap;
+use sha2::{Digest as _, Sha256};
+
+use crate::{
+    delta::{DeltaPlan, DeltaWriter, SIGNATURE_NAME, Signature},
+    disk::{available_space, preallocate, sync_dir},
+    error::{Result, SecSnailError},
+    feedback::encode_data_ack,
+    fsm_recv::{self, fsm::RcvEvent},
+    manifest::{decode_manifest, encode_missing},
+    meta::{
+        Digest, NamePolicy, SynAck, SynMeta, Verdict, check_file_name, check_relative_path,
+        encode_syn_ack, encode_verdict, file_digest, stored_path,
+    },
+    pck::{Flag, Packet},
+    timestamp::{micros, split_stamp},
+    util::u8_to_bool,
+};
+
+use super::{
+    DatagramTransport, RecvResult, SecSnailSocket, TransferReport, TransferStats,
+    clamp_to_deadline,
+    journal::{Journal, TransferDirection},
+    on_trusted_link,
+};
+
+/// suffix of a file being received, renamed to its name once complete
+const PARTIAL_SUFFIX: &str = ".secsnail-partial";
+/// suffix of the plan of a delta transfer, see `delta`
+const DELTA_PLAN_SUFFIX: &str = ".secsnail-delta";
+
+/// hidden file next to `path` with `suffix`
+fn hidden_sibling(path: &Path, suffix: &str) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+    Some(path.parent()?.join(format!(".{name}{suffix}")))
+}
+
+/// partial file in the spool dir of the file received to `path`, named
+/// after a hash of the whole path so files of the same name in different
+/// dirs do not meet
+fn spooled(spool_dir: &Path, path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+    let hash = Sha256::digest(path.as_os_str().as_encoded_bytes());
+    let hash: String = hash[..8].iter().map(|b| format!("{b:02x}")).collect();
+    Some(spool_dir.join(format!(".{name}-{hash}{PARTIAL_SUFFIX}")))
+}
+
+/// move the complete `partial` to `path`, through a hidden copy next to
+/// `path` if the spool dir is on another file system
+fn move_into_place(partial: &Path, path: &Path, sync: bool) -> io::Result<()> {
+    match fs::rename(partial, path) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let copy = hidden_sibling(path, PARTIAL_SUFFIX).unwrap();
+            fs::copy(partial, &copy)?;
+            if sync {
+                File::open(&copy)?.sync_all()?;
+            }
+            fs::rename(&copy, path)?;
+            fs::remove_file(partial)
+        }
+        r => r,
+    }
+}
+
+/// free names tried after `path` itself by `claim_free_name`
+const MAX_RENAMES: usize = 9999;
+
+/// create the first of `path`, `name (1).ext`, `name (2).ext`, ... which
+/// does not exist, exclusively so concurrent receivers never pick the same
+fn claim_free_name(path: &Path) -> io::Result<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map_or(String::new(), |e| format!(".{}", e.to_string_lossy()));
+    for n in 0..=MAX_RENAMES {
+        let candidate = match n {
+            0 => path.to_path_buf(),
+            n => path.with_file_name(format!("{stem} ({n}){ext}")),
+        };
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(_) => return Ok(candidate),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("no free name left for {}", path.display()),
+    ))
+}
+
+/// callback of `SecSnailSocketBuilder::path_resolver`
+pub(super) type PathResolver = Arc<dyn Fn(SocketAddr, &str, Option<u64>) -> PathBuf + Send + Sync>;
+
+/// name of the file of an anonymous sender, by its arrival in milliseconds
+/// since the epoch and the address of the sender
+fn anonymous_file_name(peer: Option<SocketAddr>, now: SystemTime) -> String {
+    let millis = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    match peer {
+        // `:` of an ipv6 address is reserved on windows
+        Some(peer) => format!("anonymous-{millis}-{}-{}", peer.ip(), peer.port()).replace(':', "_"),
+        None => format!("anonymous-{millis}"),
+    }
+}
+
+/// what happens to the partial file of an interrupted transfer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// remove the partial file, the next transfer starts over
+    #[default]
+    Overwrite,
+    /// keep the partial file and resume from its length if the sender of
+    /// the same file name offers to
+    Resume,
+    /// like `Overwrite`, but keep a file already at the destination and
+    /// store the received one as `name (1).ext`, `name (2).ext`, ...
+    /// whichever is free, see `TransferReport::path` and
+    /// `SendReport::stored_as`
+    RenameWithSuffix,
+}
+
+/// when a receiver flushes and syncs the file it receives to disk, so a
+/// power failure loses at most the bytes since the last checkpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CheckpointPolicy {
+    /// bytes received since the last checkpoint, `None` for no limit
+    pub bytes: Option<u64>,
+    /// time since the last checkpoint, `None` for no limit
+    pub interval: Option<Duration>,
+}
+
+impl CheckpointPolicy {
+    fn is_due(&self, bytes: u64, elapsed: Duration) -> bool {
+        self.bytes.is_some_and(|b| bytes >= b) || self.interval.is_some_and(|i| elapsed >= i)
+    }
+}
+
+/// where received files end up
+enum RecvTarget<'w> {
+    /// every file is created in the dir under its announced name
+    Dir(PathBuf),
+    /// a single file is written into the writer, announced name is ignored
+    Writer(Option<Box<dyn Write + Send + 'w>>),
+}
+
+/// state of the receiving side, owned independently of the socket
+/// so reception can be suspended between polls or driven asynchronously
+pub(super) struct RecvSession<'w> {
+    snd_addr: Option<SocketAddr>,
+    /// transfer id of the sender socket, echoed by every reply
+    transfer_id: u8,
+    buf_wrt: Option<BufWriter<Box<dyn Write + Send + 'w>>>,
+    /// handle of the file written to, synced at every checkpoint
+    sync_file: Option<File>,
+    checkpoints: CheckpointPolicy,
+    /// sync a completed file before its finack, and its directory once renamed
+    sync_on_close: bool,
+    /// bytes of the open file and time of its last checkpoint
+    last_checkpoint: (u64, Instant),
+    connection_timeout: Duration,
+    connection_timer_start: Option<Instant>,
+    target: RecvTarget<'w>,
+    data_counter: usize,
+    ack_retransmits: usize,
+    transfer_deadline: Option<Duration>,
+    /// largest file accepted, checked against the syn and every append
+    max_file_size: Option<u64>,
+    /// name, path and start of the open file
+    open: Option<(String, Option<PathBuf>, Instant)>,
+    /// hidden file written until the open file is complete
+    partial: Option<PathBuf>,
+    /// dir partial files are written to instead of next to their target
+    spool_dir: Option<PathBuf>,
+    overwrite_policy: OverwritePolicy,
+    name_policy: NamePolicy,
+    /// name chosen by the application instead of the announced one
+    file_name: Option<String>,
+    path_resolver: Option<PathResolver>,
+    /// journal the partial files are noted in, with the entry of the open one
+    journal: Option<Arc<Journal>>,
+    journal_entry: Option<u64>,
+    /// file size the sender announced, `None` for a stream
+    announced_size: Option<u64>,
+    /// announced size if the sender of the last syn offered to resume
+    resume_offer: Option<u64>,
+    /// bytes of the open file held from an interrupted transfer
+    resumed_from: u64,
+    /// skip checksums if the sender asks for it
+    accept_trusted: bool,
+    /// the sender of the last syn asked for a trusted link
+    trusted_offer: bool,
+    /// echo timestamps if the sender asks for it, see `timestamp`
+    accept_timestamps: bool,
+    /// the sender of the last syn offered timestamps
+    timestamps_offer: bool,
+    /// the open file is sent with timestamps
+    timestamps: bool,
+    /// stamp of the last data packet and when it arrived, echoed by its ack
+    echo: Option<(u32, u32)>,
+    /// writes taking longer flag the receiver as falling behind
+    congestion_threshold: Option<Duration>,
+    /// the last write took longer than `congestion_threshold`, told by its ack
+    congested: bool,
+    /// the last syn announced a manifest, see `manifest`
+    manifest_offer: bool,
+    /// the last syn announced a path relative to the target dir
+    path_offer: bool,
+    /// the open file is a manifest, buffered instead of written
+    manifest: Option<Vec<u8>>,
+    /// the last syn announced the signature of a delta transfer
+    signature_offer: bool,
+    /// the last syn announced the missing blocks of a delta transfer
+    delta_offer: bool,
+    /// the open file is a signature, buffered instead of written
+    signature: Option<Vec<u8>>,
+    /// size, SHA-256 and plan of the open file put together from a delta
+    delta: Option<(u64, Digest, PathBuf)>,
+    /// bitmap of the missing entries of the last manifest or blocks of the
+    /// last signature, sent with its finack
+    manifest_reply: Option<Vec<u8>>,
+    /// name the open file is stored under instead of the announced one,
+    /// see `OverwritePolicy::RenameWithSuffix`, sent with its finack
+    stored_name: Option<String>,
+    /// whether an identical file is held, set by a syn which only verifies
+    verdict: Option<Verdict>,
+    report: Option<TransferReport>,
+    /// counters of the open or last closed file
+    stats: TransferStats,
+}
+
+impl<'w> RecvSession<'w> {
+    pub fn new(target_dir: PathBuf, connection_timeout: Duration) -> Self {
+        Self::with_target(RecvTarget::Dir(target_dir), connection_timeout)
+    }
+
+    pub fn with_writer(writer: Box<dyn Write + Send + 'w>, connection_timeout: Duration) -> Self {
+        Self::with_target(RecvTarget::Writer(Some(writer)), connection_timeout)
+    }
+
+    fn with_target(target: RecvTarget<'w>, connection_timeout: Duration) -> Self {
+        Self {
+            target,
+            connection_timeout,
+            connection_timer_start: None,
+            snd_addr: None,
+            transfer_id: 0,
+            buf_wrt: None,
+            sync_file: None,
+            checkpoints: CheckpointPolicy::default(),
+            sync_on_close: false,
+            last_checkpoint: (0, Instant::now()),
+            data_counter: 0,
+            ack_retransmits: 0,
+            transfer_deadline: None,
+            max_file_size: None,
+            open: None,
+            partial: None,
+            spool_dir: None,
+            overwrite_policy: OverwritePolicy::default(),
+            name_policy: NamePolicy::default(),
+            file_name: None,
+            path_resolver: None,
+            journal: None,
+            journal_entry: None,
+            announced_size: None,
+            resume_offer: None,
+            resumed_from: 0,
+            accept_trusted: false,
+            trusted_offer: false,
+            accept_timestamps: false,
+            timestamps_offer: false,
+            timestamps: false,
+            echo: None,
+            congestion_threshold: None,
+            congested: false,
+            manifest_offer: false,
+            path_offer: false,
+            manifest: None,
+            signature_offer: false,
+            delta_offer: false,
+            signature: None,
+            delta: None,
+            manifest_reply: None,
+            stored_name: None,
+            verdict: None,
+            report: None,
+            stats: TransferStats::default(),
+        }
+    }
+
+    /// limit every session, starting with its syn
+    pub fn with_transfer_deadline(mut self, transfer_deadline: Option<Duration>) -> Self {
+        self.transfer_deadline = transfer_deadline;
+        self
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub fn with_overwrite_policy(mut self, overwrite_policy: OverwritePolicy) -> Self {
+        self.overwrite_policy = overwrite_policy;
+        self
+    }
+
+    pub fn with_name_policy(mut self, name_policy: NamePolicy) -> Self {
+        self.name_policy = name_policy;
+        self
+    }
+
+    /// store the file under `file_name`, whatever the sender announced
+    pub fn with_file_name(mut self, file_name: &str) -> Self {
+        self.file_name = Some(file_name.to_string());
+        self
+    }
+
+    pub fn with_path_resolver(mut self, path_resolver: Option<PathResolver>) -> Self {
+        self.path_resolver = path_resolver;
+        self
+    }
+
+    /// note partial files in `journal` and resume those it holds whatever
+    /// the overwrite policy
+    pub fn with_journal(mut self, journal: Option<Arc<Journal>>) -> Self {
+        self.journal = journal;
+        self
+    }
+
+    pub fn with_checkpoints(mut self, checkpoints: CheckpointPolicy) -> Self {
+        self.checkpoints = checkpoints;
+        self
+    }
+
+    pub fn with_spool_dir(mut self, spool_dir: Option<PathBuf>) -> Self {
+        self.spool_dir = spool_dir;
+        self
+    }
+
+    pub fn with_sync_on_close(mut self, sync_on_close: bool) -> Self {
+        self.sync_on_close = sync_on_close;
+        self
+    }
+
+    pub fn with_trusted_link(mut self, accept_trusted: bool) -> Self {
+        self.accept_trusted = accept_trusted;
+        self
+    }
+
+    pub fn with_timestamps(mut self, accept_timestamps: bool) -> Self {
+        self.accept_timestamps = accept_timestamps;
+        self
+    }
+
+    pub fn with_congestion_threshold(mut self, congestion_threshold: Option<Duration>) -> Self {
+        self.congestion_threshold = congestion_threshold;
+        self
+    }
+
+    /// deadline of the open session, `None` while waiting for a connection
+    pub fn deadline(&self) -> Option<Instant> {
+        let (_, _, start) = self.open.as_ref()?;
+        self.transfer_deadline.map(|d| *start + d)
+    }
+
+    pub fn snd_addr(&self) -> Option<SocketAddr> {
+        self.snd_addr
+    }
+
+    pub fn set_snd_addr(&mut self, snd_addr: SocketAddr, transfer_id: u8) {
+        self.snd_addr.replace(snd_addr);
+        self.transfer_id = transfer_id;
+    }
+
+    pub fn transfer_id(&self) -> u8 {
+        self.transfer_id
+    }
+
+    /// address and transfer id of the sender of the open session
+    pub fn sender(&self) -> Option<(SocketAddr, u8)> {
+        self.snd_addr.map(|addr| (addr, self.transfer_id))
+    }
+
+    pub fn connection_timeout(&self) -> Duration {
+        self.connection_timeout
+    }
+
+    /// never call this function if timer is not started
+    pub fn connection_timer_start(&self) -> Instant {
+        self.connection_timer_start.unwrap()
+    }
+
+    /// expiry of the running connection timer
+    pub fn connection_timer_end(&self) -> Option<Instant> {
+        self.connection_timer_start
+            .map(|start| start + self.connection_timeout)
+    }
+
+    pub fn start_connection_timer(&mut self, now: Instant) -> Instant {
+        *self.connection_timer_start.insert(now)
+    }
+
+    pub fn stop_connection_timer(&mut self) {
+        self.connection_timer_start.take();
+    }
+
+    /// fails with `FileTooLarge` if the announced size exceeds the limit
+    /// and with `InsufficientSpace` if it does not fit into the target dir
+    pub fn extract_file_name(&mut self, rcvpkt: &Packet) -> Result<String> {
+        let mut meta = SynMeta::decode(rcvpkt.payload())?;
+        self.signature_offer = meta.delta && meta.file_name == SIGNATURE_NAME;
+        self.delta_offer = meta.delta && !self.signature_offer;
+        if let Some(name) = &self.file_name {
+            meta.file_name = name.clone();
+        } else if meta.file_name.is_empty() {
+            meta.file_name = anonymous_file_name(self.snd_addr, SystemTime::now());
+        }
+        self.announced_size = meta.file_size;
+        self.resume_offer = meta.file_size.filter(|_| meta.resume);
+        self.trusted_offer = meta.trusted;
+        self.timestamps_offer = meta.timestamps;
+        self.manifest_offer = meta.manifest;
+        self.path_offer = meta.relative_path;
+        let Some(size) = meta.file_size else {
+            self.verdict = None;
+            return Ok(meta.file_name);
+        };
+        self.verdict = meta
+            .digest
+            .map(|digest| self.verdict_on(&meta.file_name, size, digest));
+        if self.verdict.is_some() {
+            // nothing is written
+            return Ok(meta.file_name);
+        }
+        if let Some(max) = self.max_file_size
+            && size > max
+        {
+            return Err(SecSnailError::FileTooLarge { size, max });
+        }
+        if let RecvTarget::Dir(target_dir) = &self.target
+            && let Some(available) =
+                available_space(self.spool_dir.as_deref().unwrap_or(target_dir))?
+            && size > available
+        {
+            return Err(SecSnailError::InsufficientSpace { size, available });
+        }
+        Ok(meta.file_name)
+    }
+
+    /// where the file announced as `name` of `size` is stored below
+    /// `target_dir`, by the path resolver if there is one
+    fn destination(&self, target_dir: &Path, name: &str, size: Option<u64>) -> Result<PathBuf> {
+        match (&self.path_resolver, self.snd_addr) {
+            (Some(resolve), Some(peer)) => Ok(target_dir.join(resolve(peer, name, size))),
+            _ => Ok(target_dir.join(stored_path(name, self.name_policy)?)),
+        }
+    }
+
+    /// whether the target dir holds `file_name` with `size` and `digest`
+    fn verdict_on(&self, file_name: &str, size: u64, digest: Digest) -> Verdict {
+        let RecvTarget::Dir(target_dir) = &self.target else {
+            return Verdict::Missing;
+        };
+        let Ok(path) = self.destination(target_dir, file_name, Some(size)) else {
+            return Verdict::Missing;
+        };
+        let verdict = match fs::metadata(&path) {
+            Ok(m) if m.is_file() => {
+                let identical = m.len() == size
+                    && File::open(&path)
+                        .and_then(file_digest)
+                        .is_ok_and(|d| d == digest);
+                match identical {
+                    true => Verdict::Identical,
+                    false => Verdict::Differs,
+                }
+            }
+            _ => Verdict::Missing,
+        };
+        tracing::info!(file = file_name, %verdict, "verified file");
+        verdict
+    }
+
+    /// not write to buffer if buffer was not check
+    pub fn append(&mut self, data: &[u8]) -> Result<()> {
+        #[cfg(debug_assertions)]
+        {
+            if self.buf_wrt.is_none() {
+                unreachable!("buf_wrt in ctx should always be set by calling append in fmt");
+            }
+        }
+
+        // a sender without or with a wrong announced size is cut off
+        let size = (self.data_counter + data.len()) as u64;
+        if let Some(max) = self.max_file_size
+            && size > max
+        {
+            return Err(SecSnailError::FileTooLarge { size, max });
+        }
+
+        let started = Instant::now();
+        let wrt = self.buf_wrt.as_mut().unwrap();
+        wrt.write_all(data)?;
+        self.congested = self
+            .congestion_threshold
+            .is_some_and(|threshold| started.elapsed() > threshold);
+        // the journal never notes more than the partial file holds
+        if let (Some(journal), Some(id)) = (&self.journal, self.journal_entry) {
+            journal.progress(id, size, || wrt.flush())?;
+        }
+        let (last, at) = self.last_checkpoint;
+        if self
+            .checkpoints
+            .is_due(size.saturating_sub(last), at.elapsed())
+        {
+            self.checkpoint(size)?;
+        }
+        if let Some(manifest) = &mut self.manifest {
+            manifest.extend_from_slice(data);
+        }
+        if let Some(signature) = &mut self.signature {
+            signature.extend_from_slice(data);
+        }
+        Ok(())
+    }
+
+    pub fn close_file(&mut self, now: Instant) -> Result<()> {
+        self.buf_wrt.as_mut().unwrap().flush()?;
+        self.buf_wrt.take();
+        self.sync_file.take();
+        if let (Some((size, digest, plan)), Some(partial)) = (self.delta.take(), &self.partial) {
+            fs::remove_file(plan)?;
+            let file = File::open(partial)?;
+            if file.metadata()?.len() != size || file_digest(file)? != digest {
+                fs::remove_file(self.partial.take().unwrap())?;
+                if let Some((_, Some(claimed), _)) = &self.open
+                    && self.overwrite_policy == OverwritePolicy::RenameWithSuffix
+                {
+                    fs::remove_file(claimed)?;
+                }
+                return Err(SecSnailError::ProtocolViolation(
+                    "file put together from a delta does not match its signature".to_string(),
+                ));
+            }
+        }
+        if let (Some(partial), Some((_, Some(path), _))) = (self.partial.take(), &self.open) {
+            move_into_place(&partial, path, self.sync_on_close)?;
+            if self.sync_on_close {
+                sync_dir(path)?;
+            }
+        }
+        self.finish_journaled()?;
+        let peer = self.snd_addr.take();
+        if let (Some((file_name, path, start)), Some(peer)) = (self.open.take(), peer) {
+            self.report = Some(TransferReport {
+                file_name,
+                path,
+                peer,
+                bytes: self.data_counter,
+                resumed_from: self.resumed_from,
+                verified: self.verdict,
+                duration: now.saturating_duration_since(start),
+                retransmitted_acks: self.ack_retransmits,
+                missing: self
+                    .manifest_reply
+                    .as_ref()
+                    .map(|bitmap| bitmap.iter().map(|b| b.count_ones() as usize).sum()),
+                stats: self.stats(),
+            });
+        }
+        Ok(())
+    }
+
+    /// close the open file without report, a partial file is removed
+    /// unless it is kept to be resumed, as is one noted in the journal
+    pub fn discard_file(&mut self) -> Result<()> {
+        let keep = self.overwrite_policy == OverwritePolicy::Resume || self.journal_entry.is_some();
+        if let Some(wrt) = self.buf_wrt.as_mut()
+            && keep
+        {
+            wrt.flush()?;
+        }
+        self.buf_wrt.take();
+        self.sync_file.take();
+        self.open.take();
+        self.snd_addr.take();
+        self.journal_entry = None;
+        if let Some(partial) = self.partial.take()
+            && !keep
+        {
+            fs::remove_file(partial)?;
+        }
+        Ok(())
+    }
+
+    /// make the first `bytes` of the open file durable and note them in the
+    /// journal
+    fn checkpoint(&mut self, bytes: u64) -> Result<()> {
+        self.buf_wrt.as_mut().unwrap().flush()?;
+        if let Some(file) = &self.sync_file {
+            file.sync_data()?;
+        }
+        if let (Some(journal), Some(id)) = (&self.journal, self.journal_entry) {
+            journal.checkpoint(id, bytes)?;
+        }
+        self.stats.checkpoints += 1;
+        self.last_checkpoint = (bytes, Instant::now());
+        Ok(())
+    }
+
+    /// with `OverwritePolicy::RenameWithSuffix` claim a free name for the
+    /// complete open file before its finack, `close_file` moves it there
+    fn claim_destination(&mut self) -> Result<()> {
+        let Some((_, Some(path), _)) = &mut self.open else {
+            return Ok(());
+        };
+        if self.overwrite_policy != OverwritePolicy::RenameWithSuffix || self.partial.is_none() {
+            return Ok(());
+        }
+        let claimed = claim_free_name(path)?;
+        if claimed != *path {
+            tracing::info!(file = %claimed.display(), "destination taken, renamed");
+            self.stored_name = claimed
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned());
+            *path = claimed;
+        }
+        Ok(())
+    }
+
+    /// make the whole open file durable, before its finack tells the
+    /// sender it arrived
+    fn sync_all(&mut self) -> Result<()> {
+        if let Some(wrt) = self.buf_wrt.as_mut() {
+            wrt.flush()?;
+        }
+        if let Some(file) = &self.sync_file {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// the open file leaves the journal
+    fn finish_journaled(&mut self) -> Result<()> {
+        if let (Some(journal), Some(id)) = (&self.journal, self.journal_entry.take()) {
+            journal.finish(id)?;
+        }
+        Ok(())
+    }
+
+    /// files in a dir are written to a hidden partial file first, in the
+    /// spool dir if there is one, see `close_file`
+    pub fn open_file(&mut self, filename: &str, now: Instant) -> Result<()> {
+        self.manifest = self.manifest_offer.then(Vec::new);
+        self.signature = self.signature_offer.then(Vec::new);
+        self.manifest_reply = None;
+        self.stored_name = None;
+        self.delta = None;
+        let (wrt, path): (Box<dyn Write + Send + 'w>, _) = match &mut self.target {
+            // a verifying sender sends no data, a manifest or signature is
+            // only buffered
+            _ if self.verdict.is_some() || self.manifest.is_some() || self.signature.is_some() => {
+                self.resumed_from = 0;
+                (Box::new(io::sink()), None)
+            }
+            RecvTarget::Dir(target_dir) => {
+                match self.path_offer {
+                    true => check_relative_path(filename)?,
+                    false => check_file_name(filename)?,
+                }
+                let target_dir = target_dir.clone();
+                let path = self.destination(&target_dir, filename, self.announced_size)?;
+                let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                    return Err(SecSnailError::InvalidFilename(format!(
+                        "{} has no file name",
+                        path.display()
+                    )));
+                };
+                fs::create_dir_all(parent)?;
+                let partial = match &self.spool_dir {
+                    Some(spool_dir) => spooled(spool_dir, &path).unwrap(),
+                    None => parent.join(format!(".{}{PARTIAL_SUFFIX}", name.to_string_lossy())),
+                };
+                let file = match self.resumable_len(&partial) {
+                    Some(len) => {
+                        tracing::info!(file = filename, offset = len, "resuming partial file");
+                        self.resumed_from = len;
+                        self.data_counter = len as usize;
+                        OpenOptions::new().append(true).open(&partial)?
+                    }
+                    None => {
+                        self.resumed_from = 0;
+                        File::create(&partial)?
+                    }
+                };
+                if let (Some(journal), Some(peer)) = (&self.journal, self.snd_addr) {
+                    let partial = fs::canonicalize(&partial)?;
+                    self.journal_entry =
+                        Some(journal.begin(TransferDirection::Recv, peer, filename, &partial)?);
+                }
+                if let Some(size) = self.announced_size.filter(|_| !self.delta_offer) {
+                    preallocate(&file, size)?;
+                }
+                self.partial = Some(partial);
+                self.sync_file = Some(file.try_clone()?);
+                let wrt: Box<dyn Write + Send + 'w> = match self.delta_offer {
+                    true => Box::new(self.delta_writer(&path, file)?),
+                    false => Box::new(file),
+                };
+                (wrt, Some(path))
+            }
+            RecvTarget::Writer(writer) => {
+                self.resumed_from = 0;
+                let wrt = writer.take().ok_or_else(|| {
+                    SecSnailError::ProtocolViolation(format!(
+                        "second file '{filename}' for a single file writer"
+                    ))
+                })?;
+                (wrt, None)
+            }
+        };
+        self.buf_wrt.replace(BufWriter::new(wrt));
+        self.open = Some((filename.to_string(), path, now));
+        self.ack_retransmits = 0;
+        self.stats = TransferStats {
+            trusted_link: self.accept_trusted && self.trusted_offer && self.verdict.is_none(),
+            ..TransferStats::default()
+        };
+        self.timestamps = self.accept_timestamps && self.timestamps_offer && self.verdict.is_none();
+        self.echo = None;
+        self.congested = false;
+        self.last_checkpoint = (self.data_counter as u64, Instant::now());
+        Ok(())
+    }
+
+    /// writer putting the new version of the file at `path` together into
+    /// `out`, by the plan the signature of the file left
+    fn delta_writer(&mut self, path: &Path, out: File) -> Result<DeltaWriter<File, File>> {
+        let plan_path = hidden_sibling(path, DELTA_PLAN_SUFFIX).unwrap();
+        let plan = fs::read(&plan_path).map_err(|_| {
+            SecSnailError::ProtocolViolation(format!(
+                "missing blocks of {} without its signature",
+                path.display()
+            ))
+        })?;
+        let plan = DeltaPlan::decode(&plan)?;
+        preallocate(&out, plan.size)?;
+        let old = match plan.blocks.iter().any(Option::is_some) {
+            true => Some(File::open(path)?),
+            false => None,
+        };
+        self.delta = Some((plan.size, plan.digest, plan_path));
+        Ok(DeltaWriter::new(out, old, plan)?)
+    }
+
+    /// plan of the blocks of `signature` the file at `path` holds, stored
+    /// next to it until the missing blocks arrive
+    fn plan_delta(&self, signature: &Signature, path: &Path) -> Result<DeltaPlan> {
+        let plan = match File::open(path) {
+            // SAFETY: the mapping is only read, a file modified meanwhile
+            // fails the digest of the new version
+            Ok(file) if file.metadata()?.len() > 0 => signature.plan(&unsafe { Mmap::map(&file)? }),
+            _ => signature.plan(&[]),
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(
+            hidden_sibling(path, DELTA_PLAN_SUFFIX).unwrap(),
+            plan.encode(),
+        )?;
+        Ok(plan)
+    }
+
+    /// length of a partial file which may be resumed, not if it is empty
+    /// or larger than the offered file
+    fn resumable_len(&self, partial: &Path) -> Option<u64> {
+        let size = self.resume_offer?;
+        let journaled = self.journal.as_ref().is_some_and(|j| {
+            fs::canonicalize(partial).is_ok_and(|p| j.holds(TransferDirection::Recv, &p))
+        });
+        if self.overwrite_policy != OverwritePolicy::Resume && !journaled {
+            return None;
+        }
+        let len = fs::metadata(partial).ok()?.len();
+        (len > 0 && len <= size).then_some(len)
+    }
+
+    /// payload of the ack of the syn, the offset of a resumed file
+    pub fn syn_ack_payload(&self) -> Vec<u8> {
+        match self.verdict {
+            Some(verdict) => encode_verdict(verdict),
+            None => encode_syn_ack(SynAck {
+                offset: self.resumed_from,
+                trusted: self.stats.trusted_link,
+                timestamps: self.timestamps,
+            }),
+        }
+    }
+
+    /// file data of a data packet which arrived at `now`, its timestamp is
+    /// kept for the ack
+    pub fn extract_data<'a>(&mut self, rcvpkt: &'a Packet, now: Instant) -> &'a [u8] {
+        if !self.timestamps {
+            return rcvpkt.payload();
+        }
+        let start = self.open.as_ref().map_or(now, |(_, _, start)| *start);
+        match split_stamp(rcvpkt.payload()) {
+            Some((sent, data)) => {
+                self.echo = Some((sent, micros(start, now)));
+                data
+            }
+            None => &[],
+        }
+    }
+
+    /// an ack or finack, without checksum on a trusted link, the finack
+    /// of a manifest tells the entries missing in the target dir
+    pub fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+        if f == Flag::FINACK {
+            self.claim_destination()?;
+        }
+        if f == Flag::FINACK && self.sync_on_close {
+            self.sync_all()?;
+        }
+        if f == Flag::FINACK
+            && let Some(signature) = self.signature.take()
+        {
+            let signature = Signature::decode(&signature)?;
+            let missing = match &self.target {
+                RecvTarget::Dir(target_dir) => {
+                    let path =
+                        self.destination(target_dir, &signature.name, Some(signature.size))?;
+                    self.plan_delta(&signature, &path)?.missing()
+                }
+                RecvTarget::Writer(_) => vec![true; signature.blocks.len()],
+            };
+            tracing::info!(
+                file = signature.name,
+                blocks = missing.len(),
+                missing = missing.iter().filter(|m| **m).count(),
+                "received delta signature"
+            );
+            self.manifest_reply = Some(encode_missing(&missing));
+        }
+        if f == Flag::FINACK
+            && let Some(manifest) = self.manifest.take()
+        {
+            let entries = decode_manifest(&manifest)?;
+            let missing: Vec<_> = match &self.target {
+                RecvTarget::Dir(target_dir) => entries
+                    .iter()
+                    .map(|e| {
+                        self.destination(target_dir, &e.path, Some(e.size))
+                            .map_or(true, |path| e.is_missing_at(&path))
+                    })
+                    .collect(),
+                RecvTarget::Writer(_) => vec![true; entries.len()],
+            };
+            tracing::info!(
+                entries = entries.len(),
+                missing = missing.iter().filter(|m| **m).count(),
+                "received manifest"
+            );
+            self.manifest_reply = Some(encode_missing(&missing));
+        }
+        let payload = match f {
+            Flag::FINACK => match &self.stored_name {
+                Some(name) => name.clone().into_bytes(),
+                None => self.manifest_reply.clone().unwrap_or_default(),
+            },
+            Flag::ACK => {
+                let congested = std::mem::take(&mut self.congested);
+                self.stats.congestion_signals += usize::from(congested);
+                encode_data_ack(self.echo.take(), congested)
+            }
+            _ => Vec::new(),
+        };
+        let pck = match self.stats.trusted_link {
+            true => Packet::new_unchecked(u8_to_bool(seq_n), f, payload)?,
+            false => Packet::new(u8_to_bool(seq_n), f, payload)?,
+        };
+        Ok(pck.with_transfer_id(self.transfer_id))
+    }
+
+    /// a received event, its packet is not verified on a trusted link
+    /// unless it is a syn
+    pub fn received(&self, event: RcvEvent) -> RcvEvent {
+        match event {
+            RcvEvent::RecvPck(rcvpkt, src) => {
+                RcvEvent::RecvPck(on_trusted_link(rcvpkt, self.stats.trusted_link), src)
+            }
+            event => event,
+        }
+    }
+
+    /// report of the last closed file
+    /// sender of the last closed file, until its report is taken
+    pub fn report_peer(&self) -> Option<SocketAddr> {
+        self.report.as_ref().map(|r| r.peer)
+    }
+
+    pub fn take_report(&mut self) -> Option<TransferReport> {
+        self.report.take()
+    }
+
+    pub fn data_counter(&self) -> usize {
+        self.data_counter
+    }
+
+    pub fn increase_data_counter(&mut self, n: usize) {
+        self.data_counter += n;
+    }
+
+    pub fn reset_data_counter(&mut self) {
+        self.data_counter = 0;
+    }
+
+    pub fn increase_ack_retransmit_counter(&mut self) {
+        self.ack_retransmits += 1;
+        self.stats.retransmissions += 1;
+        self.stats.duplicates_received += 1;
+    }
+
+    pub fn record_sent(&mut self, pck: &Packet) {
+        self.stats.packets_sent += 1;
+        self.stats.bytes_on_wire += pck.encode().len();
+    }
+
+    pub fn record_event(&mut self, event: &RcvEvent) {
+        match event {
+            RcvEvent::ConnectionTimeout => self.stats.timeouts += 1,
+            RcvEvent::RecvPck(Some(rcvpkt), _) if rcvpkt.notcorrupt() => {}
+            RcvEvent::RecvPck(..) => self.stats.corrupt_dropped += 1,
+        }
+    }
+
+    pub fn record_violation(&mut self) {
+        self.stats.protocol_violations += 1;
+    }
+
+    pub fn stats(&self) -> TransferStats {
+        TransferStats {
+            payload_bytes: self.data_counter - self.resumed_from as usize,
+            ..self.stats
+        }
+    }
+}
+
+/// a session dropped with an open file was interrupted, e.g. by an error
+impl Drop for RecvSession<'_> {
+    fn drop(&mut self) {
+        _ = self.discard_file();
+    }
+}
+
+pub(super) struct RecvProtocolIoContext<'a, 'w, T: DatagramTransport> {
+    sock_ref: &'a SecSnailSocket<T>,
+    session: &'a mut RecvSession<'w>,
+    /// packets of a single sender, dispatched by another thread which
+    /// receives from the socket
+    inbox: Option<(&'a Receiver<Option<Packet>>, SocketAddr)>,
+}
+
+impl<'a, 'w, T: DatagramTransport> RecvProtocolIoContext<'a, 'w, T> {
+    pub fn new(sock_ref: &'a SecSnailSocket<T>, session: &'a mut RecvSession<'w>) -> Self {
+        Self {
+            sock_ref,
+            session,
+            inbox: None,
+        }
+    }
+
+    /// wait for the packets of `peer` in `inbox` instead of the socket,
+    /// see `serve_threaded`
+    pub fn with_inbox(
+        sock_ref: &'a SecSnailSocket<T>,
+        session: &'a mut RecvSession<'w>,
+        inbox: &'a Receiver<Option<Packet>>,
+        peer: SocketAddr,
+    ) -> Self {
+        Self {
+            sock_ref,
+            session,
+            inbox: Some((inbox, peer)),
+        }
+    }
+
+    /// next packet in the inbox, `None` waits forever
+    fn wait_in_inbox(
+        inbox: &Receiver<Option<Packet>>,
+        peer: SocketAddr,
+        timeout: Option<Duration>,
+    ) -> Result<Option<RcvEvent>> {
+        let r = match timeout {
+            Some(timeout) => inbox.recv_timeout(timeout),
+            None => inbox.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match r {
+            Ok(rcvpkt) => Ok(Some(RcvEvent::RecvPck(rcvpkt, peer))),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            // the dispatching thread stopped serving
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(io::Error::from(io::ErrorKind::ConnectionAborted).into())
+            }
+        }
+    }
+
+    /// a syn of a sender which used up its quota is refused
+    fn check_quota(&mut self) -> Result<()> {
+        match (&self.sock_ref.quota, self.session.snd_addr()) {
+            (Some(quota), Some(snd_addr)) => quota
+                .lock()
+                .unwrap()
+                .check(snd_addr.ip(), self.sock_ref.inner.now()),
+            _ => Ok(()),
+        }
+    }
+
+    /// tell the sender of a refused file with a rst, it fails with `Rejected`
+    fn reset_if_refused<V>(&mut self, r: Result<V>) -> Result<V> {
+        if let (
+            Err(
+                e @ (SecSnailError::FileTooLarge { .. }
+                | SecSnailError::InsufficientSpace { .. }
+                | SecSnailError::QuotaExceeded { .. }
+                | SecSnailError::InvalidFilename(_)),
+            ),
+            Some(snd_addr),
+        ) = (&r, self.session.snd_addr())
+        {
+            tracing::info!(error = %e, "transfer refused");
+            let sndpkt = Packet::new(false, Flag::RST, e.to_string().into_bytes())?
+                .with_transfer_id(self.session.transfer_id());
+            self.sock_ref.udt_send(&sndpkt, snd_addr)?;
+        }
+        r
+    }
+}
+
+impl<T: DatagramTransport> fsm_recv::fsm::ProtocolEventSource for RecvProtocolIoContext<'_, '_, T> {
+    /// never call this functino if snd_addr is not set
+    fn wait_for_ack_or_timeout(&mut self) -> Result<RcvEvent> {
+        if let Some((inbox, peer)) = self.inbox {
+            let timer_start = self.session.connection_timer_start();
+            let (timeout, exceeds_deadline) = clamp_to_deadline(
+                timer_start,
+                self.session.connection_timeout(),
+                self.session.deadline(),
+            );
+            let remaining =
+                (timer_start + timeout).saturating_duration_since(self.sock_ref.inner.now());
+            return match Self::wait_in_inbox(inbox, peer, Some(remaining))? {
+                Some(event) => Ok(self.session.received(event)),
+                None if exceeds_deadline => Err(SecSnailError::DeadlineExceeded),
+                None => Ok(RcvEvent::ConnectionTimeout),
+            };
+        }
+        let r = self.sock_ref.wait_for_incoming_or_timeout(
+            self.session.sender(),
+            true,
+            self.session.connection_timeout(),
+            self.session.connection_timer_start(),
+            self.session.deadline(),
+        )?;
+        match r {
+            RecvResult::RecvPkt(rcvpkt, rcv_addr) => {
+                Ok(self.session.received(RcvEvent::RecvPck(rcvpkt, rcv_addr)))
+            }
+            RecvResult::Timeout => Ok(RcvEvent::ConnectionTimeout),
+        }
+    }
+
+    fn wait_for_pck_no_timeout(&mut self) -> Result<RcvEvent> {
+        if let Some((inbox, peer)) = self.inbox {
+            let event = Self::wait_in_inbox(inbox, peer, None)?.expect("waits forever");
+            return Ok(self.session.received(event));
+        }
+        self.sock_ref.check_shutdown()?;
+        self.sock_ref.inner.set_read_timeout(None)?;
+        let (src, rcv_pck) = self.sock_ref.rdt_recv()?;
+        // woken up by the shutdown
+        self.sock_ref.check_shutdown()?;
+        Ok(self.session.received(RcvEvent::RecvPck(rcv_pck, src)))
+    }
+}
+
+impl<T: DatagramTransport> fsm_recv::fsm::ProtocolIoContext for RecvProtocolIoContext<'_, '_, T> {
+    fn set_snd_addr(&mut self, snd_addr: SocketAddr, transfer_id: u8) {
+        self.session.set_snd_addr(snd_addr, transfer_id);
+    }
+
+    fn extract_data<'a>(&mut self, rcvpkt: &'a Packet) -> &'a [u8] {
+        let now = self.sock_ref.inner.now();
+        self.session.extract_data(rcvpkt, now)
+    }
+
+    fn extract_file_name(&mut self, rcvpkt: &Packet) -> Result<String> {
+        let r = self
+            .check_quota()
+            .and_then(|_| self.session.extract_file_name(rcvpkt));
+        self.reset_if_refused(r)
+    }
+
+    fn append(&mut self, data: &[u8]) -> Result<()> {
+        let r = self.session.append(data);
+        if r.is_ok()
+            && let (Some(quota), Some(snd_addr)) = (&self.sock_ref.quota, self.session.snd_addr())
+        {
+            quota
+                .lock()
+                .unwrap()
+                .record(snd_addr.ip(), self.sock_ref.inner.now(), data.len());
+        }
+        self.reset_if_refused(r)
+    }
+
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+        self.session.make_pkt(seq_n, f)
+    }
+
+    fn make_syn_ack(&mut self, seq_n: u8) -> Result<Packet> {
+        Ok(
+            Packet::new(u8_to_bool(seq_n), Flag::ACK, self.session.syn_ack_payload())?
+                .with_transfer_id(self.session.transfer_id()),
+        )
+    }
+
+    /// create start_timer instant and set read timeout to timeout Duration
+    fn start_connection_timer(&mut self) -> Result<()> {
+        let timer_start = self
+            .session
+            .start_connection_timer(self.sock_ref.inner.now());
+        // the read timeout belongs to the thread receiving from the socket
+        if self.inbox.is_some() {
+            return Ok(());
+        }
+        // no timeout occures by starting timer
+        _ = self
+            .sock_ref
+            .update_udp_sock_timeout(timer_start, self.session.connection_timeout())?;
+        Ok(())
+    }
+
+    fn stop_connection_timer(&mut self) -> Result<()> {
+        self.session.stop_connection_timer();
+        if self.inbox.is_none() {
+            self.sock_ref
+                .inner
+                .set_read_timeout(Some(self.session.connection_timeout()))?;
+        }
+        Ok(())
+    }
+    fn restart_connection_timer(&mut self) -> Result<()> {
+        self.start_connection_timer()
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        self.session.close_file(self.sock_ref.inner.now())
+    }
+
+    fn discard_file(&mut self) -> Result<()> {
+        self.session.discard_file()
+    }
+
+    fn open_file(&mut self, filename: &str) -> Result<()> {
+        let r = self.session.open_file(filename, self.sock_ref.inner.now());
+        self.reset_if_refused(r)?;
+        if let Some(peer) = self.session.snd_addr() {
+            let transfer_id = self.session.transfer_id();
+            self.sock_ref
+                .notify_recv_started(peer, transfer_id, filename);
+        }
+        Ok(())
+    }
+
+    /// call only if snd_addr is set
+    fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+        let snd_addr = self.session.snd_addr().unwrap();
+        self.sock_ref.emit("recv", pck, snd_addr)?;
+        self.session.record_sent(pck);
+        Ok(())
+    }
+
+    fn get_data_counter(&self) -> usize {
+        self.session.data_counter()
+    }
+
+    fn increase_data_counter(&mut self, n: usize) {
+        self.session.increase_data_counter(n);
+    }
+
+    fn reset_data_counter(&mut self) {
+        self.session.reset_data_counter();
+    }
+
+    fn increase_ack_retransmit_counter(&mut self) {
+        self.session.increase_ack_retransmit_counter();
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.session.deadline()
+    }
+
+    fn now(&self) -> Instant {
+        self.sock_ref.inner.now()
+    }
+
+    fn on_event(&mut self, event: &RcvEvent) {
+        self.session.record_event(event);
+        self.sock_ref
+            .trace_event("recv", event.name(), event.packet());
+    }
+
+    fn protocol_violation(&mut self, state: &'static str, event: &RcvEvent) -> Result<()> {
+        self.session.record_violation();
+        self.sock_ref
+            .strictness
+            .check("recv", state, event.name(), event.packet())
+    }
+
+    fn on_transition(&mut self, state: &'static str, event: &'static str, next: &'static str) {
+        self.sock_ref.record_transition("recv", state, event, next);
+    }
+}
@@ -0,0 +1,92 @@
+//! Public address discovery, a minimal STUN.
+//!
+//! Any receiver answers an echo request with the address it saw the request
+//! from, see `crate::discovery`. Asked from the socket a transfer later runs
+//! on, that is the address the NAT maps the socket to, which the peer has
+//! to send to, e.g. to tell it through the rendezvous or hole punching.
+
+use std::{io, net::SocketAddr, time::Duration};
+
+use crate::{discovery, error::Result, pck::MAX_PAYLOAD_SIZE};
+
+use super::{DatagramTransport, SecSnailSocket};
+
+/// requests are repeated until a reply arrives
+const ECHO_INTERVAL: Duration = Duration::from_millis(250);
+const ECHO_TIMEOUT: Duration = Duration::from_secs(3);
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// ask the receiver at `probe_server` which address this socket sends
+    /// from, as seen behind any NAT between them
+    ///
+    /// # Return
+    /// the public address of this socket, fails with `TimedOut` if the
+    /// receiver does not answer within a few seconds
+    pub fn discover_public_addr(&self, probe_server: SocketAddr) -> Result<SocketAddr> {
+        let _span = tracing::info_span!("discover_public_addr", %probe_server).entered();
+        let nonce = rand::random();
+        let deadline = self.inner.now() + ECHO_TIMEOUT;
+        let mut next_send = self.inner.now();
+        let mut buf = [0; MAX_PAYLOAD_SIZE];
+        loop {
+            let now = self.inner.now();
+            if now >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "probe server did not answer the echo request",
+                )
+                .into());
+            }
+            if now >= next_send {
+                self.inner
+                    .send_to(&discovery::echo_request(nonce), probe_server)?;
+                next_send = now + ECHO_INTERVAL;
+            }
+            let wait = next_send.min(deadline).saturating_duration_since(now);
+            self.inner
+                .set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+            let (n, src) = match self.inner.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if src == probe_server
+                && let Some(addr) = discovery::decode_echo_reply(&buf[..n], nonce)
+            {
+                tracing::info!(public = %addr, "public address discovered");
+                return Ok(addr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn receiver_echoes_the_address_of_the_sender() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-reflexive", std::process::id()));
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let recv = thread::spawn(move || receiver.recv_file_blocking(dir).unwrap());
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let public = sender.discover_public_addr(recv_addr).unwrap();
+        assert_eq!(public, sender.local_addr().unwrap());
+
+        // the echo left the receiver waiting for a transfer
+        sender
+            .send_reader_blocking(io::Cursor::new(b"echo".to_vec()), "echo.txt", recv_addr)
+            .unwrap();
+        assert_eq!(recv.join().unwrap().peer, public);
+    }
+}
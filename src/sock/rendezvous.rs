@@ -0,0 +1,281 @@
+//! NAT traversal through a rendezvous server.
+//!
+//! Two peers behind NATs register the same token with a rendezvous server,
+//! which tells each of them the public address it observed of the other.
+//! Both then punch holes into their NATs by sending punches to each other
+//! until one arrives, afterwards the sender starts the regular handshake.
+//!
+//! ```text
+//!  register: ┌────────────────┬──────────────────┐
+//!            │ "SNAIL@" │ 0x01│ Token (UTF-8)    │
+//!            └────────────────┴──────────────────┘
+//!  peer:     ┌────────────────┬──────────────────┬──────────────────┐
+//!            │ "SNAIL=" │ 0x01│ IP (4 or 16 B)   │ Port (16 bit)    │
+//!            └────────────────┴──────────────────┴──────────────────┘
+//!  punch:    ┌────────────────┬──────────────────┐
+//!            │ "SNAIL#" │ 0x01│ Token (UTF-8)    │
+//!            └────────────────┴──────────────────┘
+//! ```
+//!
+//! Like a discovery probe, none of them decodes as a packet, so a late
+//! punch is dropped by the fsm like a corrupt packet.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    discovery::{decode_addr, encode_addr},
+    error::{Result, SecSnailError},
+    pck::MAX_PAYLOAD_SIZE,
+};
+
+use super::{DatagramTransport, SecSnailSocket};
+
+pub const DEFAULT_RENDEZVOUS_PORT: u16 = 55056;
+
+const REGISTER_MAGIC: &[u8] = b"SNAIL@";
+const PEER_MAGIC: &[u8] = b"SNAIL=";
+const PUNCH_MAGIC: &[u8] = b"SNAIL#";
+const VERSION: u8 = 0x01;
+
+const MAX_TOKEN_LEN: usize = 64;
+/// registrations are repeated until the server names the peer
+const REGISTER_INTERVAL: Duration = Duration::from_millis(500);
+const PUNCH_INTERVAL: Duration = Duration::from_millis(100);
+/// punches answering the first punch of the peer, which may not have got
+/// one of ours yet
+const FINAL_PUNCHES: usize = 3;
+/// a peer which stopped registering is forgotten by the server
+const REGISTRATION_TTL: Duration = Duration::from_secs(60);
+
+fn check_token(token: &str) -> Result<()> {
+    match token.len() {
+        1..=MAX_TOKEN_LEN => Ok(()),
+        _ => Err(SecSnailError::InvalidConfig(format!(
+            "rendezvous token must have 1 to {MAX_TOKEN_LEN} bytes"
+        ))),
+    }
+}
+
+fn register(token: &str) -> Vec<u8> {
+    [REGISTER_MAGIC, &[VERSION], token.as_bytes()].concat()
+}
+
+fn decode_register(buf: &[u8]) -> Option<&str> {
+    let token = buf.strip_prefix(REGISTER_MAGIC)?.strip_prefix(&[VERSION])?;
+    let token = str::from_utf8(token).ok()?;
+    check_token(token).ok().map(|_| token)
+}
+
+fn punch(token: &str) -> Vec<u8> {
+    [PUNCH_MAGIC, &[VERSION], token.as_bytes()].concat()
+}
+
+fn is_punch(buf: &[u8], token: &str) -> bool {
+    buf.strip_prefix(PUNCH_MAGIC)
+        .and_then(|rest| rest.strip_prefix(&[VERSION]))
+        == Some(token.as_bytes())
+}
+
+fn peer(addr: SocketAddr) -> Vec<u8> {
+    [PEER_MAGIC, &[VERSION], &encode_addr(addr)].concat()
+}
+
+fn decode_peer(buf: &[u8]) -> Option<SocketAddr> {
+    decode_addr(buf.strip_prefix(PEER_MAGIC)?.strip_prefix(&[VERSION])?)
+}
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// meet the peer which registered `token` with the rendezvous `server`
+    /// and punch a hole through the NATs of both
+    ///
+    /// # Return
+    /// public address of the peer once a punch of it arrived, to send a file
+    /// to or to receive one from, fails with `TimedOut` after `timeout`
+    pub fn rendezvous(
+        &self,
+        server: SocketAddr,
+        token: &str,
+        timeout: Duration,
+    ) -> Result<SocketAddr> {
+        check_token(token)?;
+        let _span = tracing::info_span!("rendezvous", %server).entered();
+        let deadline = self.inner.now() + timeout;
+        let mut peer_addr = None;
+        let mut next_send = self.inner.now();
+        let mut buf = [0; MAX_PAYLOAD_SIZE];
+        loop {
+            let now = self.inner.now();
+            if now >= deadline {
+                return Err(
+                    io::Error::new(io::ErrorKind::TimedOut, "no peer at the rendezvous").into(),
+                );
+            }
+            if now >= next_send {
+                next_send = match peer_addr {
+                    None => {
+                        self.inner.send_to(&register(token), server)?;
+                        now + REGISTER_INTERVAL
+                    }
+                    Some(addr) => {
+                        self.inner.send_to(&punch(token), addr)?;
+                        now + PUNCH_INTERVAL
+                    }
+                };
+            }
+            let wait = next_send.min(deadline).saturating_duration_since(now);
+            self.inner
+                .set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+            let (n, src) = match self.inner.recv_from(&mut buf) {
+                Ok(r) => r,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if src == server
+                && let Some(addr) = decode_peer(&buf[..n])
+            {
+                if peer_addr.is_none() {
+                    tracing::info!(peer = %addr, "rendezvous server named the peer");
+                    next_send = now;
+                }
+                peer_addr = Some(addr);
+            } else if is_punch(&buf[..n], token) {
+                // the nat of the peer may map it to another port than the server saw
+                for _ in 0..FINAL_PUNCHES {
+                    self.inner.send_to(&punch(token), src)?;
+                }
+                tracing::info!(peer = %src, "hole punched");
+                return Ok(src);
+            }
+        }
+    }
+}
+
+/// pairs the two peers which register the same token, see the module docs
+pub struct RendezvousServer {
+    sock: UdpSocket,
+    /// up to two peers per token, with the time of their last registration
+    tokens: HashMap<String, Vec<(SocketAddr, Instant)>>,
+}
+
+impl RendezvousServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<RendezvousServer> {
+        Ok(RendezvousServer {
+            sock: UdpSocket::bind(addr)?,
+            tokens: HashMap::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.sock.local_addr()?)
+    }
+
+    /// answer registrations, returns only on an i/o error
+    pub fn run(&mut self) -> Result<()> {
+        let mut buf = [0; MAX_PAYLOAD_SIZE];
+        loop {
+            let (n, src) = self.sock.recv_from(&mut buf)?;
+            for (reply, addr) in self.handle(&buf[..n], src, Instant::now()) {
+                self.sock.send_to(&reply, addr)?;
+            }
+        }
+    }
+
+    /// replies to the datagram `buf` of `src`, both peers of a token learn
+    /// the address of the other once the second one registered
+    fn handle(&mut self, buf: &[u8], src: SocketAddr, now: Instant) -> Vec<(Vec<u8>, SocketAddr)> {
+        self.tokens.retain(|_, peers| {
+            peers.retain(|(_, at)| now.saturating_duration_since(*at) < REGISTRATION_TTL);
+            !peers.is_empty()
+        });
+        let Some(token) = decode_register(buf) else {
+            return Vec::new();
+        };
+        let peers = self.tokens.entry(token.to_string()).or_default();
+        match peers.iter().position(|(addr, _)| *addr == src) {
+            Some(i) => peers[i].1 = now,
+            None if peers.len() < 2 => peers.push((src, now)),
+            // a third peer of a token is ignored
+            None => return Vec::new(),
+        }
+        match peers.as_slice() {
+            [(a, _), (b, _)] => vec![(peer(*b), *a), (peer(*a), *b)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pck::Packet;
+    use std::thread;
+
+    #[test]
+    fn server_pairs_two_peers_of_a_token() {
+        let mut server = RendezvousServer::bind("127.0.0.1:0").unwrap();
+        let now = Instant::now();
+        let a: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+        let b: SocketAddr = "[2001:db8::1]:50000".parse().unwrap();
+        let c: SocketAddr = "203.0.113.9:60000".parse().unwrap();
+        assert!(server.handle(&register("snail"), a, now).is_empty());
+        assert!(server.handle(&register("other"), c, now).is_empty());
+        let replies = server.handle(&register("snail"), b, now);
+        assert_eq!(replies, [(peer(b), a), (peer(a), b)]);
+        assert_eq!(decode_peer(&peer(b)), Some(b));
+        assert!(server.handle(&register("snail"), c, now).is_empty());
+        assert!(server.handle(&register(""), c, now).is_empty());
+
+        let later = now + REGISTRATION_TTL;
+        assert!(server.handle(&register("snail"), a, later).is_empty());
+        for datagram in [register("snail"), peer(a), punch("snail")] {
+            assert!(Packet::decode(datagram).is_err());
+        }
+    }
+
+    #[test]
+    fn peers_meet_and_transfer() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-rendezvous", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("snail.txt"), b"met at the rendezvous").unwrap();
+
+        let mut server = RendezvousServer::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            let sender = receiver
+                .rendezvous(server_addr, "snail", Duration::from_secs(10))
+                .unwrap();
+            (sender, receiver.recv_file_blocking(out).unwrap().peer)
+        });
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = sender
+            .rendezvous(server_addr, "snail", Duration::from_secs(10))
+            .unwrap();
+        sender
+            .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+            .unwrap();
+
+        let (punched, peer) = recv.join().unwrap();
+        assert_eq!(punched, sender.local_addr().unwrap());
+        assert_eq!(peer, punched);
+        assert_eq!(
+            std::fs::read(dir.join("out/snail.txt")).unwrap(),
+            b"met at the rendezvous"
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use crate::{error::Result, meta::Verdict, timestamp::QueueingDelay};
+
+/// file sent by `send_matching_blocking` or `send_dir_blocking` and its
+/// report or error
+pub type SendOutcome = (PathBuf, Result<SendReport>);
+
+/// summary of a sent file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SendReport {
+    /// file name announced to the receiver
+    pub file_name: String,
+    /// receiver, or the group of a multicast transfer
+    pub peer: SocketAddr,
+    /// payload bytes sent, without those a resuming receiver already held
+    pub bytes: usize,
+    /// from the syn to the finack of the last attempt
+    pub duration: Duration,
+    /// mean time from sending a packet to its ack, packets sent more than
+    /// once are left out, `None` if none was acknowledged at first try
+    pub mean_rtt: Option<Duration>,
+    /// time the data spent queued on the way, `None` unless the receiver
+    /// accepted timestamps, see `SecSnailSocketBuilder::timestamps`
+    pub queueing_delay: Option<QueueingDelay>,
+    /// whether the receiver holds an identical file, only set by
+    /// `SecSnailSocket::verify_file_blocking`
+    pub verdict: Option<Verdict>,
+    /// entries of a sent manifest the receiver misses or holds stale, only
+    /// set by `SecSnailSocket::sync_dir_blocking`, or blocks of a file
+    /// which were sent by `SecSnailSocket::send_file_delta_blocking`
+    pub missing: Option<Vec<usize>>,
+    /// name the receiver stored the file under because one of the announced
+    /// name existed, see `OverwritePolicy::RenameWithSuffix`
+    pub stored_as: Option<String>,
+    /// packets and bytes on the wire, retransmissions, timeouts and attempts
+    pub stats: TransferStats,
+}
+
+impl SendReport {
+    /// payload bytes per second
+    pub fn goodput(&self) -> f64 {
+        match self.duration.as_secs_f64() {
+            0.0 => 0.0,
+            secs => self.bytes as f64 / secs,
+        }
+    }
+
+    /// single line json object, durations in seconds, e.g. for scripts
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"file_name":{},"peer":{},"bytes":{},"duration":{},"goodput":{},"mean_rtt":{},"verdict":{},"stats":{}}}"#,
+            json_str(&self.file_name),
+            json_str(&self.peer.to_string()),
+            self.bytes,
+            self.duration.as_secs_f64(),
+            self.goodput(),
+            json_opt(self.mean_rtt.map(|d| d.as_secs_f64())),
+            json_opt(self.verdict.map(|v| json_str(&v.to_string()))),
+            self.stats.to_json()
+        )
+    }
+}
+
+/// state of a running send, see `SecSnailSocket::set_progress_callback`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Progress {
+    /// file bytes sent so far, the packet in flight included
+    pub bytes: usize,
+    /// size of the file, `None` for a stream
+    pub total: Option<u64>,
+    pub retransmissions: usize,
+}
+
+/// summary of a received file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransferReport {
+    /// file name announced by the sender
+    pub file_name: String,
+    /// path the file was written to, renamed if the policy is
+    /// `OverwritePolicy::RenameWithSuffix`, `None` if it was written into a
+    /// writer
+    pub path: Option<PathBuf>,
+    pub peer: SocketAddr,
+    pub bytes: usize,
+    /// bytes kept from an interrupted transfer, included in `bytes`
+    pub resumed_from: u64,
+    /// the sender only verified its file, nothing was written, the
+    /// verdict on the file held under `file_name`
+    pub verified: Option<Verdict>,
+    /// from the syn to the fin
+    pub duration: Duration,
+    /// acks sent again because the sender retransmitted a packet
+    pub retransmitted_acks: usize,
+    /// entries of a received manifest missing in the target dir, see
+    /// `SecSnailSocket::sync_dir_blocking`
+    pub missing: Option<usize>,
+    pub stats: TransferStats,
+}
+
+/// counters of a single transfer, on the sending or the receiving side
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransferStats {
+    /// packets sent, including retransmissions
+    pub packets_sent: usize,
+    /// packets sent again, data after a timeout or acks for a duplicate
+    pub retransmissions: usize,
+    /// acks of an already acknowledged packet at the sender, data packets
+    /// received again at the receiver
+    pub duplicates_received: usize,
+    /// received datagrams dropped for a bad checksum or format
+    pub corrupt_dropped: usize,
+    /// expired retransmission or connection timers
+    pub timeouts: usize,
+    /// data acks flagging a receiver which falls behind, see
+    /// `SecSnailSocketBuilder::congestion_threshold`
+    pub congestion_signals: usize,
+    /// flushes of a received file to disk, see `SecSnailSocketBuilder::checkpoints`
+    pub checkpoints: usize,
+    /// time the sender held data packets back, for a receiver falling
+    /// behind or `SecSnailSocketBuilder::min_packet_gap`
+    pub paced: Duration,
+    /// size of all sent packets including header, before the impairment
+    pub bytes_on_wire: usize,
+    /// file bytes transferred
+    pub payload_bytes: usize,
+    /// whole-transfer attempts of the sender, the counters above are those
+    /// of the last one, see `SecSnailSocketBuilder::transfer_retry_policy`,
+    /// 0 at the receiver
+    pub attempts: usize,
+    /// events without a transition in the current state, see `Strictness`
+    pub protocol_violations: usize,
+    /// checksums were skipped after the syn, see `SecSnailSocketBuilder::trusted_link`
+    pub trusted_link: bool,
+}
+
+impl TransferReport {
+    /// single line json object, durations in seconds, e.g. for scripts
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"file_name":{},"path":{},"peer":{},"bytes":{},"resumed_from":{},"verified":{},"duration":{},"retransmitted_acks":{},"stats":{}}}"#,
+            json_str(&self.file_name),
+            json_opt(
+                self.path
+                    .as_ref()
+                    .map(|p| json_str(&p.display().to_string()))
+            ),
+            json_str(&self.peer.to_string()),
+            self.bytes,
+            self.resumed_from,
+            json_opt(self.verified.map(|v| json_str(&v.to_string()))),
+            self.duration.as_secs_f64(),
+            self.retransmitted_acks,
+            self.stats.to_json()
+        )
+    }
+}
+
+impl TransferStats {
+    /// single line json object of all counters
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"packets_sent":{},"retransmissions":{},"duplicates_received":{},"corrupt_dropped":{},"timeouts":{},"congestion_signals":{},"checkpoints":{},"paced":{},"bytes_on_wire":{},"payload_bytes":{},"attempts":{},"protocol_violations":{},"trusted_link":{}}}"#,
+            self.packets_sent,
+            self.retransmissions,
+            self.duplicates_received,
+            self.corrupt_dropped,
+            self.timeouts,
+            self.congestion_signals,
+            self.checkpoints,
+            self.paced.as_secs_f64(),
+            self.bytes_on_wire,
+            self.payload_bytes,
+            self.attempts,
+            self.protocol_violations,
+            self.trusted_link
+        )
+    }
+}
+
+/// counters of all transfers of a socket since it was created or its
+/// stats were reset, see `SecSnailSocket::stats_snapshot`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SocketStats {
+    /// transfers started but not ended yet, kept by a reset
+    pub active: usize,
+    /// files or streams sent, also failed ones
+    pub sends: usize,
+    /// files received, also failed ones
+    pub receives: usize,
+    /// counters of all sends added up, `trusted_link` if any was
+    pub sent: TransferStats,
+    /// counters of all receives added up, `trusted_link` if any was
+    pub received: TransferStats,
+}
+
+impl SocketStats {
+    pub(super) fn record_send(&mut self, stats: &TransferStats) {
+        self.sends += 1;
+        self.sent.add(stats);
+    }
+
+    pub(super) fn record_recv(&mut self, stats: &TransferStats) {
+        self.receives += 1;
+        self.received.add(stats);
+    }
+}
+
+impl TransferStats {
+    fn add(&mut self, other: &TransferStats) {
+        self.packets_sent += other.packets_sent;
+        self.retransmissions += other.retransmissions;
+        self.duplicates_received += other.duplicates_received;
+        self.corrupt_dropped += other.corrupt_dropped;
+        self.timeouts += other.timeouts;
+        self.congestion_signals += other.congestion_signals;
+        self.checkpoints += other.checkpoints;
+        self.paced += other.paced;
+        self.bytes_on_wire += other.bytes_on_wire;
+        self.payload_bytes += other.payload_bytes;
+        self.attempts += other.attempts;
+        self.protocol_violations += other.protocol_violations;
+        self.trusted_link |= other.trusted_link;
+    }
+}
+
+/// json array of the path and the report or error of every outcome, see
+/// `SendReport::to_json`
+pub fn outcomes_to_json(outcomes: &[SendOutcome]) -> String {
+    let rows: Vec<_> = outcomes
+        .iter()
+        .map(|(path, r)| {
+            let path = json_str(&path.display().to_string());
+            match r {
+                Ok(report) => format!(r#"{{"path":{path},"report":{}}}"#, report.to_json()),
+                Err(e) => format!(r#"{{"path":{path},"error":{}}}"#, json_str(&e.to_string())),
+            }
+        })
+        .collect();
+    format!("[{}]", rows.join(","))
+}
+
+/// `s` as a quoted json string
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// `null` for `None`
+fn json_opt<T: std::fmt::Display>(v: Option<T>) -> String {
+    v.map_or_else(|| "null".to_string(), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_as_json() {
+        let report = TransferReport {
+            file_name: "a \"snail\".txt".to_string(),
+            path: None,
+            peer: "127.0.0.1:4000".parse().unwrap(),
+            bytes: 3,
+            resumed_from: 0,
+            verified: Some(Verdict::Identical),
+            duration: Duration::from_millis(1500),
+            retransmitted_acks: 0,
+            missing: None,
+            stats: TransferStats::default(),
+        };
+        let json = report.to_json();
+        assert!(
+            json.starts_with(
+                r#"{"file_name":"a \"snail\".txt","path":null,"peer":"127.0.0.1:4000""#
+            )
+        );
+        assert!(json.contains(r#""verified":"identical","duration":1.5,"#));
+        assert!(json.ends_with(r#""protocol_violations":0,"trusted_link":false}}"#));
+        assert_eq!(json_str("tab\t\u{1}"), r#""tab\t\u0001""#);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        let report = SendReport {
+            file_name: "snail.txt".to_string(),
+            peer: "127.0.0.1:55055".parse().unwrap(),
+            bytes: 42,
+            duration: Duration::from_millis(7),
+            mean_rtt: None,
+            queueing_delay: None,
+            verdict: Some(Verdict::Missing),
+            missing: None,
+            stored_as: None,
+            stats: TransferStats {
+                packets_sent: 3,
+                ..TransferStats::default()
+            },
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<SendReport>(&json).unwrap(), report);
+    }
+}
@@ -0,0 +1,114 @@
+//! Stopping a receiving socket from another thread.
+//!
+//! The stop flag is checked whenever the socket waits for a new session,
+//! an open transfer is always finished. A socket blocked in a receive is
+//! woken up by a datagram sent to itself, which the leading `S` makes
+//! undecodable as a packet like a discovery probe.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use crate::error::Result;
+
+const WAKEUP_MAGIC: &[u8] = b"SNAIL.";
+
+pub(super) fn is_wakeup(buf: &[u8]) -> bool {
+    buf == WAKEUP_MAGIC
+}
+
+/// stops the receiving of a socket, see `SecSnailSocket::shutdown_handle`
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    stop: Arc<AtomicBool>,
+    /// where the socket receives, `None` if it can not be woken up
+    wakeup_addr: Option<SocketAddr>,
+}
+
+impl ShutdownHandle {
+    pub(super) fn new(stop: Arc<AtomicBool>, local_addr: Option<SocketAddr>) -> ShutdownHandle {
+        let wakeup_addr = local_addr.map(|mut addr| {
+            match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+                IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+                _ => {}
+            }
+            addr
+        });
+        ShutdownHandle { stop, wakeup_addr }
+    }
+
+    /// stop accepting new sessions, the socket returns once the open
+    /// transfers are finished
+    ///
+    /// `recv_file_blocking` and `accept` fail with `SecSnailError::Shutdown`
+    /// afterwards, `serve` returns and `incoming` ends
+    pub fn shutdown(&self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(addr) = self.wakeup_addr {
+            let unspecified: SocketAddr = match addr {
+                SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+            };
+            UdpSocket::bind(unspecified)?.send_to(WAKEUP_MAGIC, addr)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, ops::ControlFlow, thread};
+
+    use crate::{error::SecSnailError, sock::SecSnailSocket};
+
+    #[test]
+    fn wakes_up_blocked_receive() {
+        let mut receiver = SecSnailSocket::bind("0.0.0.0:0").unwrap();
+        let handle = receiver.shutdown_handle();
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-shutdown", std::process::id()));
+        let recv = thread::spawn(move || receiver.recv_file_blocking(dir));
+
+        handle.shutdown().unwrap();
+        assert!(handle.is_shutdown());
+        assert!(matches!(recv.join().unwrap(), Err(SecSnailError::Shutdown)));
+    }
+
+    #[test]
+    fn serve_returns_after_shutdown() {
+        let dir =
+            std::env::temp_dir().join(format!("secsnail-{}-shutdown-serve", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("snail.txt"), vec![5; 4000]).unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let handle = receiver.shutdown_handle();
+        let out = dir.join("out");
+        let serve = thread::spawn(move || {
+            let mut received = 0;
+            receiver
+                .serve(out, |_| {
+                    received += 1;
+                    ControlFlow::Continue(())
+                })
+                .map(|_| received)
+        });
+
+        let mut sender = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_file_to_blocking(dir.join("snail.txt"), recv_addr)
+            .unwrap();
+        handle.shutdown().unwrap();
+        assert_eq!(serve.join().unwrap().unwrap(), 1);
+    }
+}
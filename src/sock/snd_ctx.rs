@@ -0,0 +1,894 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        Arc, Mutex, PoisonError,
+        mpsc::{Receiver, RecvTimeoutError},
+    },
+    time::{Duration, Instant},
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    delta::{SIGNATURE_NAME, Signature},
+    error::{Result, SecSnailError},
+    feedback::{Pacer, decode_data_ack},
+    fsm_send::{self, fsm::SndEvent},
+    manifest::{MANIFEST_NAME, ManifestEntry, decode_missing, encode_manifest},
+    meta::{
+        Digest, SynMeta, Verdict, check_file_name, check_relative_path, decode_syn_ack,
+        decode_verdict, file_digest, utf8_file_name,
+    },
+    pck::{Flag, Packet},
+    timestamp::{DelayEstimator, TIMESTAMP_LEN, micros, stamp},
+    util::u8_to_bool,
+};
+
+use super::{
+    DatagramTransport, Progress, RecvResult, SecSnailSocket, SendReport, TransferStats,
+    clamp_to_deadline, expired, journal::Journal, on_trusted_link, prefetch::Prefetch,
+};
+
+/// what a send transfer reads its data from
+enum Source {
+    File(File),
+    /// a stream of unknown size, e.g. stdin, the mutex only keeps the
+    /// socket `Sync` and is never locked
+    Stream(Mutex<Box<dyn Read + Send>>),
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::File(f) => f.read(buf),
+            Source::Stream(r) => r
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner)
+                .read(buf),
+        }
+    }
+}
+
+impl Seek for Source {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Source::File(f) => f.seek(pos),
+            Source::Stream(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "can not seek in a stream",
+            )),
+        }
+    }
+}
+
+/// how a send transfer takes its payloads from the source
+enum Reader {
+    Buffered(BufReader<Source>),
+    /// a file mapped into memory and the offset of the next payload,
+    /// payloads are sliced out of the mapping without a read buffer
+    Mapped(Mmap, usize),
+    /// a thread reads ahead, started once the syn is acked
+    Prefetched(Prefetch),
+}
+
+/// state of a single send transfer, owned independently of the socket
+/// so a transfer can be suspended between polls or driven asynchronously
+pub(super) struct SendSession {
+    timeout: Duration,
+    timer_start: Option<Instant>,
+    recv_addr: SocketAddr,
+    /// tells the concurrent transfers of the socket apart, see `concurrent`
+    transfer_id: u8,
+    reader: Reader,
+    file_name: String,
+    /// `None` for a stream
+    file_size: Option<u64>,
+    data_counter: usize,
+    deadline: Option<Instant>,
+    stats: TransferStats,
+    /// last packet put on the wire, sending it again is a retransmission
+    last_sent: Option<Packet>,
+    /// offer the receiver to resume a partial file
+    resume: bool,
+    /// bytes the receiver held of a resumed file
+    offset: u64,
+    /// journal and entry the acknowledged bytes are noted in
+    journal: Option<(Arc<Journal>, u64)>,
+    /// chunks read ahead, `0` reads them on demand
+    read_ahead: usize,
+    /// ask the receiver for a trusted link without checksums
+    offer_trusted: bool,
+    /// ask the receiver to echo timestamps, see `timestamp`
+    offer_timestamps: bool,
+    /// the receiver accepted timestamps, which count from `clock_origin`
+    timestamps: bool,
+    clock_origin: Option<Instant>,
+    delays: DelayEstimator,
+    /// the syn was acked, later acks carry feedback, see `feedback`
+    established: bool,
+    pacer: Pacer,
+    /// entries of the manifest of a sync which is sent
+    manifest: Option<usize>,
+    /// blocks of the signature of a delta transfer which is sent
+    signature: Option<usize>,
+    /// the data is a signature or the missing blocks of a file, see `delta`
+    delta: bool,
+    /// entries of the manifest or blocks of the signature the receiver
+    /// asked for
+    missing: Option<Vec<usize>>,
+    /// name the receiver stored the file under instead of the announced one
+    stored_as: Option<String>,
+    /// `file_name` is a path relative to the target directory
+    relative_path: bool,
+    /// announce no name, `file_name` is only reported locally
+    anonymous: bool,
+    /// digest of a file which is only verified, not sent
+    digest: Option<Digest>,
+    /// verdict of the receiver on a verified file
+    verdict: Option<Verdict>,
+    /// when the packet in flight was sent, `None` once it was retransmitted
+    sent_at: Option<Instant>,
+    rtt_sum: Duration,
+    rtt_samples: u32,
+}
+
+impl SendSession {
+    pub fn new<P: AsRef<Path>>(recv_addr: SocketAddr, path: P, timeout: Duration) -> Result<Self> {
+        // file io
+        let path = path.as_ref();
+        let file_name = path.file_name().map(utf8_file_name).ok_or_else(|| {
+            SecSnailError::InvalidFilename(format!("{} has no file name", path.display()))
+        })?;
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        Ok(SendSession::with_source(
+            recv_addr,
+            Source::File(file),
+            file_name,
+            Some(file_size),
+            timeout,
+        ))
+    }
+
+    /// send everything `reader` yields until its end under `file_name`,
+    /// the receiver does not learn the size in advance
+    pub fn from_reader<R: Read + Send + 'static>(
+        recv_addr: SocketAddr,
+        reader: R,
+        file_name: &str,
+        timeout: Duration,
+    ) -> Result<Self> {
+        check_file_name(file_name)?;
+        Ok(SendSession::with_source(
+            recv_addr,
+            Source::Stream(Mutex::new(Box::new(reader))),
+            file_name.to_string(),
+            None,
+            timeout,
+        ))
+    }
+
+    /// send the manifest of a sync, see `manifest`
+    pub fn from_manifest(
+        recv_addr: SocketAddr,
+        entries: &[ManifestEntry],
+        timeout: Duration,
+    ) -> Result<Self> {
+        let manifest = encode_manifest(entries);
+        let mut session = SendSession::with_source(
+            recv_addr,
+            Source::Stream(Mutex::new(Box::new(io::Cursor::new(manifest.clone())))),
+            MANIFEST_NAME.to_string(),
+            Some(manifest.len() as u64),
+            timeout,
+        );
+        session.manifest = Some(entries.len());
+        Ok(session)
+    }
+
+    /// send the signature of a delta transfer, see `delta`
+    pub fn from_signature(
+        recv_addr: SocketAddr,
+        signature: &Signature,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let data = signature.encode();
+        let mut session = SendSession::with_source(
+            recv_addr,
+            Source::Stream(Mutex::new(Box::new(io::Cursor::new(data.clone())))),
+            SIGNATURE_NAME.to_string(),
+            Some(data.len() as u64),
+            timeout,
+        );
+        session.signature = Some(signature.blocks.len());
+        session.delta = true;
+        Ok(session)
+    }
+
+    /// send the `len` bytes of the blocks `reader` yields which the
+    /// receiver of the signature of `name` misses
+    pub fn from_missing_blocks<R: Read + Send + 'static>(
+        recv_addr: SocketAddr,
+        reader: R,
+        name: &str,
+        len: u64,
+        timeout: Duration,
+    ) -> Result<Self> {
+        check_file_name(name)?;
+        let mut session = SendSession::with_source(
+            recv_addr,
+            Source::Stream(Mutex::new(Box::new(reader))),
+            name.to_string(),
+            Some(len),
+            timeout,
+        );
+        session.delta = true;
+        Ok(session)
+    }
+
+    fn with_source(
+        recv_addr: SocketAddr,
+        source: Source,
+        file_name: String,
+        file_size: Option<u64>,
+        timeout: Duration,
+    ) -> Self {
+        SendSession {
+            timer_start: None,
+            file_name,
+            file_size,
+            recv_addr,
+            transfer_id: 0,
+            reader: Reader::Buffered(BufReader::new(source)),
+            timeout,
+            data_counter: 0,
+            deadline: None,
+            stats: TransferStats::default(),
+            last_sent: None,
+            resume: false,
+            offset: 0,
+            journal: None,
+            read_ahead: 0,
+            offer_trusted: false,
+            offer_timestamps: false,
+            timestamps: false,
+            clock_origin: None,
+            delays: DelayEstimator::default(),
+            established: false,
+            pacer: Pacer::default(),
+            manifest: None,
+            signature: None,
+            delta: false,
+            missing: None,
+            stored_as: None,
+            relative_path: false,
+            anonymous: false,
+            digest: None,
+            verdict: None,
+            sent_at: None,
+            rtt_sum: Duration::ZERO,
+            rtt_samples: 0,
+        }
+    }
+
+    /// announce `name` instead of the local file name
+    pub fn with_remote_name(mut self, name: &str) -> Result<Self> {
+        check_file_name(name)?;
+        self.file_name = name.to_string();
+        Ok(self)
+    }
+
+    /// announce `path` relative to the target directory of the receiver
+    pub fn with_remote_path(mut self, path: &str) -> Result<Self> {
+        check_relative_path(path)?;
+        self.file_name = path.to_string();
+        self.relative_path = true;
+        Ok(self)
+    }
+
+    /// announce no name, the receiver names the file itself
+    pub fn with_anonymous(mut self) -> Self {
+        self.anonymous = true;
+        self.relative_path = false;
+        self
+    }
+
+    /// note the acknowledged bytes in `journal` as entry `id`
+    pub fn with_journal(mut self, journal: Arc<Journal>, id: u64) -> Self {
+        self.journal = Some((journal, id));
+        self
+    }
+
+    /// name a journaled send is resumed under, `None` for a send which is
+    /// not journaled as it can not be resumed the same way
+    pub fn journaled_name(&self) -> Option<&str> {
+        let plain = !self.anonymous && !self.relative_path && self.digest.is_none();
+        (plain && self.file_size.is_some()).then_some(self.file_name.as_str())
+    }
+
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// ask the receiver to skip checksums after the syn, a stream has no
+    /// size to announce the flags with and is always checked
+    pub fn with_trusted_link(mut self, trusted: bool) -> Self {
+        self.offer_trusted = trusted && self.file_size.is_some();
+        self
+    }
+
+    /// stamp data packets with the time they are sent if the receiver
+    /// agrees, like a trusted link only offered with a size
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.offer_timestamps = timestamps && self.file_size.is_some();
+        self
+    }
+
+    /// read up to `chunks` payloads ahead in a background thread, a
+    /// mapped file is not read ahead
+    pub fn with_read_ahead(mut self, chunks: usize) -> Self {
+        self.read_ahead = chunks;
+        self
+    }
+
+    /// wait at least `gap` between two sent packets
+    pub fn with_min_gap(mut self, gap: Duration) -> Self {
+        self.pacer = Pacer::with_min_gap(gap);
+        self
+    }
+
+    /// read a file through a memory mapping instead of a buffered reader,
+    /// streams and empty files are read as before
+    ///
+    /// the file must not be truncated while it is sent, reading a page
+    /// beyond its end kills the process with `SIGBUS`
+    pub fn with_mmap(mut self, mmap: bool) -> Result<Self> {
+        let Reader::Buffered(redr) = &self.reader else {
+            return Ok(self);
+        };
+        match redr.get_ref() {
+            // SAFETY: the mapping is only read, a file modified while it is
+            // sent yields a corrupt copy like a buffered read would
+            Source::File(file) if mmap && self.file_size != Some(0) => {
+                self.reader = Reader::Mapped(unsafe { Mmap::map(file)? }, 0);
+            }
+            _ => {}
+        }
+        Ok(self)
+    }
+
+    /// only ask the receiver whether it holds an identical file, no data
+    /// is sent
+    pub fn with_verify(mut self) -> Result<Self> {
+        match &mut self.reader {
+            Reader::Buffered(redr) => {
+                let Source::File(file) = redr.get_mut() else {
+                    return Err(SecSnailError::InvalidConfig(
+                        "a stream can not be verified".to_string(),
+                    ));
+                };
+                self.digest = Some(file_digest(&*file)?);
+                file.seek(SeekFrom::Start(0))?;
+            }
+            Reader::Mapped(map, _) => self.digest = Some(file_digest(&map[..])?),
+            Reader::Prefetched(_) => unreachable!("read ahead starts with the ack of the syn"),
+        }
+        Ok(self)
+    }
+
+    pub fn syn_acked(&mut self, payload: &[u8]) -> Result<()> {
+        if self.digest.is_some() {
+            self.verdict = Some(decode_verdict(payload)?);
+            return Ok(());
+        }
+        let ack = decode_syn_ack(payload)?;
+        if ack.trusted && !self.offer_trusted {
+            return Err(SecSnailError::ProtocolViolation(
+                "receiver accepted a trusted link which was not offered".to_string(),
+            ));
+        }
+        if ack.timestamps && !self.offer_timestamps {
+            return Err(SecSnailError::ProtocolViolation(
+                "receiver accepted timestamps which were not offered".to_string(),
+            ));
+        }
+        self.stats.trusted_link = ack.trusted;
+        self.timestamps = ack.timestamps;
+        self.established = true;
+        if ack.offset != 0 {
+            self.resume_at(ack.offset)?;
+        }
+        self.start_read_ahead()
+    }
+
+    /// the finack of a manifest tells the entries the receiver misses
+    pub fn fin_acked(&mut self, payload: &[u8]) -> Result<()> {
+        if let Some(entries) = self.manifest {
+            self.missing = Some(decode_missing(payload, entries)?);
+        }
+        // a receiver which does not know signatures answers without bitmap
+        if let Some(blocks) = self.signature {
+            self.missing = decode_missing(payload, blocks).ok();
+        } else if self.manifest.is_none() && !payload.is_empty() {
+            self.stored_as = Some(String::from_utf8_lossy(payload).into_owned());
+        }
+        Ok(())
+    }
+
+    /// a received packet, not verified on a trusted link
+    pub fn received(&self, rcvpkt: Option<Packet>) -> Option<Packet> {
+        on_trusted_link(rcvpkt, self.stats.trusted_link)
+    }
+
+    /// hand the reader to a prefetch thread, at the offset the receiver
+    /// resumes at
+    fn start_read_ahead(&mut self) -> Result<()> {
+        if self.read_ahead == 0 || !matches!(self.reader, Reader::Buffered(_)) {
+            return Ok(());
+        }
+        // an empty stream stands in until the prefetch owns the reader
+        let placeholder = BufReader::new(Source::Stream(Mutex::new(Box::new(io::empty()))));
+        let Reader::Buffered(redr) =
+            std::mem::replace(&mut self.reader, Reader::Buffered(placeholder))
+        else {
+            unreachable!()
+        };
+        self.reader = Reader::Prefetched(Prefetch::spawn(redr, self.chunk_len(), self.read_ahead)?);
+        Ok(())
+    }
+
+    /// skip the first `offset` bytes the receiver already holds
+    fn resume_at(&mut self, offset: u64) -> Result<()> {
+        // a stream is sent without size, so resume is never offered
+        let size = self.file_size.filter(|_| self.resume);
+        if size.is_none_or(|size| offset > size) {
+            return Err(SecSnailError::ProtocolViolation(format!(
+                "receiver resumed at {offset} of {size:?} bytes"
+            )));
+        }
+        match &mut self.reader {
+            Reader::Buffered(redr) => _ = redr.seek(SeekFrom::Start(offset))?,
+            Reader::Mapped(_, pos) => *pos = offset as usize,
+            Reader::Prefetched(_) => unreachable!("read ahead starts after resuming"),
+        }
+        tracing::info!(offset, "receiver resumes partial file");
+        self.offset = offset;
+        Ok(())
+    }
+
+    /// limit the whole transfer, starting at `now`
+    pub fn with_transfer_deadline(
+        mut self,
+        transfer_deadline: Option<Duration>,
+        now: Instant,
+    ) -> Self {
+        self.deadline = transfer_deadline.map(|d| now + d);
+        self
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn recv_addr(&self) -> SocketAddr {
+        self.recv_addr
+    }
+
+    /// stamp every packet with `transfer_id`, the receiver echoes it
+    pub fn with_transfer_id(mut self, transfer_id: u8) -> Self {
+        self.transfer_id = transfer_id;
+        self
+    }
+
+    pub fn transfer_id(&self) -> u8 {
+        self.transfer_id
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// never call this function if timer is not started
+    pub fn timer_start(&self) -> Instant {
+        self.timer_start.unwrap()
+    }
+
+    pub fn start_timer(&mut self, now: Instant) -> Instant {
+        *self.timer_start.insert(now)
+    }
+
+    pub fn stop_timer(&mut self) {
+        self.timer_start.take();
+    }
+
+    pub fn data_available(&mut self) -> Result<bool> {
+        if self.digest.is_some() {
+            return Ok(false);
+        }
+        match &mut self.reader {
+            Reader::Buffered(redr) => Ok(!redr.fill_buf()?.is_empty()),
+            Reader::Mapped(map, pos) => Ok(*pos < map.len()),
+            Reader::Prefetched(prefetch) => Ok(prefetch.data_available()?),
+        }
+    }
+
+    /// file bytes per data packet, less the timestamp
+    fn chunk_len(&self) -> usize {
+        match self.timestamps {
+            true => Packet::max_pck_payload_size() - TIMESTAMP_LEN,
+            false => Packet::max_pck_payload_size(),
+        }
+    }
+
+    /// data packets are stamped with `now` if the receiver accepted
+    /// timestamps
+    pub fn make_pkt(&mut self, seq_n: u8, f: Flag, now: Instant) -> Result<Packet> {
+        let chunk_len = self.chunk_len();
+        let mut payload: Vec<u8> = match f {
+            Flag::Data => match &mut self.reader {
+                Reader::Buffered(redr) => {
+                    let mut buf: Vec<u8> = vec![0; chunk_len];
+                    let n = redr.read(&mut buf)?;
+
+                    let slice: &[u8] = &buf[..n];
+                    slice.to_vec()
+                }
+                Reader::Mapped(map, pos) => {
+                    let end = map.len().min(*pos + chunk_len);
+                    let payload = map[*pos..end].to_vec();
+                    *pos = end;
+                    payload
+                }
+                Reader::Prefetched(prefetch) => prefetch.next_chunk()?,
+            },
+            Flag::SYN => {
+                // init data: file_name and file_size
+                SynMeta {
+                    file_name: match self.anonymous {
+                        true => String::new(),
+                        false => self.file_name.clone(),
+                    },
+                    file_size: self.file_size,
+                    resume: self.resume,
+                    digest: self.digest,
+                    trusted: self.offer_trusted,
+                    manifest: self.manifest.is_some(),
+                    relative_path: self.relative_path,
+                    timestamps: self.offer_timestamps,
+                    delta: self.delta,
+                }
+                .encode()
+            }
+
+            // ACK, FIN, FINACK
+            _ => vec![],
+        };
+        if f == Flag::Data && self.timestamps {
+            let origin = *self.clock_origin.get_or_insert(now);
+            payload = stamp(micros(origin, now), &payload);
+        }
+
+        let pck = match self.stats.trusted_link && f != Flag::SYN {
+            true => Packet::new_unchecked(u8_to_bool(seq_n), f, payload)?,
+            false => Packet::new(u8_to_bool(seq_n), f, payload)?,
+        };
+        Ok(pck.with_transfer_id(self.transfer_id))
+    }
+
+    pub fn data_counter(&self) -> usize {
+        self.data_counter
+    }
+
+    /// `n` payload bytes of a data packet, its timestamp not counted
+    pub fn increase_data_counter(&mut self, n: usize) {
+        self.data_counter += match self.timestamps {
+            true => n.saturating_sub(TIMESTAMP_LEN),
+            false => n,
+        };
+    }
+
+    pub fn record_sent(&mut self, pck: &Packet, now: Instant) {
+        self.pacer.sent(now);
+        self.stats.packets_sent += 1;
+        self.stats.bytes_on_wire += pck.encode().len();
+        if self.last_sent.as_ref() == Some(pck) {
+            self.stats.retransmissions += 1;
+            // the ack could answer any of the copies
+            self.sent_at = None;
+        } else {
+            self.last_sent = Some(pck.clone());
+            self.sent_at = Some(now);
+        }
+    }
+
+    pub fn record_event(&mut self, event: &SndEvent, now: Instant) {
+        match event {
+            SndEvent::Timeout => self.stats.timeouts += 1,
+            SndEvent::RecvPck(Some(rcvpkt)) if rcvpkt.notcorrupt() => {
+                if !(rcvpkt.is_ACK() || rcvpkt.is_FINACK()) {
+                    return;
+                }
+                let in_flight_n = self.last_sent.as_ref().map(Packet::n);
+                if in_flight_n.is_some_and(|n| n != rcvpkt.n()) {
+                    // ack of the previous packet
+                    self.stats.duplicates_received += 1;
+                    return;
+                }
+                let (echo, congested) = match self.established && rcvpkt.is_ACK() {
+                    true => decode_data_ack(rcvpkt.payload()),
+                    false => (None, false),
+                };
+                if self.established {
+                    self.stats.congestion_signals += usize::from(congested);
+                    self.pacer.acked(congested, now);
+                }
+                if let Some((journal, id)) = &self.journal
+                    && let Err(e) =
+                        journal.progress(*id, self.offset + self.data_counter as u64, || Ok(()))
+                {
+                    tracing::warn!(error = %e, "failed to update the journal");
+                }
+                if let Some(sent_at) = self.sent_at.take() {
+                    self.rtt_sum += now.saturating_duration_since(sent_at);
+                    self.rtt_samples += 1;
+                    // a retransmitted packet keeps its stamp, like its rtt
+                    // its delay is left out
+                    if let Some((sent, received)) = echo.filter(|_| self.timestamps) {
+                        self.delays.record(sent, received);
+                    }
+                }
+            }
+            SndEvent::RecvPck(_) => self.stats.corrupt_dropped += 1,
+            SndEvent::InitSYN | SndEvent::DataAvailable(_) => {}
+        }
+    }
+
+    /// earliest time to send the next data packet, see `feedback`
+    pub fn next_send(&self) -> Option<Instant> {
+        self.pacer.next_send()
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            bytes: self.data_counter,
+            total: self.file_size,
+            retransmissions: self.stats.retransmissions,
+        }
+    }
+
+    /// a data packet was held back for `waited`, see `feedback`
+    pub fn record_paced(&mut self, waited: Duration) {
+        self.stats.paced += waited;
+    }
+
+    pub fn record_violation(&mut self) {
+        self.stats.protocol_violations += 1;
+    }
+
+    pub fn report(&self, duration: Duration) -> SendReport {
+        SendReport {
+            file_name: self.file_name.clone(),
+            peer: self.recv_addr,
+            bytes: self.data_counter,
+            duration,
+            mean_rtt: (self.rtt_samples > 0).then(|| self.rtt_sum / self.rtt_samples),
+            queueing_delay: self.delays.queueing_delay(),
+            verdict: self.verdict,
+            missing: self.missing.clone(),
+            stored_as: self.stored_as.clone(),
+            stats: self.stats(),
+        }
+    }
+
+    pub fn stats(&self) -> TransferStats {
+        TransferStats {
+            payload_bytes: self.data_counter,
+            attempts: 1,
+            ..self.stats
+        }
+    }
+}
+
+/// datagrams of a single transfer id and their sources, dispatched by
+/// another thread which receives from the socket
+pub(super) type Inbox = Receiver<(SocketAddr, Option<Packet>)>;
+
+pub(super) struct SendProtocolIoContext<'a, T: DatagramTransport> {
+    sock_ref: &'a SecSnailSocket<T>,
+    session: &'a mut SendSession,
+    inbox: Option<&'a Inbox>,
+}
+
+impl<'a, T: DatagramTransport> SendProtocolIoContext<'a, T> {
+    pub fn new(sock_ref: &'a SecSnailSocket<T>, session: &'a mut SendSession) -> Self {
+        Self {
+            sock_ref,
+            session,
+            inbox: None,
+        }
+    }
+
+    /// wait for the replies in `inbox` instead of the socket, see
+    /// `send_concurrently_blocking`
+    pub fn with_inbox(
+        sock_ref: &'a SecSnailSocket<T>,
+        session: &'a mut SendSession,
+        inbox: &'a Inbox,
+    ) -> Self {
+        Self {
+            sock_ref,
+            session,
+            inbox: Some(inbox),
+        }
+    }
+
+    /// next packet of the receiver and transfer id of the session
+    fn wait_for_reply(
+        &self,
+        timeout: Duration,
+        timer_start: Instant,
+        deadline: Option<Instant>,
+    ) -> Result<RecvResult> {
+        let recv_addr = self.session.recv_addr();
+        let Some(inbox) = self.inbox else {
+            return self.sock_ref.wait_for_incoming_or_timeout(
+                Some((recv_addr, self.session.transfer_id())),
+                false,
+                timeout,
+                timer_start,
+                deadline,
+            );
+        };
+        let (timeout, exceeds_deadline) = clamp_to_deadline(timer_start, timeout, deadline);
+        loop {
+            let remaining =
+                (timer_start + timeout).saturating_duration_since(self.sock_ref.inner.now());
+            match inbox.recv_timeout(remaining) {
+                Ok((src, rcvpkt)) if src == recv_addr => {
+                    return Ok(RecvResult::RecvPkt(rcvpkt, src));
+                }
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => return expired(exceeds_deadline),
+                // the dispatching thread stopped
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::Error::from(io::ErrorKind::ConnectionAborted).into());
+                }
+            }
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.session.progress()
+    }
+
+    /// send nothing until `until`, duplicate acks meanwhile are dropped,
+    /// a non-blocking socket fails with `WouldBlock` before
+    fn pause_until(&mut self, until: Instant) -> Result<()> {
+        loop {
+            let now = self.sock_ref.inner.now();
+            if now >= until {
+                return Ok(());
+            }
+            let r = self.wait_for_reply(until - now, now, None)?;
+            if let RecvResult::Timeout = r {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<T: DatagramTransport> fsm_send::fsm::ProtocolEventSource for SendProtocolIoContext<'_, T> {
+    fn wait_for_ack_or_timeout(&mut self) -> Result<SndEvent> {
+        let r = self.wait_for_reply(
+            self.session.timeout(),
+            self.session.timer_start(),
+            self.session.deadline(),
+        )?;
+        match r {
+            RecvResult::RecvPkt(rcvpkt, _) => Ok(SndEvent::RecvPck(self.session.received(rcvpkt))),
+            RecvResult::Timeout => Ok(SndEvent::Timeout),
+        }
+    }
+}
+
+impl<T: DatagramTransport> fsm_send::fsm::ProtocolIoContext for SendProtocolIoContext<'_, T> {
+    /// waits out the gap of a receiver which falls behind or the minimum
+    /// gap between packets first
+    fn data_available(&mut self) -> Result<bool> {
+        let available = self.session.data_available()?;
+        if available && let Some(until) = self.session.next_send() {
+            let start = self.now();
+            self.pause_until(until)?;
+            let waited = self.now().saturating_duration_since(start);
+            self.session.record_paced(waited);
+        }
+        Ok(available)
+    }
+
+    fn make_pkt(&mut self, seq_n: u8, f: Flag) -> Result<Packet> {
+        let now = self.now();
+        self.session.make_pkt(seq_n, f, now)
+    }
+
+    /// create start_timer instant and set read timeout to timeout Duration
+    fn start_timer(&mut self) -> Result<()> {
+        let timer_start = self.session.start_timer(self.sock_ref.inner.now());
+        // the dispatching thread owns the read timeout of the socket
+        if self.inbox.is_some() {
+            return Ok(());
+        }
+        // no timeout occures by starting timer
+        _ = self
+            .sock_ref
+            .update_udp_sock_timeout(timer_start, self.session.timeout())?;
+        Ok(())
+    }
+
+    fn stop_timer(&mut self) -> Result<()> {
+        self.session.stop_timer();
+        if self.inbox.is_some() {
+            return Ok(());
+        }
+        self.sock_ref
+            .inner
+            .set_read_timeout(Some(self.session.timeout()))?;
+        Ok(())
+    }
+
+    fn udt_send(&mut self, pck: &Packet) -> Result<()> {
+        self.sock_ref.emit("send", pck, self.session.recv_addr())?;
+        let now = self.now();
+        self.sock_ref.record_sent_packet(self.session, pck, now);
+        Ok(())
+    }
+
+    fn get_data_counter(&self) -> usize {
+        self.session.data_counter()
+    }
+
+    fn increase_data_counter(&mut self, n: usize) {
+        self.session.increase_data_counter(n);
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.session.deadline()
+    }
+
+    fn syn_acked(&mut self, payload: &[u8]) -> Result<()> {
+        self.session.syn_acked(payload)
+    }
+
+    fn fin_acked(&mut self, payload: &[u8]) -> Result<()> {
+        self.session.fin_acked(payload)
+    }
+
+    fn now(&self) -> Instant {
+        self.sock_ref.inner.now()
+    }
+
+    fn on_event(&mut self, event: &SndEvent) {
+        let now = self.now();
+        self.session.record_event(event, now);
+        self.sock_ref
+            .trace_event("send", event.name(), event.packet());
+    }
+
+    fn protocol_violation(&mut self, state: &'static str, event: &SndEvent) -> Result<()> {
+        self.session.record_violation();
+        self.sock_ref
+            .strictness
+            .check("send", state, event.name(), event.packet())
+    }
+
+    fn on_transition(&mut self, state: &'static str, event: &'static str, next: &'static str) {
+        self.sock_ref.record_transition("send", state, event, next);
+    }
+}
@@ -0,0 +1,78 @@
+//! One-way sync of a directory tree.
+//!
+//! The sender announces the files below a directory in a manifest, see
+//! `crate::manifest`, the receiver answers with the entries it misses or
+//! holds another version of, and only those are sent, each under its path
+//! relative to the directory. Files the receiver holds but the sender does
+//! not are left alone.
+
+use std::{net::SocketAddr, path::Path};
+
+use crate::{
+    error::{Result, SecSnailError},
+    manifest::{MAX_MANIFEST_ENTRIES, ManifestEntry},
+    meta::utf8_file_name,
+};
+
+use super::{
+    DatagramTransport, SecSnailSocket, SendOutcome, reject_case_collisions, snd_ctx::SendSession,
+    walk_dir,
+};
+
+impl<T: DatagramTransport> SecSnailSocket<T> {
+    /// make the target directory of `recv_addr` hold the files below `dir`
+    /// in the same tree, sending only the files it misses or holds stale
+    ///
+    /// # Return
+    /// the result of every file which was sent or could not be read, a file
+    /// which failed does not stop the others, fails if `dir` can not be read
+    /// or the receiver does not answer a manifest
+    pub fn sync_dir_blocking<P: AsRef<Path>>(
+        &mut self,
+        dir: P,
+        recv_addr: SocketAddr,
+    ) -> Result<Vec<SendOutcome>> {
+        let dir = dir.as_ref();
+        let _span =
+            tracing::info_span!("sync_dir", dir = %dir.display(), peer = %recv_addr).entered();
+        let mut files = Vec::new();
+        let mut results = Vec::new();
+        walk_dir(dir, &mut files, &mut results)?;
+        reject_case_collisions(&mut files, &mut results, |path| {
+            let relative = path.strip_prefix(dir).unwrap_or(path);
+            relative
+                .iter()
+                .map(utf8_file_name)
+                .collect::<Vec<_>>()
+                .join("/")
+        });
+        let mut entries = Vec::new();
+        for path in files {
+            match ManifestEntry::of_file(dir, &path) {
+                Ok(entry) => entries.push((path, entry)),
+                Err(e) => results.push((path, Err(e))),
+            }
+        }
+
+        for chunk in entries.chunks(MAX_MANIFEST_ENTRIES) {
+            let manifest: Vec<_> = chunk.iter().map(|(_, entry)| entry.clone()).collect();
+            let session =
+                SendSession::from_manifest(recv_addr, &manifest, self.snd_timeout_config)?
+                    .with_transfer_deadline(self.transfer_deadline, self.inner.now());
+            let missing = self.send_session(session, 1)?.missing.ok_or_else(|| {
+                SecSnailError::ProtocolViolation("receiver did not answer the manifest".to_string())
+            })?;
+            tracing::info!(
+                entries = chunk.len(),
+                missing = missing.len(),
+                "receiver answered manifest"
+            );
+            for (path, entry) in missing.into_iter().map(|i| &chunk[i]) {
+                let r = self
+                    .send_path_with_retries(path, recv_addr, |s| s.with_remote_path(&entry.path));
+                results.push((path.clone(), r));
+            }
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,150 @@
+//! Textual protocol trace of a socket.
+//!
+//! Every event consumed by an fsm and every packet it emits is written as
+//! one line of `key=value` pairs, e.g.
+//!
+//! ```text
+//! t_us=0 fsm=send event=init_syn
+//! t_us=12 fsm=send emit=SYN n=0 len=8 peer=10.0.0.2:55055
+//! t_us=1043 fsm=send event=rcv_pkt flag=ACK n=0 len=0 checksum=ok
+//! ```
+//!
+//! `t_us` counts microseconds on the clock of the transport since the
+//! trace was started, so traces of a simulated network are reproducible.
+
+use std::{
+    io::{self, Write},
+    net::SocketAddr,
+    sync::Mutex,
+    time::Instant,
+};
+
+use crate::pck::Packet;
+
+pub(super) struct TraceLog {
+    wrt: Mutex<Box<dyn Write + Send>>,
+    start: Instant,
+}
+
+impl TraceLog {
+    pub fn new(wrt: Box<dyn Write + Send>, start: Instant) -> TraceLog {
+        TraceLog {
+            wrt: Mutex::new(wrt),
+            start,
+        }
+    }
+
+    /// `fsm` consumed the event `name`, which carried `pck` if received
+    pub fn event(&self, now: Instant, fsm: &str, name: &str, pck: Option<&Packet>) {
+        let mut line = format!("{} fsm={fsm} event={name}", self.stamp(now));
+        if let Some(pck) = pck {
+            line.push_str(&format!(
+                " flag={} n={} len={} checksum={}",
+                pck.flag(),
+                pck.n(),
+                pck.payload().len(),
+                if pck.notcorrupt() { "ok" } else { "corrupt" }
+            ));
+        }
+        self.write_line(&line);
+    }
+
+    /// `fsm` emitted `pck` to `peer`, before any impairment
+    pub fn emit(&self, now: Instant, fsm: &str, pck: &Packet, peer: SocketAddr) {
+        let line = format!(
+            "{} fsm={fsm} emit={} n={} len={} peer={peer}",
+            self.stamp(now),
+            pck.flag(),
+            pck.n(),
+            pck.payload().len()
+        );
+        self.write_line(&line);
+    }
+
+    fn stamp(&self, now: Instant) -> String {
+        format!(
+            "t_us={}",
+            now.saturating_duration_since(self.start).as_micros()
+        )
+    }
+
+    fn write_line(&self, line: &str) {
+        let mut wrt = self.wrt.lock().unwrap();
+        // a broken trace must not break the transfer
+        let r: io::Result<()> = writeln!(wrt, "{line}").and_then(|_| wrt.flush());
+        if let Err(e) = r {
+            tracing::warn!(error = %e, "writing protocol trace failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{sim::SimNetwork, sock::SecSnailSocket};
+    use std::{
+        fs,
+        sync::{Arc, Mutex},
+    };
+
+    /// writer of a trace shared with the test
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(seed: u64, dir: &std::path::Path) -> (String, String) {
+        let net = SimNetwork::new(seed).loss_p(0.2);
+        let mut sender = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.1:4000".parse().unwrap()))
+            .unwrap();
+        let mut receiver = SecSnailSocket::builder()
+            .build_with_transport(net.endpoint("10.0.0.2:55055".parse().unwrap()))
+            .unwrap();
+        let (snd_buf, rcv_buf) = (SharedBuf::default(), SharedBuf::default());
+        sender.set_trace_writer(snd_buf.clone());
+        receiver.set_trace_writer(rcv_buf.clone());
+
+        net.run_transfer(
+            &mut sender,
+            &mut receiver,
+            dir.join("snail.txt"),
+            dir.join("out"),
+        )
+        .unwrap();
+        let text = |b: SharedBuf| String::from_utf8(b.0.lock().unwrap().clone()).unwrap();
+        (text(snd_buf), text(rcv_buf))
+    }
+
+    #[test]
+    fn traces_events_and_emitted_packets() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-trace", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("snail.txt"), vec![3u8; 3000]).unwrap();
+
+        let (snd, rcv) = trace(5, &dir);
+        let first: Vec<&str> = snd.lines().take(2).collect();
+        assert_eq!(first[0], "t_us=0 fsm=send event=init_syn");
+        assert_eq!(
+            first[1],
+            "t_us=0 fsm=send emit=SYN n=0 len=18 peer=10.0.0.2:55055"
+        );
+        assert!(snd.lines().any(|l| l.contains("event=timeout")));
+        assert!(
+            rcv.lines()
+                .all(|l| l.starts_with("t_us=") && l.contains(" fsm=recv "))
+        );
+        assert!(rcv.contains("emit=ACK"));
+
+        // same seed, same trace
+        assert_eq!(trace(5, &dir), (snd, rcv));
+    }
+}
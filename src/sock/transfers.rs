@@ -0,0 +1,265 @@
+//! Sending in the background.
+//!
+//! A socket handed to a `TransferWorker` sends on a thread of its own, files
+//! are queued with `queue_send` from any thread and sent one after another
+//! through a `SendQueue`, with its priorities and preemption. Every queued
+//! file gets a `TransferHandle` to watch its progress, cancel it or wait for
+//! its report, so no thread has to block per file.
+
+use std::{
+    fs,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::error::{Result, SecSnailError};
+
+use super::{DatagramTransport, Progress, SecSnailSocket, SendQueue, SendReport};
+
+/// where a queued file is at, see `TransferHandle::status`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferStatus {
+    Queued,
+    Sending,
+    /// paused for a file of higher priority, sent again later
+    Paused,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug)]
+struct State {
+    status: TransferStatus,
+    progress: Progress,
+    outcome: Option<Result<SendReport>>,
+}
+
+/// state of a queued file, shared by its handle and the worker
+#[derive(Debug)]
+pub(super) struct TransferState {
+    state: Mutex<State>,
+    finished: Condvar,
+    cancel: AtomicBool,
+}
+
+impl TransferState {
+    fn new(total: Option<u64>) -> TransferState {
+        TransferState {
+            state: Mutex::new(State {
+                status: TransferStatus::Queued,
+                progress: Progress {
+                    bytes: 0,
+                    total,
+                    retransmissions: 0,
+                },
+                outcome: None,
+            }),
+            finished: Condvar::new(),
+            cancel: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn set_status(&self, status: TransferStatus) {
+        self.state.lock().unwrap().status = status;
+    }
+
+    pub fn set_progress(&self, progress: Progress) {
+        self.state.lock().unwrap().progress = progress;
+    }
+
+    fn finish(&self, outcome: Result<SendReport>) {
+        let mut state = self.state.lock().unwrap();
+        state.status = match &outcome {
+            Ok(_) => TransferStatus::Done,
+            Err(SecSnailError::Cancelled) => TransferStatus::Cancelled,
+            Err(_) => TransferStatus::Failed,
+        };
+        state.outcome = Some(outcome);
+        self.finished.notify_all();
+    }
+}
+
+/// a file queued with `TransferWorker::queue_send`
+#[derive(Debug)]
+pub struct TransferHandle {
+    state: Arc<TransferState>,
+}
+
+impl TransferHandle {
+    pub fn status(&self) -> TransferStatus {
+        self.state.state.lock().unwrap().status
+    }
+
+    /// bytes sent so far, they start over if the receiver does not resume
+    /// a paused file
+    pub fn progress(&self) -> Progress {
+        self.state.state.lock().unwrap().progress
+    }
+
+    /// whether the file is sent, failed or cancelled
+    pub fn is_finished(&self) -> bool {
+        self.state.state.lock().unwrap().outcome.is_some()
+    }
+
+    /// drop the file from the queue or stop sending it, the receiver of a
+    /// stopped transfer is not told and times out
+    pub fn cancel(&self) {
+        self.state.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// block until the file is finished
+    ///
+    /// # Return
+    /// report of the sent file, `SecSnailError::Cancelled` if it was
+    /// cancelled
+    pub fn wait(self) -> Result<SendReport> {
+        let state = self.state.state.lock().unwrap();
+        let mut state = self
+            .state
+            .finished
+            .wait_while(state, |s| s.outcome.is_none())
+            .unwrap();
+        state.outcome.take().unwrap()
+    }
+}
+
+/// a socket sending queued files on a thread of its own, see the module
+/// docs
+///
+/// Dropping the worker lets it send the files still queued before its
+/// thread ends, `join` waits for them and returns the socket.
+pub struct TransferWorker<T: DatagramTransport + Send + 'static> {
+    queue: SendQueue,
+    /// a message per queued file, dropped to end the thread
+    wake: Option<Sender<()>>,
+    thread: Option<JoinHandle<SecSnailSocket<T>>>,
+}
+
+impl<T: DatagramTransport + Send + 'static> SecSnailSocket<T> {
+    /// move the socket to a worker thread which sends the files queued with
+    /// `TransferWorker::queue_send`
+    pub fn spawn_transfer_worker(mut self) -> TransferWorker<T> {
+        let queue = SendQueue::new();
+        let (wake, woken) = mpsc::channel();
+        let thread = thread::spawn({
+            let queue = queue.clone();
+            move || {
+                while woken.recv().is_ok() {
+                    self.drain_queue(&queue, |file, outcome| {
+                        if let Some(state) = file.state {
+                            state.finish(outcome);
+                        }
+                    });
+                }
+                self
+            }
+        });
+        TransferWorker {
+            queue,
+            wake: Some(wake),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<T: DatagramTransport + Send + 'static> TransferWorker<T> {
+    /// queue the file at `path` for `recv_addr`
+    pub fn queue_send<P: AsRef<Path>>(&self, path: P, recv_addr: SocketAddr) -> TransferHandle {
+        self.queue_send_with_priority(path, recv_addr, 0)
+    }
+
+    /// queue the file at `path` for `recv_addr`, a higher `priority` is sent
+    /// earlier, see `SendQueue`
+    pub fn queue_send_with_priority<P: AsRef<Path>>(
+        &self,
+        path: P,
+        recv_addr: SocketAddr,
+        priority: u8,
+    ) -> TransferHandle {
+        let path = path.as_ref();
+        let total = fs::metadata(path).ok().map(|m| m.len());
+        let state = Arc::new(TransferState::new(total));
+        self.queue
+            .push_with_state(path, recv_addr, priority, Some(state.clone()));
+        if let Some(wake) = &self.wake {
+            let _ = wake.send(());
+        }
+        TransferHandle { state }
+    }
+
+    /// files waiting, not counting the one in flight
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// wait until every queued file is finished
+    ///
+    /// # Return
+    /// the socket, to send with it directly again
+    pub fn join(mut self) -> SecSnailSocket<T> {
+        self.wake = None;
+        match self.thread.take().unwrap().join() {
+            Ok(sock) => sock,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl<T: DatagramTransport + Send + 'static> Drop for TransferWorker<T> {
+    fn drop(&mut self) {
+        self.wake = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn worker_sends_queued_files_and_cancels() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-transfers", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let bulk: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(dir.join("bulk.bin"), &bulk).unwrap();
+        fs::write(dir.join("late.txt"), b"never sent").unwrap();
+        fs::write(dir.join("small.txt"), b"snail mail").unwrap();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            (0..2)
+                .map(|_| receiver.recv_file_blocking(&out).unwrap().file_name)
+                .collect::<Vec<_>>()
+        });
+
+        let worker = SecSnailSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .spawn_transfer_worker();
+        let bulk_handle = worker.queue_send(dir.join("bulk.bin"), recv_addr);
+        let late = worker.queue_send(dir.join("late.txt"), recv_addr);
+        let small = worker.queue_send(dir.join("small.txt"), recv_addr);
+        late.cancel();
+        assert_eq!(bulk_handle.progress().total, Some(100_000));
+
+        assert_eq!(bulk_handle.wait().unwrap().bytes, 100_000);
+        assert!(matches!(late.wait(), Err(SecSnailError::Cancelled)));
+        small.wait().unwrap();
+        assert!(worker.join().last_transfer_stats().is_some());
+        assert_eq!(recv.join().unwrap(), ["bulk.bin", "small.txt"]);
+        assert_eq!(fs::read(dir.join("out/bulk.bin")).unwrap(), bulk);
+    }
+}
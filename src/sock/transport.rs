@@ -0,0 +1,291 @@
+//! Datagram transport underneath a `SecSnailSocket`.
+//!
+//! The socket only needs to send and receive single datagrams with a read
+//! timeout, so the `UdpSocket` can be replaced, e.g. by an in-memory
+//! transport to drive both fsms in tests without real sockets.
+
+use std::{
+    io,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+/// blocking datagram i/o of a `SecSnailSocket`
+pub trait DatagramTransport {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// send to the connected peer, only called if the socket was built with `connect`
+    fn send(&self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+
+    /// wait for the next datagram, an expired read timeout returns `WouldBlock`
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// `None` waits forever, a zero timeout is never set
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// a non-blocking transport returns `WouldBlock` instead of waiting
+    fn set_nonblocking(&self, _nonblocking: bool) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// clock of all timers, virtual in a simulated network
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl DatagramTransport for UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        UdpSocket::send(self, buf)
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        UdpSocket::recv_from(self, buf)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UdpSocket::set_read_timeout(self, timeout)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UdpSocket::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::SecSnailError, sock::SecSnailSocket};
+    use std::{
+        cell::Cell,
+        fs,
+        sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel},
+        thread,
+    };
+
+    /// one end of a lossless in-memory link
+    struct ChannelTransport {
+        addr: SocketAddr,
+        tx: Sender<(Vec<u8>, SocketAddr)>,
+        rx: Receiver<(Vec<u8>, SocketAddr)>,
+        timeout: Cell<Option<Duration>>,
+    }
+
+    fn link(a: SocketAddr, b: SocketAddr) -> (ChannelTransport, ChannelTransport) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        let end = |addr, tx, rx| ChannelTransport {
+            addr,
+            tx,
+            rx,
+            timeout: Cell::new(None),
+        };
+        (end(a, a_tx, a_rx), end(b, b_tx, b_rx))
+    }
+
+    impl DatagramTransport for ChannelTransport {
+        fn send_to(&self, buf: &[u8], _addr: SocketAddr) -> io::Result<usize> {
+            // a vanished peer is like a lost datagram
+            _ = self.tx.send((buf.to_vec(), self.addr));
+            Ok(buf.len())
+        }
+
+        fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let (datagram, src) = match self.timeout.get() {
+                Some(timeout) => self.rx.recv_timeout(timeout).map_err(|e| match e {
+                    RecvTimeoutError::Timeout => io::ErrorKind::WouldBlock,
+                    RecvTimeoutError::Disconnected => io::ErrorKind::ConnectionReset,
+                })?,
+                None => self
+                    .rx
+                    .recv()
+                    .map_err(|_| io::Error::from(io::ErrorKind::ConnectionReset))?,
+            };
+            let n = datagram.len().min(buf.len());
+            buf[..n].copy_from_slice(&datagram[..n]);
+            Ok((n, src))
+        }
+
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.timeout.set(timeout);
+            Ok(())
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Ok(self.addr)
+        }
+    }
+
+    /// fails every other receive with `Interrupted` after half its read
+    /// timeout, like a signal arriving while the socket waits
+    struct InterruptingTransport {
+        inner: ChannelTransport,
+        calls: Cell<usize>,
+    }
+
+    impl DatagramTransport for InterruptingTransport {
+        fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+            self.inner.send_to(buf, addr)
+        }
+
+        fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let calls = self.calls.get();
+            self.calls.set(calls + 1);
+            if calls % 2 == 1 {
+                return self.inner.recv_from(buf);
+            }
+            thread::sleep(self.inner.timeout.get().unwrap_or_default() / 2);
+            Err(io::ErrorKind::Interrupted.into())
+        }
+
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.inner.set_read_timeout(timeout)
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.inner.local_addr()
+        }
+    }
+
+    fn interrupting(inner: ChannelTransport) -> InterruptingTransport {
+        InterruptingTransport {
+            inner,
+            calls: Cell::new(0),
+        }
+    }
+
+    #[test]
+    fn interrupted_receive_is_retried() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-eintr", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("snail.txt");
+        let content: Vec<u8> = (0..3000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(&src, &content).unwrap();
+
+        let snd_addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let recv_addr: SocketAddr = "10.0.0.2:55055".parse().unwrap();
+        let (snd_end, recv_end) = link(snd_addr, recv_addr);
+
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            let mut receiver = SecSnailSocket::builder()
+                .rcv_timeout(Duration::from_millis(300))
+                .build_with_transport(recv_end)
+                .unwrap();
+            receiver.recv_file_blocking(out).unwrap()
+        });
+        let mut sender = SecSnailSocket::builder()
+            .snd_timeout(Duration::from_millis(100))
+            .build_with_transport(interrupting(snd_end))
+            .unwrap();
+        let report = sender.send_file_to_blocking(&src, recv_addr).unwrap();
+
+        recv.join().unwrap();
+        assert!(sender.inner.calls.get() > 2);
+        // every ack arrived within the remaining timeout
+        assert_eq!(report.stats.timeouts, 0);
+        assert_eq!(report.stats.retransmissions, 0);
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+    }
+
+    #[test]
+    fn interrupted_receive_keeps_its_timeout() {
+        let dir =
+            std::env::temp_dir().join(format!("secsnail-{}-eintr-timeout", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("snail.txt");
+        fs::write(&src, b"slow").unwrap();
+
+        let snd_addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let recv_addr: SocketAddr = "10.0.0.2:55055".parse().unwrap();
+        // nobody answers at the other end
+        let (snd_end, _recv_end) = link(snd_addr, recv_addr);
+        let mut sender = SecSnailSocket::builder()
+            .snd_timeout(Duration::from_millis(100))
+            .max_retransmits(2)
+            .build_with_transport(interrupting(snd_end))
+            .unwrap();
+
+        let start = Instant::now();
+        let r = sender.send_file_to_blocking(&src, recv_addr);
+        let elapsed = start.elapsed();
+
+        assert!(matches!(r, Err(SecSnailError::MaxRetransmitsExceeded)));
+        // three timeouts of 100ms, each interrupted halfway, restarting
+        // them would take 150ms each
+        let timeouts = sender.last_transfer_stats().unwrap().timeouts;
+        assert_eq!(timeouts, 3);
+        assert!(elapsed >= Duration::from_millis(300), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(420), "{elapsed:?}");
+    }
+
+    #[test]
+    fn transfer_over_channels() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-channel", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("snail.txt");
+        let content: Vec<u8> = (0..5000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(&src, &content).unwrap();
+
+        let snd_addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let recv_addr: SocketAddr = "10.0.0.2:55055".parse().unwrap();
+        let (snd_end, recv_end) = link(snd_addr, recv_addr);
+
+        let out = dir.join("out");
+        let recv = thread::spawn(move || {
+            let mut receiver = SecSnailSocket::builder()
+                .build_with_transport(recv_end)
+                .unwrap();
+            receiver.recv_file_blocking(out).unwrap()
+        });
+        let mut sender = SecSnailSocket::builder()
+            .build_with_transport(snd_end)
+            .unwrap();
+        let sent = sender.send_file_to_blocking(&src, recv_addr).unwrap().bytes;
+
+        let report = recv.join().unwrap();
+        assert_eq!(sent, content.len());
+        assert_eq!(report.peer, snd_addr);
+        assert_eq!(fs::read(dir.join("out/snail.txt")).unwrap(), content);
+    }
+
+    #[test]
+    fn polled_receive_ends_on_io_error() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-poll-io", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let snd_addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let recv_addr: SocketAddr = "10.0.0.2:55055".parse().unwrap();
+        // the link is gone, every receive fails
+        let (_, recv_end) = link(snd_addr, recv_addr);
+        let mut receiver = SecSnailSocket::builder()
+            .build_with_transport(recv_end)
+            .unwrap();
+        receiver.start_recv_file(dir.join("out")).unwrap();
+
+        let r = receiver.poll_recv_progress();
+        assert!(matches!(
+            r,
+            Err(SecSnailError::Io(e)) if e.kind() == io::ErrorKind::ConnectionReset
+        ));
+        assert!(matches!(
+            receiver.poll_recv_progress(),
+            Err(SecSnailError::NoActiveTransfer)
+        ));
+    }
+}
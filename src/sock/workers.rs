@@ -0,0 +1,208 @@
+//! Receiving with a worker thread per session.
+//!
+//! The serving thread receives every datagram from the socket and hands it
+//! to the worker of its sender and transfer id through a channel. A worker drives the fsm
+//! of its session and sends through the shared socket, so the file i/o of a
+//! slow transfer does not hold back the syns of other senders.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    ops::ControlFlow,
+    path::Path,
+    sync::mpsc::{self, Receiver, SendError, Sender},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    error::Result,
+    fsm_recv::{
+        driver::{handle_event, run_rcv_fsm_loop},
+        fsm::{RcvEvent, RcvFsm},
+    },
+    pck::Packet,
+};
+
+use super::{
+    DatagramTransport, SecSnailSocket, TransferDirection, TransferReport, TransferStats,
+    demux::{SessionKey, SessionOutcome},
+    is_sender_failure, prepare_target_dir,
+    rcv_ctx::{RecvProtocolIoContext, RecvSession},
+};
+
+/// how often the serving thread checks for finished workers while idle
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+impl<T: DatagramTransport + Sync> SecSnailSocket<T> {
+    /// like `serve`, but every session is received by a thread of its own
+    ///
+    /// `handler` is called on the calling thread, once it breaks the open
+    /// sessions are aborted, a shutdown lets them finish
+    pub fn serve_threaded<P, F>(&mut self, target_dir: P, mut handler: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(TransferReport) -> ControlFlow<()>,
+    {
+        let target_dir = target_dir.as_ref();
+        prepare_target_dir(target_dir)?;
+
+        let sock = &*self;
+        let mut last_stats = None;
+        let r = thread::scope(|scope| {
+            let (done_tx, done_rx) = mpsc::channel::<SessionOutcome>();
+            // dropping an inbox aborts its worker
+            let mut inboxes: HashMap<SessionKey, Sender<Option<Packet>>> = HashMap::new();
+            loop {
+                while let Ok((key, outcome)) = done_rx.try_recv() {
+                    inboxes.remove(&key);
+                    let (peer, transfer_id) = key;
+                    let report = match outcome {
+                        Err(e) if is_sender_failure(&e) => {
+                            let stats = TransferStats::default();
+                            sock.notify_ended(
+                                TransferDirection::Recv,
+                                Some(peer),
+                                transfer_id,
+                                &stats,
+                                Some(&e),
+                            );
+                            continue;
+                        }
+                        r => r?,
+                    };
+                    last_stats = Some(report.stats);
+                    sock.totals.lock().unwrap().record_recv(&report.stats);
+                    sock.notify_ended(
+                        TransferDirection::Recv,
+                        Some(report.peer),
+                        transfer_id,
+                        &report.stats,
+                        None,
+                    );
+                    if handler(report).is_break() {
+                        return Ok(());
+                    }
+                }
+                let stopped = sock.check_shutdown().is_err();
+                if stopped && inboxes.is_empty() {
+                    return Ok(());
+                }
+
+                sock.inner.set_read_timeout(Some(POLL_INTERVAL))?;
+                let (peer, rcvpkt) = match sock.rdt_recv() {
+                    Ok(v) => v,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                let key = (peer, rcvpkt.as_ref().map_or(0, Packet::transfer_id));
+                // a finished worker leaves the packet to a new session
+                let rcvpkt = match inboxes.get(&key) {
+                    Some(inbox) => match inbox.send(rcvpkt) {
+                        Ok(()) => continue,
+                        Err(SendError(rcvpkt)) => {
+                            inboxes.remove(&key);
+                            rcvpkt
+                        }
+                    },
+                    None => rcvpkt,
+                };
+
+                let mut session = sock.new_recv_session(target_dir)?;
+                if !rcvpkt
+                    .as_ref()
+                    .is_some_and(|p| p.notcorrupt() && p.is_SYN())
+                {
+                    // e.g. a retransmitted fin of a closed session
+                    let mut ctx = RecvProtocolIoContext::new(sock, &mut session);
+                    handle_event(RcvFsm::init(), RcvEvent::RecvPck(rcvpkt, peer), &mut ctx)?;
+                    continue;
+                }
+                if stopped {
+                    continue;
+                }
+
+                let (inbox_tx, inbox) = mpsc::channel();
+                inboxes.insert(key, inbox_tx);
+                let done_tx = done_tx.clone();
+                thread::Builder::new()
+                    .name(format!("secsnail-recv-{peer}"))
+                    .spawn_scoped(scope, move || {
+                        let outcome = receive_session(sock, session, inbox, peer, rcvpkt);
+                        _ = done_tx.send((key, outcome));
+                    })?;
+            }
+        });
+        if last_stats.is_some() {
+            self.last_stats = last_stats;
+        }
+        r
+    }
+}
+
+/// drive the session opened by `syn` with the packets of `peer` in `inbox`
+fn receive_session<T: DatagramTransport>(
+    sock: &SecSnailSocket<T>,
+    mut session: RecvSession<'static>,
+    inbox: Receiver<Option<Packet>>,
+    peer: SocketAddr,
+    syn: Option<Packet>,
+) -> Result<TransferReport> {
+    let _span = tracing::info_span!("recv_file", %peer).entered();
+    let mut ctx = RecvProtocolIoContext::with_inbox(sock, &mut session, &inbox, peer);
+    let fsm = handle_event(RcvFsm::init(), RcvEvent::RecvPck(syn, peer), &mut ctx)?;
+    run_rcv_fsm_loop(fsm, &mut ctx)?;
+    Ok(session.take_report().expect("closed session has a report"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, ops::ControlFlow, thread};
+
+    use crate::sock::SecSnailSocket;
+
+    #[test]
+    fn worker_per_sender() {
+        let dir = std::env::temp_dir().join(format!("secsnail-{}-workers", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let content: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut receiver = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+        let recv_addr = receiver.local_addr().unwrap();
+
+        let senders: Vec<_> = ["a.bin", "b.bin", "c.bin"]
+            .into_iter()
+            .map(|name| {
+                let src = dir.join(name);
+                fs::write(&src, &content).unwrap();
+                thread::spawn(move || {
+                    let mut sock = SecSnailSocket::bind("127.0.0.1:0").unwrap();
+                    sock.send_file_to_blocking(src, recv_addr).unwrap()
+                })
+            })
+            .collect();
+
+        let mut received = Vec::new();
+        receiver
+            .serve_threaded(dir.join("out"), |report| {
+                received.push(report.file_name);
+                match received.len() {
+                    3 => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                }
+            })
+            .unwrap();
+
+        for sender in senders {
+            sender.join().unwrap();
+        }
+        received.sort();
+        assert_eq!(received, ["a.bin", "b.bin", "c.bin"]);
+        for name in received {
+            assert_eq!(fs::read(dir.join("out").join(name)).unwrap(), content);
+        }
+        assert!(receiver.last_transfer_stats().is_some());
+    }
+}
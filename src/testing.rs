@@ -0,0 +1,207 @@
+//! Property-based test harness of the protocol.
+//!
+//! `run` wires the sans-IO sender and receiver through a channel which
+//! treats every datagram as scripted by a `Schedule`, on a virtual clock.
+//! `check` asserts the end-to-end invariants of a transfer:
+//!
+//! - the file is delivered byte-identical
+//! - no data is written twice
+//! - the transfer terminates, as the loss of a schedule is bounded
+//!
+//! ```
+//! use proptest::prelude::*;
+//! use secsnail::testing::{check, schedule};
+//!
+//! proptest!(|(file in prop::collection::vec(any::<u8>(), 0..2000), schedule in schedule(64))| {
+//!     check(&file, &schedule).unwrap();
+//! });
+//! ```
+
+use std::{collections::VecDeque, net::SocketAddr, time::Instant};
+
+use proptest::{collection, prelude::*};
+
+use crate::{
+    error::Result,
+    proto::{Action, RecvInput, SnailReceiver, bytes::BytesSender},
+};
+
+/// handling of a single datagram by the channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fate {
+    Deliver,
+    Drop,
+    /// a bit of the datagram is flipped
+    Corrupt,
+    /// delivered twice
+    Duplicate,
+}
+
+/// fates of the datagrams in the order they are sent by either side, all
+/// datagrams after the end of the schedule are delivered
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schedule {
+    pub fates: Vec<Fate>,
+}
+
+/// what happened during a transfer of `run`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outcome {
+    /// name and data of every file closed by the receiver
+    pub files: Vec<(String, Vec<u8>)>,
+    /// `Action::WriteData` of the receiver
+    pub writes: usize,
+    /// bytes of all writes
+    pub written: usize,
+    /// datagrams put on the channel by either side
+    pub datagrams: usize,
+    /// the sender got its fin acknowledged
+    pub finished: bool,
+}
+
+/// file name of the transfers of `run`
+pub const FILE_NAME: &str = "snail.bin";
+
+/// inputs handed to the sender or receiver before `run` gives up
+const MAX_STEPS: usize = 1_000_000;
+
+/// channel which applies the fates of `schedule` in order
+struct Channel<'a> {
+    fates: std::slice::Iter<'a, Fate>,
+    datagrams: usize,
+}
+
+impl Channel<'_> {
+    fn transmit(&mut self, mut datagram: Vec<u8>) -> Vec<Vec<u8>> {
+        self.datagrams += 1;
+        match self.fates.next().copied().unwrap_or(Fate::Deliver) {
+            Fate::Deliver => vec![datagram],
+            Fate::Drop => vec![],
+            Fate::Corrupt => {
+                if let Some(b) = datagram.last_mut() {
+                    *b ^= 1;
+                }
+                vec![datagram]
+            }
+            Fate::Duplicate => vec![datagram.clone(), datagram],
+        }
+    }
+}
+
+/// send `file` from a sender to a receiver over a channel impaired by `schedule`
+///
+/// # Return
+/// the outcome once the sender finished or gave up, fails if the sender
+/// failed, e.g. with `MaxRetransmitsExceeded`
+pub fn run(file: &[u8], schedule: &Schedule) -> Result<Outcome> {
+    let peer: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+    let mut now = Instant::now();
+    let mut channel = Channel {
+        fates: schedule.fates.iter(),
+        datagrams: 0,
+    };
+    let mut sender = BytesSender::new(FILE_NAME, file.to_vec(), now)?;
+    let mut receiver = SnailReceiver::new();
+    let mut outcome = Outcome::default();
+    let mut open: Option<Vec<u8>> = None;
+
+    let mut to_sender = VecDeque::new();
+    for _ in 0..MAX_STEPS {
+        if sender.is_finished() {
+            break;
+        }
+        if let Some(datagram) = sender.poll_transmit() {
+            for datagram in channel.transmit(datagram) {
+                let Ok(actions) = receiver.handle(RecvInput::Datagram(datagram, peer)) else {
+                    // the session got aborted
+                    open = None;
+                    continue;
+                };
+                for action in actions {
+                    match action {
+                        Action::EmitPacket(pck) => to_sender.extend(channel.transmit(pck)),
+                        Action::OpenFile(_) => open = Some(Vec::new()),
+                        Action::WriteData(data) => {
+                            outcome.writes += 1;
+                            outcome.written += data.len();
+                            open.get_or_insert_default().extend(data);
+                        }
+                        Action::CloseFile => outcome
+                            .files
+                            .extend(open.take().map(|f| (FILE_NAME.to_string(), f))),
+                        Action::DiscardFile => open = None,
+                        _ => {}
+                    }
+                }
+            }
+        } else if let Some(datagram) = to_sender.pop_front() {
+            sender.recv(&datagram, now)?;
+        } else if let Some(t) = sender.next_timeout() {
+            now = now.max(t);
+            sender.tick(now)?;
+        }
+    }
+    outcome.finished = sender.is_finished();
+    outcome.datagrams = channel.datagrams;
+    Ok(outcome)
+}
+
+/// run a transfer and check the end-to-end invariants
+///
+/// # Return
+/// the outcome, fails with a description of the first broken invariant
+pub fn check(file: &[u8], schedule: &Schedule) -> std::result::Result<Outcome, String> {
+    let outcome = run(file, schedule).map_err(|e| format!("transfer failed: {e}"))?;
+    if !outcome.finished {
+        return Err(format!("no termination after {MAX_STEPS} steps"));
+    }
+    if outcome.written != file.len() {
+        return Err(format!(
+            "{} bytes written in {} writes for a file of {} bytes",
+            outcome.written,
+            outcome.writes,
+            file.len()
+        ));
+    }
+    match outcome.files.as_slice() {
+        [(_, data)] if data == file => Ok(outcome),
+        [(_, _)] => Err("delivered file differs".to_string()),
+        files => Err(format!("{} files delivered instead of one", files.len())),
+    }
+}
+
+/// schedules of up to `max_len` fates, the sender retransmits every packet
+/// often enough to survive any of them
+pub fn schedule(max_len: usize) -> impl Strategy<Value = Schedule> {
+    let fate = prop_oneof![
+        4 => Just(Fate::Deliver),
+        2 => Just(Fate::Drop),
+        1 => Just(Fate::Corrupt),
+        1 => Just(Fate::Duplicate),
+    ];
+    collection::vec(fate, 0..=max_len).prop_map(|fates| Schedule { fates })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn delivers_under_bounded_loss(
+            file in collection::vec(any::<u8>(), 0..3000),
+            schedule in schedule(64),
+        ) {
+            check(&file, &schedule).map_err(TestCaseError::fail)?;
+        }
+    }
+
+    #[test]
+    fn lossless_transfer_sends_each_packet_once() {
+        let file = vec![7; 1200];
+        let outcome = check(&file, &Schedule::default()).unwrap();
+        assert_eq!(outcome.writes, 3);
+        // syn, 3 data and the fin with their acks
+        assert_eq!(outcome.datagrams, 10);
+    }
+}
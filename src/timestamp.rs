@@ -0,0 +1,129 @@
+//! Snail Transfer Protocol – timestamps
+//!
+//! A sender offering timestamps in its SYN, which the receiver accepts in
+//! the ACK of the SYN, see `meta`, stamps every data packet with the time
+//! it was sent, by its own clock. The receiver answers each with an ACK
+//! echoing the stamp, followed by the time it got the packet by its clock.
+//!
+//! ```text
+//!  data:  ┌──────────────────────┬───────────────────────────┐
+//!         │ Sent (32 bit)        │ Application Data          │
+//!         └──────────────────────┴───────────────────────────┘
+//!  ack:   ┌──────────────────────┬──────────────────────┐
+//!         │ Sent (32 bit)        │ Received (32 bit)    │
+//!         └──────────────────────┴──────────────────────┘
+//! ```
+//!
+//! Times are microseconds, wrapping, since the start of the session on
+//! either side. The clocks are not synchronized, so the difference of the
+//! two times is the one-way delay plus an unknown but constant offset. Its
+//! smallest value is taken as a packet which met no queue, whatever a
+//! later packet took longer was spent queued on the way.
+
+use std::time::{Duration, Instant};
+
+/// bytes of a stamp in front of the data
+pub const TIMESTAMP_LEN: usize = 4;
+
+/// microseconds from `origin` to `now`, wrapping after about 71 minutes
+pub fn micros(origin: Instant, now: Instant) -> u32 {
+    now.saturating_duration_since(origin).as_micros() as u32
+}
+
+/// `data` stamped with `sent`
+pub fn stamp(sent: u32, data: &[u8]) -> Vec<u8> {
+    [&sent.to_be_bytes()[..], data].concat()
+}
+
+/// stamp and data of a stamped payload, `None` if it is too short
+pub fn split_stamp(payload: &[u8]) -> Option<(u32, &[u8])> {
+    let (stamp, data) = payload.split_first_chunk::<TIMESTAMP_LEN>()?;
+    Some((u32::from_be_bytes(*stamp), data))
+}
+
+/// payload of the ack of a stamped packet
+pub fn encode_echo(sent: u32, received: u32) -> Vec<u8> {
+    [sent.to_be_bytes(), received.to_be_bytes()].concat()
+}
+
+pub fn decode_echo(payload: &[u8]) -> Option<(u32, u32)> {
+    let (sent, received) = split_stamp(payload)?;
+    Some((sent, u32::from_be_bytes(received.try_into().ok()?)))
+}
+
+/// time the data packets of a send spent queued on the way to the
+/// receiver, from the timestamps echoed in its acks, see `timestamp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueueingDelay {
+    /// acks which echoed a timestamp
+    pub samples: u32,
+    pub mean: Duration,
+    pub max: Duration,
+    /// of the last acked packet, above `mean` while a queue builds up
+    pub last: Duration,
+}
+
+/// one-way delays plus the offset of the clocks, see the module docs
+#[derive(Debug, Default)]
+pub struct DelayEstimator {
+    samples: u32,
+    sum: i64,
+    min: i64,
+    max: i64,
+    last: i64,
+}
+
+impl DelayEstimator {
+    pub fn record(&mut self, sent: u32, received: u32) {
+        // the difference is small, whichever clock is ahead
+        let delay = i64::from(received.wrapping_sub(sent) as i32);
+        if self.samples == 0 {
+            (self.min, self.max) = (delay, delay);
+        }
+        self.samples += 1;
+        self.sum += delay;
+        self.min = self.min.min(delay);
+        self.max = self.max.max(delay);
+        self.last = delay;
+    }
+
+    /// `None` before the first sample
+    pub fn queueing_delay(&self) -> Option<QueueingDelay> {
+        let above_min = |delay: i64| Duration::from_micros((delay - self.min) as u64);
+        let samples = i64::from(self.samples);
+        (self.samples > 0).then(|| QueueingDelay {
+            samples: self.samples,
+            mean: above_min(self.min + (self.sum - self.min * samples) / samples),
+            max: above_min(self.max),
+            last: above_min(self.last),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queueing_delay_above_the_fastest_packet() {
+        assert_eq!(split_stamp(&stamp(7, b"snail")), Some((7, &b"snail"[..])));
+        assert_eq!(split_stamp(&[0, 1]), None);
+        assert_eq!(decode_echo(&encode_echo(1, 2)), Some((1, 2)));
+        assert_eq!(decode_echo(&encode_echo(1, 2)[..7]), None);
+
+        let mut estimator = DelayEstimator::default();
+        assert_eq!(estimator.queueing_delay(), None);
+        // the clock of the receiver is behind, across the wrap
+        let offset = 5_000u32;
+        for (sent, delay) in [(0u32, 1_000u32), (10_000, 1_500), (20_000, 4_000)] {
+            let received = sent.wrapping_add(delay).wrapping_sub(offset);
+            estimator.record(sent, received);
+        }
+        let q = estimator.queueing_delay().unwrap();
+        assert_eq!(q.samples, 3);
+        assert_eq!(q.max, Duration::from_micros(3_000));
+        assert_eq!(q.last, Duration::from_micros(3_000));
+        assert_eq!(q.mean, Duration::from_micros(1_166));
+    }
+}